@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS contact_verifications (
+    user_id TEXT PRIMARY KEY NOT NULL,
+    identity_public_key TEXT,
+    e2ee_identity_key TEXT,
+    verified INTEGER NOT NULL DEFAULT 0,
+    verified_at TEXT,
+    key_changed_at TEXT,
+    updated_at TEXT NOT NULL
+);
@@ -0,0 +1,5 @@
+-- Message content is sealed before it reaches the table, so the index can no
+-- longer be fed by triggers. The application writes blinded terms instead and
+-- replaces the existing plaintext entries when the message store is unlocked.
+DROP TRIGGER IF EXISTS messages_ai;
+DROP TRIGGER IF EXISTS messages_au;
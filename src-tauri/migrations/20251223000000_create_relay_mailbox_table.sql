@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS relay_mailbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    recipient TEXT NOT NULL,
+    depositor TEXT NOT NULL,
+    digest TEXT NOT NULL,
+    envelope BLOB NOT NULL,
+    size INTEGER NOT NULL,
+    deposited_at INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    UNIQUE (recipient, digest)
+);
+
+CREATE INDEX IF NOT EXISTS idx_relay_mailbox_recipient ON relay_mailbox(recipient, id);
+CREATE INDEX IF NOT EXISTS idx_relay_mailbox_expires ON relay_mailbox(expires_at);
+CREATE INDEX IF NOT EXISTS idx_relay_mailbox_depositor ON relay_mailbox(depositor, expires_at);
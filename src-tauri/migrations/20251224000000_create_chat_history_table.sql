@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS chat_history (
+    id TEXT PRIMARY KEY NOT NULL,
+    chat_id TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    author TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    payload BLOB
+);
+
+CREATE INDEX IF NOT EXISTS idx_chat_history_chat ON chat_history(chat_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_chat_history_message ON chat_history(message_id);
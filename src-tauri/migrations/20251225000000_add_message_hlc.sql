@@ -0,0 +1,31 @@
+ALTER TABLE messages ADD COLUMN hlc TEXT;
+
+-- Messages stored before the clock are ordered by their own timestamps.
+UPDATE messages
+SET hlc = printf(
+    '%015d-%010d',
+    CAST(strftime('%s', timestamp) AS INTEGER) * 1000 + CAST(substr(strftime('%f', timestamp), 4) AS INTEGER),
+    0
+)
+WHERE hlc IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_messages_chat_hlc ON messages(chat_id, hlc);
+
+-- Buffered operations count against their author's share of the buffer.
+CREATE TABLE IF NOT EXISTS pending_message_operations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    message_id TEXT NOT NULL,
+    author TEXT NOT NULL,
+    operation BLOB NOT NULL,
+    size INTEGER NOT NULL,
+    received_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_pending_message_operations_message ON pending_message_operations(message_id);
+CREATE INDEX IF NOT EXISTS idx_pending_message_operations_author ON pending_message_operations(author);
+
+-- Newest stamp our clock issued, so it carries on from there after a restart.
+CREATE TABLE IF NOT EXISTS hybrid_clock (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    last TEXT NOT NULL
+);
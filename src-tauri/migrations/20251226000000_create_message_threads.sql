@@ -0,0 +1,24 @@
+-- Root message of the thread a reply belongs to.
+ALTER TABLE messages ADD COLUMN thread_id TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_messages_thread_hlc ON messages(thread_id, hlc);
+
+CREATE TABLE IF NOT EXISTS message_threads (
+    root_message_id TEXT PRIMARY KEY NOT NULL,
+    chat_id TEXT NOT NULL,
+    creator_id TEXT NOT NULL,
+    title TEXT,
+    created_at TEXT NOT NULL,
+    reply_count INTEGER NOT NULL DEFAULT 0,
+    last_activity_hlc TEXT NOT NULL,
+    archived INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_threads_chat_activity ON message_threads(chat_id, last_activity_hlc);
+
+CREATE TABLE IF NOT EXISTS thread_followers (
+    root_message_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    followed_at TEXT NOT NULL,
+    PRIMARY KEY (root_message_id, user_id)
+);
@@ -0,0 +1,329 @@
+//! Chat history peers reconcile with one another. Every signed chat message,
+//! edit, reaction, deletion and thread in a shared chat is a
+//! [`HistoryEvent`]. Peers compare per-day digests of the event ids they hold
+//! for a chat and fetch the events they are missing, so messages sent while
+//! someone was offline still reach them.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{AepMessage, MessageDeletionScope};
+
+/// Width of the time buckets digests are taken over.
+pub const BUCKET_MS: i64 = 24 * 60 * 60 * 1000;
+
+const BUCKET_DOMAIN: &[u8] = b"aegis/history-bucket/v1";
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Archive, RkyvSerialize, RkyvDeserialize,
+)]
+#[serde(rename_all = "lowercase")]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+pub enum HistoryKind {
+    Message,
+    Edit,
+    Reaction,
+    Delete,
+    Thread,
+}
+
+impl HistoryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryKind::Message => "message",
+            HistoryKind::Edit => "edit",
+            HistoryKind::Reaction => "reaction",
+            HistoryKind::Delete => "delete",
+            HistoryKind::Thread => "thread",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "message" => Some(HistoryKind::Message),
+            "edit" => Some(HistoryKind::Edit),
+            "reaction" => Some(HistoryKind::Reaction),
+            "delete" => Some(HistoryKind::Delete),
+            "thread" => Some(HistoryKind::Thread),
+            _ => None,
+        }
+    }
+}
+
+/// One signed change to a chat, carried as the bincode of the
+/// [`AepMessage`] its author signed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+pub struct HistoryEvent {
+    /// The message id for messages; a digest of the payload otherwise.
+    pub id: String,
+    pub chat_id: String,
+    /// Message the event creates or changes.
+    pub message_id: String,
+    pub kind: HistoryKind,
+    pub author: String,
+    /// Signing time for messages, edits and threads. Reactions and deletions
+    /// carry none, so they take the signing time of the message they target
+    /// and every peer files them under the same day.
+    pub timestamp_ms: i64,
+    pub payload: Vec<u8>,
+}
+
+impl HistoryEvent {
+    /// The event for `message`, or `None` for messages that are not part of
+    /// a shared chat's history: anything but messages, edits, reactions,
+    /// threads and deletions for everyone, direct messages, and disappearing
+    /// messages. Reactions and deletions also need `target_ms`, the signing
+    /// time of the message named by [`HistoryEvent::target_of`].
+    pub fn from_message(message: &AepMessage, target_ms: Option<i64>) -> Option<Self> {
+        let described = describe(message)?;
+        let timestamp_ms = match described.signed_at {
+            Some(signed_at) => signed_at.timestamp_millis(),
+            None => target_ms?,
+        };
+        let payload = bincode::serialize(message).ok()?;
+        let id = match described.kind {
+            HistoryKind::Message => described.message_id.to_string(),
+            kind => payload_id(kind, &payload),
+        };
+        Some(HistoryEvent {
+            id,
+            chat_id: described.chat_id.to_string(),
+            message_id: described.message_id.to_string(),
+            kind: described.kind,
+            author: described.author.to_string(),
+            timestamp_ms,
+            payload,
+        })
+    }
+
+    /// Chat and message whose signing time a reaction or deletion is filed
+    /// under; `None` for events that carry their own time.
+    pub fn target_of(message: &AepMessage) -> Option<(&str, &str)> {
+        let described = describe(message)?;
+        described
+            .signed_at
+            .is_none()
+            .then_some((described.chat_id, described.message_id))
+    }
+
+    /// The message the event carries, provided it is the one the event's id,
+    /// chat, kind and author describe. Its signature still has to be checked
+    /// before it is applied.
+    pub fn message(&self) -> Option<AepMessage> {
+        let message: AepMessage = bincode::deserialize(&self.payload).ok()?;
+        let expected = HistoryEvent::from_message(&message, Some(self.timestamp_ms))?;
+        let describes = expected.id == self.id
+            && expected.chat_id == self.chat_id
+            && expected.message_id == self.message_id
+            && expected.kind == self.kind
+            && expected.author == self.author;
+        describes.then_some(message)
+    }
+
+    pub fn key(&self) -> HistoryKey {
+        HistoryKey { id: self.id.clone(), timestamp_ms: self.timestamp_ms }
+    }
+}
+
+/// What a history message is about, before it is stamped.
+struct Described<'a> {
+    kind: HistoryKind,
+    chat_id: &'a str,
+    message_id: &'a str,
+    author: &'a str,
+    signed_at: Option<DateTime<Utc>>,
+}
+
+fn describe(message: &AepMessage) -> Option<Described<'_>> {
+    let (kind, chat_id, message_id, author, signed_at) = match message {
+        AepMessage::ChatMessage {
+            id,
+            timestamp,
+            sender,
+            channel_id,
+            server_id,
+            conversation_id,
+            expires_at: None,
+            ..
+        } => {
+            let chat_id = conversation_id.as_ref().or(channel_id.as_ref()).or(server_id.as_ref())?;
+            (HistoryKind::Message, chat_id, id, sender, Some(*timestamp))
+        }
+        AepMessage::EditMessage { message_id, chat_id, editor_id, edited_at, .. } => {
+            (HistoryKind::Edit, chat_id, message_id, editor_id, Some(*edited_at))
+        }
+        AepMessage::MessageReaction { message_id, chat_id, user_id, .. } => {
+            (HistoryKind::Reaction, chat_id, message_id, user_id, None)
+        }
+        AepMessage::DeleteMessage {
+            message_id,
+            chat_id,
+            initiator_id,
+            scope: MessageDeletionScope::Everyone,
+            ..
+        } => (HistoryKind::Delete, chat_id, message_id, initiator_id, None),
+        AepMessage::CreateThread { root_message_id, chat_id, creator_id, created_at, .. } => {
+            (HistoryKind::Thread, chat_id, root_message_id, creator_id, Some(*created_at))
+        }
+        _ => return None,
+    };
+    Some(Described { kind, chat_id, message_id, author, signed_at })
+}
+
+fn payload_id(kind: HistoryKind, payload: &[u8]) -> String {
+    let digest: String = Sha256::digest(payload)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}:{}", kind.as_str(), digest)
+}
+
+/// An event as listed in summaries: its id and the time it is bucketed by.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+pub struct HistoryKey {
+    pub id: String,
+    pub timestamp_ms: i64,
+}
+
+/// Digest of the event ids a peer holds for one day of a chat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+#[archive_attr(derive(Debug))]
+pub struct BucketDigest {
+    /// Days since the Unix epoch.
+    pub day: i64,
+    pub count: u32,
+    pub hash: Vec<u8>,
+}
+
+pub fn bucket_of(timestamp_ms: i64) -> i64 {
+    timestamp_ms.div_euclid(BUCKET_MS)
+}
+
+/// Per-day digests of `keys`, oldest day first. The digest does not depend
+/// on the order the keys are given in.
+pub fn summarize(keys: &[HistoryKey]) -> Vec<BucketDigest> {
+    let mut buckets: BTreeMap<i64, Vec<&str>> = BTreeMap::new();
+    for key in keys {
+        buckets.entry(bucket_of(key.timestamp_ms)).or_default().push(&key.id);
+    }
+    buckets
+        .into_iter()
+        .map(|(day, mut ids)| {
+            ids.sort_unstable();
+            ids.dedup();
+            let mut hasher = Sha256::new();
+            hasher.update(BUCKET_DOMAIN);
+            for id in &ids {
+                hasher.update((id.len() as u64).to_be_bytes());
+                hasher.update(id.as_bytes());
+            }
+            BucketDigest { day, count: ids.len() as u32, hash: hasher.finalize().to_vec() }
+        })
+        .collect()
+}
+
+/// Days on which `ours` holds events `theirs` may lack: those they have no
+/// digest for, and those whose digests differ.
+pub fn differing_buckets(ours: &[BucketDigest], theirs: &[BucketDigest]) -> Vec<i64> {
+    let theirs: BTreeMap<i64, &BucketDigest> =
+        theirs.iter().map(|digest| (digest.day, digest)).collect();
+    ours.iter()
+        .filter(|digest| theirs.get(&digest.day) != Some(digest))
+        .map(|digest| digest.day)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str, timestamp_ms: i64) -> HistoryKey {
+        HistoryKey { id: id.into(), timestamp_ms }
+    }
+
+    fn chat_message(id: &str, conversation_id: Option<String>) -> AepMessage {
+        AepMessage::ChatMessage {
+            id: id.into(),
+            timestamp: Utc::now(),
+            sender: "alice".into(),
+            content: "hello".into(),
+            channel_id: None,
+            server_id: None,
+            conversation_id,
+            attachments: Vec::new(),
+            expires_at: None,
+            reply_to_message_id: None,
+            reply_snapshot_author: None,
+            reply_snapshot_snippet: None,
+            signature: Some(vec![1, 2, 3]),
+        }
+    }
+
+    #[test]
+    fn digests_ignore_order_and_spot_missing_events() {
+        let day = BUCKET_MS;
+        let ours = summarize(&[key("a", 10), key("b", 20), key("c", day + 5)]);
+        let shuffled = summarize(&[key("c", day + 5), key("b", 20), key("a", 10)]);
+        assert_eq!(ours, shuffled);
+        assert_eq!(ours.len(), 2);
+
+        let theirs = summarize(&[key("a", 10), key("b", 20)]);
+        assert_eq!(differing_buckets(&ours, &theirs), vec![1]);
+        assert!(differing_buckets(&theirs, &ours).is_empty());
+
+        let diverged = summarize(&[key("a", 10), key("x", 20), key("c", day + 5)]);
+        assert_eq!(differing_buckets(&ours, &diverged), vec![0]);
+    }
+
+    #[test]
+    fn events_only_carry_the_message_they_describe() {
+        let message = chat_message("m1", Some("group".into()));
+        let event = HistoryEvent::from_message(&message, None).unwrap();
+        assert_eq!(event.id, "m1");
+        assert_eq!(event.chat_id, "group");
+        assert!(event.message().is_some());
+
+        let mut moved = event.clone();
+        moved.chat_id = "elsewhere".into();
+        assert!(moved.message().is_none());
+
+        let mut swapped = event;
+        swapped.payload = bincode::serialize(&chat_message("m2", Some("group".into()))).unwrap();
+        assert!(swapped.message().is_none());
+
+        // Direct messages have no shared history.
+        assert!(HistoryEvent::from_message(&chat_message("m3", None), None).is_none());
+    }
+
+    #[test]
+    fn reactions_are_filed_under_the_message_they_target() {
+        let reaction = AepMessage::MessageReaction {
+            message_id: "m1".into(),
+            chat_id: "group".into(),
+            emoji: "+1".into(),
+            user_id: "bob".into(),
+            action: crate::ReactionAction::Add,
+            signature: Some(vec![1]),
+        };
+        assert_eq!(HistoryEvent::target_of(&reaction), Some(("group", "m1")));
+        assert!(HistoryEvent::from_message(&reaction, None).is_none());
+
+        // Peers that saw the reaction at different times still agree on it.
+        let event = HistoryEvent::from_message(&reaction, Some(42)).unwrap();
+        assert_eq!(event.timestamp_ms, 42);
+        assert_eq!(event, HistoryEvent::from_message(&reaction, Some(42)).unwrap());
+        assert!(event.message().is_some());
+
+        assert_eq!(HistoryEvent::target_of(&chat_message("m2", Some("group".into()))), None);
+    }
+}
@@ -0,0 +1,156 @@
+//! Hybrid logical clocks. A stamp is the wall time of the newest event a
+//! peer has seen, sent or received, plus a counter that orders events within
+//! the same millisecond. Stamps follow causality even when peers' clocks
+//! disagree: a reply is always stamped after the message it answers.
+
+use std::fmt;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How far a peer's stamp may run ahead of our clock. Later stamps are not
+/// taken in, so one peer with a wrong clock cannot drag everyone's along.
+pub const MAX_DRIFT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+pub struct Hlc {
+    /// Milliseconds since the Unix epoch.
+    pub wall_ms: i64,
+    pub counter: u32,
+}
+
+impl Hlc {
+    /// Stamp of an event known only by its wall time, such as a message from
+    /// a peer that predates the clock.
+    pub fn from_timestamp(at: DateTime<Utc>) -> Self {
+        Hlc { wall_ms: at.timestamp_millis().max(0), counter: 0 }
+    }
+
+    /// Stamp of an event known only by the wall time its sender claims for
+    /// it, which is not followed more than [`MAX_DRIFT`] past `now`.
+    pub fn from_claimed(at: DateTime<Utc>, now: DateTime<Utc>) -> Self {
+        let drift = chrono::Duration::from_std(MAX_DRIFT).expect("drift fits");
+        Self::from_timestamp(at.min(now + drift))
+    }
+
+    /// Wall time the stamp was taken at.
+    pub fn wall_time(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp_millis(self.wall_ms)
+    }
+
+    /// Fixed-width text form that sorts the same way the stamps do.
+    pub fn encode(&self) -> String {
+        format!("{:015}-{:010}", self.wall_ms.max(0), self.counter)
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let (wall_ms, counter) = value.split_once('-')?;
+        Some(Hlc { wall_ms: wall_ms.parse().ok()?, counter: counter.parse().ok()? })
+    }
+
+    fn successor(&self) -> Self {
+        match self.counter.checked_add(1) {
+            Some(counter) => Hlc { wall_ms: self.wall_ms, counter },
+            None => Hlc { wall_ms: self.wall_ms + 1, counter: 0 },
+        }
+    }
+}
+
+impl fmt::Display for Hlc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.encode())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HybridClock {
+    last: Hlc,
+}
+
+impl HybridClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Carries on from `last`, a stamp issued or taken in before a restart,
+    /// so stamps never go backwards when the wall clock does.
+    pub fn resume(&mut self, last: Hlc) {
+        self.last = self.last.max(last);
+    }
+
+    /// Stamp for an event of ours, such as sending a message.
+    pub fn tick(&mut self, now: DateTime<Utc>) -> Hlc {
+        let physical = Hlc::from_timestamp(now);
+        self.last = if physical.wall_ms > self.last.wall_ms {
+            physical
+        } else {
+            self.last.successor()
+        };
+        self.last
+    }
+
+    /// Takes in a stamp received from a peer and returns the one to order
+    /// the received event by: the peer's own, unless it runs more than
+    /// [`MAX_DRIFT`] ahead of us, in which case the event is stamped on
+    /// arrival instead.
+    pub fn observe(&mut self, remote: Hlc, now: DateTime<Utc>) -> Hlc {
+        let drift = chrono::Duration::from_std(MAX_DRIFT).expect("drift fits");
+        if remote.wall_ms > (now + drift).timestamp_millis() {
+            return self.tick(now);
+        }
+        let merged = self.tick(now).max(remote.successor());
+        self.last = merged;
+        remote
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_follow_causality_despite_a_slow_clock() {
+        let now = Utc::now();
+        let mut clock = HybridClock::new();
+        let first = clock.tick(now);
+        let second = clock.tick(now);
+        assert!(second > first);
+
+        // A peer a few seconds ahead: our next stamp still comes after theirs.
+        let remote = Hlc::from_timestamp(now + chrono::Duration::seconds(5));
+        assert_eq!(clock.observe(remote, now), remote);
+        assert!(clock.tick(now) > remote);
+
+        // Clocks set far into the future are not followed.
+        let wild = Hlc::from_timestamp(now + chrono::Duration::days(1));
+        let stamped = clock.observe(wild, now);
+        assert!(stamped < wild);
+        assert!(clock.tick(now) < wild);
+        assert!(Hlc::from_claimed(now + chrono::Duration::days(1), now) < wild);
+        assert_eq!(Hlc::from_claimed(now, now), Hlc::from_timestamp(now));
+    }
+
+    #[test]
+    fn resumed_clocks_stamp_after_their_last_stamp() {
+        let now = Utc::now();
+        let before_restart = Hlc::from_timestamp(now + chrono::Duration::seconds(30));
+        let mut clock = HybridClock::new();
+        clock.resume(before_restart);
+        assert!(clock.tick(now) > before_restart);
+
+        // An older stamp does not move the clock back.
+        clock.resume(Hlc::default());
+        assert!(clock.tick(now) > before_restart);
+    }
+
+    #[test]
+    fn encoded_stamps_sort_like_stamps() {
+        let early = Hlc { wall_ms: 999, counter: 12 };
+        let late = Hlc { wall_ms: 1_000, counter: 3 };
+        assert!(early.encode() < late.encode());
+        assert!(early.encode() < early.successor().encode());
+        assert_eq!(Hlc::parse(&late.encode()), Some(late));
+        assert_eq!(Hlc::parse("garbage"), None);
+    }
+}
@@ -2,6 +2,11 @@ use chrono::{DateTime, Utc};
 use rkyv::{Archive, Deserialize as RkyvDeserialize, Serialize as RkyvSerialize};
 use serde::{Deserialize, Serialize};
 
+pub mod history;
+pub mod hlc;
+pub mod sealed;
+pub mod wire;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum AepMessage {
     ChatMessage {
@@ -20,6 +25,7 @@ pub enum AepMessage {
         signature: Option<Vec<u8>>,
     },
     EncryptedChatMessage {
+        message_id: String,
         sender: String,
         recipient: String,
         init: Option<Vec<u8>>,
@@ -33,6 +39,7 @@ pub enum AepMessage {
         signature: Option<Vec<u8>>,
     },
     GroupKeyUpdate {
+        issuer_id: String,
         server_id: String,
         channel_id: Option<String>,
         epoch: u64,
@@ -224,6 +231,248 @@ pub enum AepMessage {
         timestamp: DateTime<Utc>,
         signature: Option<Vec<u8>>,
     },
+    DeliveryAck {
+        message_id: String,
+        sender_id: String,
+        recipient_id: String,
+        timestamp: DateTime<Utc>,
+        signature: Option<Vec<u8>>,
+    },
+    /// Direct message whose sender and signature travel inside the
+    /// ciphertext as a [`sealed::SealedContent`], in an Olm packet sealed
+    /// once more to the recipient. The frame is sent unsigned and names no
+    /// origin; see [`sealed`] for what it does and does not hide.
+    SealedChatMessage {
+        message_id: String,
+        ephemeral_key: Vec<u8>,
+        recipient_hint: Vec<u8>,
+        ciphertext: Vec<u8>,
+    },
+    /// Confirms a [`AepMessage::SealedChatMessage`] was stored without naming
+    /// either end: only its recipient can compute `proof`, and only its
+    /// sender can check it.
+    SealedDeliveryAck {
+        message_id: String,
+        proof: Vec<u8>,
+    },
+    /// Opens a thread on `root_message_id`. Replies to the root, and replies
+    /// to those, belong to the thread from then on.
+    CreateThread {
+        root_message_id: String,
+        chat_id: String,
+        creator_id: String,
+        title: Option<String>,
+        created_at: DateTime<Utc>,
+        signature: Option<Vec<u8>>,
+    },
+}
+
+impl AepMessage {
+    /// Peer a point-to-point message is meant for. These are delivered over
+    /// the direct route instead of being published to the whole mesh.
+    /// Sealed messages and their acks do not say; the recipient is looked up
+    /// from the stored message.
+    pub fn direct_recipient(&self) -> Option<&str> {
+        match self {
+            AepMessage::EncryptedChatMessage { recipient, .. } => Some(recipient),
+            AepMessage::DeliveryAck { sender_id, .. } => Some(sender_id),
+            AepMessage::FriendRequest { target_id, .. }
+            | AepMessage::FriendRequestResponse { target_id, .. } => Some(target_id),
+            AepMessage::FileTransferRequest { recipient_id, .. }
+            | AepMessage::FileTransferChunk { recipient_id, .. }
+            | AepMessage::FileTransferComplete { recipient_id, .. }
+            | AepMessage::FileTransferError { recipient_id, .. }
+            | AepMessage::CallSignal { recipient_id, .. } => Some(recipient_id),
+            _ => None,
+        }
+    }
+
+    /// Id of the stored chat message this frame delivers, if any.
+    pub fn chat_message_id(&self) -> Option<&str> {
+        match self {
+            AepMessage::ChatMessage { id, .. } => Some(id),
+            AepMessage::EncryptedChatMessage { message_id, .. }
+            | AepMessage::SealedChatMessage { message_id, .. } => Some(message_id),
+            _ => None,
+        }
+    }
+
+    /// Whether the recipient confirms this frame with a
+    /// [`AepMessage::DeliveryAck`].
+    pub fn expects_delivery_ack(&self) -> bool {
+        matches!(
+            self,
+            AepMessage::EncryptedChatMessage { .. } | AepMessage::SealedChatMessage { .. }
+        )
+    }
+
+    /// Whether the frame is only worth sending right away. Typing indicators
+    /// and presence are stale within seconds, so they are not retried for
+    /// long.
+    pub fn is_ephemeral(&self) -> bool {
+        matches!(
+            self,
+            AepMessage::TypingIndicator { .. }
+                | AepMessage::PresenceUpdate { .. }
+                | AepMessage::PeerDiscovery { .. }
+        )
+    }
+
+    /// How a point-to-point message travels. Messages whose loss is costly go
+    /// over several disjoint routes: friend requests, which are never
+    /// retried, and delivery acks, whose loss makes the sender resend the
+    /// whole message. Everything else, such as file chunks and call
+    /// signalling, takes only the best route. Key updates are broadcast and
+    /// reach every subscriber anyway.
+    pub fn delivery_mode(&self) -> DeliveryMode {
+        match self {
+            AepMessage::FriendRequest { .. }
+            | AepMessage::FriendRequestResponse { .. }
+            | AepMessage::DeliveryAck { .. }
+            | AepMessage::SealedDeliveryAck { .. } => DeliveryMode::Multipath,
+            _ => DeliveryMode::SinglePath,
+        }
+    }
+
+    /// Audience a broadcast message is published to. Messages that introduce
+    /// someone to a server or group stay global, since the newcomer is not on
+    /// its topic yet.
+    pub fn topic_scope(&self) -> TopicScope {
+        match self {
+            AepMessage::ChatMessage {
+                server_id: Some(server_id),
+                ..
+            } => TopicScope::Server(server_id.clone()),
+            AepMessage::ChatMessage {
+                conversation_id: Some(conversation_id),
+                ..
+            } => TopicScope::Conversation(conversation_id.clone()),
+            AepMessage::GroupKeyUpdate { server_id, .. }
+            | AepMessage::EncryptedGroupMessage { server_id, .. }
+            | AepMessage::JoinServer { server_id, .. }
+            | AepMessage::DeleteServer { server_id, .. } => TopicScope::Server(server_id.clone()),
+            AepMessage::CreateChannel { channel, .. } => {
+                TopicScope::Server(channel.server_id.clone())
+            }
+            AepMessage::LeaveGroupChat { group_id, .. }
+            | AepMessage::RenameGroupChat { group_id, .. }
+            | AepMessage::RemoveGroupChatMember { group_id, .. } => {
+                TopicScope::Conversation(group_id.clone())
+            }
+            AepMessage::MessageReaction { chat_id, .. }
+            | AepMessage::DeleteMessage { chat_id, .. }
+            | AepMessage::EditMessage { chat_id, .. }
+            | AepMessage::CreateThread { chat_id, .. }
+            | AepMessage::ReadReceipt { chat_id, .. }
+            | AepMessage::TypingIndicator { chat_id, .. } => {
+                TopicScope::Conversation(chat_id.clone())
+            }
+            _ => TopicScope::Global,
+        }
+    }
+
+    /// User the message claims to come from. `None` for messages that do
+    /// not name their sender: server changes, which only the owner may make,
+    /// and sealed direct messages.
+    pub fn author(&self) -> Option<&str> {
+        match self {
+            AepMessage::ChatMessage { sender, .. }
+            | AepMessage::EncryptedChatMessage { sender, .. }
+            | AepMessage::EncryptedGroupMessage { sender, .. } => Some(sender),
+            AepMessage::PrekeyBundle { user_id, .. }
+            | AepMessage::PresenceUpdate { user_id, .. }
+            | AepMessage::JoinServer { user_id, .. }
+            | AepMessage::MessageReaction { user_id, .. }
+            | AepMessage::TypingIndicator { user_id, .. } => Some(user_id),
+            AepMessage::GroupKeyUpdate { issuer_id, .. } => Some(issuer_id),
+            AepMessage::PeerDiscovery { peer_id, .. } => Some(peer_id),
+            AepMessage::ProfileUpdate { user, .. } => Some(&user.id),
+            AepMessage::FriendRequest { sender_id, .. }
+            | AepMessage::FriendRequestResponse { sender_id, .. }
+            | AepMessage::FileTransferRequest { sender_id, .. }
+            | AepMessage::FileTransferChunk { sender_id, .. }
+            | AepMessage::FileTransferComplete { sender_id, .. }
+            | AepMessage::FileTransferError { sender_id, .. }
+            | AepMessage::CallSignal { sender_id, .. }
+            | AepMessage::CollaborationUpdate { sender_id, .. } => Some(sender_id),
+            AepMessage::BlockUser { blocker_id, .. } => Some(blocker_id),
+            AepMessage::UnblockUser { unblocker_id, .. } => Some(unblocker_id),
+            AepMessage::RemoveFriendship { remover_id, .. }
+            | AepMessage::RemoveGroupChatMember { remover_id, .. } => Some(remover_id),
+            AepMessage::CreateGroupChat { creator_id, .. } => Some(creator_id),
+            AepMessage::LeaveGroupChat { member_id, .. } => Some(member_id),
+            AepMessage::RenameGroupChat { updater_id, .. } => Some(updater_id),
+            AepMessage::AddGroupChatMembers { adder_id, .. } => Some(adder_id),
+            AepMessage::CreateServer { server, .. } => Some(&server.owner_id),
+            AepMessage::DeleteMessage { initiator_id, .. } => Some(initiator_id),
+            AepMessage::EditMessage { editor_id, .. } => Some(editor_id),
+            AepMessage::CreateThread { creator_id, .. } => Some(creator_id),
+            AepMessage::ReadReceipt { reader_id, .. } => Some(reader_id),
+            AepMessage::DeliveryAck { recipient_id, .. } => Some(recipient_id),
+            AepMessage::CreateChannel { .. }
+            | AepMessage::DeleteChannel { .. }
+            | AepMessage::DeleteServer { .. }
+            | AepMessage::SendServerInvite { .. }
+            | AepMessage::SealedChatMessage { .. }
+            | AepMessage::SealedDeliveryAck { .. } => None,
+        }
+    }
+
+    /// Who must have signed the frame carrying this message before it is
+    /// handled or passed on through the mesh.
+    pub fn signature_policy(&self) -> SignaturePolicy {
+        match self {
+            AepMessage::SealedChatMessage { .. } | AepMessage::SealedDeliveryAck { .. } => {
+                SignaturePolicy::Sealed
+            }
+            AepMessage::CreateChannel { .. }
+            | AepMessage::DeleteChannel { .. }
+            | AepMessage::DeleteServer { .. }
+            | AepMessage::SendServerInvite { .. } => SignaturePolicy::Signed,
+            _ => SignaturePolicy::Author,
+        }
+    }
+}
+
+/// Signature a frame must carry, by the kind of message inside.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignaturePolicy {
+    /// Signed by the user [`AepMessage::author`] names.
+    Author,
+    /// Signed by anyone; whether the signer may make the change is checked
+    /// against the server's owner when the message is handled.
+    Signed,
+    /// Sent unsigned, since a signature would give the sender away. The
+    /// sender signs inside the ciphertext instead, and an ack carries a
+    /// proof only the two ends can compute.
+    Sealed,
+}
+
+/// Routing mode for a message sent to a single recipient.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// The router's best route only.
+    SinglePath,
+    /// A copy over each of several disjoint routes; the recipient keeps the
+    /// first to arrive.
+    Multipath,
+}
+
+/// Gossip audience of a message: everyone, the members of one server, or the
+/// members of one group conversation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TopicScope {
+    Global,
+    Server(String),
+    Conversation(String),
+}
+
+impl TopicScope {
+    /// Whether a message of scope `message` belongs on this topic. The global
+    /// topic accepts everything so older peers that publish there still work.
+    pub fn admits(&self, message: &TopicScope) -> bool {
+        matches!(self, TopicScope::Global) || self == message
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Archive, RkyvSerialize, RkyvDeserialize)]
@@ -305,6 +554,15 @@ pub struct MessageEditData {
     pub edited_at: DateTime<Utc>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateThreadData {
+    pub root_message_id: String,
+    pub chat_id: String,
+    pub creator_id: String,
+    pub title: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CreateGroupChatData {
     pub group_id: String,
@@ -405,6 +663,16 @@ pub struct ReadReceiptData {
     pub timestamp: DateTime<Utc>,
 }
 
+/// Signed body of [`AepMessage::DeliveryAck`]: `recipient_id` confirms it
+/// stored `message_id` sent by `sender_id`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeliveryAckData {
+    pub message_id: String,
+    pub sender_id: String,
+    pub recipient_id: String,
+    pub timestamp: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TypingIndicatorData {
     pub chat_id: String,
@@ -0,0 +1,297 @@
+//! Sealed sender for direct messages. The sender's identity and signature
+//! are encrypted along with the message, and the Olm packet carrying them is
+//! encrypted once more to a key only the sender and the recipient can derive,
+//! so the prekey message of a new session does not show the sender's
+//! identity key either.
+//!
+//! This hides the sender from peers that only see the frame: hops further
+//! along the route, mailbox relays and anyone holding a copy. It does not
+//! make the sender anonymous. The first hop sees which neighbour handed the
+//! frame over, and every hop sees the destination the frame is routed to.
+//!
+//! Sealing agrees a secret between a per-message ephemeral key and the
+//! recipient's identity key, converted from Ed25519 to X25519. The ephemeral
+//! key is derived from the sender's identity key and the message id, so the
+//! sender can derive the secret again to check the recipient's
+//! [`crate::AepMessage::SealedDeliveryAck`] without keeping any state. A
+//! message sealed again, when it is resent, gets the same key, so every seal
+//! draws a fresh random nonce that travels in front of the ciphertext.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use curve25519_dalek::edwards::CompressedEdwardsY;
+use libp2p::identity::{ed25519, Keypair, PublicKey};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use thiserror::Error;
+use x25519_dalek::{PublicKey as X25519PublicKey, SharedSecret, StaticSecret};
+
+/// Bytes of the recipient hint kept from the digest.
+pub const RECIPIENT_HINT_LEN: usize = 16;
+
+const EPHEMERAL_DOMAIN: &[u8] = b"aegis/sealed-ephemeral/v1";
+const HINT_LABEL: &[u8] = b"aegis/sealed-recipient/v2";
+const KEY_LABEL: &[u8] = b"aegis/sealed-key/v1";
+const ACK_LABEL: &[u8] = b"aegis/sealed-ack/v1";
+
+const NONCE_LEN: usize = 24;
+
+/// Multihash code of the identity hash, which peer ids of Ed25519 keys use
+/// to carry the key itself.
+const IDENTITY_MULTIHASH: u64 = 0;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SealError {
+    #[error("sealed messages need an Ed25519 identity")]
+    NotEd25519,
+    #[error("invalid sealing key")]
+    InvalidKey,
+    #[error("sealed message could not be opened")]
+    Unopenable,
+    #[error("malformed sealed message: {0}")]
+    Malformed(String),
+}
+
+/// Plaintext of an [`crate::AepMessage::SealedChatMessage`] before it is
+/// encrypted for the recipient.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedContent {
+    pub sender: String,
+    /// Sender's signature over [`signed_bytes`].
+    pub signature: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+/// Olm packet carrying a [`SealedContent`], which is what the outer layer
+/// encrypts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedPacket {
+    pub init: Option<Vec<u8>>,
+    pub enc_header: Vec<u8>,
+    pub enc_content: Vec<u8>,
+}
+
+/// Outer layer of a sealed message as it travels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sealed {
+    pub ephemeral_key: Vec<u8>,
+    pub recipient_hint: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Secret the sender and the recipient of one sealed message share.
+pub struct SealSecret {
+    shared: [u8; 32],
+    ephemeral_key: [u8; 32],
+    recipient_key: [u8; 32],
+}
+
+impl SealSecret {
+    fn derive(&self, label: &[u8], message_id: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(label);
+        hasher.update(self.shared);
+        hasher.update(self.ephemeral_key);
+        hasher.update(self.recipient_key);
+        hasher.update(message_id.as_bytes());
+        hasher.finalize().into()
+    }
+
+    /// Lets the recipient tell which sealed messages are meant for it. Only
+    /// the two ends can compute it, and it changes with every message.
+    pub fn recipient_hint(&self, message_id: &str) -> Vec<u8> {
+        self.derive(HINT_LABEL, message_id)[..RECIPIENT_HINT_LEN].to_vec()
+    }
+
+    /// What the recipient sends back to show it opened `message_id`.
+    pub fn ack_proof(&self, message_id: &str) -> Vec<u8> {
+        self.derive(ACK_LABEL, message_id).to_vec()
+    }
+
+    fn cipher(&self, message_id: &str) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(&self.derive(KEY_LABEL, message_id)))
+    }
+
+    /// Encrypts `packet` under the key of this message with a random nonce,
+    /// which is kept in front of the ciphertext. The key is the same each
+    /// time the message is sealed, the nonce never is.
+    pub fn seal(&self, message_id: &str, packet: &SealedPacket) -> Result<Sealed, SealError> {
+        let plaintext = bincode::serialize(packet).map_err(|e| SealError::Malformed(e.to_string()))?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = self
+            .cipher(message_id)
+            .encrypt(&nonce, Payload { msg: &plaintext, aad: message_id.as_bytes() })
+            .map_err(|_| SealError::InvalidKey)?;
+        let mut ciphertext = Vec::with_capacity(NONCE_LEN + encrypted.len());
+        ciphertext.extend_from_slice(&nonce);
+        ciphertext.extend_from_slice(&encrypted);
+        Ok(Sealed {
+            ephemeral_key: self.ephemeral_key.to_vec(),
+            recipient_hint: self.recipient_hint(message_id),
+            ciphertext,
+        })
+    }
+
+    pub fn open(&self, message_id: &str, ciphertext: &[u8]) -> Result<SealedPacket, SealError> {
+        if ciphertext.len() < NONCE_LEN {
+            return Err(SealError::Malformed("ciphertext is shorter than its nonce".to_string()));
+        }
+        let (nonce, encrypted) = ciphertext.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher(message_id)
+            .decrypt(XNonce::from_slice(nonce), Payload { msg: encrypted, aad: message_id.as_bytes() })
+            .map_err(|_| SealError::Unopenable)?;
+        bincode::deserialize(&plaintext).map_err(|e| SealError::Malformed(e.to_string()))
+    }
+}
+
+/// Secret `sender` seals `message_id` to `recipient` with. Deriving it again
+/// gives the same secret.
+pub fn sender_secret(sender: &Keypair, recipient: &PeerId, message_id: &str) -> Result<SealSecret, SealError> {
+    let Keypair::Ed25519(sender) = sender else {
+        return Err(SealError::NotEd25519);
+    };
+    let recipient_key = x25519_public(&ed25519_key_of(recipient)?)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(EPHEMERAL_DOMAIN);
+    hasher.update(sender.secret().as_ref());
+    hasher.update(recipient.to_bytes());
+    hasher.update(message_id.as_bytes());
+    let ephemeral = StaticSecret::from(<[u8; 32]>::from(hasher.finalize()));
+
+    let shared = ephemeral.diffie_hellman(&recipient_key);
+    secret(shared, X25519PublicKey::from(&ephemeral).to_bytes(), &recipient_key)
+}
+
+/// Secret the sender of a sealed message with `ephemeral_key` shares with
+/// `recipient`, if it was sealed for them.
+pub fn recipient_secret(recipient: &Keypair, ephemeral_key: &[u8]) -> Result<SealSecret, SealError> {
+    let Keypair::Ed25519(recipient) = recipient else {
+        return Err(SealError::NotEd25519);
+    };
+    let ephemeral_key: [u8; 32] = ephemeral_key.try_into().map_err(|_| SealError::InvalidKey)?;
+    let digest = Sha512::digest(recipient.secret().as_ref());
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&digest[..32]);
+    let own = StaticSecret::from(scalar);
+    let shared = own.diffie_hellman(&X25519PublicKey::from(ephemeral_key));
+    secret(shared, ephemeral_key, &X25519PublicKey::from(&own))
+}
+
+fn secret(shared: SharedSecret, ephemeral_key: [u8; 32], recipient_key: &X25519PublicKey) -> Result<SealSecret, SealError> {
+    // A low-order point would give a secret anyone can compute.
+    if !shared.was_contributory() {
+        return Err(SealError::InvalidKey);
+    }
+    Ok(SealSecret { shared: shared.to_bytes(), ephemeral_key, recipient_key: recipient_key.to_bytes() })
+}
+
+/// Ed25519 key a peer id carries inline.
+fn ed25519_key_of(peer: &PeerId) -> Result<ed25519::PublicKey, SealError> {
+    let multihash: &libp2p::multihash::Multihash = peer.as_ref();
+    if multihash.code() != IDENTITY_MULTIHASH {
+        return Err(SealError::NotEd25519);
+    }
+    match PublicKey::from_protobuf_encoding(multihash.digest()) {
+        Ok(PublicKey::Ed25519(key)) => Ok(key),
+        _ => Err(SealError::NotEd25519),
+    }
+}
+
+/// The X25519 key matching an Ed25519 key, the birational map XEdDSA uses.
+fn x25519_public(key: &ed25519::PublicKey) -> Result<X25519PublicKey, SealError> {
+    let point = CompressedEdwardsY(key.encode()).decompress().ok_or(SealError::InvalidKey)?;
+    Ok(X25519PublicKey::from(point.to_montgomery().to_bytes()))
+}
+
+/// Bytes the sender signs. Binding the recipient and message id stops a
+/// recipient from passing a sealed message on to someone else, or replaying
+/// it under another id, as if it had come from the sender directly.
+pub fn signed_bytes(
+    message_id: &str,
+    sender: &str,
+    recipient: &str,
+    payload: &[u8],
+) -> Result<Vec<u8>, bincode::Error> {
+    bincode::serialize(&(message_id, sender, recipient, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet() -> SealedPacket {
+        SealedPacket { init: Some(b"prekey".to_vec()), enc_header: b"header".to_vec(), enc_content: b"content".to_vec() }
+    }
+
+    #[test]
+    fn only_the_recipient_opens_a_sealed_message() {
+        let alice = Keypair::generate_ed25519();
+        let bob = Keypair::generate_ed25519();
+        let mallory = Keypair::generate_ed25519();
+        let bob_id = bob.public().to_peer_id();
+
+        let sealed = sender_secret(&alice, &bob_id, "m1").unwrap().seal("m1", &packet()).unwrap();
+
+        let secret = recipient_secret(&bob, &sealed.ephemeral_key).unwrap();
+        assert_eq!(secret.recipient_hint("m1"), sealed.recipient_hint);
+        assert_eq!(secret.open("m1", &sealed.ciphertext).unwrap(), packet());
+        // Moving the ciphertext to another message id breaks it.
+        assert_eq!(secret.open("m2", &sealed.ciphertext), Err(SealError::Unopenable));
+
+        let other = recipient_secret(&mallory, &sealed.ephemeral_key).unwrap();
+        assert_ne!(other.recipient_hint("m1"), sealed.recipient_hint);
+        assert_eq!(other.open("m1", &sealed.ciphertext), Err(SealError::Unopenable));
+    }
+
+    #[test]
+    fn hints_do_not_link_messages_to_one_recipient() {
+        let alice = Keypair::generate_ed25519();
+        let bob = Keypair::generate_ed25519().public().to_peer_id();
+        let carol = Keypair::generate_ed25519().public().to_peer_id();
+
+        let first = sender_secret(&alice, &bob, "m1").unwrap().seal("m1", &packet()).unwrap();
+        let second = sender_secret(&alice, &bob, "m2").unwrap().seal("m2", &packet()).unwrap();
+        let to_carol = sender_secret(&alice, &carol, "m1").unwrap().seal("m1", &packet()).unwrap();
+        assert_eq!(first.recipient_hint.len(), RECIPIENT_HINT_LEN);
+        assert_ne!(first.recipient_hint, second.recipient_hint);
+        assert_ne!(first.ephemeral_key, second.ephemeral_key);
+        assert_ne!(first.recipient_hint, to_carol.recipient_hint);
+    }
+
+    #[test]
+    fn sealing_a_message_again_uses_a_fresh_nonce() {
+        let alice = Keypair::generate_ed25519();
+        let bob = Keypair::generate_ed25519();
+        let bob_id = bob.public().to_peer_id();
+
+        let first = sender_secret(&alice, &bob_id, "m1").unwrap().seal("m1", &packet()).unwrap();
+        let resent = sender_secret(&alice, &bob_id, "m1").unwrap().seal("m1", &packet()).unwrap();
+        assert_eq!(first.ephemeral_key, resent.ephemeral_key);
+        assert_ne!(first.ciphertext, resent.ciphertext);
+        assert_ne!(first.ciphertext[..NONCE_LEN], resent.ciphertext[..NONCE_LEN]);
+
+        let secret = recipient_secret(&bob, &resent.ephemeral_key).unwrap();
+        assert_eq!(secret.open("m1", &first.ciphertext).unwrap(), packet());
+        assert_eq!(secret.open("m1", &resent.ciphertext).unwrap(), packet());
+        assert!(matches!(secret.open("m1", &first.ciphertext[..NONCE_LEN - 1]), Err(SealError::Malformed(_))));
+    }
+
+    #[test]
+    fn sender_checks_the_recipient_ack_without_keeping_state() {
+        let alice = Keypair::generate_ed25519();
+        let bob = Keypair::generate_ed25519();
+        let bob_id = bob.public().to_peer_id();
+
+        let sealed = sender_secret(&alice, &bob_id, "m1").unwrap().seal("m1", &packet()).unwrap();
+        let proof = recipient_secret(&bob, &sealed.ephemeral_key).unwrap().ack_proof("m1");
+
+        let again = sender_secret(&alice, &bob_id, "m1").unwrap();
+        assert_eq!(again.ack_proof("m1"), proof);
+        assert_ne!(again.ack_proof("m2"), proof);
+        let impostor = recipient_secret(&Keypair::generate_ed25519(), &sealed.ephemeral_key).unwrap();
+        assert_ne!(impostor.ack_proof("m1"), proof);
+    }
+}
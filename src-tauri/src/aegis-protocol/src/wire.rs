@@ -0,0 +1,653 @@
+use chrono::{DateTime, TimeZone, Utc};
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::hlc::Hlc;
+use crate::AepMessage;
+
+/// Marker prefixed to every enveloped frame so legacy raw `AepMessage` blobs
+/// can still be told apart from versioned ones.
+pub const WIRE_MAGIC: [u8; 4] = *b"AEGW";
+
+/// Version written into outgoing envelopes. Version 2 changed the bincode
+/// layout of some messages; see [`layout_version`].
+pub const WIRE_VERSION: u16 = 2;
+
+/// Oldest envelope version this build can still decode.
+pub const MIN_WIRE_VERSION: u16 = 1;
+
+/// Optional capabilities a frame may rely on. A receiver rejects frames whose
+/// `required_features` contain bits it does not know about.
+pub mod features {
+    pub const NONE: u32 = 0;
+    /// The payload is length-prefixed and zero-padded; see [`super::pad`].
+    pub const PADDED: u32 = 1 << 0;
+    /// Cover traffic: carries no message and is discarded on receipt.
+    pub const COVER: u32 = 1 << 1;
+    /// The payload is signed by the peer that published it; see
+    /// [`super::sign`].
+    pub const SIGNED: u32 = 1 << 2;
+    /// The payload carries the sender's hybrid logical clock stamp; see
+    /// [`super::seal_clocked`].
+    pub const CLOCKED: u32 = 1 << 3;
+}
+
+/// Feature bits understood by this build.
+pub const SUPPORTED_FEATURES: u32 =
+    features::PADDED | features::COVER | features::SIGNED | features::CLOCKED;
+
+/// Sizes padded frames are rounded up to. Frames beyond the last bucket grow
+/// in multiples of it.
+pub const PADDING_BUCKETS: [usize; 5] = [256, 1024, 4096, 16 * 1024, 64 * 1024];
+
+/// Bytes bincode spends on an envelope besides its payload: magic, version,
+/// feature bits and the payload's length.
+const ENVELOPE_OVERHEAD: usize = 4 + 2 + 4 + 8;
+
+/// Length of the real payload, written in front of it when padding.
+const PADDED_LENGTH_PREFIX: usize = 4;
+
+/// Prefix used in the identify protocol string so peers can learn which
+/// envelope versions we accept before exchanging any frames.
+pub const IDENTIFY_PROTOCOL_PREFIX: &str = "aegis/wire";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WireEnvelope {
+    pub magic: [u8; 4],
+    pub version: u16,
+    pub required_features: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Payload of a frame with [`features::SIGNED`] set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SignedPayload {
+    /// Protobuf encoding of the publisher's public key.
+    public_key: Vec<u8>,
+    /// Milliseconds since the Unix epoch when the frame was signed.
+    signed_at: i64,
+    signature: Vec<u8>,
+    payload: Vec<u8>,
+}
+
+impl SignedPayload {
+    fn signing_bytes(signed_at: i64, payload: &[u8]) -> Result<Vec<u8>, WireError> {
+        bincode::serialize(&("aegis-wire-signature-v1", signed_at, payload))
+            .map_err(|e| WireError::Malformed(e.to_string()))
+    }
+}
+
+/// Payload of a frame with [`features::CLOCKED`] set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ClockedPayload {
+    clock: Hlc,
+    payload: Vec<u8>,
+}
+
+/// Publisher of a signed frame, as proven by its signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameSigner {
+    pub peer_id: PeerId,
+    pub signed_at: DateTime<Utc>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum WireError {
+    #[error("unsupported wire version {version} (supported {min}..={max})")]
+    UnsupportedVersion { version: u16, min: u16, max: u16 },
+    #[error("unsupported wire features {missing:#x}")]
+    UnsupportedFeatures { missing: u32 },
+    #[error("malformed wire frame: {0}")]
+    Malformed(String),
+    #[error("cover traffic carries no message")]
+    Cover,
+    #[error("bad frame signature: {0}")]
+    BadSignature(String),
+}
+
+/// Message decoded from the wire together with the envelope version it was
+/// received with and who signed it, if anyone did. Legacy frames without an
+/// envelope report version `0`.
+#[derive(Debug, Clone)]
+pub struct DecodedMessage {
+    pub version: u16,
+    pub message: AepMessage,
+    pub signer: Option<FrameSigner>,
+    /// Sender's clock when it sent the message, for frames that carry one.
+    pub clock: Option<Hlc>,
+}
+
+/// Capabilities a peer advertised through identify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCapabilities {
+    pub min_version: u16,
+    pub max_version: u16,
+    pub features: u32,
+}
+
+impl PeerCapabilities {
+    pub fn local() -> Self {
+        Self {
+            min_version: MIN_WIRE_VERSION,
+            max_version: WIRE_VERSION,
+            features: SUPPORTED_FEATURES,
+        }
+    }
+
+    /// Highest envelope version both sides understand.
+    pub fn negotiate(&self, other: &PeerCapabilities) -> Option<u16> {
+        let max = self.max_version.min(other.max_version);
+        let min = self.min_version.max(other.min_version);
+        (min <= max).then_some(max)
+    }
+
+    pub fn common_features(&self, other: &PeerCapabilities) -> u32 {
+        self.features & other.features
+    }
+
+    /// What both sides can read, for frames that go to several peers at once.
+    pub fn intersect(&self, other: &PeerCapabilities) -> PeerCapabilities {
+        PeerCapabilities {
+            min_version: self.min_version.max(other.min_version),
+            max_version: self.max_version.min(other.max_version),
+            features: self.common_features(other),
+        }
+    }
+}
+
+/// Oldest envelope version whose message layout `message` is written in.
+/// Version 2 added `message_id` to `EncryptedChatMessage` and `issuer_id` to
+/// `GroupKeyUpdate`, and introduced `DeliveryAck`, `SealedChatMessage`,
+/// `SealedDeliveryAck` and `CreateThread`. Older peers would misread these, so they are never sent
+/// to them and never accepted from frames that claim an older version.
+pub fn layout_version(message: &AepMessage) -> u16 {
+    match message {
+        AepMessage::EncryptedChatMessage { .. }
+        | AepMessage::GroupKeyUpdate { .. }
+        | AepMessage::DeliveryAck { .. }
+        | AepMessage::SealedChatMessage { .. }
+        | AepMessage::SealedDeliveryAck { .. }
+        | AepMessage::CreateThread { .. } => 2,
+        _ => 1,
+    }
+}
+
+fn unsupported_layout(message: &AepMessage, version: u16, min: u16, max: u16) -> Result<(), WireError> {
+    let required = layout_version(message);
+    // Legacy raw frames share version 1's layout.
+    if version.max(1) < required {
+        return Err(WireError::UnsupportedVersion { version: required, min, max });
+    }
+    Ok(())
+}
+
+/// Builds the identify protocol string, e.g. `aegis/wire/1-2/0xf`.
+pub fn identify_protocol_version() -> String {
+    let local = PeerCapabilities::local();
+    format!(
+        "{}/{}-{}/{:#x}",
+        IDENTIFY_PROTOCOL_PREFIX, local.min_version, local.max_version, local.features
+    )
+}
+
+/// Parses a protocol string produced by [`identify_protocol_version`]. Peers
+/// still announcing the old `aegis/1.0.0` string are treated as legacy-only.
+pub fn parse_identify_protocol_version(value: &str) -> Option<PeerCapabilities> {
+    if value == "aegis/1.0.0" {
+        return Some(PeerCapabilities {
+            min_version: 0,
+            max_version: 0,
+            features: features::NONE,
+        });
+    }
+
+    let rest = value.strip_prefix(IDENTIFY_PROTOCOL_PREFIX)?.strip_prefix('/')?;
+    let (versions, feature_bits) = rest.split_once('/')?;
+    let (min, max) = versions.split_once('-')?;
+    let features = u32::from_str_radix(feature_bits.trim_start_matches("0x"), 16).ok()?;
+
+    Some(PeerCapabilities {
+        min_version: min.parse().ok()?,
+        max_version: max.parse().ok()?,
+        features,
+    })
+}
+
+/// Wraps an already serialized `AepMessage` in the current envelope.
+pub fn seal(payload: Vec<u8>) -> Result<Vec<u8>, WireError> {
+    seal_with_features(payload, features::NONE)
+}
+
+pub fn seal_with_features(payload: Vec<u8>, required_features: u32) -> Result<Vec<u8>, WireError> {
+    let envelope = WireEnvelope {
+        magic: WIRE_MAGIC,
+        version: WIRE_VERSION,
+        required_features,
+        payload,
+    };
+    bincode::serialize(&envelope).map_err(|e| WireError::Malformed(e.to_string()))
+}
+
+/// Wraps an already serialized `AepMessage` in the current envelope,
+/// stamped with the sender's clock. The stamp is covered by the frame's
+/// signature when it is signed afterwards.
+pub fn seal_clocked(payload: Vec<u8>, clock: Hlc) -> Result<Vec<u8>, WireError> {
+    let clocked = bincode::serialize(&ClockedPayload { clock, payload })
+        .map_err(|e| WireError::Malformed(e.to_string()))?;
+    seal_with_features(clocked, features::CLOCKED)
+}
+
+/// Rewrites a message frame, before it is signed or padded, for a receiver
+/// with `caps`: the envelope takes the highest version both sides share, a
+/// receiver without one gets the legacy raw message, and a receiver that
+/// does not know [`features::CLOCKED`] gets the frame without its clock
+/// stamp. Fails with
+/// [`WireError::UnsupportedVersion`] when that version predates the
+/// message's layout.
+pub fn adapt(frame: Vec<u8>, caps: &PeerCapabilities) -> Result<Vec<u8>, WireError> {
+    if !frame.starts_with(&WIRE_MAGIC) {
+        return Ok(frame);
+    }
+    let mut envelope: WireEnvelope =
+        bincode::deserialize(&frame).map_err(|e| WireError::Malformed(e.to_string()))?;
+    if envelope.required_features & (features::SIGNED | features::PADDED | features::COVER) != 0 {
+        return Err(WireError::Malformed("only message frames can be adapted, before signing and padding".into()));
+    }
+    let version = PeerCapabilities::local().negotiate(caps).unwrap_or(0);
+    let message = if envelope.required_features & features::CLOCKED != 0 {
+        let clocked: ClockedPayload =
+            bincode::deserialize(&envelope.payload).map_err(|e| WireError::Malformed(e.to_string()))?;
+        bincode::deserialize::<AepMessage>(&clocked.payload)
+    } else {
+        bincode::deserialize::<AepMessage>(&envelope.payload)
+    }
+    .map_err(|e| WireError::Malformed(e.to_string()))?;
+    unsupported_layout(&message, version, caps.min_version, caps.max_version)?;
+
+    if version == 0 || caps.features & features::CLOCKED == 0 {
+        strip_clock(&mut envelope)?;
+    }
+    if version == 0 {
+        return Ok(envelope.payload);
+    }
+    envelope.version = version;
+    bincode::serialize(&envelope).map_err(|e| WireError::Malformed(e.to_string()))
+}
+
+/// Takes the clock stamp off a frame, leaving the message it was wrapped
+/// around.
+fn strip_clock(envelope: &mut WireEnvelope) -> Result<(), WireError> {
+    if envelope.required_features & features::CLOCKED != 0 {
+        let clocked: ClockedPayload =
+            bincode::deserialize(&envelope.payload).map_err(|e| WireError::Malformed(e.to_string()))?;
+        envelope.payload = clocked.payload;
+        envelope.required_features &= !features::CLOCKED;
+    }
+    Ok(())
+}
+
+pub fn encode(message: &AepMessage) -> Result<Vec<u8>, WireError> {
+    let payload = bincode::serialize(message).map_err(|e| WireError::Malformed(e.to_string()))?;
+    seal(payload)
+}
+
+/// Signs an enveloped frame as published by `keypair` at `signed_at`. Frames
+/// are signed before they are padded; signing a frame twice keeps the first
+/// signature.
+pub fn sign(frame: Vec<u8>, keypair: &Keypair, signed_at: DateTime<Utc>) -> Result<Vec<u8>, WireError> {
+    if !frame.starts_with(&WIRE_MAGIC) {
+        return Err(WireError::Malformed("legacy frames cannot be signed".into()));
+    }
+    let mut envelope: WireEnvelope =
+        bincode::deserialize(&frame).map_err(|e| WireError::Malformed(e.to_string()))?;
+    if envelope.required_features & features::SIGNED != 0 {
+        return Ok(frame);
+    }
+    if envelope.required_features & (features::PADDED | features::COVER) != 0 {
+        return Err(WireError::Malformed("only message frames can be signed, before padding".into()));
+    }
+
+    let signed_at = signed_at.timestamp_millis();
+    let signature = keypair
+        .sign(&SignedPayload::signing_bytes(signed_at, &envelope.payload)?)
+        .map_err(|e| WireError::Malformed(e.to_string()))?;
+    let signed = SignedPayload {
+        public_key: keypair.public().to_protobuf_encoding(),
+        signed_at,
+        signature,
+        payload: envelope.payload,
+    };
+    envelope.payload = bincode::serialize(&signed).map_err(|e| WireError::Malformed(e.to_string()))?;
+    envelope.required_features |= features::SIGNED;
+    bincode::serialize(&envelope).map_err(|e| WireError::Malformed(e.to_string()))
+}
+
+fn open_signed(payload: &[u8]) -> Result<(FrameSigner, Vec<u8>), WireError> {
+    let signed: SignedPayload =
+        bincode::deserialize(payload).map_err(|e| WireError::Malformed(e.to_string()))?;
+    let public_key = PublicKey::from_protobuf_encoding(&signed.public_key)
+        .map_err(|e| WireError::BadSignature(format!("invalid public key: {}", e)))?;
+    if !public_key.verify(&SignedPayload::signing_bytes(signed.signed_at, &signed.payload)?, &signed.signature) {
+        return Err(WireError::BadSignature("signature does not match".into()));
+    }
+    let signed_at = Utc
+        .timestamp_millis_opt(signed.signed_at)
+        .single()
+        .ok_or_else(|| WireError::BadSignature("invalid signing time".into()))?;
+    let signer = FrameSigner {
+        peer_id: public_key.to_peer_id(),
+        signed_at,
+        signature: signed.signature,
+    };
+    Ok((signer, signed.payload))
+}
+
+/// Pads an enveloped frame so its encoded size is one of
+/// [`PADDING_BUCKETS`]. Legacy raw frames are returned as they are, since
+/// peers that send those could not strip the padding.
+pub fn pad(frame: Vec<u8>) -> Result<Vec<u8>, WireError> {
+    pad_to(frame, 0)
+}
+
+/// A frame of the smallest bucket that receivers drop unread.
+pub fn cover_frame() -> Result<Vec<u8>, WireError> {
+    pad_to(seal_with_features(Vec::new(), features::COVER)?, PADDING_BUCKETS[0])
+}
+
+/// Size a padded frame that needs `needed` bytes is rounded up to: the
+/// smallest of [`PADDING_BUCKETS`] that fits, or a multiple of the largest.
+pub fn padded_size(needed: usize) -> usize {
+    let largest = PADDING_BUCKETS[PADDING_BUCKETS.len() - 1];
+    PADDING_BUCKETS
+        .iter()
+        .copied()
+        .find(|bucket| *bucket >= needed)
+        .unwrap_or_else(|| needed.div_ceil(largest) * largest)
+}
+
+/// Bytes padding adds to a frame at the least.
+pub const PADDING_OVERHEAD: usize = PADDED_LENGTH_PREFIX;
+
+fn pad_to(frame: Vec<u8>, min_size: usize) -> Result<Vec<u8>, WireError> {
+    let needed = (frame.len() + PADDING_OVERHEAD).max(min_size);
+    pad_exact(frame, padded_size(needed))
+}
+
+/// Pads an enveloped frame to exactly `size` encoded bytes, for frames
+/// carried inside an outer frame whose own size is what gets rounded up.
+/// Fails when `size` is below the frame plus [`PADDING_OVERHEAD`]. Legacy
+/// raw frames and frames already padded are returned as they are.
+pub fn pad_exact(frame: Vec<u8>, size: usize) -> Result<Vec<u8>, WireError> {
+    if !frame.starts_with(&WIRE_MAGIC) {
+        return Ok(frame);
+    }
+    let mut envelope: WireEnvelope =
+        bincode::deserialize(&frame).map_err(|e| WireError::Malformed(e.to_string()))?;
+    if envelope.required_features & features::PADDED != 0 {
+        return Ok(frame);
+    }
+    let length = u32::try_from(envelope.payload.len())
+        .map_err(|_| WireError::Malformed("payload too large to pad".into()))?;
+    if size < ENVELOPE_OVERHEAD + PADDED_LENGTH_PREFIX + envelope.payload.len() {
+        return Err(WireError::Malformed(format!("frame does not fit in {} bytes", size)));
+    }
+
+    let mut payload = Vec::with_capacity(size - ENVELOPE_OVERHEAD);
+    payload.extend_from_slice(&length.to_le_bytes());
+    payload.extend_from_slice(&envelope.payload);
+    payload.resize(size - ENVELOPE_OVERHEAD, 0);
+    envelope.payload = payload;
+    envelope.required_features |= features::PADDED;
+    bincode::serialize(&envelope).map_err(|e| WireError::Malformed(e.to_string()))
+}
+
+fn unpad(payload: &[u8]) -> Result<&[u8], WireError> {
+    let malformed = || WireError::Malformed("bad padding".into());
+    let prefix: [u8; PADDED_LENGTH_PREFIX] = payload
+        .get(..PADDED_LENGTH_PREFIX)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(malformed)?;
+    let length = u32::from_le_bytes(prefix) as usize;
+    payload
+        .get(PADDED_LENGTH_PREFIX..PADDED_LENGTH_PREFIX + length)
+        .ok_or_else(malformed)
+}
+
+/// Decodes an enveloped frame, falling back to the legacy raw encoding for
+/// peers that predate the envelope.
+pub fn decode(bytes: &[u8]) -> Result<DecodedMessage, WireError> {
+    if !bytes.starts_with(&WIRE_MAGIC) {
+        let message = bincode::deserialize::<AepMessage>(bytes)
+            .map_err(|e| WireError::Malformed(e.to_string()))?;
+        unsupported_layout(&message, 0, MIN_WIRE_VERSION, WIRE_VERSION)?;
+        return Ok(DecodedMessage { version: 0, message, signer: None, clock: None });
+    }
+
+    let envelope: WireEnvelope =
+        bincode::deserialize(bytes).map_err(|e| WireError::Malformed(e.to_string()))?;
+
+    if envelope.version < MIN_WIRE_VERSION || envelope.version > WIRE_VERSION {
+        return Err(WireError::UnsupportedVersion {
+            version: envelope.version,
+            min: MIN_WIRE_VERSION,
+            max: WIRE_VERSION,
+        });
+    }
+
+    let missing = envelope.required_features & !SUPPORTED_FEATURES;
+    if missing != 0 {
+        return Err(WireError::UnsupportedFeatures { missing });
+    }
+
+    if envelope.required_features & features::COVER != 0 {
+        return Err(WireError::Cover);
+    }
+    let payload = if envelope.required_features & features::PADDED != 0 {
+        unpad(&envelope.payload)?
+    } else {
+        &envelope.payload
+    };
+    let (signer, payload) = if envelope.required_features & features::SIGNED != 0 {
+        let (signer, payload) = open_signed(payload)?;
+        (Some(signer), payload)
+    } else {
+        (None, payload.to_vec())
+    };
+    let (clock, payload) = if envelope.required_features & features::CLOCKED != 0 {
+        let clocked: ClockedPayload =
+            bincode::deserialize(&payload).map_err(|e| WireError::Malformed(e.to_string()))?;
+        (Some(clocked.clock), clocked.payload)
+    } else {
+        (None, payload)
+    };
+
+    let message = bincode::deserialize::<AepMessage>(&payload)
+        .map_err(|e| WireError::Malformed(e.to_string()))?;
+    unsupported_layout(&message, envelope.version, MIN_WIRE_VERSION, WIRE_VERSION)?;
+
+    Ok(DecodedMessage {
+        version: envelope.version,
+        message,
+        signer,
+        clock,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> AepMessage {
+        AepMessage::PeerDiscovery {
+            peer_id: "peer".into(),
+            address: "/ip4/127.0.0.1/tcp/1".into(),
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn round_trips_enveloped_message() {
+        let bytes = encode(&sample()).expect("encode");
+        assert!(bytes.starts_with(&WIRE_MAGIC));
+        let decoded = decode(&bytes).expect("decode");
+        assert_eq!(decoded.version, WIRE_VERSION);
+        assert!(matches!(decoded.message, AepMessage::PeerDiscovery { .. }));
+    }
+
+    #[test]
+    fn clock_stamps_survive_signing_and_padding() {
+        let keypair = Keypair::generate_ed25519();
+        let clock = Hlc { wall_ms: 1_700_000_000_000, counter: 7 };
+        let payload = bincode::serialize(&sample()).expect("serialize");
+        let frame = seal_clocked(payload, clock).expect("seal");
+        let frame = pad(sign(frame, &keypair, Utc::now()).expect("sign")).expect("pad");
+        let decoded = decode(&frame).expect("decode");
+        assert_eq!(decoded.clock, Some(clock));
+        assert!(decoded.signer.is_some());
+        assert!(decode(&encode(&sample()).expect("encode")).expect("decode").clock.is_none());
+    }
+
+    #[test]
+    fn accepts_legacy_raw_frames() {
+        let bytes = bincode::serialize(&sample()).expect("serialize");
+        let decoded = decode(&bytes).expect("decode");
+        assert_eq!(decoded.version, 0);
+    }
+
+    #[test]
+    fn rejects_newer_versions_with_typed_error() {
+        let payload = bincode::serialize(&sample()).expect("serialize");
+        let envelope = WireEnvelope {
+            magic: WIRE_MAGIC,
+            version: WIRE_VERSION + 1,
+            required_features: 0,
+            payload,
+        };
+        let bytes = bincode::serialize(&envelope).expect("serialize");
+        assert_eq!(
+            decode(&bytes).unwrap_err(),
+            WireError::UnsupportedVersion {
+                version: WIRE_VERSION + 1,
+                min: MIN_WIRE_VERSION,
+                max: WIRE_VERSION,
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_newer_layouts_from_older_peers() {
+        let ack = AepMessage::DeliveryAck {
+            message_id: "m".into(),
+            sender_id: "a".into(),
+            recipient_id: "b".into(),
+            timestamp: Utc::now(),
+            signature: None,
+        };
+        let v1 = PeerCapabilities { min_version: 1, max_version: 1, features: SUPPORTED_FEATURES };
+        let frame = encode(&ack).expect("encode");
+        assert!(matches!(adapt(frame, &v1), Err(WireError::UnsupportedVersion { version: 2, .. })));
+        let adapted = adapt(encode(&sample()).expect("encode"), &v1).expect("adapt");
+        assert_eq!(decode(&adapted).expect("decode").version, 1);
+
+        let payload = bincode::serialize(&ack).expect("serialize");
+        let envelope = WireEnvelope { magic: WIRE_MAGIC, version: 1, required_features: 0, payload: payload.clone() };
+        let bytes = bincode::serialize(&envelope).expect("serialize");
+        assert!(matches!(decode(&bytes), Err(WireError::UnsupportedVersion { version: 2, .. })));
+        assert!(matches!(decode(&payload), Err(WireError::UnsupportedVersion { version: 2, .. })));
+    }
+
+    #[test]
+    fn rejects_unknown_required_features() {
+        let payload = bincode::serialize(&sample()).expect("serialize");
+        let bytes = seal_with_features(payload, 1 << 31).expect("seal");
+        assert_eq!(
+            decode(&bytes).unwrap_err(),
+            WireError::UnsupportedFeatures { missing: 1 << 31 }
+        );
+    }
+
+    #[test]
+    fn padding_rounds_frames_up_to_buckets() {
+        let short = pad(encode(&sample()).expect("encode")).expect("pad");
+        assert_eq!(short.len(), PADDING_BUCKETS[0]);
+        assert!(matches!(decode(&short).expect("decode").message, AepMessage::PeerDiscovery { .. }));
+        assert_eq!(pad(short.clone()).expect("pad again"), short);
+
+        let long = AepMessage::PeerDiscovery {
+            peer_id: "p".repeat(2000),
+            address: String::new(),
+            signature: None,
+        };
+        let padded = pad(encode(&long).expect("encode")).expect("pad");
+        assert_eq!(padded.len(), PADDING_BUCKETS[2]);
+        match decode(&padded).expect("decode").message {
+            AepMessage::PeerDiscovery { peer_id, .. } => assert_eq!(peer_id.len(), 2000),
+            other => panic!("unexpected message {:?}", other),
+        }
+
+        let frame = encode(&sample()).expect("encode");
+        let exact = pad_exact(frame.clone(), 300).expect("pad exactly");
+        assert_eq!(exact.len(), 300);
+        assert!(matches!(decode(&exact).expect("decode").message, AepMessage::PeerDiscovery { .. }));
+        assert!(pad_exact(frame.clone(), frame.len() + PADDING_OVERHEAD - 1).is_err());
+    }
+
+    #[test]
+    fn signed_frames_name_their_publisher() {
+        let keypair = Keypair::generate_ed25519();
+        let signed_at = Utc.timestamp_millis_opt(1_700_000_000_000).unwrap();
+        let signed = sign(encode(&sample()).expect("encode"), &keypair, signed_at).expect("sign");
+
+        let padded = pad(signed.clone()).expect("pad");
+        let signer = decode(&padded).expect("decode").signer.expect("signer");
+        assert_eq!(signer.peer_id, keypair.public().to_peer_id());
+        assert_eq!(signer.signed_at, signed_at);
+        assert!(decode(&encode(&sample()).unwrap()).unwrap().signer.is_none());
+        assert_eq!(sign(signed.clone(), &keypair, Utc::now()).expect("sign again"), signed);
+        let unsigned_padded = pad(encode(&sample()).unwrap()).unwrap();
+        assert!(sign(unsigned_padded, &keypair, signed_at).is_err());
+
+        // Flipping the last byte of the message breaks the signature.
+        let mut tampered = signed;
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(decode(&tampered), Err(WireError::BadSignature(_))));
+    }
+
+    #[test]
+    fn cover_frames_look_padded_and_are_dropped() {
+        let cover = cover_frame().expect("cover");
+        assert_eq!(cover.len(), PADDING_BUCKETS[0]);
+        assert_eq!(decode(&cover).unwrap_err(), WireError::Cover);
+    }
+
+    #[test]
+    fn adapts_frames_to_the_receiver() {
+        let clock = Hlc { wall_ms: 1_700_000_000_000, counter: 1 };
+        let frame = seal_clocked(bincode::serialize(&sample()).unwrap(), clock).unwrap();
+        assert_eq!(adapt(frame.clone(), &PeerCapabilities::local()).unwrap(), frame);
+
+        let legacy = parse_identify_protocol_version("aegis/1.0.0").unwrap();
+        let raw = adapt(frame.clone(), &legacy).unwrap();
+        assert!(!raw.starts_with(&WIRE_MAGIC));
+        assert_eq!(decode(&raw).unwrap().version, 0);
+
+        let unclocked = PeerCapabilities { features: features::PADDED | features::SIGNED, ..PeerCapabilities::local() };
+        let stripped = decode(&adapt(frame.clone(), &unclocked).unwrap()).unwrap();
+        assert_eq!(stripped.version, WIRE_VERSION);
+        assert_eq!(stripped.clock, None);
+
+        let padded = pad(frame).unwrap();
+        assert!(adapt(padded, &PeerCapabilities::local()).is_err());
+    }
+
+    #[test]
+    fn identify_string_round_trips() {
+        let parsed = parse_identify_protocol_version(&identify_protocol_version()).expect("parse");
+        assert_eq!(parsed, PeerCapabilities::local());
+        let legacy = parse_identify_protocol_version("aegis/1.0.0").expect("legacy");
+        assert_eq!(PeerCapabilities::local().negotiate(&legacy), None);
+    }
+}
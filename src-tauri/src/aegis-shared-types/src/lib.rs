@@ -76,6 +76,101 @@ pub struct ConnectivityTransportStatus {
     pub wifi_direct_peers: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub local_peer_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_mode: Option<TransportProxyMode>,
+    /// SOCKS5 proxy outbound connections go through.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quic_enabled: Option<bool>,
+    /// Peers with a direct connection over each IP transport.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_peers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quic_peers: Option<Vec<String>>,
+}
+
+/// How outbound TCP connections leave this device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportProxyMode {
+    Direct,
+    /// Dials go through a SOCKS5 proxy; we still listen and use mDNS.
+    Proxied,
+    /// Dials go through the proxy, with no direct listening and no mDNS.
+    Strict,
+}
+
+/// Saved SOCKS5 proxy, e.g. a local Tor daemon at `127.0.0.1:9050`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxySettings {
+    pub address: String,
+    #[serde(default)]
+    pub strict: bool,
+}
+
+/// Privacy mode against traffic analysis: padded frames and cover traffic.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrivacySettings {
+    #[serde(default)]
+    pub pad_frames: bool,
+    #[serde(default)]
+    pub cover_traffic: bool,
+    #[serde(default)]
+    pub cover_budgets: CoverTrafficBudgets,
+}
+
+/// Cover traffic bytes per second each transport may carry. Unset ones use
+/// the network's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverTrafficBudgets {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quic: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wifi_direct: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bluetooth: Option<u32>,
+}
+
+/// Why the swarm refuses a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DeniedPeerReason {
+    Blocked,
+    ServerBan,
+    Manual,
+}
+
+/// A peer whose connections and gossip are refused, saved across restarts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeniedPeerRecord {
+    pub peer_id: String,
+    pub reason: DeniedPeerReason,
+    /// Set for server bans.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_id: Option<String>,
+    pub denied_at: DateTime<Utc>,
+    /// Permanent when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Seen-cache counters for routed and broadcast frames.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectivityFrameStats {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accepted: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicates_dropped: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expired_dropped: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
@@ -103,6 +198,10 @@ pub struct ConnectivityEventPayload {
     pub transports: Option<ConnectivityTransportStatus>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub relays: Option<Vec<RelaySnapshot>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frames: Option<ConnectivityFrameStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denied_peers: Option<Vec<DeniedPeerRecord>>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -133,6 +232,15 @@ impl Default for RelayStatus {
     }
 }
 
+/// State of our circuit reservation on a libp2p relay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelayReservationState {
+    Pending,
+    Active,
+    Failed,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct RelayConfig {
@@ -207,6 +315,13 @@ pub struct RelaySnapshot {
     pub uptime_percent: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reservation: Option<RelayReservationState>,
+    /// Address peers dial to reach us through this relay.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relayed_connections: Option<u32>,
 }
 
 impl RelayRecord {
@@ -223,6 +338,9 @@ impl RelayRecord {
             latency_ms: self.health.latency_ms,
             uptime_percent: self.health.uptime_percent,
             error: self.health.error.clone(),
+            reservation: None,
+            circuit_address: None,
+            relayed_connections: None,
         }
     }
 }
@@ -0,0 +1,225 @@
+use crypto::at_rest::{self, AtRestKey};
+use sqlx::{FromRow, Pool, Sqlite, SqliteConnection};
+use std::sync::{Arc, OnceLock, RwLock};
+
+static AT_REST_KEY: OnceLock<RwLock<Option<Arc<AtRestKey>>>> = OnceLock::new();
+
+fn key_slot() -> &'static RwLock<Option<Arc<AtRestKey>>> {
+    AT_REST_KEY.get_or_init(|| RwLock::new(None))
+}
+
+/// Installs the key used to seal message content and attachments. Until a
+/// key is installed, values are written and read as-is.
+pub fn set_at_rest_key(key: Option<AtRestKey>) {
+    *key_slot().write().expect("at-rest key lock poisoned") = key.map(Arc::new);
+}
+
+pub fn at_rest_key() -> Option<Arc<AtRestKey>> {
+    key_slot().read().expect("at-rest key lock poisoned").clone()
+}
+
+/// Marks plaintext written without a key that would otherwise read as
+/// sealed, because it starts with [`at_rest::TEXT_PREFIX`] or with this.
+const ESCAPED_TEXT_PREFIX: &str = "aegis-raw:v1:";
+
+pub(crate) fn seal_text(value: &str) -> String {
+    match at_rest_key() {
+        Some(key) => key.seal_text(value),
+        None => escape_text(value),
+    }
+}
+
+fn escape_text(value: &str) -> String {
+    if at_rest::is_sealed_text(value) || value.starts_with(ESCAPED_TEXT_PREFIX) {
+        format!("{}{}", ESCAPED_TEXT_PREFIX, value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Plaintext of a column that is not sealed: escaped values lose their
+/// marker, values written before encryption was enabled pass through.
+fn unescape_text(value: String) -> String {
+    match value.strip_prefix(ESCAPED_TEXT_PREFIX) {
+        Some(plaintext) => plaintext.to_string(),
+        None => value,
+    }
+}
+
+/// Opens a sealed text column. A sealed value fails to open while the store
+/// is locked or when it was sealed under a key we no longer hold.
+pub(crate) fn open_text(value: String) -> Result<String, sqlx::Error> {
+    if !at_rest::is_sealed_text(&value) {
+        return Ok(unescape_text(value));
+    }
+    let key = at_rest_key().ok_or_else(|| {
+        sqlx::Error::Decode("Message store is locked".to_string().into())
+    })?;
+    key.open_text(&value)
+        .map_err(|e| sqlx::Error::Decode(format!("Failed to open message content: {}", e).into()))
+}
+
+/// Marks blobs written without a key that would otherwise read as sealed,
+/// because they start with [`at_rest::BLOB_MAGIC`] or with this.
+const ESCAPED_BLOB_MAGIC: [u8; 5] = *b"AEGR\x01";
+
+pub(crate) fn seal_bytes(value: &[u8]) -> Vec<u8> {
+    match at_rest_key() {
+        Some(key) => key.seal_bytes(value),
+        None => escape_bytes(value),
+    }
+}
+
+fn escape_bytes(value: &[u8]) -> Vec<u8> {
+    if at_rest::is_sealed_bytes(value) || value.starts_with(&ESCAPED_BLOB_MAGIC) {
+        [&ESCAPED_BLOB_MAGIC[..], value].concat()
+    } else {
+        value.to_vec()
+    }
+}
+
+/// Contents of a blob that is not sealed: escaped blobs lose their marker,
+/// blobs written before encryption was enabled pass through.
+fn unescape_bytes(value: Vec<u8>) -> Vec<u8> {
+    match value.strip_prefix(&ESCAPED_BLOB_MAGIC) {
+        Some(plaintext) => plaintext.to_vec(),
+        None => value,
+    }
+}
+
+pub(crate) fn open_bytes(value: Vec<u8>) -> Result<Vec<u8>, sqlx::Error> {
+    if !at_rest::is_sealed_bytes(&value) {
+        return Ok(unescape_bytes(value));
+    }
+    let key = at_rest_key().ok_or_else(|| {
+        sqlx::Error::Decode("Message store is locked".to_string().into())
+    })?;
+    key.open_bytes(&value)
+        .map_err(|e| sqlx::Error::Decode(format!("Failed to open attachment: {}", e).into()))
+}
+
+/// Search index entry for `content`: blinded terms when a key is installed.
+pub(crate) fn index_terms(content: &str) -> String {
+    let terms = at_rest::search_terms(content);
+    match at_rest_key() {
+        Some(key) => terms
+            .iter()
+            .map(|term| key.blind_token(term))
+            .collect::<Vec<_>>()
+            .join(" "),
+        None => terms.join(" "),
+    }
+}
+
+/// FTS5 expression matching messages that contain every term of `query`.
+pub(crate) fn index_query(query: &str) -> Option<String> {
+    let tokens = index_terms(query);
+    if tokens.is_empty() {
+        return None;
+    }
+    Some(
+        tokens
+            .split(' ')
+            .map(|token| format!("\"{}\"", token))
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+pub(crate) async fn reindex_message(
+    conn: &mut SqliteConnection,
+    message_id: &str,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM messages_fts WHERE id = ?")
+        .bind(message_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("INSERT INTO messages_fts (id, content) VALUES (?, ?)")
+        .bind(message_id)
+        .bind(index_terms(content))
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AtRestMigrationReport {
+    pub messages: u64,
+    pub attachments: u64,
+}
+
+/// One-shot upgrade for databases created before at-rest encryption: seals
+/// every plaintext message and attachment, rebuilds the blinded search index
+/// and vacuums so the old cleartext pages do not linger in the file.
+pub async fn encrypt_existing_rows(
+    pool: &Pool<Sqlite>,
+) -> Result<AtRestMigrationReport, sqlx::Error> {
+    let key = match at_rest_key() {
+        Some(key) => key,
+        None => return Ok(AtRestMigrationReport::default()),
+    };
+
+    #[derive(FromRow)]
+    struct PlainMessage {
+        id: String,
+        content: String,
+        reply_snapshot_snippet: Option<String>,
+    }
+
+    #[derive(FromRow)]
+    struct PlainAttachment {
+        id: String,
+        data: Vec<u8>,
+    }
+
+    let mut report = AtRestMigrationReport::default();
+    let mut tx = pool.begin().await?;
+
+    let messages = sqlx::query_as::<_, PlainMessage>(
+        "SELECT id, content, reply_snapshot_snippet FROM messages WHERE content NOT LIKE ? || '%'",
+    )
+    .bind(at_rest::TEXT_PREFIX)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for message in messages {
+        let content = unescape_text(message.content);
+        let snippet = message
+            .reply_snapshot_snippet
+            .map(|snippet| key.seal_text(&unescape_text(snippet)));
+        sqlx::query("UPDATE messages SET content = ?, reply_snapshot_snippet = ? WHERE id = ?")
+            .bind(key.seal_text(&content))
+            .bind(snippet)
+            .bind(&message.id)
+            .execute(&mut *tx)
+            .await?;
+        reindex_message(&mut tx, &message.id, &content).await?;
+        report.messages += 1;
+    }
+
+    let attachments = sqlx::query_as::<_, PlainAttachment>(
+        "SELECT id, data FROM attachments WHERE substr(data, 1, ?) != ?",
+    )
+    .bind(at_rest::BLOB_MAGIC.len() as i64)
+    .bind(at_rest::BLOB_MAGIC.to_vec())
+    .fetch_all(&mut *tx)
+    .await?;
+
+    for attachment in attachments {
+        sqlx::query("UPDATE attachments SET data = ? WHERE id = ?")
+            .bind(key.seal_bytes(&unescape_bytes(attachment.data)))
+            .bind(&attachment.id)
+            .execute(&mut *tx)
+            .await?;
+        report.attachments += 1;
+    }
+
+    tx.commit().await?;
+
+    if report != AtRestMigrationReport::default() {
+        sqlx::query("VACUUM").execute(pool).await?;
+    }
+
+    Ok(report)
+}
@@ -287,3 +287,116 @@ pub async fn get_friendship_by_id(
     .await?;
     Ok(friendship)
 }
+
+/// Keys last seen for a contact and whether the local user verified them
+/// out of band.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ContactVerification {
+    pub user_id: String,
+    pub identity_public_key: Option<String>,
+    pub e2ee_identity_key: Option<String>,
+    pub verified: bool,
+    pub verified_at: Option<DateTime<Utc>>,
+    pub key_changed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactKeyObservation {
+    FirstSeen,
+    Unchanged,
+    Changed { was_verified: bool },
+}
+
+pub async fn get_contact_verification(
+    pool: &Pool<Sqlite>,
+    user_id: &str,
+) -> Result<Option<ContactVerification>, sqlx::Error> {
+    sqlx::query_as::<_, ContactVerification>(
+        "SELECT user_id, identity_public_key, e2ee_identity_key, verified, verified_at, key_changed_at FROM contact_verifications WHERE user_id = ?",
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Stores the keys currently advertised by `user_id`. A change of either key
+/// clears the verified flag so the UI has to ask the user to re-verify.
+pub async fn record_contact_keys(
+    pool: &Pool<Sqlite>,
+    user_id: &str,
+    identity_public_key: Option<&str>,
+    e2ee_identity_key: &str,
+) -> Result<ContactKeyObservation, sqlx::Error> {
+    let now = Utc::now().to_rfc3339();
+    let existing = get_contact_verification(pool, user_id).await?;
+
+    let Some(existing) = existing else {
+        sqlx::query(
+            "INSERT INTO contact_verifications (user_id, identity_public_key, e2ee_identity_key, verified, updated_at) VALUES (?, ?, ?, 0, ?)",
+        )
+        .bind(user_id)
+        .bind(identity_public_key)
+        .bind(e2ee_identity_key)
+        .bind(&now)
+        .execute(pool)
+        .await?;
+        return Ok(ContactKeyObservation::FirstSeen);
+    };
+
+    let e2ee_changed = existing.e2ee_identity_key.as_deref() != Some(e2ee_identity_key);
+    let identity_changed = match (existing.identity_public_key.as_deref(), identity_public_key) {
+        (Some(known), Some(seen)) => known != seen,
+        _ => false,
+    };
+
+    if !e2ee_changed && !identity_changed {
+        if existing.identity_public_key.is_none() && identity_public_key.is_some() {
+            sqlx::query("UPDATE contact_verifications SET identity_public_key = ?, updated_at = ? WHERE user_id = ?")
+                .bind(identity_public_key)
+                .bind(&now)
+                .bind(user_id)
+                .execute(pool)
+                .await?;
+        }
+        return Ok(ContactKeyObservation::Unchanged);
+    }
+
+    sqlx::query(
+        "UPDATE contact_verifications SET identity_public_key = COALESCE(?, identity_public_key), e2ee_identity_key = ?, verified = 0, verified_at = NULL, key_changed_at = ?, updated_at = ? WHERE user_id = ?",
+    )
+    .bind(identity_public_key)
+    .bind(e2ee_identity_key)
+    .bind(&now)
+    .bind(&now)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(ContactKeyObservation::Changed {
+        was_verified: existing.verified,
+    })
+}
+
+pub async fn set_contact_verified(
+    pool: &Pool<Sqlite>,
+    user_id: &str,
+    verified: bool,
+    identity_public_key: Option<&str>,
+    e2ee_identity_key: &str,
+) -> Result<(), sqlx::Error> {
+    let now = Utc::now().to_rfc3339();
+    let verified_at = verified.then(|| now.clone());
+    sqlx::query(
+        "INSERT INTO contact_verifications (user_id, identity_public_key, e2ee_identity_key, verified, verified_at, updated_at) VALUES (?, ?, ?, ?, ?, ?) \
+         ON CONFLICT(user_id) DO UPDATE SET identity_public_key = excluded.identity_public_key, e2ee_identity_key = excluded.e2ee_identity_key, verified = excluded.verified, verified_at = excluded.verified_at, key_changed_at = CASE WHEN excluded.verified = 1 THEN NULL ELSE key_changed_at END, updated_at = excluded.updated_at",
+    )
+    .bind(user_id)
+    .bind(identity_public_key)
+    .bind(e2ee_identity_key)
+    .bind(verified)
+    .bind(verified_at)
+    .bind(&now)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
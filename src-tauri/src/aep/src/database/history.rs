@@ -0,0 +1,296 @@
+use aegis_protocol::history::{HistoryEvent, HistoryKey, HistoryKind};
+use chrono::DateTime;
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::at_rest::{open_bytes, seal_bytes};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryRecord {
+    New,
+    /// Already held; its timestamp was lowered if the copy was older.
+    Known,
+    /// Stored as a tombstone, since its message was deleted by its author.
+    Deleted,
+}
+
+#[derive(FromRow)]
+struct HistoryRow {
+    id: String,
+    chat_id: String,
+    message_id: String,
+    kind: String,
+    author: String,
+    timestamp: i64,
+    payload: Vec<u8>,
+}
+
+impl HistoryRow {
+    fn into_event(self) -> Result<Option<HistoryEvent>, sqlx::Error> {
+        let Some(kind) = HistoryKind::parse(&self.kind) else {
+            return Ok(None);
+        };
+        Ok(Some(HistoryEvent {
+            id: self.id,
+            chat_id: self.chat_id,
+            message_id: self.message_id,
+            kind,
+            author: self.author,
+            timestamp_ms: self.timestamp,
+            payload: open_bytes(self.payload)?,
+        }))
+    }
+}
+
+/// Adds `event` to its chat's history. Peers may have seen the same event at
+/// different times; the earliest is kept so their digests agree.
+///
+/// Once a message is deleted by its author, the message and every event on
+/// it become tombstones: their ids stay, so they are not fetched again, but
+/// their payloads are dropped and they are no longer offered to peers.
+pub async fn record_history_event(
+    pool: &Pool<Sqlite>,
+    event: &HistoryEvent,
+) -> Result<HistoryRecord, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let inserted = sqlx::query(
+        "INSERT INTO chat_history (id, chat_id, message_id, kind, author, timestamp, payload) VALUES (?, ?, ?, ?, ?, ?, ?) ON CONFLICT(id) DO NOTHING",
+    )
+    .bind(&event.id)
+    .bind(&event.chat_id)
+    .bind(&event.message_id)
+    .bind(event.kind.as_str())
+    .bind(&event.author)
+    .bind(event.timestamp_ms)
+    .bind(seal_bytes(&event.payload))
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+    if !inserted {
+        sqlx::query("UPDATE chat_history SET timestamp = MIN(timestamp, ?) WHERE id = ?")
+            .bind(event.timestamp_ms)
+            .bind(&event.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(HistoryRecord::Known);
+    }
+
+    sqlx::query(
+        "UPDATE chat_history SET payload = NULL \
+         WHERE message_id = ? AND chat_id = ? AND kind != 'delete' AND payload IS NOT NULL \
+         AND EXISTS ( \
+             SELECT 1 FROM chat_history m \
+             INNER JOIN chat_history d ON d.message_id = m.message_id AND d.chat_id = m.chat_id AND d.author = m.author \
+             WHERE m.message_id = ? AND m.chat_id = ? AND m.kind = 'message' AND d.kind = 'delete' \
+         )",
+    )
+    .bind(&event.message_id)
+    .bind(&event.chat_id)
+    .bind(&event.message_id)
+    .bind(&event.chat_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let erased: bool =
+        sqlx::query_scalar("SELECT payload IS NULL FROM chat_history WHERE id = ?")
+            .bind(&event.id)
+            .fetch_one(&mut *tx)
+            .await?;
+    tx.commit().await?;
+    Ok(if erased {
+        HistoryRecord::Deleted
+    } else {
+        HistoryRecord::New
+    })
+}
+
+/// Keys of the events in `chat_id` we can hand to peers. Tombstones are
+/// left out.
+pub async fn history_keys(
+    pool: &Pool<Sqlite>,
+    chat_id: &str,
+) -> Result<Vec<HistoryKey>, sqlx::Error> {
+    let rows: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT id, timestamp FROM chat_history WHERE chat_id = ? AND payload IS NOT NULL ORDER BY timestamp",
+    )
+    .bind(chat_id)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|(id, timestamp_ms)| HistoryKey { id, timestamp_ms })
+        .collect())
+}
+
+/// Takes the earlier timestamps a peer lists for events we hold, and
+/// returns the ids of the listed events we do not hold at all.
+pub async fn reconcile_history_keys(
+    pool: &Pool<Sqlite>,
+    chat_id: &str,
+    keys: &[HistoryKey],
+) -> Result<Vec<String>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut missing = Vec::new();
+    for key in keys {
+        let held = sqlx::query(
+            "UPDATE chat_history SET timestamp = MIN(timestamp, ?) WHERE id = ? AND chat_id = ?",
+        )
+        .bind(key.timestamp_ms)
+        .bind(&key.id)
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        if !held {
+            missing.push(key.id.clone());
+        }
+    }
+    tx.commit().await?;
+    Ok(missing)
+}
+
+/// The events of `chat_id` among `ids` that still have their payload.
+pub async fn get_history_events(
+    pool: &Pool<Sqlite>,
+    chat_id: &str,
+    ids: &[String],
+) -> Result<Vec<HistoryEvent>, sqlx::Error> {
+    let mut events = Vec::with_capacity(ids.len());
+    for id in ids {
+        let row = sqlx::query_as::<_, HistoryRow>(
+            "SELECT id, chat_id, message_id, kind, author, timestamp, payload FROM chat_history WHERE id = ? AND chat_id = ? AND payload IS NOT NULL",
+        )
+        .bind(id)
+        .bind(chat_id)
+        .fetch_optional(pool)
+        .await?;
+        if let Some(event) = row.map(HistoryRow::into_event).transpose()?.flatten() {
+            events.push(event);
+        }
+    }
+    Ok(events)
+}
+
+/// Signing time of message `message_id` in `chat_id`, in milliseconds: from
+/// its history event, or from the message store for messages that predate
+/// the history. Reactions and deletions are filed under it.
+pub async fn history_message_time(
+    pool: &Pool<Sqlite>,
+    chat_id: &str,
+    message_id: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    let recorded: Option<i64> = sqlx::query_scalar(
+        "SELECT timestamp FROM chat_history WHERE chat_id = ? AND message_id = ? AND kind = 'message'",
+    )
+    .bind(chat_id)
+    .bind(message_id)
+    .fetch_optional(pool)
+    .await?;
+    if recorded.is_some() {
+        return Ok(recorded);
+    }
+
+    let stored: Option<String> =
+        sqlx::query_scalar("SELECT timestamp FROM messages WHERE id = ? AND chat_id = ?")
+            .bind(message_id)
+            .bind(chat_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(stored
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(&timestamp).ok())
+        .map(|timestamp| timestamp.timestamp_millis()))
+}
+
+/// Whether `user_id` may read and write `chat_id`: a channel or a server's
+/// own chat when they are a member of the server and not banned from it, a
+/// group chat when they are one of its members. Nothing records who else
+/// may see a private channel, so only the server's owner may access one.
+pub async fn may_access_chat(
+    pool: &Pool<Sqlite>,
+    chat_id: &str,
+    user_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let server: Option<(String, bool, String)> = sqlx::query_as(
+        "SELECT c.server_id, c.private, s.owner_id FROM channels c INNER JOIN servers s ON s.id = c.server_id WHERE c.id = ? \
+         UNION SELECT id, FALSE, owner_id FROM servers WHERE id = ? LIMIT 1",
+    )
+    .bind(chat_id)
+    .bind(chat_id)
+    .fetch_optional(pool)
+    .await?;
+
+    match server {
+        Some((_, true, owner_id)) => Ok(owner_id == user_id),
+        Some((server_id, false, _)) => {
+            sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM server_members WHERE server_id = ? AND user_id = ?) \
+                 AND NOT EXISTS (SELECT 1 FROM server_bans WHERE server_id = ? AND user_id = ?)",
+            )
+            .bind(&server_id)
+            .bind(user_id)
+            .bind(&server_id)
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+        }
+        None => {
+            sqlx::query_scalar(
+                "SELECT EXISTS (SELECT 1 FROM group_chat_members WHERE group_chat_id = ? AND user_id = ?)",
+            )
+            .bind(chat_id)
+            .bind(user_id)
+            .fetch_one(pool)
+            .await
+        }
+    }
+}
+
+/// Whether `chat_id` is a channel, a server's own chat or a group chat, as
+/// opposed to a direct chat between two peers.
+pub async fn is_shared_chat(pool: &Pool<Sqlite>, chat_id: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM channels WHERE id = ?) \
+         OR EXISTS (SELECT 1 FROM servers WHERE id = ?) \
+         OR EXISTS (SELECT 1 FROM group_chats WHERE id = ?)",
+    )
+    .bind(chat_id)
+    .bind(chat_id)
+    .bind(chat_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Chats both users may access: the public channels and server chats of
+/// servers they are both members of and neither is banned from, and the
+/// group chats they are both in. Private channels are left out, since only
+/// the server's owner may access them.
+pub async fn shared_chats(
+    pool: &Pool<Sqlite>,
+    user_id: &str,
+    other_id: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "WITH shared_servers AS ( \
+             SELECT a.server_id FROM server_members a \
+             INNER JOIN server_members b ON b.server_id = a.server_id AND b.user_id = ? \
+             WHERE a.user_id = ? \
+             AND NOT EXISTS (SELECT 1 FROM server_bans sb WHERE sb.server_id = a.server_id AND sb.user_id IN (?, ?)) \
+         ) \
+         SELECT id FROM channels WHERE server_id IN (SELECT server_id FROM shared_servers) AND private = FALSE \
+         UNION SELECT server_id FROM shared_servers \
+         UNION SELECT a.group_chat_id FROM group_chat_members a \
+             INNER JOIN group_chat_members b ON b.group_chat_id = a.group_chat_id AND b.user_id = ? \
+             WHERE a.user_id = ?",
+    )
+    .bind(other_id)
+    .bind(user_id)
+    .bind(user_id)
+    .bind(other_id)
+    .bind(other_id)
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
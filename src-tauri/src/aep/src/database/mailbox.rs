@@ -0,0 +1,210 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use sqlx::{FromRow, Pool, Sqlite};
+
+/// Limits a relay applies to the envelopes it holds for each recipient, and
+/// to what any one depositor may leave across all mailboxes so a single peer
+/// cannot fill them for everyone else.
+#[derive(Debug, Clone, Copy)]
+pub struct MailboxQuota {
+    pub max_messages: u32,
+    pub max_bytes: u64,
+    pub max_depositor_messages: u32,
+    pub max_depositor_bytes: u64,
+    pub ttl: Duration,
+}
+
+impl Default for MailboxQuota {
+    fn default() -> Self {
+        Self {
+            max_messages: 500,
+            max_bytes: 16 * 1024 * 1024,
+            max_depositor_messages: 200,
+            max_depositor_bytes: 4 * 1024 * 1024,
+            ttl: Duration::days(7),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MailboxDeposit {
+    /// Stored under this id. Depositing the same envelope again returns the
+    /// existing id and extends its expiry.
+    Stored(i64),
+    /// The recipient's mailbox has no room for the envelope.
+    QuotaExceeded,
+    /// The depositor already holds its share of the relay.
+    DepositorQuotaExceeded,
+}
+
+/// Envelope held for a recipient, as the encoded bytes it was deposited with.
+#[derive(Debug, Clone)]
+pub struct MailboxEnvelope {
+    pub id: i64,
+    pub depositor: String,
+    pub envelope: Vec<u8>,
+    pub deposited_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct MailboxRow {
+    id: i64,
+    depositor: String,
+    envelope: Vec<u8>,
+    deposited_at: i64,
+}
+
+impl From<MailboxRow> for MailboxEnvelope {
+    fn from(row: MailboxRow) -> Self {
+        MailboxEnvelope {
+            id: row.id,
+            depositor: row.depositor,
+            envelope: row.envelope,
+            deposited_at: Utc
+                .timestamp_millis_opt(row.deposited_at)
+                .single()
+                .unwrap_or_else(Utc::now),
+        }
+    }
+}
+
+/// Holds `envelope` for `recipient` unless that would take their mailbox, or
+/// what `depositor` holds across all mailboxes, over `quota`. Expired
+/// envelopes do not count towards either.
+pub async fn deposit_mailbox_envelope(
+    pool: &Pool<Sqlite>,
+    recipient: &str,
+    depositor: &str,
+    digest: &str,
+    envelope: &[u8],
+    quota: &MailboxQuota,
+    now: DateTime<Utc>,
+) -> Result<MailboxDeposit, sqlx::Error> {
+    let now_ms = now.timestamp_millis();
+    let expires_at = (now + quota.ttl).timestamp_millis();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM relay_mailbox WHERE recipient = ? AND expires_at <= ?")
+        .bind(recipient)
+        .bind(now_ms)
+        .execute(&mut *tx)
+        .await?;
+
+    let existing: Option<i64> =
+        sqlx::query_scalar("SELECT id FROM relay_mailbox WHERE recipient = ? AND digest = ?")
+            .bind(recipient)
+            .bind(digest)
+            .fetch_optional(&mut *tx)
+            .await?;
+    if let Some(id) = existing {
+        sqlx::query("UPDATE relay_mailbox SET expires_at = ? WHERE id = ?")
+            .bind(expires_at)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(MailboxDeposit::Stored(id));
+    }
+
+    let (count, bytes): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM relay_mailbox WHERE recipient = ?",
+    )
+    .bind(recipient)
+    .fetch_one(&mut *tx)
+    .await?;
+    let size = envelope.len() as u64;
+    if count as u64 >= quota.max_messages as u64 || bytes as u64 + size > quota.max_bytes {
+        return Ok(MailboxDeposit::QuotaExceeded);
+    }
+
+    let (count, bytes): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM relay_mailbox WHERE depositor = ? AND expires_at > ?",
+    )
+    .bind(depositor)
+    .bind(now_ms)
+    .fetch_one(&mut *tx)
+    .await?;
+    if count as u64 >= quota.max_depositor_messages as u64
+        || bytes as u64 + size > quota.max_depositor_bytes
+    {
+        return Ok(MailboxDeposit::DepositorQuotaExceeded);
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO relay_mailbox (recipient, depositor, digest, envelope, size, deposited_at, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(recipient)
+    .bind(depositor)
+    .bind(digest)
+    .bind(envelope)
+    .bind(size as i64)
+    .bind(now_ms)
+    .bind(expires_at)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(MailboxDeposit::Stored(result.last_insert_rowid()))
+}
+
+/// Unexpired envelopes held for `recipient`, oldest first, and how many more
+/// are waiting beyond `limit`.
+pub async fn fetch_mailbox(
+    pool: &Pool<Sqlite>,
+    recipient: &str,
+    now: DateTime<Utc>,
+    limit: u32,
+) -> Result<(Vec<MailboxEnvelope>, u32), sqlx::Error> {
+    let now_ms = now.timestamp_millis();
+    let envelopes: Vec<MailboxEnvelope> = sqlx::query_as::<_, MailboxRow>(
+        "SELECT id, depositor, envelope, deposited_at FROM relay_mailbox WHERE recipient = ? AND expires_at > ? ORDER BY id LIMIT ?",
+    )
+    .bind(recipient)
+    .bind(now_ms)
+    .bind(limit as i64)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(MailboxEnvelope::from)
+    .collect();
+
+    let total: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM relay_mailbox WHERE recipient = ? AND expires_at > ?",
+    )
+    .bind(recipient)
+    .bind(now_ms)
+    .fetch_one(pool)
+    .await?;
+    let remaining = (total as u64).saturating_sub(envelopes.len() as u64) as u32;
+    Ok((envelopes, remaining))
+}
+
+/// Drops the given envelopes from `recipient`'s mailbox. Ids belonging to
+/// other recipients are ignored.
+pub async fn acknowledge_mailbox_envelopes(
+    pool: &Pool<Sqlite>,
+    recipient: &str,
+    ids: &[i64],
+) -> Result<u64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let mut removed = 0;
+    for id in ids {
+        removed += sqlx::query("DELETE FROM relay_mailbox WHERE id = ? AND recipient = ?")
+            .bind(id)
+            .bind(recipient)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+    }
+    tx.commit().await?;
+    Ok(removed)
+}
+
+pub async fn purge_expired_mailbox_envelopes(
+    pool: &Pool<Sqlite>,
+    now: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM relay_mailbox WHERE expires_at <= ?")
+        .bind(now.timestamp_millis())
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -1,8 +1,15 @@
+use aegis_protocol::hlc::Hlc;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{FromRow, Pool, QueryBuilder, Sqlite};
 use std::collections::HashMap;
 
+use super::at_rest::{index_query, open_bytes, open_text, reindex_message, seal_bytes, seal_text};
+use super::outbox::DeliveryStatus;
+use super::threads::{
+    attach_to_thread, detach_from_thread, get_threads_by_root, refresh_thread, MessageThread,
+};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Attachment {
     pub id: String,
@@ -42,6 +49,18 @@ pub struct Message {
     pub edited_by: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub expires_at: Option<DateTime<Utc>>,
+    /// Least advanced delivery state across the recipients of a message we
+    /// sent. `None` for messages from other people.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivery_status: Option<DeliveryStatus>,
+    /// Root of the thread the message is a reply in. Worked out from
+    /// `reply_to_message_id` when the message is stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+    /// The thread opened on the message, with its reply count and last
+    /// activity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<MessageThread>,
 }
 
 #[derive(Debug, Clone, FromRow)]
@@ -59,6 +78,8 @@ struct MessageRow {
     edited_at: Option<String>,
     edited_by: Option<String>,
     expires_at: Option<String>,
+    #[sqlx(default)]
+    thread_id: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -77,19 +98,21 @@ pub async fn insert_message(
     let timestamp_str = message.timestamp.to_rfc3339();
     let edited_at_str = message.edited_at.map(|value| value.to_rfc3339());
     let expires_at_str = message.expires_at.map(|value| value.to_rfc3339());
+    let sealed_content = seal_text(&message.content);
+    let sealed_snippet = message.reply_snapshot_snippet.as_deref().map(seal_text);
 
     sqlx::query!(
         "INSERT INTO messages (id, chat_id, sender_id, content, timestamp, read, pinned, reply_to_message_id, reply_snapshot_author, reply_snapshot_snippet, edited_at, edited_by, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         message.id,
         message.chat_id,
         message.sender_id,
-        message.content,
+        sealed_content,
         timestamp_str,
         message.read,
         message.pinned,
         message.reply_to_message_id,
         message.reply_snapshot_author,
-        message.reply_snapshot_snippet,
+        sealed_snippet,
         edited_at_str,
         message.edited_by,
         expires_at_str,
@@ -97,6 +120,20 @@ pub async fn insert_message(
     .execute(&mut *tx)
     .await?;
 
+    // Ordered by its own timestamp until the sender's clock stamp, if any,
+    // is set with `set_message_hlc`.
+    sqlx::query("UPDATE messages SET hlc = ? WHERE id = ?")
+        .bind(Hlc::from_claimed(message.timestamp, Utc::now()).encode())
+        .bind(&message.id)
+        .execute(&mut *tx)
+        .await?;
+
+    reindex_message(&mut tx, &message.id, &message.content).await?;
+
+    if let Some(reply_to) = &message.reply_to_message_id {
+        attach_to_thread(&mut tx, reply_to).await?;
+    }
+
     if !attachment_data.is_empty() {
         let mut query_builder = QueryBuilder::<Sqlite>::new(
             "INSERT INTO attachments (id, message_id, name, content_type, size, data) ",
@@ -108,7 +145,7 @@ pub async fn insert_message(
                 .push_bind(attachment.metadata.name.clone())
                 .push_bind(attachment.metadata.content_type.clone())
                 .push_bind(attachment.metadata.size as i64)
-                .push_bind(attachment.data.clone());
+                .push_bind(seal_bytes(&attachment.data));
         });
 
         query_builder.build().execute(&mut *tx).await?;
@@ -119,25 +156,55 @@ pub async fn insert_message(
 }
 
 pub async fn delete_message(pool: &Pool<Sqlite>, message_id: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let thread_id: Option<Option<String>> =
+        sqlx::query_scalar("SELECT thread_id FROM messages WHERE id = ?")
+            .bind(message_id)
+            .fetch_optional(&mut *tx)
+            .await?;
     sqlx::query!("DELETE FROM messages WHERE id = ?", message_id)
-        .execute(pool)
+        .execute(&mut *tx)
         .await?;
+    detach_from_thread(&mut tx, message_id, thread_id.flatten().as_deref()).await?;
+    tx.commit().await?;
     Ok(())
 }
 
+/// Newest messages of `chat_id` first, by hybrid logical clock, so a reply
+/// never sorts above the message it answers however skewed the senders'
+/// clocks are.
 pub async fn get_messages_for_chat(
     pool: &Pool<Sqlite>,
     chat_id: &str,
     limit: i64,
     offset: i64,
 ) -> Result<Vec<Message>, sqlx::Error> {
-    let messages_rows = sqlx::query_as!(
-        MessageRow,
-        "SELECT id, chat_id, sender_id, content, timestamp, read, pinned, reply_to_message_id, reply_snapshot_author, reply_snapshot_snippet, edited_at, edited_by, expires_at FROM messages WHERE chat_id = ? ORDER BY timestamp DESC LIMIT ? OFFSET ?",
-        chat_id,
-        limit,
-        offset
+    let messages_rows = sqlx::query_as::<_, MessageRow>(
+        "SELECT id, chat_id, sender_id, content, timestamp, read, pinned, reply_to_message_id, reply_snapshot_author, reply_snapshot_snippet, edited_at, edited_by, expires_at, thread_id FROM messages WHERE chat_id = ? ORDER BY hlc DESC, id DESC LIMIT ? OFFSET ?",
+    )
+    .bind(chat_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool)
+    .await?;
+
+    hydrate_messages_from_rows(pool, messages_rows).await
+}
+
+/// Newest replies in the thread opened on `root_message_id` first, ordered
+/// and paged like [`get_messages_for_chat`].
+pub async fn get_messages_for_thread(
+    pool: &Pool<Sqlite>,
+    root_message_id: &str,
+    limit: i64,
+    offset: i64,
+) -> Result<Vec<Message>, sqlx::Error> {
+    let messages_rows = sqlx::query_as::<_, MessageRow>(
+        "SELECT id, chat_id, sender_id, content, timestamp, read, pinned, reply_to_message_id, reply_snapshot_author, reply_snapshot_snippet, edited_at, edited_by, expires_at, thread_id FROM messages WHERE thread_id = ? ORDER BY hlc DESC, id DESC LIMIT ? OFFSET ?",
     )
+    .bind(root_message_id)
+    .bind(limit)
+    .bind(offset)
     .fetch_all(pool)
     .await?;
 
@@ -181,7 +248,7 @@ async fn hydrate_messages_from_rows(
             id: row.id,
             chat_id: row.chat_id,
             sender_id: row.sender_id,
-            content: row.content,
+            content: open_text(row.content)?,
             timestamp,
             read: row.read,
             pinned: row.pinned,
@@ -189,10 +256,13 @@ async fn hydrate_messages_from_rows(
             reactions: HashMap::new(),
             reply_to_message_id: row.reply_to_message_id,
             reply_snapshot_author: row.reply_snapshot_author,
-            reply_snapshot_snippet: row.reply_snapshot_snippet,
+            reply_snapshot_snippet: row.reply_snapshot_snippet.map(open_text).transpose()?,
             edited_at,
             edited_by: row.edited_by,
             expires_at,
+            delivery_status: None,
+            thread_id: row.thread_id,
+            thread: None,
         });
     }
 
@@ -271,7 +341,48 @@ async fn hydrate_messages_from_rows(
                 .push(row.user_id);
         }
 
+        #[derive(FromRow)]
+        struct DeliveryRow {
+            message_id: String,
+            status: String,
+        }
+
+        let mut deliveries_query = QueryBuilder::<Sqlite>::new(
+            "SELECT message_id, status FROM message_deliveries WHERE message_id IN (",
+        );
+        {
+            let mut separated = deliveries_query.separated(", ");
+            for message_id in &message_ids {
+                separated.push_bind(message_id);
+            }
+        }
+        deliveries_query.push(")");
+        let delivery_rows = deliveries_query
+            .build_query_as::<DeliveryRow>()
+            .fetch_all(pool)
+            .await?;
+
+        let mut delivery_map: HashMap<String, DeliveryStatus> =
+            HashMap::with_capacity(message_ids.len());
+        for row in delivery_rows {
+            let Some(status) = DeliveryStatus::parse(&row.status) else {
+                continue;
+            };
+            delivery_map
+                .entry(row.message_id)
+                .and_modify(|current| *current = (*current).min(status))
+                .or_insert(status);
+        }
+
+        let mut thread_map: HashMap<String, MessageThread> = get_threads_by_root(pool, &message_ids)
+            .await?
+            .into_iter()
+            .map(|thread| (thread.root_message_id.clone(), thread))
+            .collect();
+
         for message in &mut messages {
+            message.delivery_status = delivery_map.remove(&message.id);
+            message.thread = thread_map.remove(&message.id);
             if let Some(mut attachments) = attachments_map.remove(&message.id) {
                 attachments.sort_by(|a, b| a.id.cmp(&b.id));
                 message.attachments = attachments;
@@ -294,18 +405,78 @@ pub async fn update_message_content(
     edited_by: &str,
 ) -> Result<(), sqlx::Error> {
     let edited_at_str = edited_at.to_rfc3339();
+    let sealed_content = seal_text(new_content);
+    let mut tx = pool.begin().await?;
     sqlx::query!(
         "UPDATE messages SET content = ?, edited_at = ?, edited_by = ? WHERE id = ?",
-        new_content,
+        sealed_content,
         edited_at_str,
         edited_by,
         message_id,
     )
+    .execute(&mut *tx)
+    .await?;
+    reindex_message(&mut tx, message_id, new_content).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Orders `message_id` by the stamp its sender's clock gave it, and counts
+/// it as its thread's latest activity if it is.
+pub async fn set_message_hlc(
+    pool: &Pool<Sqlite>,
+    message_id: &str,
+    hlc: Hlc,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let thread_id: Option<Option<String>> =
+        sqlx::query_scalar("UPDATE messages SET hlc = ? WHERE id = ? RETURNING thread_id")
+            .bind(hlc.encode())
+            .bind(message_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+    if let Some(thread_id) = thread_id.flatten() {
+        refresh_thread(&mut tx, &thread_id).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Records `hlc` as issued by our clock. Stamps only ever move forward.
+pub async fn save_clock(pool: &Pool<Sqlite>, hlc: Hlc) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO hybrid_clock (id, last) VALUES (0, ?) ON CONFLICT(id) DO UPDATE SET last = MAX(last, excluded.last)",
+    )
+    .bind(hlc.encode())
     .execute(pool)
     .await?;
     Ok(())
 }
 
+/// Newest stamp our clock issued or a stored message is ordered by, for the
+/// clock to carry on from after a restart.
+pub async fn load_clock(pool: &Pool<Sqlite>) -> Result<Option<Hlc>, sqlx::Error> {
+    let last: Option<String> = sqlx::query_scalar(
+        "SELECT MAX(hlc) FROM (SELECT last AS hlc FROM hybrid_clock UNION ALL SELECT MAX(hlc) FROM messages)",
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(last.as_deref().and_then(Hlc::parse))
+}
+
+/// Whether `message_id` carries an edit made after `edited_at`.
+pub async fn message_edited_since(
+    pool: &Pool<Sqlite>,
+    message_id: &str,
+    edited_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM messages WHERE id = ? AND edited_at > ?)")
+        .bind(message_id)
+        .bind(edited_at.to_rfc3339())
+        .fetch_one(pool)
+        .await
+}
+
 pub async fn mark_message_as_read(
     pool: &Pool<Sqlite>,
     message_id: &str,
@@ -430,21 +601,19 @@ pub async fn search_messages(
 ) -> Result<Vec<Message>, sqlx::Error> {
     let mut builder = QueryBuilder::<Sqlite>::new("SELECT m.id, m.chat_id, m.sender_id, m.content, m.timestamp, m.read, m.pinned, m.reply_to_message_id, m.reply_snapshot_author, m.reply_snapshot_snippet, m.edited_at, m.edited_by, m.expires_at FROM messages m");
 
-    if let Some(q) = query {
-        if !q.trim().is_empty() {
-            builder.push(" JOIN messages_fts fts ON m.id = fts.id ");
-        }
+    // The index only holds blinded terms, so the query is blinded the same
+    // way and matched as whole words.
+    let match_expr = query.and_then(index_query);
+    if match_expr.is_some() {
+        builder.push(" JOIN messages_fts fts ON m.id = fts.id ");
     }
 
     builder.push(" WHERE m.chat_id = ");
     builder.push_bind(chat_id);
 
-    if let Some(q) = query {
-        let trimmed = q.trim();
-        if !trimmed.is_empty() {
-            builder.push(" AND messages_fts MATCH ");
-            builder.push_bind(format!("\"{}\"", trimmed));
-        }
+    if let Some(expr) = match_expr {
+        builder.push(" AND messages_fts MATCH ");
+        builder.push_bind(expr);
     }
 
     if let Some(pinned) = pinned {
@@ -501,7 +670,7 @@ pub async fn get_attachment_data(
         .await?;
 
     match record {
-        Some(row) => Ok(row.data),
+        Some(row) => open_bytes(row.data),
         None => Err(sqlx::Error::RowNotFound),
     }
 }
@@ -1,21 +1,33 @@
+pub mod at_rest;
 pub mod channels;
 pub mod events;
 pub mod friendships;
 pub mod groups;
+pub mod history;
 pub mod init;
+pub mod mailbox;
 pub mod messages;
+pub mod outbox;
+pub mod pending;
 pub mod reviews;
 pub mod servers;
+pub mod threads;
 pub mod utils;
 
 pub use aegis_shared_types::{Channel, ChannelCategory, Role, Server, ServerInvite, User};
 
+pub use at_rest::{at_rest_key, encrypt_existing_rows, set_at_rest_key, AtRestMigrationReport};
 pub use init::initialize_db;
 
 pub use channels::*;
 pub use events::*;
 pub use friendships::*;
 pub use groups::*;
+pub use history::*;
+pub use mailbox::*;
 pub use messages::*;
+pub use outbox::*;
+pub use pending::*;
 pub use reviews::*;
-pub use servers::*;
\ No newline at end of file
+pub use servers::*;
+pub use threads::*;
\ No newline at end of file
@@ -0,0 +1,292 @@
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+
+use super::at_rest::{open_bytes, seal_bytes};
+
+/// Destination used for frames published to a gossip topic rather than sent
+/// to a single peer.
+pub const BROADCAST_DESTINATION: &str = "*";
+
+/// Attempts after which an entry is given up on and its message marked failed.
+pub const MAX_OUTBOX_ATTEMPTS: u32 = 16;
+
+const BASE_BACKOFF_SECS: i64 = 2;
+const MAX_BACKOFF_SECS: i64 = 15 * 60;
+
+/// Frame waiting to be handed to the network. `payload` is the sealed wire
+/// frame, stored encrypted when the message store key is installed.
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub destination: String,
+    pub message_id: Option<String>,
+    pub awaits_ack: bool,
+    pub payload: Vec<u8>,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct OutboxRow {
+    id: i64,
+    destination: String,
+    message_id: Option<String>,
+    awaits_ack: bool,
+    payload: Vec<u8>,
+    attempts: i64,
+    next_attempt_at: i64,
+}
+
+impl TryFrom<OutboxRow> for OutboxEntry {
+    type Error = sqlx::Error;
+
+    fn try_from(row: OutboxRow) -> Result<Self, Self::Error> {
+        Ok(OutboxEntry {
+            id: row.id,
+            destination: row.destination,
+            message_id: row.message_id,
+            awaits_ack: row.awaits_ack,
+            payload: open_bytes(row.payload)?,
+            attempts: row.attempts.max(0) as u32,
+            next_attempt_at: Utc
+                .timestamp_millis_opt(row.next_attempt_at)
+                .single()
+                .unwrap_or_else(Utc::now),
+        })
+    }
+}
+
+/// Delay before the next attempt once `attempts` have been made: doubles from
+/// two seconds up to fifteen minutes.
+pub fn outbox_backoff(attempts: u32) -> Duration {
+    let secs = BASE_BACKOFF_SECS
+        .saturating_mul(1i64 << attempts.min(20))
+        .min(MAX_BACKOFF_SECS);
+    Duration::seconds(secs)
+}
+
+/// Queues a frame for `destination`. Frames with an `expires_at` are dropped
+/// unsent once it passes, however many attempts they have left.
+pub async fn enqueue_outbox(
+    pool: &Pool<Sqlite>,
+    destination: &str,
+    message_id: Option<&str>,
+    awaits_ack: bool,
+    payload: &[u8],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<i64, sqlx::Error> {
+    let now = Utc::now();
+    let result = sqlx::query(
+        "INSERT INTO outbox (destination, message_id, awaits_ack, payload, attempts, next_attempt_at, expires_at, created_at) VALUES (?, ?, ?, ?, 0, ?, ?, ?)",
+    )
+    .bind(destination)
+    .bind(message_id)
+    .bind(awaits_ack)
+    .bind(seal_bytes(payload))
+    .bind(now.timestamp_millis())
+    .bind(expires_at.map(|at| at.timestamp_millis()))
+    .bind(now.to_rfc3339())
+    .execute(pool)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+pub async fn get_outbox_entry(
+    pool: &Pool<Sqlite>,
+    id: i64,
+) -> Result<Option<OutboxEntry>, sqlx::Error> {
+    sqlx::query_as::<_, OutboxRow>(
+        "SELECT id, destination, message_id, awaits_ack, payload, attempts, next_attempt_at FROM outbox WHERE id = ?",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .map(OutboxEntry::try_from)
+    .transpose()
+}
+
+/// Entries whose next attempt is due and that have not expired, oldest first
+/// within each destination.
+pub async fn due_outbox_entries(
+    pool: &Pool<Sqlite>,
+    now: DateTime<Utc>,
+    limit: i64,
+) -> Result<Vec<OutboxEntry>, sqlx::Error> {
+    sqlx::query_as::<_, OutboxRow>(
+        "SELECT id, destination, message_id, awaits_ack, payload, attempts, next_attempt_at FROM outbox WHERE next_attempt_at <= ? AND (expires_at IS NULL OR expires_at > ?) ORDER BY destination, id LIMIT ?",
+    )
+    .bind(now.timestamp_millis())
+    .bind(now.timestamp_millis())
+    .bind(limit)
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(OutboxEntry::try_from)
+    .collect()
+}
+
+/// Counts an attempt and pushes the entry back by the backoff delay. Returns
+/// the new attempt count.
+pub async fn record_outbox_attempt(
+    pool: &Pool<Sqlite>,
+    entry: &OutboxEntry,
+    now: DateTime<Utc>,
+) -> Result<u32, sqlx::Error> {
+    let attempts = entry.attempts + 1;
+    let next_attempt_at = now + outbox_backoff(attempts);
+    sqlx::query("UPDATE outbox SET attempts = ?, next_attempt_at = ? WHERE id = ?")
+        .bind(attempts as i64)
+        .bind(next_attempt_at.timestamp_millis())
+        .bind(entry.id)
+        .execute(pool)
+        .await?;
+    Ok(attempts)
+}
+
+/// Moves the next attempt for an entry to `next_attempt_at`, e.g. to give a
+/// handed-off message time to be acknowledged before it is sent again.
+pub async fn postpone_outbox_entry(
+    pool: &Pool<Sqlite>,
+    id: i64,
+    next_attempt_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE outbox SET next_attempt_at = MAX(next_attempt_at, ?) WHERE id = ?")
+        .bind(next_attempt_at.timestamp_millis())
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Makes every entry for `destination` due now, e.g. when the peer connects.
+pub async fn expedite_outbox_destination(
+    pool: &Pool<Sqlite>,
+    destination: &str,
+) -> Result<u64, sqlx::Error> {
+    let now = Utc::now().timestamp_millis();
+    let result = sqlx::query(
+        "UPDATE outbox SET next_attempt_at = ? WHERE destination = ? AND next_attempt_at > ?",
+    )
+    .bind(now)
+    .bind(destination)
+    .bind(now)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn remove_outbox_entry(pool: &Pool<Sqlite>, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM outbox WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Drops the entries that expired before they could be sent.
+pub async fn remove_expired_outbox_entries(
+    pool: &Pool<Sqlite>,
+    now: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM outbox WHERE expires_at <= ?")
+        .bind(now.timestamp_millis())
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Drops the entries carrying `message_id` to `destination` once it has been
+/// acknowledged.
+pub async fn remove_acknowledged_outbox_entries(
+    pool: &Pool<Sqlite>,
+    destination: &str,
+    message_id: &str,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM outbox WHERE destination = ? AND message_id = ?")
+        .bind(destination)
+        .bind(message_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Progress of one of our messages towards a recipient. Ordered so that a
+/// later state never regresses to an earlier one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    Failed,
+    Pending,
+    Sent,
+    Delivered,
+    Read,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Read => "read",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "failed" => Some(DeliveryStatus::Failed),
+            "pending" => Some(DeliveryStatus::Pending),
+            "sent" => Some(DeliveryStatus::Sent),
+            "delivered" => Some(DeliveryStatus::Delivered),
+            "read" => Some(DeliveryStatus::Read),
+            _ => None,
+        }
+    }
+
+    fn replaces(&self, current: DeliveryStatus) -> bool {
+        match self {
+            // Giving up only matters while nothing has reached the recipient.
+            DeliveryStatus::Failed => current < DeliveryStatus::Delivered,
+            _ => *self > current,
+        }
+    }
+}
+
+/// Records `status` for `message_id` towards `recipient_id` unless it has
+/// already progressed further. Returns whether the stored status changed.
+pub async fn record_delivery_status(
+    pool: &Pool<Sqlite>,
+    message_id: &str,
+    recipient_id: &str,
+    status: DeliveryStatus,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let current: Option<String> = sqlx::query_scalar(
+        "SELECT status FROM message_deliveries WHERE message_id = ? AND recipient_id = ?",
+    )
+    .bind(message_id)
+    .bind(recipient_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if let Some(current) = current.as_deref().and_then(DeliveryStatus::parse) {
+        if !status.replaces(current) {
+            return Ok(false);
+        }
+    }
+
+    sqlx::query(
+        "INSERT INTO message_deliveries (message_id, recipient_id, status, updated_at) VALUES (?, ?, ?, ?)
+         ON CONFLICT(message_id, recipient_id) DO UPDATE SET status = excluded.status, updated_at = excluded.updated_at",
+    )
+    .bind(message_id)
+    .bind(recipient_id)
+    .bind(status.as_str())
+    .bind(Utc::now().to_rfc3339())
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(true)
+}
@@ -0,0 +1,112 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Sqlite};
+
+use super::at_rest::{open_bytes, seal_bytes};
+
+/// How long an operation waits for its message before it is dropped.
+pub const PENDING_OPERATION_TTL: Duration = Duration::days(7);
+
+/// Limits on the operations held for messages that have not arrived, so
+/// peers cannot fill the store with operations on messages that never will.
+#[derive(Debug, Clone)]
+pub struct PendingQuota {
+    pub max_operations: u32,
+    pub max_bytes: u64,
+    pub max_author_operations: u32,
+    pub max_author_bytes: u64,
+}
+
+impl Default for PendingQuota {
+    fn default() -> Self {
+        Self {
+            max_operations: 5_000,
+            max_bytes: 32 * 1024 * 1024,
+            max_author_operations: 500,
+            max_author_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingBuffer {
+    Held,
+    /// The buffer has no room for the operation.
+    QuotaExceeded,
+    /// The operation's author already holds its share of the buffer.
+    AuthorQuotaExceeded,
+}
+
+/// Holds an edit, deletion, reaction or new thread by `author` whose message
+/// has not arrived yet. `operation` is the encoded message, already
+/// verified. Operations older than [`PENDING_OPERATION_TTL`] are dropped on
+/// the way.
+pub async fn buffer_message_operation(
+    pool: &Pool<Sqlite>,
+    message_id: &str,
+    author: &str,
+    operation: &[u8],
+    quota: &PendingQuota,
+    now: DateTime<Utc>,
+) -> Result<PendingBuffer, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM pending_message_operations WHERE received_at <= ?")
+        .bind((now - PENDING_OPERATION_TTL).timestamp_millis())
+        .execute(&mut *tx)
+        .await?;
+
+    let size = operation.len() as u64;
+    let (count, bytes): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM pending_message_operations",
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if count as u64 >= quota.max_operations as u64 || bytes as u64 + size > quota.max_bytes {
+        return Ok(PendingBuffer::QuotaExceeded);
+    }
+
+    let (count, bytes): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM pending_message_operations WHERE author = ?",
+    )
+    .bind(author)
+    .fetch_one(&mut *tx)
+    .await?;
+    if count as u64 >= quota.max_author_operations as u64
+        || bytes as u64 + size > quota.max_author_bytes
+    {
+        return Ok(PendingBuffer::AuthorQuotaExceeded);
+    }
+
+    sqlx::query(
+        "INSERT INTO pending_message_operations (message_id, author, operation, size, received_at) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(message_id)
+    .bind(author)
+    .bind(seal_bytes(operation))
+    .bind(size as i64)
+    .bind(now.timestamp_millis())
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(PendingBuffer::Held)
+}
+
+/// Removes and returns the operations waiting for `message_id`, in the order
+/// they arrived.
+pub async fn take_message_operations(
+    pool: &Pool<Sqlite>,
+    message_id: &str,
+) -> Result<Vec<Vec<u8>>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let operations: Vec<Vec<u8>> = sqlx::query_scalar(
+        "SELECT operation FROM pending_message_operations WHERE message_id = ? ORDER BY id",
+    )
+    .bind(message_id)
+    .fetch_all(&mut *tx)
+    .await?;
+    sqlx::query("DELETE FROM pending_message_operations WHERE message_id = ?")
+        .bind(message_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    operations.into_iter().map(open_bytes).collect()
+}
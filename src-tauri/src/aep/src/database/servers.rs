@@ -926,7 +926,7 @@ pub async fn create_server_invite(
     expires_at: Option<DateTime<Utc>>,
     max_uses: Option<i64>,
 ) -> Result<ServerInvite, sqlx::Error> {
-    let invite_id = Scu128::new().to_string();
+    let invite_id = Scu128::new().generate();
     let created_at_str = created_at.to_rfc3339();
     let expires_at_str = expires_at.map(|dt| dt.to_rfc3339());
 
@@ -0,0 +1,330 @@
+use aegis_protocol::hlc::Hlc;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, QueryBuilder, Sqlite, SqliteConnection};
+
+use super::at_rest::{open_text, seal_text};
+
+/// How long a thread goes without replies before it is archived, going by
+/// the clock stamps of its replies. A new reply brings it back.
+pub const THREAD_ARCHIVE_AFTER: Duration = Duration::days(7);
+
+/// A thread, keyed by the message it was opened on. Replies to the root, and
+/// replies to those, belong to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageThread {
+    pub root_message_id: String,
+    pub chat_id: String,
+    pub creator_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub reply_count: i64,
+    /// Time of the latest reply's clock stamp, or of the thread's creation
+    /// before any.
+    pub last_activity_at: DateTime<Utc>,
+    pub archived: bool,
+}
+
+#[derive(FromRow)]
+struct ThreadRow {
+    root_message_id: String,
+    chat_id: String,
+    creator_id: String,
+    title: Option<String>,
+    created_at: String,
+    reply_count: i64,
+    last_activity_hlc: String,
+    archived: bool,
+}
+
+impl ThreadRow {
+    fn into_thread(self) -> Result<MessageThread, sqlx::Error> {
+        Ok(MessageThread {
+            root_message_id: self.root_message_id,
+            chat_id: self.chat_id,
+            creator_id: self.creator_id,
+            title: self.title.map(open_text).transpose()?,
+            created_at: parse_timestamp(&self.created_at)?,
+            reply_count: self.reply_count,
+            last_activity_at: Hlc::parse(&self.last_activity_hlc)
+                .and_then(|hlc| hlc.wall_time())
+                .ok_or_else(|| sqlx::Error::Decode("Failed to parse thread activity stamp".into()))?,
+            archived: self.archived,
+        })
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, sqlx::Error> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|dt| dt.with_timezone(&Utc))
+        .map_err(|e| sqlx::Error::Decode(format!("Failed to parse timestamp: {}", e).into()))
+}
+
+fn into_threads(rows: Vec<ThreadRow>) -> Result<Vec<MessageThread>, sqlx::Error> {
+    rows.into_iter().map(ThreadRow::into_thread).collect()
+}
+
+/// Opens a thread on `root_message_id` and takes in the replies already
+/// stored. Returns `false` when the thread already exists, or when the root
+/// is itself a reply in another thread.
+pub async fn create_thread(
+    pool: &Pool<Sqlite>,
+    root_message_id: &str,
+    chat_id: &str,
+    creator_id: &str,
+    title: Option<&str>,
+    created_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let created_hlc = Hlc::from_claimed(created_at, Utc::now()).encode();
+    let created_at = created_at.to_rfc3339();
+    let mut tx = pool.begin().await?;
+    let created = sqlx::query(
+        "INSERT INTO message_threads (root_message_id, chat_id, creator_id, title, created_at, last_activity_hlc) \
+         SELECT ?, ?, ?, ?, ?, ? \
+         WHERE NOT EXISTS (SELECT 1 FROM messages WHERE id = ? AND thread_id IS NOT NULL) \
+         ON CONFLICT(root_message_id) DO NOTHING",
+    )
+    .bind(root_message_id)
+    .bind(chat_id)
+    .bind(creator_id)
+    .bind(title.map(seal_text))
+    .bind(&created_at)
+    .bind(&created_hlc)
+    .bind(root_message_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+    if created {
+        link_thread(&mut tx, root_message_id).await?;
+    }
+    tx.commit().await?;
+    Ok(created)
+}
+
+/// Adds a newly stored reply to `reply_to`'s thread, if it has one, along
+/// with any replies to it that arrived first.
+pub(crate) async fn attach_to_thread(
+    conn: &mut SqliteConnection,
+    reply_to: &str,
+) -> Result<(), sqlx::Error> {
+    let root: Option<String> = sqlx::query_scalar(
+        "SELECT root_message_id FROM message_threads WHERE root_message_id = ? \
+         UNION ALL SELECT thread_id FROM messages WHERE id = ? AND thread_id IS NOT NULL \
+         LIMIT 1",
+    )
+    .bind(reply_to)
+    .bind(reply_to)
+    .fetch_optional(&mut *conn)
+    .await?;
+    if let Some(root) = root {
+        link_thread(conn, &root).await?;
+    }
+    Ok(())
+}
+
+/// Takes a deleted message out of its thread. A deleted root takes its
+/// thread with it; its replies stay as plain replies.
+pub(crate) async fn detach_from_thread(
+    conn: &mut SqliteConnection,
+    message_id: &str,
+    thread_id: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM message_threads WHERE root_message_id = ?")
+        .bind(message_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("DELETE FROM thread_followers WHERE root_message_id = ?")
+        .bind(message_id)
+        .execute(&mut *conn)
+        .await?;
+    sqlx::query("UPDATE messages SET thread_id = NULL WHERE thread_id = ?")
+        .bind(message_id)
+        .execute(&mut *conn)
+        .await?;
+    if let Some(thread_id) = thread_id {
+        refresh_thread(conn, thread_id).await?;
+    }
+    Ok(())
+}
+
+/// Puts every stored reply below `root` in its thread. Roots of other
+/// threads, and the replies below them, are left where they are.
+async fn link_thread(conn: &mut SqliteConnection, root: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "WITH RECURSIVE replies(id) AS ( \
+             SELECT id FROM messages WHERE reply_to_message_id = ? \
+             UNION SELECT m.id FROM messages m INNER JOIN replies r ON m.reply_to_message_id = r.id \
+             WHERE r.id NOT IN (SELECT root_message_id FROM message_threads) \
+         ) \
+         UPDATE messages SET thread_id = ? \
+         WHERE id IN (SELECT id FROM replies WHERE id NOT IN (SELECT root_message_id FROM message_threads))",
+    )
+    .bind(root)
+    .bind(root)
+    .execute(&mut *conn)
+    .await?;
+    refresh_thread(conn, root).await
+}
+
+/// Recounts a thread's replies and moves its last activity up to the latest
+/// reply's clock stamp. A reply stamped after its last activity takes it out
+/// of the archive.
+pub(crate) async fn refresh_thread(
+    conn: &mut SqliteConnection,
+    root: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "WITH stats AS (SELECT COUNT(*) AS replies, MAX(hlc) AS latest FROM messages WHERE thread_id = ?) \
+         UPDATE message_threads SET \
+             reply_count = (SELECT replies FROM stats), \
+             last_activity_hlc = MAX(last_activity_hlc, COALESCE((SELECT latest FROM stats), '')), \
+             archived = CASE WHEN (SELECT latest FROM stats) > last_activity_hlc THEN 0 ELSE archived END \
+         WHERE root_message_id = ?",
+    )
+    .bind(root)
+    .bind(root)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_thread(
+    pool: &Pool<Sqlite>,
+    root_message_id: &str,
+) -> Result<Option<MessageThread>, sqlx::Error> {
+    sqlx::query_as::<_, ThreadRow>(
+        "SELECT root_message_id, chat_id, creator_id, title, created_at, reply_count, last_activity_hlc, archived FROM message_threads WHERE root_message_id = ?",
+    )
+    .bind(root_message_id)
+    .fetch_optional(pool)
+    .await?
+    .map(ThreadRow::into_thread)
+    .transpose()
+}
+
+/// Threads of `chat_id`, most recently active first.
+pub async fn get_threads_for_chat(
+    pool: &Pool<Sqlite>,
+    chat_id: &str,
+    include_archived: bool,
+) -> Result<Vec<MessageThread>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, ThreadRow>(
+        "SELECT root_message_id, chat_id, creator_id, title, created_at, reply_count, last_activity_hlc, archived FROM message_threads WHERE chat_id = ? AND (? OR archived = 0) ORDER BY last_activity_hlc DESC",
+    )
+    .bind(chat_id)
+    .bind(include_archived)
+    .fetch_all(pool)
+    .await?;
+    into_threads(rows)
+}
+
+/// Threads `user_id` follows, most recently active first.
+pub async fn get_followed_threads(
+    pool: &Pool<Sqlite>,
+    user_id: &str,
+) -> Result<Vec<MessageThread>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, ThreadRow>(
+        "SELECT t.root_message_id, t.chat_id, t.creator_id, t.title, t.created_at, t.reply_count, t.last_activity_hlc, t.archived \
+         FROM message_threads t INNER JOIN thread_followers f ON f.root_message_id = t.root_message_id \
+         WHERE f.user_id = ? ORDER BY t.last_activity_hlc DESC",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+    into_threads(rows)
+}
+
+/// Threads opened on any of `root_message_ids`.
+pub(crate) async fn get_threads_by_root(
+    pool: &Pool<Sqlite>,
+    root_message_ids: &[String],
+) -> Result<Vec<MessageThread>, sqlx::Error> {
+    if root_message_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut query_builder = QueryBuilder::<Sqlite>::new(
+        "SELECT root_message_id, chat_id, creator_id, title, created_at, reply_count, last_activity_hlc, archived FROM message_threads WHERE root_message_id IN (",
+    );
+    {
+        let mut separated = query_builder.separated(", ");
+        for root in root_message_ids {
+            separated.push_bind(root);
+        }
+    }
+    query_builder.push(")");
+    let rows = query_builder.build_query_as::<ThreadRow>().fetch_all(pool).await?;
+    into_threads(rows)
+}
+
+pub async fn follow_thread(
+    pool: &Pool<Sqlite>,
+    root_message_id: &str,
+    user_id: &str,
+    followed_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO thread_followers (root_message_id, user_id, followed_at) VALUES (?, ?, ?) ON CONFLICT(root_message_id, user_id) DO NOTHING",
+    )
+    .bind(root_message_id)
+    .bind(user_id)
+    .bind(followed_at.to_rfc3339())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn unfollow_thread(
+    pool: &Pool<Sqlite>,
+    root_message_id: &str,
+    user_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM thread_followers WHERE root_message_id = ? AND user_id = ?")
+        .bind(root_message_id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn is_following_thread(
+    pool: &Pool<Sqlite>,
+    root_message_id: &str,
+    user_id: &str,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM thread_followers WHERE root_message_id = ? AND user_id = ?)",
+    )
+    .bind(root_message_id)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await
+}
+
+/// Root of the thread `message_id` is a reply in.
+pub async fn thread_of_message(
+    pool: &Pool<Sqlite>,
+    message_id: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    let thread_id: Option<Option<String>> =
+        sqlx::query_scalar("SELECT thread_id FROM messages WHERE id = ?")
+            .bind(message_id)
+            .fetch_optional(pool)
+            .await?;
+    Ok(thread_id.flatten())
+}
+
+/// Archives the threads without replies for [`THREAD_ARCHIVE_AFTER`] and
+/// returns their roots.
+pub async fn archive_inactive_threads(
+    pool: &Pool<Sqlite>,
+    now: DateTime<Utc>,
+) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(
+        "UPDATE message_threads SET archived = 1 WHERE archived = 0 AND last_activity_hlc < ? RETURNING root_message_id",
+    )
+    .bind(Hlc::from_timestamp(now - THREAD_ARCHIVE_AFTER).encode())
+    .fetch_all(pool)
+    .await
+}
@@ -1,12 +1,13 @@
 use crate::database::{self, messages::AttachmentWithData};
 use crate::rkyv_utils::serialize;
-use crate::utils::verify_signature;
+use crate::utils::{validate_id, verify_signature};
 use aegis_protocol::{
-    AepMessage, ChatMessageData, DeleteMessageData, MessageDeletionScope, MessageEditData,
-    MessageReactionData, ReactionAction,
+    AepMessage, ChatMessageData, CreateThreadData, DeleteMessageData, MessageDeletionScope,
+    MessageEditData, MessageReactionData, ReactionAction,
 };
 use aegis_shared_types::AppState;
 use aegis_types::AegisError;
+use chrono::Utc;
 use sqlx::{Pool, Sqlite};
 
 pub async fn handle_chat_message_wrapper(
@@ -44,9 +45,18 @@ pub async fn handle_chat_message_wrapper(
                 reply_snapshot_author: reply_snapshot_author.clone(),
                 reply_snapshot_snippet: reply_snapshot_snippet.clone(),
             };
+            validate_id("message", &id)?;
+            if let Some(reply_to) = &reply_to_message_id {
+                validate_id("message", reply_to)?;
+            }
             let bytes = serialize(&data)?;
             verify_signature(db_pool, &sender, &bytes, signature.as_ref()).await?;
 
+            // Delivered again, by the mesh or by history sync.
+            if database::get_message_metadata(db_pool, &id).await?.is_some() {
+                return Ok(());
+            }
+
             println!(
                 "Received chat message from {}: {} (Channel: {:?}, Server: {:?})",
                 sender, content, channel_id, server_id
@@ -106,10 +116,62 @@ pub async fn handle_chat_message_wrapper(
                 edited_at: None,
                 edited_by: None,
                 expires_at,
+                delivery_status: None,
+                thread_id: None,
+                thread: None,
             };
 
             database::insert_message(db_pool, &new_message, &attachment_data).await?;
+            apply_pending_operations(db_pool, &state, &new_message.id).await?;
+        }
+        AepMessage::MessageReaction { .. }
+        | AepMessage::DeleteMessage { .. }
+        | AepMessage::EditMessage { .. }
+        | AepMessage::CreateThread { .. } => {
+            let Some((target, author)) = operation_target(&message) else {
+                return Ok(());
+            };
+            validate_id("message", target)?;
+            verify_operation(db_pool, &message).await?;
+            if database::get_message_metadata(db_pool, target).await?.is_none() {
+                // Its message has not reached us yet; applied once it does.
+                let buffered = database::buffer_message_operation(
+                    db_pool,
+                    target,
+                    author,
+                    &serialize(&message)?,
+                    &database::PendingQuota::default(),
+                    Utc::now(),
+                )
+                .await?;
+                if buffered != database::PendingBuffer::Held {
+                    eprintln!("Dropped operation by {} on {}: {:?}", author, target, buffered);
+                }
+                return Ok(());
+            }
+            apply_operation(message, db_pool, &state).await?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Message an edit, deletion, reaction or new thread applies to, and who
+/// signed it.
+fn operation_target(message: &AepMessage) -> Option<(&str, &str)> {
+    match message {
+        AepMessage::MessageReaction { message_id, user_id, .. } => Some((message_id, user_id)),
+        AepMessage::DeleteMessage { message_id, initiator_id, .. } => Some((message_id, initiator_id)),
+        AepMessage::EditMessage { message_id, editor_id, .. } => Some((message_id, editor_id)),
+        AepMessage::CreateThread { root_message_id, creator_id, .. } => {
+            Some((root_message_id, creator_id))
         }
+        _ => None,
+    }
+}
+
+async fn verify_operation(db_pool: &Pool<Sqlite>, message: &AepMessage) -> Result<(), AegisError> {
+    match message {
         AepMessage::MessageReaction {
             message_id,
             chat_id,
@@ -126,23 +188,7 @@ pub async fn handle_chat_message_wrapper(
                 action: action.clone(),
             };
             let bytes = serialize(&data)?;
-            verify_signature(db_pool, &user_id, &bytes, signature.as_ref()).await?;
-
-            match action {
-                ReactionAction::Add => {
-                    database::add_reaction_to_message(db_pool, &message_id, &user_id, &emoji)
-                        .await?;
-                }
-                ReactionAction::Remove => {
-                    database::remove_reaction_from_message(
-                        db_pool,
-                        &message_id,
-                        &user_id,
-                        &emoji,
-                    )
-                    .await?;
-                }
-            }
+            verify_signature(db_pool, user_id, &bytes, signature.as_ref()).await
         }
         AepMessage::DeleteMessage {
             message_id,
@@ -158,8 +204,114 @@ pub async fn handle_chat_message_wrapper(
                 scope: scope.clone(),
             };
             let bytes = serialize(&data)?;
-            verify_signature(db_pool, &initiator_id, &bytes, signature.as_ref()).await?;
+            verify_signature(db_pool, initiator_id, &bytes, signature.as_ref()).await
+        }
+        AepMessage::EditMessage {
+            message_id,
+            chat_id,
+            editor_id,
+            new_content,
+            edited_at,
+            signature,
+        } => {
+            let data = MessageEditData {
+                message_id: message_id.clone(),
+                chat_id: chat_id.clone(),
+                editor_id: editor_id.clone(),
+                new_content: new_content.clone(),
+                edited_at: *edited_at,
+            };
+            let bytes = serialize(&data)?;
+            verify_signature(db_pool, editor_id, &bytes, signature.as_ref()).await
+        }
+        AepMessage::CreateThread {
+            root_message_id,
+            chat_id,
+            creator_id,
+            title,
+            created_at,
+            signature,
+        } => {
+            let data = CreateThreadData {
+                root_message_id: root_message_id.clone(),
+                chat_id: chat_id.clone(),
+                creator_id: creator_id.clone(),
+                title: title.clone(),
+                created_at: *created_at,
+            };
+            let bytes = serialize(&data)?;
+            verify_signature(db_pool, creator_id, &bytes, signature.as_ref()).await
+        }
+        _ => Ok(()),
+    }
+}
 
+/// Applies operations that arrived before `message_id` itself.
+async fn apply_pending_operations(
+    db_pool: &Pool<Sqlite>,
+    state: &AppState,
+    message_id: &str,
+) -> Result<(), AegisError> {
+    for bytes in database::take_message_operations(db_pool, message_id).await? {
+        let operation: AepMessage = match bincode::deserialize(&bytes) {
+            Ok(operation) => operation,
+            Err(e) => {
+                eprintln!("Dropped buffered operation for {}: {}", message_id, e);
+                continue;
+            }
+        };
+        if let Err(e) = apply_operation(operation, db_pool, state).await {
+            eprintln!("Failed to apply buffered operation for {}: {}", message_id, e);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `creator_id` may open a thread on `root`: a member of its server
+/// or group chat, or in a direct chat one of the two peers in it.
+async fn may_open_thread(
+    db_pool: &Pool<Sqlite>,
+    state: &AppState,
+    root: &database::MessageMetadata,
+    creator_id: &str,
+) -> Result<bool, AegisError> {
+    if database::is_shared_chat(db_pool, &root.chat_id).await? {
+        return Ok(database::may_access_chat(db_pool, &root.chat_id, creator_id).await?);
+    }
+    let local_id = state.identity.peer_id().to_base58();
+    Ok([local_id.as_str(), root.chat_id.as_str(), root.sender_id.as_str()].contains(&creator_id))
+}
+
+/// Applies a verified edit, deletion, reaction or new thread to its stored
+/// message.
+async fn apply_operation(
+    message: AepMessage,
+    db_pool: &Pool<Sqlite>,
+    state: &AppState,
+) -> Result<(), AegisError> {
+    match message {
+        AepMessage::MessageReaction {
+            message_id,
+            emoji,
+            user_id,
+            action,
+            ..
+        } => match action {
+            ReactionAction::Add => {
+                database::add_reaction_to_message(db_pool, &message_id, &user_id, &emoji).await?;
+            }
+            ReactionAction::Remove => {
+                database::remove_reaction_from_message(db_pool, &message_id, &user_id, &emoji)
+                    .await?;
+            }
+        },
+        AepMessage::DeleteMessage {
+            message_id,
+            chat_id,
+            initiator_id,
+            scope,
+            ..
+        } => {
             let my_id = state.identity.peer_id().to_base58();
             let should_apply = match &scope {
                 MessageDeletionScope::Everyone => true,
@@ -207,18 +359,8 @@ pub async fn handle_chat_message_wrapper(
             editor_id,
             new_content,
             edited_at,
-            signature,
+            ..
         } => {
-            let data = MessageEditData {
-                message_id: message_id.clone(),
-                chat_id: chat_id.clone(),
-                editor_id: editor_id.clone(),
-                new_content: new_content.clone(),
-                edited_at,
-            };
-            let bytes = serialize(&data)?;
-            verify_signature(db_pool, &editor_id, &bytes, signature.as_ref()).await?;
-
             if let Some(metadata) =
                 database::get_message_metadata(db_pool, &message_id).await?
             {
@@ -242,6 +384,12 @@ pub async fn handle_chat_message_wrapper(
                 }
             }
 
+            // A late edit, such as one backfilled by history sync, must not
+            // undo a newer one.
+            if database::message_edited_since(db_pool, &message_id, edited_at).await? {
+                return Ok(());
+            }
+
             database::update_message_content(
                 db_pool,
                 &message_id,
@@ -251,7 +399,49 @@ pub async fn handle_chat_message_wrapper(
             )
             .await?;
         }
+        AepMessage::CreateThread {
+            root_message_id,
+            chat_id,
+            creator_id,
+            title,
+            created_at,
+            ..
+        } => {
+            let Some(metadata) =
+                database::get_message_metadata(db_pool, &root_message_id).await?
+            else {
+                return Ok(());
+            };
+            if metadata.chat_id != chat_id {
+                eprintln!(
+                    "CreateThread chat mismatch: expected {}, received {}",
+                    metadata.chat_id, chat_id
+                );
+                return Err(AegisError::InvalidInput(
+                    "CreateThread chat mismatch.".into(),
+                ));
+            }
+            if !may_open_thread(db_pool, state, &metadata, &creator_id).await? {
+                eprintln!(
+                    "CreateThread refused: {} may not access {}",
+                    creator_id, chat_id
+                );
+                return Err(AegisError::InvalidInput(
+                    "CreateThread creator may not access the chat.".into(),
+                ));
+            }
+
+            database::create_thread(
+                db_pool,
+                &root_message_id,
+                &chat_id,
+                &creator_id,
+                title.as_deref(),
+                created_at,
+            )
+            .await?;
+        }
         _ => {}
     }
     Ok(())
-}
\ No newline at end of file
+}
@@ -25,7 +25,7 @@ pub async fn handle_friend_request(
     println!("Received friend request from {} to {}", sender_id, target_id);
     let now = chrono::Utc::now();
     let friendship = Friendship {
-        id: Scu128::new().to_string(),
+        id: Scu128::new().generate(),
         user_a_id: sender_id,
         user_b_id: target_id,
         status: FriendshipStatus::Pending.to_string(),
@@ -94,7 +94,7 @@ pub async fn handle_block_user(
         database::update_friendship_status(db_pool, &friendship.id, new_status).await?;
     } else {
         let friendship = Friendship {
-            id: Scu128::new().to_string(),
+            id: Scu128::new().generate(),
             user_a_id: blocker_id,
             user_b_id: blocked_id,
             status: FriendshipStatus::BlockedByA.to_string(),
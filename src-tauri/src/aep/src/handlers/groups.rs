@@ -1,6 +1,6 @@
 use crate::database;
 use crate::rkyv_utils::serialize;
-use crate::utils::verify_signature;
+use crate::utils::{validate_id, verify_signature};
 use aegis_protocol::{
     AddGroupChatMembersData, AepMessage, CreateGroupChatData, LeaveGroupChatData,
     RemoveGroupChatMemberData, RenameGroupChatData,
@@ -30,6 +30,7 @@ pub async fn handle_group_message_wrapper(
                 member_ids: member_ids.clone(),
                 created_at: created_at.clone(),
             };
+            validate_id("group chat", &group_id)?;
             let bytes = serialize(&data)?;
             verify_signature(db_pool, &creator_id, &bytes, signature.as_ref()).await?;
 
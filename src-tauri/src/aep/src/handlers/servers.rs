@@ -25,7 +25,7 @@ pub async fn handle_server_message_wrapper(
             database::insert_server(db_pool, &server).await?;
             database::add_server_member(db_pool, &server.id, &server.owner_id).await?;
             let default_channel = database::Channel {
-                id: Scu128::new().to_string(),
+                id: Scu128::new().generate(),
                 server_id: server.id.clone(),
                 name: "general".to_string(),
                 channel_type: "text".to_string(),
@@ -80,6 +80,7 @@ pub async fn handle_aep_message(
         | AepMessage::MessageReaction { .. }
         | AepMessage::DeleteMessage { .. }
         | AepMessage::EditMessage { .. }
+        | AepMessage::CreateThread { .. }
         | AepMessage::ReadReceipt { .. }
         | AepMessage::TypingIndicator { .. } => {
             chat::handle_chat_message_wrapper(message, db_pool, state).await
@@ -160,8 +161,11 @@ pub async fn handle_aep_message(
         AepMessage::CollaborationUpdate { .. } => Ok(()),
 
         AepMessage::EncryptedChatMessage { .. }
+        | AepMessage::SealedChatMessage { .. }
+        | AepMessage::SealedDeliveryAck { .. }
         | AepMessage::PrekeyBundle { .. }
         | AepMessage::GroupKeyUpdate { .. }
-        | AepMessage::EncryptedGroupMessage { .. } => Ok(()),
+        | AepMessage::EncryptedGroupMessage { .. }
+        | AepMessage::DeliveryAck { .. } => Ok(()),
     }
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 use crate::{user_service, AegisError};
 use bs58;
 use libp2p::identity::PublicKey;
+use scu128::Scu128Id;
 use sqlx::{Pool, Sqlite};
 use std::path::Path;
 
@@ -79,4 +80,11 @@ pub async fn verify_signature(
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Rejects `id` unless it is an id made by [`scu128::Scu128`]. `kind` names
+/// what the id is for in the error.
+pub fn validate_id(kind: &str, id: &str) -> Result<(), AegisError> {
+    Scu128Id::validate(id)
+        .map_err(|e| AegisError::InvalidInput(format!("Invalid {} id {}: {}", kind, id, e)))
+}
@@ -1,4 +1,3 @@
-pub(crate) const MAX_OUTBOX_MESSAGES: usize = 256;
 pub(crate) const MAX_FILE_SIZE_BYTES: u64 = 1_073_741_824; // 1 GiB
 pub(crate) const MAX_INFLIGHT_FILE_BYTES: u64 = 536_870_912; // 512 MiB
 pub(crate) const MAX_UNAPPROVED_BUFFER_BYTES: u64 = 8_388_608; // 8 MiB
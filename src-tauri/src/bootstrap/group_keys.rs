@@ -3,19 +3,29 @@ use tokio::sync::mpsc::Sender as TokioSender;
 use aegis_protocol::EncryptedDmSlot;
 use crypto::identity::Identity;
 
-pub(super) async fn broadcast_group_key_update(
+/// Epoch for a fresh rotation: wall-clock seconds, but always past `current`
+/// so back-to-back rotations still move forward.
+pub(crate) fn next_group_epoch(current: Option<u64>) -> u64 {
+    let now = chrono::Utc::now().timestamp().max(0) as u64;
+    match current {
+        Some(current) => now.max(current + 1),
+        None => now,
+    }
+}
+
+/// Hands our sender key for the group to every current member over their
+/// Olm sessions. `recipients` narrows the member list, e.g. to the set the
+/// owner distributed its own key to after removing someone.
+async fn distribute_sender_key(
     db_pool: &sqlx::Pool<sqlx::Sqlite>,
     identity: Identity,
     net_tx: &TokioSender<Vec<u8>>,
     server_id: &str,
     channel_id: &Option<String>,
+    epoch: u64,
+    key_bytes: &[u8],
+    recipients: Option<&[String]>,
 ) -> Result<(), String> {
-    let (epoch, key_bytes) = {
-        let arc = e2ee::init_global_manager();
-        let mgr = arc.lock().await;
-        mgr.get_group_key(server_id, channel_id)
-            .ok_or_else(|| "Missing group key for broadcast".to_string())?
-    };
     let issuer_id = identity.peer_id().to_base58();
 
     let members = aep::database::get_server_members(db_pool, server_id)
@@ -26,9 +36,12 @@ pub(super) async fn broadcast_group_key_update(
         if m.id == issuer_id {
             continue;
         }
+        if recipients.is_some_and(|allowed| !allowed.contains(&m.id)) {
+            continue;
+        }
         let arc = e2ee::init_global_manager();
         let mut mgr = arc.lock().await;
-        if let Ok(pkt) = mgr.encrypt_for(&m.id, &key_bytes) {
+        if let Ok(pkt) = mgr.encrypt_for(&m.id, key_bytes) {
             slots.push(EncryptedDmSlot {
                 recipient: m.id,
                 init: pkt.init,
@@ -38,13 +51,15 @@ pub(super) async fn broadcast_group_key_update(
         }
     }
 
-    let payload = rkyv::to_bytes::<_, 1024>(&(issuer_id.clone(), server_id.to_string(), channel_id.clone(), epoch, slots.clone())).map(|v| v.to_vec()).map_err(|e| e.to_string())?;
+    let payload = bincode::serialize(&(&issuer_id, server_id, channel_id, epoch, &slots))
+        .map_err(|e| e.to_string())?;
     let signature = identity
         .keypair()
         .sign(&payload)
         .map_err(|e| e.to_string())?;
 
     let msg = aegis_protocol::AepMessage::GroupKeyUpdate {
+        issuer_id,
         server_id: server_id.to_string(),
         channel_id: channel_id.clone(),
         epoch,
@@ -55,54 +70,110 @@ pub(super) async fn broadcast_group_key_update(
     net_tx.send(bytes).await.map_err(|e| e.to_string())
 }
 
-pub(super) async fn rotate_and_broadcast_group_key(
+pub(super) async fn broadcast_group_key_update(
+    db_pool: &sqlx::Pool<sqlx::Sqlite>,
+    identity: Identity,
+    net_tx: &TokioSender<Vec<u8>>,
+    server_id: &str,
+    channel_id: &Option<String>,
+) -> Result<(), String> {
+    let (epoch, key_bytes) = {
+        let arc = e2ee::init_global_manager();
+        let mgr = arc.lock().await;
+        mgr.get_group_key(server_id, channel_id)
+            .ok_or_else(|| "Missing group key for broadcast".to_string())?
+    };
+    distribute_sender_key(
+        db_pool, identity, net_tx, server_id, channel_id, epoch, &key_bytes, None,
+    )
+    .await
+}
+
+pub(crate) async fn rotate_and_broadcast_group_key(
     db_pool: &sqlx::Pool<sqlx::Sqlite>,
     identity: Identity,
     net_tx: &TokioSender<Vec<u8>>,
     server_id: &str,
     channel_id: &Option<String>,
     epoch: u64,
+    recipients: Option<&[String]>,
 ) -> Result<(), String> {
     let key = {
         let arc = e2ee::init_global_manager();
         let mut mgr = arc.lock().await;
         mgr.generate_and_set_group_key(server_id, channel_id, epoch)
     };
-    let issuer_id = identity.peer_id().to_base58();
+    distribute_sender_key(
+        db_pool, identity, net_tx, server_id, channel_id, epoch, &key, recipients,
+    )
+    .await
+}
 
-    let members = aep::database::get_server_members(db_pool, server_id)
-        .await
-        .map_err(|e| e.to_string())?;
-    let mut slots: Vec<EncryptedDmSlot> = Vec::new();
-    for m in members {
-        if m.id == issuer_id {
-            continue;
-        }
+/// Makes sure we have a sender key for the group before sending to it,
+/// joining the newest epoch other members are using.
+pub(crate) async fn ensure_sender_key(
+    db_pool: &sqlx::Pool<sqlx::Sqlite>,
+    identity: Identity,
+    net_tx: &TokioSender<Vec<u8>>,
+    server_id: &str,
+    channel_id: &Option<String>,
+) -> Result<(), String> {
+    let epoch = {
         let arc = e2ee::init_global_manager();
-        let mut mgr = arc.lock().await;
-        if let Ok(pkt) = mgr.encrypt_for(&m.id, &key) {
-            slots.push(EncryptedDmSlot {
-                recipient: m.id,
-                init: pkt.init,
-                enc_header: pkt.enc_header,
-                enc_content: pkt.enc_content,
-            });
+        let mgr = arc.lock().await;
+        if mgr.outbound_group_epoch(server_id, channel_id).is_some() {
+            return Ok(());
         }
-    }
+        mgr.latest_group_epoch(server_id, channel_id)
+            .unwrap_or_else(|| next_group_epoch(None))
+    };
+    rotate_and_broadcast_group_key(db_pool, identity, net_tx, server_id, channel_id, epoch, None)
+        .await
+}
 
-    let payload = rkyv::to_bytes::<_, 1024>(&(issuer_id.clone(), server_id.to_string(), channel_id.clone(), epoch, slots.clone())).map(|v| v.to_vec()).map_err(|e| e.to_string())?;
-    let signature = identity
-        .keypair()
-        .sign(&payload)
+/// Starts a new epoch for every channel of the server, whether or not we
+/// have sent in it yet. Called after a member is removed so they cannot read
+/// anything sent from now on, and so the next send does not join an epoch
+/// they still hold.
+pub(crate) async fn rotate_server_group_keys(
+    db_pool: &sqlx::Pool<sqlx::Sqlite>,
+    identity: Identity,
+    net_tx: &TokioSender<Vec<u8>>,
+    server_id: &str,
+) -> Result<(), String> {
+    let channels = aep::database::get_channels_for_server(db_pool, server_id)
+        .await
         .map_err(|e| e.to_string())?;
-
-    let msg = aegis_protocol::AepMessage::GroupKeyUpdate {
-        server_id: server_id.to_string(),
-        channel_id: channel_id.clone(),
-        epoch,
-        slots,
-        signature: Some(signature),
+    let groups = {
+        let arc = e2ee::init_global_manager();
+        let mgr = arc.lock().await;
+        let mut channel_ids = mgr.outbound_groups_for_server(server_id);
+        for channel in channels {
+            let channel_id = Some(channel.id);
+            if !channel_ids.contains(&channel_id) {
+                channel_ids.push(channel_id);
+            }
+        }
+        channel_ids
+            .into_iter()
+            .map(|channel_id| {
+                let epoch = next_group_epoch(mgr.latest_group_epoch(server_id, &channel_id));
+                (channel_id, epoch)
+            })
+            .collect::<Vec<_>>()
     };
-    let bytes = bincode::serialize(&msg).map_err(|e| e.to_string())?;
-    net_tx.send(bytes).await.map_err(|e| e.to_string())
+
+    for (channel_id, epoch) in groups {
+        rotate_and_broadcast_group_key(
+            db_pool,
+            identity.clone(),
+            net_tx,
+            server_id,
+            &channel_id,
+            epoch,
+            None,
+        )
+        .await?;
+    }
+    Ok(())
 }
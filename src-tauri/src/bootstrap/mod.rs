@@ -2,8 +2,13 @@ mod constants;
 mod file_transfer;
 mod group_keys;
 mod setup;
+mod topics;
 
+pub(crate) use group_keys::{
+    ensure_sender_key, rotate_and_broadcast_group_key, rotate_server_group_keys,
+};
 pub(crate) use setup::initialize_app_state;
+pub(crate) use topics::request_topic_resync;
 
 use constants::*;
 use file_transfer::*;
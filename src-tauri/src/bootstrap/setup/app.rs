@@ -1,4 +1,3 @@
-use std::collections::VecDeque;
 use std::sync::Arc;
 
 use tauri::{AppHandle, Runtime, State};
@@ -14,7 +13,11 @@ use super::identity::initialize_identity_state;
 use super::network::initialize_network;
 use super::state::build_app_state;
 use super::swarm::spawn_swarm_processing;
-use super::tasks::{spawn_event_dispatcher, spawn_group_key_rotation};
+use super::tasks::{
+    spawn_deny_list_expiry, spawn_dht_refresh, spawn_event_dispatcher, spawn_group_key_rotation,
+    spawn_link_state_adverts, spawn_prekey_maintenance, spawn_relay_circuits,
+    spawn_thread_archiving, spawn_topic_subscriptions,
+};
 
 pub(crate) async fn initialize_app_state<R: Runtime>(
     app: AppHandle<R>,
@@ -25,17 +28,32 @@ pub(crate) async fn initialize_app_state<R: Runtime>(
 
     aep::initialize_aep();
 
-    let network = initialize_network(&identity).await?;
     let directories = AppDirectories::prepare(&app)?;
     let persisted_settings = directories.load_persisted_settings();
+    let network = initialize_network(
+        &identity,
+        persisted_settings.proxy.as_ref(),
+        &persisted_settings.denied_peers,
+    )
+    .await?;
+    if let Some(privacy) = persisted_settings.privacy.as_ref() {
+        crate::network::privacy::set_privacy_config(crate::connectivity::privacy_config(privacy));
+    }
     let initial_acl = persisted_settings.initial_file_acl();
 
     let db_pool = directories.initialize_database().await?;
+    directories
+        .unlock_message_store(&db_pool, password)
+        .await?;
+    match aep::database::load_clock(&db_pool).await {
+        Ok(Some(last)) => network.clock.lock().await.resume(last),
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to load clock: {}", e),
+    }
 
     let (net_tx, net_rx) = mpsc::channel::<Vec<u8>>(100);
     let (file_tx, file_rx) = mpsc::channel::<aegis_shared_types::FileTransferCommand>(16);
     let (event_tx, event_rx) = mpsc::channel::<AepMessage>(100);
-    let outbox: Arc<Mutex<VecDeque<Vec<u8>>>> = Arc::new(Mutex::new(VecDeque::new()));
     let connectivity_snapshot = Arc::new(Mutex::new(None));
 
     let app_state = build_app_state(
@@ -57,22 +75,37 @@ pub(crate) async fn initialize_app_state<R: Runtime>(
         app.clone(),
         network.shared_swarm.clone(),
         network.router.clone(),
+        network.deny_list.clone(),
         identity.peer_id(),
         connectivity_snapshot,
     );
 
-    initialize_identity_state(&identity, &db_pool, &directories, &app_state).await?;
+    initialize_identity_state(&identity, &db_pool, &directories, &app_state, password).await?;
 
     spawn_event_dispatcher(app.clone(), event_rx);
 
+    spawn_prekey_maintenance(identity.clone(), app_state.network_tx.clone());
+
     spawn_group_key_rotation(
         db_pool.clone(),
         identity.clone(),
         app_state.network_tx.clone(),
     );
 
+    spawn_thread_archiving(app.clone(), db_pool.clone());
+
+    spawn_topic_subscriptions(network.clone(), db_pool.clone(), identity.peer_id().to_base58());
+
+    spawn_link_state_adverts(network.clone(), identity.clone());
+
+    spawn_dht_refresh(network.clone());
+
+    spawn_deny_list_expiry(network.clone());
+
+    spawn_relay_circuits(network.clone(), app_state.relays.clone());
+
     spawn_swarm_processing(
-        app, network, app_state, db_pool, net_rx, file_rx, event_tx, outbox,
+        app, network, app_state, db_pool, net_rx, file_rx, event_tx,
     );
 
     Ok(())
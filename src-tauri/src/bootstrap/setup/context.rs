@@ -1,7 +1,5 @@
-use std::collections::VecDeque;
-use std::sync::Arc;
 use tauri::{AppHandle, Runtime};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc;
 use aegis_protocol::AepMessage;
 use aegis_shared_types::AppState;
 use super::network::NetworkResources;
@@ -13,5 +11,4 @@ pub struct AppContext<R: Runtime> {
     pub app_state: AppState,
     pub db_pool: sqlx::Pool<sqlx::Sqlite>,
     pub event_tx: mpsc::Sender<AepMessage>,
-    pub outbox: Arc<Mutex<VecDeque<Vec<u8>>>>,
 }
\ No newline at end of file
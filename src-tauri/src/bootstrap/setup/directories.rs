@@ -77,6 +77,28 @@ impl AppDirectories {
             .await
             .map_err(|e| format!("Failed to initialize database: {}", e))
     }
+
+    /// Installs the at-rest key for the message store and seals any rows left
+    /// over from before encryption was enabled.
+    pub async fn unlock_message_store(
+        &self,
+        db_pool: &sqlx::Pool<sqlx::Sqlite>,
+        password: &str,
+    ) -> Result<(), String> {
+        let key = crate::commands::identity::message_store_key(self.data_dir(), password)?;
+        aep::database::set_at_rest_key(Some(key));
+
+        let report = aep::database::encrypt_existing_rows(db_pool)
+            .await
+            .map_err(|e| format!("Failed to encrypt message store: {}", e))?;
+        if report.messages > 0 || report.attachments > 0 {
+            eprintln!(
+                "Encrypted {} messages and {} attachments at rest",
+                report.messages, report.attachments
+            );
+        }
+        Ok(())
+    }
 }
 
 pub(super) trait PersistedSettingsExt {
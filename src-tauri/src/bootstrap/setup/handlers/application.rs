@@ -1,25 +1,45 @@
 use tauri::{Emitter, Runtime};
 use libp2p::PeerId;
-use aegis_protocol::{AepMessage, ReadReceiptData, TypingIndicatorData, EncryptedDmSlot};
+use aegis_protocol::hlc::Hlc;
+use aegis_protocol::{sealed, wire, AepMessage, DeliveryAckData, ReadReceiptData, SignaturePolicy, TypingIndicatorData, EncryptedDmSlot};
+use crate::network::RoutedEnvelope;
 use super::super::context::AppContext;
+use super::super::identity::publish_prekey_bundle;
+use super::{history, outbox};
 use scu128::Scu128;
 use std::sync::Arc;
 
 pub async fn handle_message<R: Runtime>(
     ctx: &Arc<AppContext<R>>,
     message: AepMessage,
-    propagation_source: PeerId
+    _propagation_source: PeerId,
+    clock: Option<Hlc>,
 ) -> Result<(), anyhow::Error> {
-    
+    // Taken in before the message is handled, so anything we send in
+    // response is stamped after it.
+    let stamp = match clock {
+        Some(remote) => Some(ctx.network.clock.lock().await.observe(remote, chrono::Utc::now())),
+        None => None,
+    };
+
     match &message {
         AepMessage::PrekeyBundle { user_id, bundle, signature } => {
             process_prekey(ctx, user_id, bundle, signature).await;
         }
-        AepMessage::EncryptedChatMessage { sender, recipient, init, enc_header, enc_content, signature } => {
-            process_chat(ctx, sender, recipient, init, enc_header, enc_content, signature).await;
+        AepMessage::EncryptedChatMessage { message_id, sender, recipient, init, enc_header, enc_content, signature } => {
+            process_chat(ctx, message_id, sender, recipient, init, enc_header, enc_content, signature).await;
+        }
+        AepMessage::SealedChatMessage { message_id, ephemeral_key, recipient_hint, ciphertext } => {
+            process_sealed_chat(ctx, message_id, ephemeral_key, recipient_hint, ciphertext).await;
         }
-        AepMessage::GroupKeyUpdate { server_id, channel_id, epoch, slots, signature } => {
-            process_group_key(ctx, &propagation_source, server_id, channel_id, epoch, slots, signature).await;
+        AepMessage::SealedDeliveryAck { message_id, proof } => {
+            process_sealed_delivery_ack(ctx, message_id, proof).await;
+        }
+        AepMessage::DeliveryAck { message_id, sender_id, recipient_id, timestamp, signature } => {
+            process_delivery_ack(ctx, message_id, sender_id, recipient_id, timestamp, signature).await;
+        }
+        AepMessage::GroupKeyUpdate { issuer_id, server_id, channel_id, epoch, slots, signature } => {
+            process_group_key(ctx, issuer_id, server_id, channel_id, *epoch, slots, signature).await;
         }
         AepMessage::ReadReceipt { chat_id, message_id, reader_id, timestamp, signature } => {
             process_read_receipt(ctx, chat_id, message_id, reader_id, timestamp, signature).await;
@@ -39,46 +59,347 @@ pub async fn handle_message<R: Runtime>(
             }
         }
         _ => {
-            let _ = aep::handle_aep_message(message.clone(), &ctx.db_pool, ctx.app_state.clone()).await;
+            if aep::handle_aep_message(message.clone(), &ctx.db_pool, ctx.app_state.clone()).await.is_ok() {
+                history::record_message(ctx, &message).await;
+                if let AepMessage::ChatMessage { id, .. } = &message {
+                    notify_followed_thread(ctx, id).await;
+                }
+            }
+        }
+    }
+
+    if let (Some(stamp), Some(message_id)) = (stamp, message.chat_message_id()) {
+        if let Err(e) = aep::database::set_message_hlc(&ctx.db_pool, message_id, stamp).await {
+            eprintln!("Failed to stamp message {}: {}", message_id, e);
         }
     }
 
+    if matches!(
+        message,
+        AepMessage::CreateServer { .. }
+            | AepMessage::JoinServer { .. }
+            | AepMessage::DeleteServer { .. }
+            | AepMessage::SendServerInvite { .. }
+            | AepMessage::CreateGroupChat { .. }
+            | AepMessage::AddGroupChatMembers { .. }
+            | AepMessage::RemoveGroupChatMember { .. }
+            | AepMessage::LeaveGroupChat { .. }
+    ) {
+        crate::bootstrap::request_topic_resync();
+    }
+
     let _ = ctx.event_tx.send(message.clone()).await;
     let _ = ctx.app.emit("new-message", message);
     Ok(())
 }
 
+/// Handles the payload of a routed envelope that reached its destination
+/// `via` a neighbour, a mailbox relay, or an older peer that published it on
+/// gossip. The envelope's `origin` is only a claim made along the way, so
+/// the frame has to be signed by that peer.
+/// Sealed frames name no origin and go unsigned; they are authenticated
+/// once opened.
+pub async fn handle_routed_payload<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    envelope: &RoutedEnvelope,
+    via: PeerId,
+) {
+    let decoded = match wire::decode(&envelope.payload) {
+        Ok(decoded) => decoded,
+        // Sent by neighbours in privacy mode only to mask real traffic.
+        Err(wire::WireError::Cover) => return,
+        Err(error) => {
+            report_rejected_frame(ctx, via, &error);
+            return;
+        }
+    };
+    let source = match &decoded.signer {
+        Some(signer) if signer.peer_id.to_base58() == envelope.origin => signer.peer_id,
+        None if envelope.origin.is_empty() && decoded.message.signature_policy() == SignaturePolicy::Sealed => via,
+        _ => {
+            eprintln!("Dropping frame from {} via {}: not signed by its origin", envelope.origin, via);
+            return;
+        }
+    };
+    if ctx.network.deny_list.lock().await.is_denied(&source) {
+        return;
+    }
+    let _ = handle_message(ctx, decoded.message, source, decoded.clock).await;
+}
+
+pub fn report_rejected_frame<R: Runtime>(ctx: &Arc<AppContext<R>>, source: PeerId, error: &wire::WireError) {
+    eprintln!("Rejected frame from {}: {}", source, error);
+    let _ = ctx.app.emit("wire-frame-rejected", serde_json::json!({
+        "peerId": source.to_base58(),
+        "error": error.to_string(),
+        "unsupported": !matches!(error, wire::WireError::Malformed(_) | wire::WireError::BadSignature(_)),
+    }));
+}
+
+/// Tells the UI about a reply in a thread we follow.
+async fn notify_followed_thread<R: Runtime>(ctx: &Arc<AppContext<R>>, message_id: &str) {
+    let Ok(Some(thread_id)) = aep::database::thread_of_message(&ctx.db_pool, message_id).await else {
+        return;
+    };
+    let my_id = ctx.app_state.identity.peer_id().to_base58();
+    if let Ok(true) = aep::database::is_following_thread(&ctx.db_pool, &thread_id, &my_id).await {
+        let _ = ctx.app.emit("followed-thread-reply", serde_json::json!({
+            "threadId": thread_id, "messageId": message_id
+        }));
+    }
+}
+
+/// Takes in a peer's prekey bundle once its identity key, the one its user
+/// ID commits to, is found to have signed it.
 async fn process_prekey<R: Runtime>(ctx: &Arc<AppContext<R>>, user_id: &str, bundle: &[u8], signature: &Option<Vec<u8>>) {
-    if verify_sig(ctx, user_id, bundle, signature.as_deref()).await {
-         if let Ok(bundle_obj) = bincode::deserialize::<e2ee::PrekeyBundle>(bundle) {
-            e2ee::init_global_manager().lock().await.add_remote_bundle(user_id, bundle_obj);
-         }
+    let Some(signature) = signature else { return };
+    let Some(owner_key) = ed25519_identity_key(ctx, user_id).await else {
+        eprintln!("No Ed25519 identity key known for {}", user_id);
+        return;
+    };
+    let verified = match e2ee::PrekeyBundle::open(bundle, signature, &owner_key, chrono::Utc::now().timestamp()) {
+        Ok(verified) => verified,
+        Err(e) => {
+            eprintln!("Rejected prekey bundle from {}: {}", user_id, e);
+            return;
+        }
+    };
+    let identity_key = verified.bundle().identity_key.clone();
+    if let Err(e) = e2ee::init_global_manager().lock().await.add_remote_bundle(user_id, verified) {
+        eprintln!("Rejected prekey bundle from {}: {}", user_id, e);
+        return;
+    }
+    crate::commands::verification::note_contact_e2ee_key(&ctx.app, &ctx.db_pool, user_id, &identity_key).await;
+}
+
+/// Raw Ed25519 key `user_id` is known by, if it is the key the ID was
+/// derived from.
+async fn ed25519_identity_key<R: Runtime>(ctx: &Arc<AppContext<R>>, user_id: &str) -> Option<[u8; 32]> {
+    let user = aep::user_service::get_user(&ctx.db_pool, user_id).await.ok().flatten()?;
+    let bytes = bs58::decode(user.public_key?).into_vec().ok()?;
+    let public_key = libp2p::identity::PublicKey::from_protobuf_encoding(&bytes).ok()?;
+    if public_key.to_peer_id().to_base58() != user_id {
+        return None;
+    }
+    match public_key {
+        libp2p::identity::PublicKey::Ed25519(key) => Some(key.encode()),
+        _ => None,
     }
 }
 
-async fn process_chat<R: Runtime>(ctx: &Arc<AppContext<R>>, sender: &String, recipient: &String, init: &Option<Vec<u8>>, header: &[u8], content: &[u8], signature: &Option<Vec<u8>>) {
-    let payload = bincode::serialize(&(sender.clone(), recipient.clone(), header, content)).unwrap_or_default();
+async fn process_chat<R: Runtime>(ctx: &Arc<AppContext<R>>, message_id: &String, sender: &String, recipient: &String, init: &Option<Vec<u8>>, header: &[u8], content: &[u8], signature: &Option<Vec<u8>>) {
+    let payload = bincode::serialize(&(message_id.clone(), sender.clone(), recipient.clone(), header, content)).unwrap_or_default();
     if !verify_sig(ctx, sender, &payload, signature.as_deref()).await { return; }
 
     let my_id = ctx.app_state.identity.peer_id().to_base58();
     if recipient != &my_id { return; }
 
+    // The sender retries until it sees our ack, and the ratchet cannot decrypt
+    // a copy twice, so a repeat only needs acknowledging again.
+    if let Ok(Some(_)) = aep::database::get_message_metadata(&ctx.db_pool, message_id).await {
+        send_delivery_ack(ctx, message_id, sender).await;
+        return;
+    }
+
     let packet = e2ee::EncryptedPacket { init: init.clone(), enc_header: header.to_vec(), enc_content: content.to_vec() };
-    if let Ok(plaintext) = e2ee::init_global_manager().lock().await.decrypt_from(sender, &packet) {
-        insert_db_message(ctx, sender, sender, plaintext).await;
+    let (decrypted, refreshed_bundle) = {
+        let mut manager = e2ee::init_global_manager().lock().await;
+        let decrypted = manager.decrypt_from(sender, &packet);
+        let refreshed = if init.is_some() { manager.replenish_prekeys().ok().flatten() } else { None };
+        (decrypted, refreshed)
+    };
+
+    publish_replenished_prekeys(ctx, refreshed_bundle).await;
+
+    if let Ok(plaintext) = decrypted {
+        if insert_db_message(ctx, Some(message_id), sender, sender, plaintext).await {
+            send_delivery_ack(ctx, message_id, sender).await;
+        }
     }
 }
 
-async fn process_group_key<R: Runtime>(ctx: &Arc<AppContext<R>>, source: &PeerId, server: &String, channel: &Option<String>, epoch: &u64, slots: &[EncryptedDmSlot], signature: &Option<Vec<u8>>) {
-    let issuer = source.to_base58();
-    let payload = bincode::serialize(&(issuer.clone(), server, channel, *epoch, slots)).unwrap_or_default();
-    if !verify_sig(ctx, &issuer, &payload, signature.as_deref()).await { return; }
+/// Opens a direct message that names neither end. The sender is only known
+/// once decrypted: it must be the peer whose session opened the message and
+/// must have signed the message for us.
+async fn process_sealed_chat<R: Runtime>(ctx: &Arc<AppContext<R>>, message_id: &String, ephemeral_key: &[u8], recipient_hint: &[u8], ciphertext: &[u8]) {
+    let Ok(secret) = sealed::recipient_secret(ctx.app_state.identity.keypair(), ephemeral_key) else { return; };
+    if recipient_hint != secret.recipient_hint(message_id).as_slice() { return; }
+
+    // As in process_chat, a repeat only needs acknowledging again, to the
+    // sender we learnt when the first copy was opened.
+    if let Ok(Some(_)) = aep::database::get_message_metadata(&ctx.db_pool, message_id).await {
+        send_sealed_delivery_ack(ctx, message_id, &secret).await;
+        return;
+    }
+
+    let packet = match secret.open(message_id, ciphertext) {
+        Ok(packet) => packet,
+        Err(e) => {
+            eprintln!("Failed to open sealed message {}: {}", message_id, e);
+            return;
+        }
+    };
+    let is_init = packet.init.is_some();
+    let packet = e2ee::EncryptedPacket { init: packet.init, enc_header: packet.enc_header, enc_content: packet.enc_content };
+    let (opened, refreshed_bundle) = {
+        let mut manager = e2ee::init_global_manager().lock().await;
+        let opened = manager.decrypt_sealed(&packet);
+        let refreshed = if is_init { manager.replenish_prekeys().ok().flatten() } else { None };
+        (opened, refreshed)
+    };
+    publish_replenished_prekeys(ctx, refreshed_bundle).await;
 
+    let (peer_id, plaintext) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            eprintln!("Failed to open sealed message {}: {}", message_id, e);
+            return;
+        }
+    };
+    let content = match bincode::deserialize::<sealed::SealedContent>(&plaintext) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Malformed sealed message {} from {}: {}", message_id, peer_id, e);
+            return;
+        }
+    };
+    if content.sender != peer_id {
+        eprintln!("Sealed message {} claims to be from {} but came from {}", message_id, content.sender, peer_id);
+        return;
+    }
+    let my_id = ctx.app_state.identity.peer_id().to_base58();
+    let Ok(signed) = sealed::signed_bytes(message_id, &content.sender, &my_id, &content.payload) else { return; };
+    if !verify_sig(ctx, &content.sender, &signed, Some(&content.signature)).await { return; }
+
+    if insert_db_message(ctx, Some(message_id), &content.sender, &content.sender, content.payload).await {
+        send_sealed_delivery_ack(ctx, message_id, &secret).await;
+        let _ = ctx.app.emit("direct-message-received", serde_json::json!({
+            "chatId": content.sender, "messageId": message_id
+        }));
+    }
+}
+
+/// Acks a sealed message without naming either end. The outbox routes it to
+/// the sender the stored message names.
+async fn send_sealed_delivery_ack<R: Runtime>(ctx: &Arc<AppContext<R>>, message_id: &str, secret: &sealed::SealSecret) {
+    let ack = AepMessage::SealedDeliveryAck {
+        message_id: message_id.into(),
+        proof: secret.ack_proof(message_id),
+    };
+    if let Ok(bytes) = bincode::serialize(&ack) {
+        let _ = ctx.app_state.network_tx.send(bytes).await;
+    }
+}
+
+/// Takes an ack for a sealed message we sent once its proof shows it comes
+/// from the recipient, the peer the stored message names.
+async fn process_sealed_delivery_ack<R: Runtime>(ctx: &Arc<AppContext<R>>, message_id: &str, proof: &[u8]) {
     let my_id = ctx.app_state.identity.peer_id().to_base58();
-    if let Some(slot) = slots.iter().find(|s| s.recipient == my_id) {
-        let packet = e2ee::EncryptedPacket { init: slot.init.clone(), enc_header: slot.enc_header.clone(), enc_content: slot.enc_content.clone() };
-        if let Ok(key_bytes) = e2ee::init_global_manager().lock().await.decrypt_from(&my_id, &packet) {
-            e2ee::init_global_manager().lock().await.set_group_key(server.as_str(), channel, *epoch, &key_bytes);
+    let Ok(Some(stored)) = aep::database::get_message_metadata(&ctx.db_pool, message_id).await else { return; };
+    if stored.sender_id != my_id { return; }
+    let Ok(recipient) = stored.chat_id.parse::<PeerId>() else { return; };
+    let Ok(secret) = sealed::sender_secret(ctx.app_state.identity.keypair(), &recipient, message_id) else { return; };
+    if secret.ack_proof(message_id) != proof {
+        eprintln!("Dropping sealed delivery ack for {}: bad proof", message_id);
+        return;
+    }
+    acknowledge_delivery(ctx, message_id, &stored.chat_id).await;
+}
+
+async fn publish_replenished_prekeys<R: Runtime>(ctx: &Arc<AppContext<R>>, refreshed_bundle: Option<e2ee::PrekeyBundle>) {
+    if let Some(bundle) = refreshed_bundle {
+        if let Err(e) = publish_prekey_bundle(&ctx.app_state.identity, &bundle, &ctx.app_state.network_tx).await {
+            eprintln!("Failed to publish replenished prekeys: {}", e);
+        }
+    }
+}
+
+async fn send_delivery_ack<R: Runtime>(ctx: &Arc<AppContext<R>>, message_id: &str, sender_id: &str) {
+    let data = DeliveryAckData {
+        message_id: message_id.into(),
+        sender_id: sender_id.into(),
+        recipient_id: ctx.app_state.identity.peer_id().to_base58(),
+        timestamp: chrono::Utc::now(),
+    };
+    let signature = match bincode::serialize(&data).map_err(|e| e.to_string()).and_then(|bytes| {
+        ctx.app_state.identity.keypair().sign(&bytes).map_err(|e| e.to_string())
+    }) {
+        Ok(signature) => signature,
+        Err(e) => {
+            eprintln!("Failed to sign delivery ack for {}: {}", message_id, e);
+            return;
+        }
+    };
+    let ack = AepMessage::DeliveryAck {
+        message_id: data.message_id,
+        sender_id: data.sender_id,
+        recipient_id: data.recipient_id,
+        timestamp: data.timestamp,
+        signature: Some(signature),
+    };
+    if let Ok(bytes) = bincode::serialize(&ack) {
+        let _ = ctx.app_state.network_tx.send(bytes).await;
+    }
+}
+
+async fn process_delivery_ack<R: Runtime>(ctx: &Arc<AppContext<R>>, msg_id: &str, sender: &str, recipient: &String, ts: &chrono::DateTime<chrono::Utc>, sig: &Option<Vec<u8>>) {
+    let my_id = ctx.app_state.identity.peer_id().to_base58();
+    if sender != my_id { return; }
+
+    let data = DeliveryAckData { message_id: msg_id.into(), sender_id: sender.into(), recipient_id: recipient.clone(), timestamp: *ts };
+    let bytes = bincode::serialize(&data).unwrap_or_default();
+    if !verify_sig(ctx, recipient, &bytes, sig.as_deref()).await { return; }
+
+    acknowledge_delivery(ctx, msg_id, recipient).await;
+}
+
+async fn acknowledge_delivery<R: Runtime>(ctx: &Arc<AppContext<R>>, msg_id: &str, recipient: &str) {
+    if let Err(e) = aep::database::remove_acknowledged_outbox_entries(&ctx.db_pool, recipient, msg_id).await {
+        eprintln!("Failed to clear acknowledged outbox entries: {}", e);
+    }
+    outbox::update_delivery_status(ctx, msg_id, recipient, aep::database::DeliveryStatus::Delivered).await;
+}
+
+async fn process_group_key<R: Runtime>(ctx: &Arc<AppContext<R>>, issuer: &String, server: &String, channel: &Option<String>, epoch: u64, slots: &[EncryptedDmSlot], signature: &Option<Vec<u8>>) {
+    let payload = bincode::serialize(&(issuer, server, channel, epoch, slots)).unwrap_or_default();
+    if !verify_sig(ctx, issuer, &payload, signature.as_deref()).await { return; }
+
+    let my_id = ctx.app_state.identity.peer_id().to_base58();
+    let members = aep::database::get_server_members(&ctx.db_pool, server).await.unwrap_or_default();
+    if !members.iter().any(|m| &m.id == issuer) { return; }
+
+    let Some(slot) = slots.iter().find(|s| s.recipient == my_id) else { return; };
+    let packet = e2ee::EncryptedPacket { init: slot.init.clone(), enc_header: slot.enc_header.clone(), enc_content: slot.enc_content.clone() };
+    let our_epoch = {
+        let mut manager = e2ee::init_global_manager().lock().await;
+        let stored = manager
+            .decrypt_from(issuer, &packet)
+            .and_then(|key_bytes| manager.add_sender_key(server, channel, issuer, epoch, &key_bytes));
+        if let Err(e) = stored {
+            eprintln!("Rejected sender key from {} for {}: {}", issuer, server, e);
+            return;
+        }
+        manager.outbound_group_epoch(server, channel)
+    };
+
+    // A newer epoch from the owner means membership changed. Follow it with our
+    // own sender key, sent only to the members the owner still trusts.
+    let owner_id = aep::database::get_server_by_id(&ctx.db_pool, server).await.map(|s| s.owner_id).ok();
+    if owner_id.as_ref() == Some(issuer) && our_epoch.is_some_and(|ours| ours < epoch) {
+        let mut recipients: Vec<String> = slots.iter().map(|s| s.recipient.clone()).collect();
+        recipients.push(issuer.clone());
+        if let Err(e) = crate::bootstrap::rotate_and_broadcast_group_key(
+            &ctx.db_pool,
+            ctx.app_state.identity.clone(),
+            &ctx.app_state.network_tx,
+            server,
+            channel,
+            epoch,
+            Some(&recipients),
+        )
+        .await
+        {
+            eprintln!("Failed to follow group key rotation for {}: {}", server, e);
         }
     }
 }
@@ -89,6 +410,12 @@ async fn process_read_receipt<R: Runtime>(ctx: &Arc<AppContext<R>>, chat_id: &st
     if !verify_sig(ctx, reader, &bytes, sig.as_deref()).await { return; }
 
     let _ = aep::database::mark_message_as_read(&ctx.db_pool, msg_id).await;
+    let my_id = ctx.app_state.identity.peer_id().to_base58();
+    if let Ok(Some(meta)) = aep::database::get_message_metadata(&ctx.db_pool, msg_id).await {
+        if meta.sender_id == my_id && reader != &my_id {
+            outbox::update_delivery_status(ctx, msg_id, reader, aep::database::DeliveryStatus::Read).await;
+        }
+    }
     let _ = ctx.app.emit("message-read", crate::commands::messages::ReadReceiptEventPayload {
         chat_id: chat_id.into(), message_id: msg_id.into(), reader_id: reader.clone(), timestamp: ts.to_rfc3339()
     });
@@ -109,9 +436,9 @@ async fn process_group_chat<R: Runtime>(ctx: &Arc<AppContext<R>>, sender: &Strin
     let payload = bincode::serialize(&(sender.clone(), server, channel, epoch, nonce, ciphertext)).unwrap_or_default();
     if !verify_sig(ctx, sender, &payload, sig.as_deref()).await { return; }
 
-    if let Ok(plaintext) = e2ee::init_global_manager().lock().await.decrypt_group_message(server, channel, ciphertext) {
+    if let Ok(plaintext) = e2ee::init_global_manager().lock().await.decrypt_group_message(server, channel, sender, epoch, ciphertext) {
         let chat_id = channel.clone().unwrap_or_else(|| server.to_string());
-        insert_db_message(ctx, &chat_id, sender, plaintext).await;
+        insert_db_message(ctx, None, &chat_id, sender, plaintext).await;
     }
 }
 
@@ -130,15 +457,17 @@ async fn verify_sig<R: Runtime>(ctx: &Arc<AppContext<R>>, user_id: &str, data: &
     false
 }
 
-async fn insert_db_message<R: Runtime>(ctx: &Arc<AppContext<R>>, chat_id: &str, sender_id: &str, plaintext: Vec<u8>) {
-    let message_id = Scu128::new().to_string();
+/// Stores a decrypted message, keeping the sender's id when it sent one so
+/// acks and read receipts refer to the same message on both sides.
+async fn insert_db_message<R: Runtime>(ctx: &Arc<AppContext<R>>, message_id: Option<&str>, chat_id: &str, sender_id: &str, plaintext: Vec<u8>) -> bool {
+    let message_id = message_id.map(str::to_string).unwrap_or_else(|| Scu128::new().generate());
     let mut db_attachments = Vec::new();
     let mut attachment_data = Vec::new();
 
     let (content, reply_to, snap_author, snap_snip) = if let Ok(pl) = bincode::deserialize::<crate::commands::messages::EncryptedDmPayload>(&plaintext) {
         for d in pl.attachments {
             if d.data.is_empty() { continue; }
-            let att_id = Scu128::new().to_string();
+            let att_id = Scu128::new().generate();
             let att = aep::database::Attachment {
                 id: att_id, message_id: message_id.clone(), name: d.name, content_type: d.content_type, size: d.data.len() as u64
             };
@@ -154,8 +483,15 @@ async fn insert_db_message<R: Runtime>(ctx: &Arc<AppContext<R>>, chat_id: &str,
         id: message_id, chat_id: chat_id.into(), sender_id: sender_id.into(), content, timestamp: chrono::Utc::now(),
         read: false, pinned: false, attachments: db_attachments, reactions: Default::default(),
         reply_to_message_id: reply_to, reply_snapshot_author: snap_author, reply_snapshot_snippet: snap_snip,
-        edited_at: None, edited_by: None, expires_at: None
+        edited_at: None, edited_by: None, expires_at: None, delivery_status: None,
+        thread_id: None, thread: None
     };
 
-    let _ = aep::database::insert_message(&ctx.db_pool, &msg, &attachment_data).await;
+    match aep::database::insert_message(&ctx.db_pool, &msg, &attachment_data).await {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("Failed to store message from {}: {}", sender_id, e);
+            false
+        }
+    }
 }
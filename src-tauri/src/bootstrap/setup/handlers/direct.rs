@@ -0,0 +1,189 @@
+use std::sync::Arc;
+use tauri::Runtime;
+use libp2p::request_response::{RequestId, RequestResponseEvent, RequestResponseMessage, ResponseChannel};
+use libp2p::PeerId;
+use crate::network::{DirectDeliveryResponse, FrameVerdict, RoutedEnvelope};
+use crate::bootstrap::setup::context::AppContext;
+use crate::bootstrap::setup::handlers::{application, outbox};
+use crate::connectivity::{
+    bridge_can_forward_to, emit_bridge_snapshot, note_bridge_forward_attempt,
+    note_bridge_forward_failure, note_bridge_forward_success,
+};
+
+pub async fn handle_direct_event<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    event: RequestResponseEvent<RoutedEnvelope, DirectDeliveryResponse>,
+) {
+    match event {
+        RequestResponseEvent::Message { peer, message } => match message {
+            RequestResponseMessage::Request { request, channel, .. } => {
+                handle_envelope(ctx, peer, request, channel).await;
+            }
+            RequestResponseMessage::Response { request_id, response } => {
+                handle_response(ctx, request_id, response).await;
+            }
+        },
+        RequestResponseEvent::OutboundFailure { peer, request_id, error } => {
+            eprintln!("Direct delivery to {} failed: {:?}", peer, error);
+            handle_failure(ctx, request_id, format!("{:?}", error)).await;
+        }
+        RequestResponseEvent::InboundFailure { peer, error, .. } => {
+            eprintln!("Inbound direct delivery from {} failed: {:?}", peer, error);
+        }
+        RequestResponseEvent::ResponseSent { .. } => {}
+    }
+}
+
+async fn respond<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    channel: ResponseChannel<DirectDeliveryResponse>,
+    response: DirectDeliveryResponse,
+) {
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    if swarm.behaviour_mut().direct.send_response(channel, response).is_err() {
+        eprintln!("Direct delivery response channel closed");
+    }
+}
+
+async fn handle_envelope<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    peer: PeerId,
+    envelope: RoutedEnvelope,
+    channel: ResponseChannel<DirectDeliveryResponse>,
+) {
+    let local_id = ctx.app_state.identity.peer_id();
+    match crate::network::admit_frame(&envelope.frame_id, &envelope.origin, &envelope.payload, envelope.ttl) {
+        FrameVerdict::Fresh => {}
+        // A copy of a multipath delivery that lost the race reached us too.
+        FrameVerdict::Duplicate if envelope.destination == local_id.to_base58() => {
+            respond(ctx, channel, DirectDeliveryResponse::Delivered).await;
+            return;
+        }
+        FrameVerdict::Duplicate => {
+            respond(ctx, channel, DirectDeliveryResponse::Rejected("duplicate frame".into())).await;
+            return;
+        }
+        FrameVerdict::Expired => {
+            respond(ctx, channel, DirectDeliveryResponse::Rejected("ttl expired".into())).await;
+            return;
+        }
+    }
+
+    // Neither accept nor relay traffic from peers we refuse to deal with.
+    let origin = envelope.origin.parse::<PeerId>().ok();
+    if let Some(origin) = origin.as_ref() {
+        if ctx.network.deny_list.lock().await.is_denied(origin) {
+            respond(ctx, channel, DirectDeliveryResponse::Rejected("origin denied".into())).await;
+            return;
+        }
+    }
+
+    let path_peers = envelope.path_peers();
+    {
+        let mut router = ctx.network.router.lock().await;
+        router.observe_peer(peer.clone());
+        for hop in &path_peers { router.observe_peer(hop.clone()); }
+    }
+
+    if envelope.destination == local_id.to_base58() {
+        respond(ctx, channel, DirectDeliveryResponse::Delivered).await;
+        {
+            let mut router = ctx.network.router.lock().await;
+            router.record_route_success(&path_peers, Some(envelope.metrics.total_latency_ms));
+        }
+        application::handle_routed_payload(ctx, &envelope, peer).await;
+        return;
+    }
+
+    let next_peer = match envelope.next_hop_after(&local_id) {
+        Some(next_peer) => next_peer,
+        None => {
+            respond(ctx, channel, DirectDeliveryResponse::Rejected("not on route".into())).await;
+            return;
+        }
+    };
+
+    if !bridge_can_forward_to(&next_peer).await {
+        respond(ctx, channel, DirectDeliveryResponse::Rejected("forwarding disabled".into())).await;
+        return;
+    }
+
+    note_bridge_forward_attempt().await;
+    let request_id = {
+        let mut swarm = ctx.network.shared_swarm.lock().await;
+        let Some(request_id) = crate::network::forward_envelope(&mut swarm, &next_peer, envelope) else {
+            drop(swarm);
+            respond(ctx, channel, DirectDeliveryResponse::Rejected("ttl expired".into())).await;
+            return;
+        };
+        if swarm
+            .behaviour_mut()
+            .direct
+            .send_response(channel, DirectDeliveryResponse::Forwarded)
+            .is_err()
+        {
+            eprintln!("Direct delivery response channel closed");
+        }
+        request_id
+    };
+    ctx.network.pending_direct.lock().await.insert(
+        request_id,
+        super::super::network::PendingDelivery { path: path_peers, outbox_id: None },
+    );
+}
+
+async fn handle_response<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    request_id: RequestId,
+    response: DirectDeliveryResponse,
+) {
+    if let DirectDeliveryResponse::Rejected(reason) = response {
+        handle_failure(ctx, request_id, reason).await;
+        return;
+    }
+
+    let pending = ctx.network.pending_direct.lock().await.remove(&request_id);
+    let Some(pending) = pending else { return; };
+    {
+        let mut router = ctx.network.router.lock().await;
+        router.record_route_success(&pending.path, None);
+    }
+    match pending.outbox_id {
+        Some(outbox_id) => {
+            if outbox::settle_copy(ctx, outbox_id, true).await == Some(true) {
+                outbox::delivery_accepted(ctx, outbox_id).await;
+            }
+        }
+        None => {
+            note_bridge_forward_success().await;
+            let _ = emit_bridge_snapshot(&ctx.app).await;
+        }
+    }
+}
+
+/// Penalises the route. Frames we sent ourselves go to a mailbox relay, or
+/// wait in the outbox for their next retry.
+async fn handle_failure<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    request_id: RequestId,
+    reason: String,
+) {
+    let pending = ctx.network.pending_direct.lock().await.remove(&request_id);
+    let Some(pending) = pending else { return; };
+    {
+        let mut router = ctx.network.router.lock().await;
+        router.record_route_failure(&pending.path);
+    }
+
+    match pending.outbox_id {
+        Some(outbox_id) => {
+            if outbox::settle_copy(ctx, outbox_id, false).await == Some(false) {
+                outbox::direct_delivery_failed(ctx, outbox_id).await;
+            }
+        }
+        None => {
+            note_bridge_forward_failure(format!("Forwarding failed: {}", reason)).await;
+            let _ = emit_bridge_snapshot(&ctx.app).await;
+        }
+    }
+}
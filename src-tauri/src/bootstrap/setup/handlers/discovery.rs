@@ -1,7 +1,10 @@
 use super::super::context::AppContext;
 use network::LinkQuality;
 use tauri::Runtime;
+use libp2p::identify::IdentifyEvent;
+use libp2p::kad::KademliaEvent;
 use libp2p::mdns::MdnsEvent;
+use libp2p::Multiaddr;
 use std::sync::Arc;
 
 pub async fn handle_mdns_event<R: Runtime>(
@@ -38,3 +41,75 @@ pub async fn handle_mdns_event<R: Runtime>(
         }
     }
 }
+
+pub async fn handle_identify_event<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    event: IdentifyEvent
+) {
+    if let IdentifyEvent::Received { peer_id, info } = event {
+        let local_peer = ctx.app_state.identity.peer_id();
+        match network::record_peer_capabilities(&local_peer, peer_id, &info.protocol_version) {
+            Some(_) if network::negotiated_version(&local_peer, &peer_id).is_none() => {
+                eprintln!(
+                    "Peer {} speaks incompatible protocol {}",
+                    peer_id, info.protocol_version
+                );
+            }
+            Some(_) => {}
+            None => {
+                eprintln!(
+                    "Peer {} announced unknown protocol {}",
+                    peer_id, info.protocol_version
+                );
+            }
+        }
+        {
+            let mut swarm = ctx.network.shared_swarm.lock().await;
+            network::dht::record_identified(&mut swarm, &peer_id, &info.listen_addrs);
+            network::circuit::upgrade_to_direct(&mut swarm, &peer_id, &info.listen_addrs);
+        }
+        if network::advertises_mailbox(&info.protocols) {
+            super::mailbox::relay_identified(ctx, peer_id).await;
+        }
+    }
+}
+
+pub async fn handle_kademlia_event<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    event: KademliaEvent,
+) {
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    if let Some(peer) = network::dht::on_kademlia_event(&mut swarm, &event) {
+        eprintln!("Found {} through the DHT, dialling", peer);
+    }
+}
+
+/// Seeds the DHT with the bootstrap peers saved in settings.
+pub async fn bootstrap_dht<R: Runtime>(ctx: &Arc<AppContext<R>>) {
+    let settings_path = ctx.app_state.app_data_dir.join("settings.json");
+    let peers: Vec<Multiaddr> = match crate::settings_store::load_settings(&settings_path) {
+        Ok(settings) => settings
+            .bootstrap_peers
+            .iter()
+            .filter_map(|peer| peer.parse().ok())
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to load bootstrap peers: {}", e);
+            return;
+        }
+    };
+    if peers.is_empty() {
+        return;
+    }
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    network::dht::bootstrap(&mut swarm, &peers);
+}
+
+/// Merges a peer's link-state advert, verified by the gossip validator, into
+/// the routing graph.
+pub async fn handle_link_state_advert<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    advert: network::VerifiedAdvert,
+) {
+    ctx.network.router.lock().await.merge_advert(advert);
+}
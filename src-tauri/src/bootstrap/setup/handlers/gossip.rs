@@ -1,104 +1,115 @@
 use std::str::FromStr;
 use tauri::Runtime;
-use libp2p::gossipsub::GossipsubEvent;
-use aegis_protocol::AepMessage;
-use crate::network::{RoutedFrame, RoutedEnvelope as Envelope};
+use libp2p::gossipsub::{GossipsubEvent, MessageAcceptance, MessageId};
+use libp2p::PeerId;
+use crate::network::validation::Rejection;
+use crate::network::{FrameVerdict, RoutedFrame};
 use crate::bootstrap::setup::context::AppContext;
-use crate::connectivity::{
-    bridge_can_forward_to, emit_bridge_snapshot, note_bridge_forward_attempt,
-    note_bridge_forward_failure, note_bridge_forward_success,
-};
-use crate::bootstrap::setup::handlers::application;
+use crate::bootstrap::setup::handlers::{application, discovery};
+use aegis_protocol::wire::WireError;
 use std::sync::Arc;
 
 pub async fn handle_gossip_event<R: Runtime>(
     ctx: &Arc<AppContext<R>>,
     event: GossipsubEvent
 ) {
-    if let GossipsubEvent::Message { propagation_source, message, .. } = event {
-        if message.topic != ctx.network.topic.hash() { return; }
+    if let GossipsubEvent::Message { propagation_source, message_id, message } = event {
+        if message.topic == crate::network::link_state_topic().hash() {
+            let result = ctx.network.validator.lock().await.validate_link_state(&message.data);
+            match result {
+                Ok(advert) => {
+                    report_validation(ctx, &message_id, &propagation_source, MessageAcceptance::Accept).await;
+                    discovery::handle_link_state_advert(ctx, advert).await;
+                }
+                Err(rejection) => {
+                    eprintln!("Dropping link-state advert from {}: {}", propagation_source, rejection);
+                    report_validation(ctx, &message_id, &propagation_source, rejection.acceptance()).await;
+                }
+            }
+            return;
+        }
+
+        let topic_scope = ctx.network.topics.lock().await.scope_of(&message.topic).cloned();
+        // Traffic for a server or conversation we have left can still arrive
+        // while the mesh catches up; drop it unread.
+        let Some(topic_scope) = topic_scope else {
+            report_validation(ctx, &message_id, &propagation_source, MessageAcceptance::Ignore).await;
+            return;
+        };
 
         {
             let mut router = ctx.network.router.lock().await;
             router.observe_peer(propagation_source.clone());
         }
 
-        let payload_opt = match bincode::deserialize::<RoutedFrame>(&message.data) {
-            Ok(RoutedFrame::Broadcast { origin, payload }) => {
-                if let Ok(origin_peer) = libp2p::PeerId::from_str(&origin) {
-                    let mut router = ctx.network.router.lock().await;
-                    router.observe_peer(origin_peer);
+        let (origin, payload) = match crate::network::decode_frame(&message.data) {
+            Ok(RoutedFrame::Broadcast { frame_id, ttl, origin, payload }) => {
+                // The same frame can reach us over several transports or
+                // bridges; handle it once.
+                if crate::network::admit_frame(&frame_id, &origin, &payload, ttl) != FrameVerdict::Fresh {
+                    report_validation(ctx, &message_id, &propagation_source, MessageAcceptance::Ignore).await;
+                    return;
                 }
-                Some(payload)
+                (Some(origin), payload)
             }
+            // Routed envelopes travel over the direct-delivery protocol. Older
+            // peers may still publish them here; accept the ones meant for us
+            // but never relay them through the mesh.
             Ok(RoutedFrame::Routed { envelope }) => {
-                let local_id = ctx.app_state.identity.peer_id();
-                let path_peers: Vec<libp2p::PeerId> = envelope.path.iter()
-                    .filter_map(|v| libp2p::PeerId::from_str(v).ok())
-                    .collect();
-
-                {
-                    let mut router = ctx.network.router.lock().await;
-                    for peer in &path_peers { router.observe_peer(peer.clone()); }
-                }
-
-                if envelope.destination != local_id.to_base58() {
-                    handle_forwarding(ctx, &envelope, &path_peers, &local_id).await;
+                report_validation(ctx, &message_id, &propagation_source, MessageAcceptance::Ignore).await;
+                if envelope.destination != ctx.app_state.identity.peer_id().to_base58() {
                     return;
                 }
-                
+                if crate::network::admit_frame(&envelope.frame_id, &envelope.origin, &envelope.payload, envelope.ttl)
+                    != FrameVerdict::Fresh
                 {
-                    let mut router = ctx.network.router.lock().await;
-                    if !path_peers.is_empty() {
-                        router.record_route_success(&path_peers, Some(envelope.metrics.total_latency_ms));
-                    }
+                    return;
                 }
-                Some(envelope.payload)
+                application::handle_routed_payload(ctx, &envelope, propagation_source).await;
+                return;
             }
-            Err(_) => Some(message.data),
+            Err(_) => (None, message.data),
         };
 
-        if let Some(bytes) = payload_opt {
-            if let Ok(msg) = bincode::deserialize::<AepMessage>(&bytes) {
-                let _ = application::handle_message(ctx, msg, propagation_source).await;
+        let result = ctx
+            .network
+            .validator
+            .lock()
+            .await
+            .validate(&payload, &topic_scope, chrono::Utc::now());
+        let decoded = match result {
+            Ok(decoded) => decoded,
+            Err(rejection) => {
+                report_validation(ctx, &message_id, &propagation_source, rejection.acceptance()).await;
+                match rejection {
+                    Rejection::Wire(WireError::Cover) => {}
+                    Rejection::Wire(error) => application::report_rejected_frame(ctx, propagation_source, &error),
+                    rejection => eprintln!("Rejected gossip frame from {}: {}", propagation_source, rejection),
+                }
+                return;
             }
+        };
+        report_validation(ctx, &message_id, &propagation_source, MessageAcceptance::Accept).await;
+
+        if let Some(Ok(origin_peer)) = origin.as_deref().map(PeerId::from_str) {
+            let mut router = ctx.network.router.lock().await;
+            router.observe_peer(origin_peer);
         }
+        let _ = application::handle_message(ctx, decoded.message, propagation_source, decoded.clock).await;
     }
 }
 
-async fn handle_forwarding<R: Runtime>(
+/// Tells gossipsub whether to forward a message and whether to hold it
+/// against the peer that sent it.
+async fn report_validation<R: Runtime>(
     ctx: &Arc<AppContext<R>>,
-    envelope: &Envelope,
-    path_peers: &[libp2p::PeerId],
-    local_id: &libp2p::PeerId
+    message_id: &MessageId,
+    source: &PeerId,
+    acceptance: MessageAcceptance,
 ) {
-    let next_hop = path_peers.iter().position(|p| p == local_id)
-        .and_then(|idx| path_peers.get(idx + 1).cloned());
-
-    if let Some(next_peer) = next_hop {
-        if bridge_can_forward_to(&next_peer).await {
-            note_bridge_forward_attempt().await;
-            
-            let frame = RoutedFrame::Routed { envelope: envelope.clone() };
-            if let Ok(bytes) = bincode::serialize(&frame) {
-                let outcome = {
-                    let mut swarm = ctx.network.shared_swarm.lock().await;
-                    swarm.behaviour_mut().gossipsub.publish(ctx.network.topic.clone(), bytes)
-                };
-
-                match outcome {
-                    Ok(_) => {
-                        note_bridge_forward_success().await;
-                        let _ = emit_bridge_snapshot(&ctx.app).await;
-                    }
-                    Err(e) => {
-                        let msg = format!("Forwarding failed: {}", e);
-                        note_bridge_forward_failure(msg.clone()).await;
-                        eprintln!("{}", msg);
-                        let _ = emit_bridge_snapshot(&ctx.app).await;
-                    }
-                }
-            }
-        }
-    }
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    let _ = swarm
+        .behaviour_mut()
+        .gossipsub
+        .report_message_validation_result(message_id, source, acceptance);
 }
@@ -0,0 +1,287 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{Emitter, Runtime};
+use libp2p::request_response::{OutboundFailure, RequestResponseEvent, RequestResponseMessage};
+use libp2p::PeerId;
+use aegis_protocol::history::{self, HistoryEvent, HistoryKind};
+use aegis_protocol::AepMessage;
+use aep::database::{self, HistoryRecord};
+use crate::network::{self, sync::FETCH_BUDGET_BYTES, SyncRequest, SyncResponse, MAX_FETCH_EVENTS};
+use crate::bootstrap::setup::context::AppContext;
+
+/// How often chats are reconciled with the peers we stay connected to.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub async fn handle_sync_event<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    event: RequestResponseEvent<SyncRequest, SyncResponse>,
+) {
+    match event {
+        RequestResponseEvent::Message { peer, message } => match message {
+            RequestResponseMessage::Request { request, channel, .. } => {
+                let response = answer(ctx, &peer, request).await;
+                let mut swarm = ctx.network.shared_swarm.lock().await;
+                if swarm.behaviour_mut().sync.send_response(channel, response).is_err() {
+                    eprintln!("History sync response channel to {} closed", peer);
+                }
+            }
+            RequestResponseMessage::Response { response, .. } => {
+                handle_response(ctx, peer, response).await;
+            }
+        },
+        // Peers from before history sync.
+        RequestResponseEvent::OutboundFailure { error: OutboundFailure::UnsupportedProtocols, .. } => {}
+        RequestResponseEvent::OutboundFailure { peer, error, .. } => {
+            eprintln!("History sync with {} failed: {:?}", peer, error);
+        }
+        RequestResponseEvent::InboundFailure { .. } | RequestResponseEvent::ResponseSent { .. } => {}
+    }
+}
+
+/// Starts a round for every chat we share with `peer`. The peer starts its
+/// own when it sees us connect, so each side pulls what the other has.
+pub async fn sync_with_peer<R: Runtime>(ctx: &Arc<AppContext<R>>, peer: &PeerId) {
+    if ctx.network.deny_list.lock().await.is_denied(peer) {
+        return;
+    }
+    let local = ctx.app_state.identity.peer_id().to_base58();
+    let chats = match database::shared_chats(&ctx.db_pool, &local, &peer.to_base58()).await {
+        Ok(chats) => chats,
+        Err(e) => {
+            eprintln!("Failed to list chats shared with {}: {}", peer, e);
+            return;
+        }
+    };
+
+    let mut summaries = Vec::with_capacity(chats.len());
+    for chat_id in chats {
+        match database::history_keys(&ctx.db_pool, &chat_id).await {
+            Ok(keys) => summaries.push((chat_id, history::summarize(&keys))),
+            Err(e) => eprintln!("Failed to summarize history of {}: {}", chat_id, e),
+        }
+    }
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    for (chat_id, buckets) in summaries {
+        network::request_history_summary(&mut swarm, peer, chat_id, buckets);
+    }
+}
+
+pub async fn sync_connected_peers<R: Runtime>(ctx: &Arc<AppContext<R>>) {
+    let peers: Vec<PeerId> = {
+        let swarm = ctx.network.shared_swarm.lock().await;
+        swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .map(|(peer, _)| *peer)
+            .filter(|peer| swarm.is_connected(peer))
+            .collect()
+    };
+    for peer in peers {
+        sync_with_peer(ctx, &peer).await;
+    }
+}
+
+/// Adds a message we sent, or one applied to the store after arriving from
+/// the mesh, to its chat's history. Messages from outside the chat are left
+/// out, so they are never handed on to other members.
+pub async fn record_message<R: Runtime>(ctx: &Arc<AppContext<R>>, message: &AepMessage) {
+    let Some(event) = event_for(ctx, message).await else {
+        return;
+    };
+    if author_may_access(ctx, &event).await {
+        record(ctx, &event).await;
+    }
+}
+
+/// The history event for `message`. Reactions and deletions are filed under
+/// the time of the message they target, so they are left out until it is
+/// known.
+async fn event_for<R: Runtime>(ctx: &Arc<AppContext<R>>, message: &AepMessage) -> Option<HistoryEvent> {
+    let target_ms = match HistoryEvent::target_of(message) {
+        Some((chat_id, message_id)) => {
+            match database::history_message_time(&ctx.db_pool, chat_id, message_id).await {
+                Ok(time) => time,
+                Err(e) => {
+                    eprintln!("Failed to look up message {} in {}: {}", message_id, chat_id, e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    HistoryEvent::from_message(message, target_ms)
+}
+
+async fn answer<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    peer: &PeerId,
+    request: SyncRequest,
+) -> SyncResponse {
+    let chat_id = match &request {
+        SyncRequest::Summary { chat_id, .. } | SyncRequest::Fetch { chat_id, .. } => chat_id.clone(),
+    };
+    if ctx.network.deny_list.lock().await.is_denied(peer) {
+        return SyncResponse::Rejected("peer is denied".into());
+    }
+    match database::may_access_chat(&ctx.db_pool, &chat_id, &peer.to_base58()).await {
+        Ok(true) => {}
+        Ok(false) => return SyncResponse::Rejected(format!("not a member of {}", chat_id)),
+        Err(e) => {
+            eprintln!("Failed to check access of {} to {}: {}", peer, chat_id, e);
+            return SyncResponse::Rejected("history unavailable".into());
+        }
+    }
+
+    let result = match request {
+        SyncRequest::Summary { chat_id, buckets } => {
+            database::history_keys(&ctx.db_pool, &chat_id).await.map(|keys| {
+                let days = history::differing_buckets(&history::summarize(&keys), &buckets);
+                let keys = keys
+                    .into_iter()
+                    .filter(|key| days.contains(&history::bucket_of(key.timestamp_ms)))
+                    .collect();
+                SyncResponse::Keys { chat_id, keys }
+            })
+        }
+        SyncRequest::Fetch { chat_id, mut ids } => {
+            ids.truncate(MAX_FETCH_EVENTS);
+            database::get_history_events(&ctx.db_pool, &chat_id, &ids)
+                .await
+                .map(|events| SyncResponse::Events { chat_id, events: within_budget(events) })
+        }
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("Failed to answer history sync from {}: {}", peer, e);
+        SyncResponse::Rejected("history unavailable".into())
+    })
+}
+
+/// As many of `events` as fit in the requester's read limit. The rest are
+/// still missing on its side and come with its next round.
+fn within_budget(events: Vec<HistoryEvent>) -> Vec<HistoryEvent> {
+    let mut budget = FETCH_BUDGET_BYTES;
+    events
+        .into_iter()
+        .filter(|event| {
+            let fits = event.payload.len() <= budget;
+            if fits {
+                budget -= event.payload.len();
+            }
+            fits
+        })
+        .collect()
+}
+
+async fn handle_response<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    peer: PeerId,
+    response: SyncResponse,
+) {
+    match response {
+        SyncResponse::Keys { chat_id, keys } => {
+            let missing = match database::reconcile_history_keys(&ctx.db_pool, &chat_id, &keys).await {
+                Ok(missing) => missing,
+                Err(e) => {
+                    eprintln!("Failed to compare history of {} with {}: {}", chat_id, peer, e);
+                    return;
+                }
+            };
+            let mut swarm = ctx.network.shared_swarm.lock().await;
+            for batch in missing.chunks(MAX_FETCH_EVENTS) {
+                network::fetch_history_events(&mut swarm, &peer, chat_id.clone(), batch.to_vec());
+            }
+        }
+        SyncResponse::Events { chat_id, events } => {
+            apply_events(ctx, peer, &chat_id, events).await;
+        }
+        SyncResponse::Rejected(reason) => {
+            eprintln!("{} declined history sync: {}", peer, reason);
+        }
+    }
+}
+
+/// Applies events fetched from `peer` the way live ones are, so each is
+/// checked against its author's signature before it is stored. Messages go
+/// first, then the threads, edits, reactions and deletions that refer to
+/// them.
+async fn apply_events<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    peer: PeerId,
+    chat_id: &str,
+    events: Vec<HistoryEvent>,
+) {
+    let local = ctx.app_state.identity.peer_id().to_base58();
+    if !matches!(database::may_access_chat(&ctx.db_pool, chat_id, &local).await, Ok(true)) {
+        return;
+    }
+
+    let mut events: Vec<HistoryEvent> = events
+        .into_iter()
+        .filter(|event| event.chat_id == chat_id)
+        .take(MAX_FETCH_EVENTS)
+        .collect();
+    events.sort_by_key(|event| (apply_order(event.kind), event.timestamp_ms));
+
+    let mut applied = 0;
+    for event in events {
+        let Some(message) = event.message() else {
+            eprintln!("Dropped history event {} from {}: payload does not match", event.id, peer);
+            continue;
+        };
+        if !author_may_access(ctx, &event).await {
+            continue;
+        }
+        // Filed under our own record of the time, not the peer's.
+        let Some(event) = event_for(ctx, &message).await else {
+            eprintln!("Dropped history event {} from {}: its message is not known", event.id, peer);
+            continue;
+        };
+        if let Err(e) = aep::handle_aep_message(message, &ctx.db_pool, ctx.app_state.clone()).await {
+            eprintln!("Dropped history event {} from {}: {}", event.id, peer, e);
+            continue;
+        }
+        record(ctx, &event).await;
+        applied += 1;
+    }
+
+    if applied > 0 {
+        let _ = ctx.app.emit("chat-history-synced", serde_json::json!({
+            "chatId": chat_id, "events": applied
+        }));
+    }
+}
+
+fn apply_order(kind: HistoryKind) -> u8 {
+    match kind {
+        HistoryKind::Message => 0,
+        HistoryKind::Thread => 1,
+        HistoryKind::Edit => 2,
+        HistoryKind::Reaction => 3,
+        HistoryKind::Delete => 4,
+    }
+}
+
+async fn author_may_access<R: Runtime>(ctx: &Arc<AppContext<R>>, event: &HistoryEvent) -> bool {
+    match database::may_access_chat(&ctx.db_pool, &event.chat_id, &event.author).await {
+        Ok(allowed) => allowed,
+        Err(e) => {
+            eprintln!("Failed to check access of {} to {}: {}", event.author, event.chat_id, e);
+            false
+        }
+    }
+}
+
+/// Stores `event`. A message whose deletion reached us first is kept only as
+/// a tombstone, so it is taken out of the message store again.
+async fn record<R: Runtime>(ctx: &Arc<AppContext<R>>, event: &HistoryEvent) {
+    match database::record_history_event(&ctx.db_pool, event).await {
+        Ok(HistoryRecord::Deleted) if event.kind == HistoryKind::Message => {
+            if let Err(e) = database::delete_message(&ctx.db_pool, &event.message_id).await {
+                eprintln!("Failed to drop deleted message {}: {}", event.message_id, e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Failed to record history event {}: {}", event.id, e),
+    }
+}
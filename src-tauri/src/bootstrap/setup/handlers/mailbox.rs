@@ -0,0 +1,139 @@
+use std::sync::Arc;
+use tauri::Runtime;
+use libp2p::identity::Keypair;
+use libp2p::request_response::{RequestId, RequestResponseEvent, RequestResponseMessage};
+use libp2p::swarm::Swarm;
+use libp2p::PeerId;
+use crate::network::{self, MailboxItem, MailboxRequest, MailboxResponse};
+use crate::bootstrap::setup::context::AppContext;
+use crate::bootstrap::setup::handlers::{application, outbox};
+
+/// Envelopes collected from a relay per fetch.
+const FETCH_BATCH: u32 = 64;
+
+pub async fn handle_mailbox_event<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    event: RequestResponseEvent<MailboxRequest, MailboxResponse>,
+) {
+    match event {
+        RequestResponseEvent::Message { peer, message } => {
+            // Clients only speak the protocol outbound, so requests never
+            // reach us.
+            if let RequestResponseMessage::Response { request_id, response } = message {
+                handle_response(ctx, peer, request_id, response).await;
+            }
+        }
+        RequestResponseEvent::OutboundFailure { peer, request_id, error } => {
+            eprintln!("Mailbox request to {} failed: {:?}", peer, error);
+            ctx.network.pending_deposits.lock().await.remove(&request_id);
+        }
+        RequestResponseEvent::InboundFailure { .. } | RequestResponseEvent::ResponseSent { .. } => {}
+    }
+}
+
+async fn handle_response<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    relay: PeerId,
+    request_id: RequestId,
+    response: MailboxResponse,
+) {
+    let deposit = ctx.network.pending_deposits.lock().await.remove(&request_id);
+    match response {
+        MailboxResponse::Deposited => {
+            if let Some(outbox_id) = deposit {
+                outbox::delivery_accepted(ctx, outbox_id).await;
+            }
+        }
+        MailboxResponse::Messages { items, remaining } => {
+            collect(ctx, relay, items, remaining).await;
+        }
+        MailboxResponse::Acknowledged => {}
+        MailboxResponse::Rejected(reason) => {
+            eprintln!("Mailbox relay {} rejected request: {}", relay, reason);
+        }
+    }
+}
+
+/// Processes envelopes a relay held for us, acknowledges them and asks for
+/// the next page while more are waiting.
+async fn collect<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    relay: PeerId,
+    items: Vec<MailboxItem>,
+    remaining: u32,
+) {
+    let local = ctx.app_state.identity.peer_id().to_base58();
+    let mut ids = Vec::with_capacity(items.len());
+    for item in items {
+        ids.push(item.id);
+        if item.envelope.destination != local {
+            continue;
+        }
+        application::handle_routed_payload(ctx, &item.envelope, relay).await;
+    }
+    if ids.is_empty() {
+        return;
+    }
+
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    network::acknowledge_mailbox(&mut swarm, &relay, ids);
+    if remaining > 0 {
+        network::fetch_mailbox(&mut swarm, &relay, FETCH_BATCH);
+    }
+}
+
+/// Remembers a peer that keeps mailboxes and collects anything it holds for
+/// us. Identify runs on every connection and periodically after that, so
+/// this also polls relays we stay connected to.
+pub async fn relay_identified<R: Runtime>(ctx: &Arc<AppContext<R>>, relay: PeerId) {
+    ctx.network.mailbox_relays.lock().await.insert(relay.clone());
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    network::fetch_mailbox(&mut swarm, &relay, FETCH_BATCH);
+}
+
+/// Leaves a copy of an outbox entry with every connected mailbox relay so
+/// an offline destination can collect it later, signed by `sender` unless
+/// it is sealed. Returns whether any relay was asked.
+pub(super) async fn deposit<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    swarm: &mut Swarm<network::Behaviour>,
+    sender: Option<&Keypair>,
+    destination: &PeerId,
+    outbox_id: i64,
+    data: &[u8],
+) -> bool {
+    let relays: Vec<PeerId> = ctx
+        .network
+        .mailbox_relays
+        .lock()
+        .await
+        .iter()
+        .filter(|relay| *relay != destination && swarm.is_connected(relay))
+        .cloned()
+        .collect();
+    if relays.is_empty() {
+        return false;
+    }
+
+    let mut pending = ctx.network.pending_deposits.lock().await;
+    for relay in relays {
+        let request_id = network::deposit_in_mailbox(
+            swarm,
+            sender,
+            &relay,
+            destination,
+            data.to_vec(),
+        );
+        pending.insert(request_id, outbox_id);
+    }
+    true
+}
+
+/// Dials the registered relays whose URLs are libp2p multiaddrs, such as a
+/// headless relay node on the LAN, and reserves circuits on those that name
+/// their peer ID.
+pub async fn dial_configured_relays<R: Runtime>(ctx: &Arc<AppContext<R>>) {
+    let relays = ctx.app_state.relays.lock().await.clone();
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    crate::connectivity::sync_relay_circuits(&mut swarm, &relays);
+}
@@ -1,5 +1,8 @@
 pub mod application;
+pub mod direct;
 pub mod discovery;
 pub mod files;
 pub mod gossip;
+pub mod history;
+pub mod mailbox;
 pub mod outbox;
\ No newline at end of file
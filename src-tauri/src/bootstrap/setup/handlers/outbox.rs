@@ -1,48 +1,351 @@
 use network;
+use aegis_protocol::{wire, AepMessage, DeliveryMode, SignaturePolicy, TopicScope};
+use aep::database::{self, DeliveryStatus, OutboxEntry, BROADCAST_DESTINATION, MAX_OUTBOX_ATTEMPTS};
 use super::super::context::AppContext;
-use crate::bootstrap::MAX_OUTBOX_MESSAGES;
-use tauri::Runtime;
+use super::super::network::{MultipathDelivery, PendingDelivery};
+use crate::commands::messages::DeliveryStatusEventPayload;
+use libp2p::identity::Keypair;
+use libp2p::swarm::Swarm;
+use libp2p::PeerId;
+use tauri::{Emitter, Runtime};
 use std::sync::Arc;
 
+/// Due entries handed to the network per flush.
+const FLUSH_BATCH: i64 = 64;
+
+/// How long a handed-off message waits for its delivery ack before it is
+/// sent again.
+const ACK_TIMEOUT_SECS: i64 = 30;
+
+/// How long ephemeral frames, such as typing indicators, wait in the outbox
+/// before they are dropped unsent.
+const EPHEMERAL_TTL_SECS: i64 = 15;
+
+/// Persists an outgoing frame for its destination peer, or for the whole
+/// topic when it is a broadcast, and tries to send it straight away.
+/// Ephemeral frames only stay queued for a few seconds.
 pub async fn handle_outgoing_data<R: Runtime>(
     ctx: &Arc<AppContext<R>>,
     data: Vec<u8>
 ) {
-    let mut swarm = ctx.network.shared_swarm.lock().await;
-    
-    if network::has_any_peers(&swarm) {
-        let mut router = ctx.network.router.lock().await;
-        if let Err(e) = network::send_data(&mut swarm, &ctx.network.topic, &mut router, data).await {
-            eprintln!("Failed to send data over network: {}", e);
+    let clock = ctx.network.clock.lock().await.tick(chrono::Utc::now());
+    if let Err(e) = database::save_clock(&ctx.db_pool, clock).await {
+        eprintln!("Failed to save clock: {}", e);
+    }
+    let data = match wire::seal_clocked(data, clock) {
+        Ok(sealed) => sealed,
+        Err(e) => {
+            eprintln!("Failed to seal outgoing frame: {}", e);
+            return;
         }
-    } else {
-        drop(swarm);
-        let mut queue = ctx.outbox.lock().await;
-        queue.push_back(data);
-        if queue.len() > MAX_OUTBOX_MESSAGES {
-            queue.pop_front();
+    };
+
+    let message = wire::decode(&data).ok().map(|decoded| decoded.message);
+    let message_id = message
+        .as_ref()
+        .and_then(|message| message.chat_message_id())
+        .map(str::to_string);
+    let destination = match message.as_ref() {
+        Some(message) if message.signature_policy() == SignaturePolicy::Sealed => {
+            // Publishing a sealed frame to the mesh would hand it to everyone.
+            let Some(recipient) = sealed_recipient(ctx, message).await else {
+                eprintln!("Dropping sealed frame: its recipient is not known");
+                return;
+            };
+            recipient
+        }
+        Some(message) => message
+            .direct_recipient()
+            .map_or_else(|| BROADCAST_DESTINATION.to_string(), str::to_string),
+        None => BROADCAST_DESTINATION.to_string(),
+    };
+    let awaits_ack = message.as_ref().is_some_and(|message| message.expects_delivery_ack());
+    let expires_at = message
+        .as_ref()
+        .is_some_and(|message| message.is_ephemeral())
+        .then(|| chrono::Utc::now() + chrono::Duration::seconds(EPHEMERAL_TTL_SECS));
+
+    if let Err(e) = database::enqueue_outbox(
+        &ctx.db_pool,
+        &destination,
+        message_id.as_deref(),
+        awaits_ack,
+        &data,
+        expires_at,
+    )
+    .await
+    {
+        eprintln!("Failed to persist outgoing frame: {}", e);
+        return;
+    }
+    if let Some(message_id) = &message_id {
+        if let Err(e) = database::set_message_hlc(&ctx.db_pool, message_id, clock).await {
+            eprintln!("Failed to stamp message {}: {}", message_id, e);
+        }
+        update_delivery_status(ctx, message_id, &destination, DeliveryStatus::Pending).await;
+    }
+    if let Some(message) = &message {
+        super::history::record_message(ctx, message).await;
+    }
+
+    flush_pending(ctx).await;
+}
+
+/// Recipient of a sealed direct message or of its ack, which only the stored
+/// message names: direct messages are stored under the other end of the
+/// conversation, whichever way they went.
+async fn sealed_recipient<R: Runtime>(ctx: &Arc<AppContext<R>>, message: &AepMessage) -> Option<String> {
+    let (AepMessage::SealedChatMessage { message_id, .. } | AepMessage::SealedDeliveryAck { message_id, .. }) = message
+    else {
+        return None;
+    };
+    match database::get_message_metadata(&ctx.db_pool, message_id).await {
+        Ok(metadata) => metadata.map(|metadata| metadata.chat_id),
+        Err(e) => {
+            eprintln!("Failed to look up recipient of {}: {}", message_id, e);
+            None
         }
     }
 }
 
+/// Drops expired outbox entries and sends every due one. Each attempt is counted and rescheduled
+/// before sending, so an entry still waiting on its first hop is not sent
+/// twice.
 pub async fn flush_pending<R: Runtime>(ctx: &Arc<AppContext<R>>) {
+    let now = chrono::Utc::now();
+    if let Err(e) = database::remove_expired_outbox_entries(&ctx.db_pool, now).await {
+        eprintln!("Failed to drop expired outbox entries: {}", e);
+    }
     {
         let guard = ctx.network.shared_swarm.lock().await;
         if !network::has_any_peers(&guard) { return; }
     }
 
-    let mut pending = ctx.outbox.lock().await;
-    if pending.is_empty() { return; }
+    let due = match database::due_outbox_entries(&ctx.db_pool, now, FLUSH_BATCH).await {
+        Ok(due) => due,
+        Err(e) => {
+            eprintln!("Failed to load outbox: {}", e);
+            return;
+        }
+    };
+
+    for entry in due {
+        attempt(ctx, entry).await;
+    }
+}
+
+async fn attempt<R: Runtime>(ctx: &Arc<AppContext<R>>, entry: OutboxEntry) {
+    let attempts = match database::record_outbox_attempt(&ctx.db_pool, &entry, chrono::Utc::now()).await {
+        Ok(attempts) => attempts,
+        Err(e) => {
+            eprintln!("Failed to update outbox entry {}: {}", entry.id, e);
+            return;
+        }
+    };
+    if attempts > MAX_OUTBOX_ATTEMPTS {
+        give_up(ctx, &entry).await;
+        return;
+    }
+
+    let mut swarm = ctx.network.shared_swarm.lock().await;
+    if entry.destination == BROADCAST_DESTINATION {
+        let published = broadcast(ctx, &mut swarm, &scope_of(&entry), entry.payload.clone()).await;
+        drop(swarm);
+        match published {
+            Ok(true) => handed_off(ctx, &entry).await,
+            Ok(false) => {}
+            Err(e) => eprintln!("Failed to send data over network: {}", e),
+        }
+        return;
+    }
+
+    // Point-to-point frames only ever travel along a route or through a
+    // mailbox relay; publishing them on a topic would hand them to everyone.
+    let Ok(destination) = entry.destination.parse::<PeerId>() else {
+        drop(swarm);
+        eprintln!("Outbox entry {} names no valid peer: {}", entry.id, entry.destination);
+        give_up(ctx, &entry).await;
+        return;
+    };
+    let routes = match mode_of(&entry) {
+        DeliveryMode::Multipath => network::MULTIPATH_ROUTES,
+        DeliveryMode::SinglePath => 1,
+    };
+    let sent = {
+        let mut router = ctx.network.router.lock().await;
+        network::send_multipath(&mut swarm, &mut router, sender_of(ctx, &entry), &destination, routes, entry.payload.clone())
+    };
+    if sent.is_empty() {
+        deposit_or_wait(ctx, &mut swarm, &destination, &entry).await;
+        return;
+    }
+    if sent.len() > 1 {
+        ctx.network.multipath.lock().await.insert(
+            entry.id,
+            MultipathDelivery { outstanding: sent.len(), accepted: false },
+        );
+    }
+    let mut pending = ctx.network.pending_direct.lock().await;
+    for dispatch in sent {
+        pending.insert(
+            dispatch.request_id,
+            PendingDelivery { path: dispatch.path, outbox_id: Some(entry.id) },
+        );
+    }
+}
+
+/// A point-to-point frame could not be routed. Messages awaiting an ack are
+/// left with mailbox relays in case the destination is offline; anything
+/// else stays queued until the next retry, and fails once those run out.
+async fn deposit_or_wait<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    swarm: &mut Swarm<network::Behaviour>,
+    destination: &PeerId,
+    entry: &OutboxEntry,
+) {
+    if entry.awaits_ack
+        && super::mailbox::deposit(ctx, swarm, sender_of(ctx, entry), destination, entry.id, &entry.payload).await
+    {
+        return;
+    }
+    eprintln!(
+        "No route or mailbox relay for {}; outbox entry {} waits for its next retry",
+        destination, entry.id
+    );
+}
+
+fn scope_of(entry: &OutboxEntry) -> TopicScope {
+    wire::decode(&entry.payload)
+        .map(|decoded| decoded.message.topic_scope())
+        .unwrap_or(TopicScope::Global)
+}
+
+/// Who an entry goes out as: us, or nobody for sealed frames, which travel
+/// unsigned and name no origin.
+fn sender_of<'a, R: Runtime>(ctx: &'a Arc<AppContext<R>>, entry: &OutboxEntry) -> Option<&'a Keypair> {
+    let sealed = wire::decode(&entry.payload)
+        .is_ok_and(|decoded| decoded.message.signature_policy() == SignaturePolicy::Sealed);
+    (!sealed).then(|| ctx.app_state.identity.keypair())
+}
+
+fn mode_of(entry: &OutboxEntry) -> DeliveryMode {
+    wire::decode(&entry.payload)
+        .map(|decoded| decoded.message.delivery_mode())
+        .unwrap_or(DeliveryMode::SinglePath)
+}
+
+/// Publishes on the topic of the message's server or conversation, or the
+/// global topic when we are not subscribed to it. Returns whether any peer
+/// was there to receive it.
+pub(super) async fn broadcast<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    swarm: &mut Swarm<network::Behaviour>,
+    scope: &TopicScope,
+    data: Vec<u8>,
+) -> Result<bool, String> {
+    let topic = ctx.network.topics.lock().await.publish_topic(scope);
+    network::send_data(swarm, &topic, ctx.app_state.identity.keypair(), data)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// The frame left this node. Entries that expect a delivery ack stay queued
+/// and are sent again if the ack does not arrive in time.
+async fn handed_off<R: Runtime>(ctx: &Arc<AppContext<R>>, entry: &OutboxEntry) {
+    let result = if entry.awaits_ack {
+        let retry_at = chrono::Utc::now() + chrono::Duration::seconds(ACK_TIMEOUT_SECS);
+        database::postpone_outbox_entry(&ctx.db_pool, entry.id, retry_at).await
+    } else {
+        database::remove_outbox_entry(&ctx.db_pool, entry.id).await
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to update outbox entry {}: {}", entry.id, e);
+    }
+    if let Some(message_id) = &entry.message_id {
+        update_delivery_status(ctx, message_id, &entry.destination, DeliveryStatus::Sent).await;
+    }
+}
 
+async fn give_up<R: Runtime>(ctx: &Arc<AppContext<R>>, entry: &OutboxEntry) {
+    if let Err(e) = database::remove_outbox_entry(&ctx.db_pool, entry.id).await {
+        eprintln!("Failed to drop outbox entry {}: {}", entry.id, e);
+    }
+    if let Some(message_id) = &entry.message_id {
+        update_delivery_status(ctx, message_id, &entry.destination, DeliveryStatus::Failed).await;
+    }
+}
+
+/// Records how one copy of a direct delivery fared. Returns the outcome to
+/// act on: for a multipath delivery that is the first accepted copy, or the
+/// last copy failing when none was accepted; `None` while that is not known
+/// yet or was already acted on.
+pub(super) async fn settle_copy<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    outbox_id: i64,
+    accepted: bool,
+) -> Option<bool> {
+    let mut multipath = ctx.network.multipath.lock().await;
+    let Some(delivery) = multipath.get_mut(&outbox_id) else {
+        return Some(accepted);
+    };
+    delivery.outstanding = delivery.outstanding.saturating_sub(1);
+    let first_accepted = accepted && !delivery.accepted;
+    delivery.accepted |= accepted;
+    let all_failed = delivery.outstanding == 0 && !delivery.accepted;
+    if delivery.outstanding == 0 {
+        multipath.remove(&outbox_id);
+    }
+    if first_accepted {
+        Some(true)
+    } else if all_failed {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// The first hop of a direct delivery, or a mailbox relay, accepted the frame.
+pub(super) async fn delivery_accepted<R: Runtime>(ctx: &Arc<AppContext<R>>, outbox_id: i64) {
+    if let Ok(Some(entry)) = database::get_outbox_entry(&ctx.db_pool, outbox_id).await {
+        handed_off(ctx, &entry).await;
+    }
+}
+
+/// Every copy of a direct delivery failed. The frame goes to a mailbox relay
+/// when it can, or waits in the outbox for its next retry.
+pub(super) async fn direct_delivery_failed<R: Runtime>(ctx: &Arc<AppContext<R>>, outbox_id: i64) {
+    let Ok(Some(entry)) = database::get_outbox_entry(&ctx.db_pool, outbox_id).await else {
+        return;
+    };
+    let Ok(destination) = entry.destination.parse::<PeerId>() else {
+        return;
+    };
     let mut swarm = ctx.network.shared_swarm.lock().await;
-    let mut router = ctx.network.router.lock().await;
-    let mut remaining: Vec<Vec<u8>> = Vec::new();
+    deposit_or_wait(ctx, &mut swarm, &destination, &entry).await;
+}
 
-    for data in pending.drain(..) {
-        if let Err(e) = network::send_data(&mut swarm, &ctx.network.topic, &mut router, data.clone()).await {
-            eprintln!("Failed to flush queued data: {}", e);
-            remaining.push(data);
+/// Makes everything queued for `peer` due now; called when it connects.
+pub async fn expedite_peer<R: Runtime>(ctx: &Arc<AppContext<R>>, peer: &PeerId) {
+    if let Err(e) = database::expedite_outbox_destination(&ctx.db_pool, &peer.to_base58()).await {
+        eprintln!("Failed to expedite outbox for {}: {}", peer, e);
+    }
+}
+
+pub(super) async fn update_delivery_status<R: Runtime>(
+    ctx: &Arc<AppContext<R>>,
+    message_id: &str,
+    recipient_id: &str,
+    status: DeliveryStatus,
+) {
+    match database::record_delivery_status(&ctx.db_pool, message_id, recipient_id, status).await {
+        Ok(true) => {
+            let _ = ctx.app.emit("message-delivery-updated", DeliveryStatusEventPayload {
+                message_id: message_id.to_string(),
+                recipient_id: recipient_id.to_string(),
+                status,
+            });
         }
+        Ok(false) => {}
+        Err(e) => eprintln!("Failed to record delivery status for {}: {}", message_id, e),
     }
-    pending.extend(remaining);
 }
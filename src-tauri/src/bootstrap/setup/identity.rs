@@ -12,6 +12,7 @@ pub(super) async fn initialize_identity_state(
     db_pool: &sqlx::Pool<sqlx::Sqlite>,
     directories: &AppDirectories,
     app_state: &AppState,
+    password: &str,
 ) -> Result<(), String> {
     let my_peer_id = identity.peer_id().to_base58();
     let my_pubkey_b58 = bs58::encode(identity.public_key_protobuf_bytes()).into_string();
@@ -49,7 +50,7 @@ pub(super) async fn initialize_identity_state(
     }
 
     broadcast_profile(identity, &ensure_user, &app_state.network_tx).await?;
-    broadcast_prekey_bundle(identity, directories, &app_state.network_tx).await?;
+    broadcast_prekey_bundle(identity, directories, password, &app_state.network_tx).await?;
 
     Ok(())
 }
@@ -78,18 +79,31 @@ async fn broadcast_profile(
 async fn broadcast_prekey_bundle(
     identity: &Identity,
     directories: &AppDirectories,
+    password: &str,
     network_tx: &TokioSender<Vec<u8>>,
 ) -> Result<(), String> {
     let e2ee_dir = directories.data_dir().join("e2ee");
     if !e2ee_dir.exists() {
         std::fs::create_dir_all(&e2ee_dir).map_err(|e| e.to_string())?;
     }
-    let mgr_arc = e2ee::init_with_dir(&e2ee_dir);
-    let bundle_bytes = {
-        let mut mgr = mgr_arc.lock().await;
-        let bundle = mgr.generate_prekey_bundle(8).map_err(|e| e.to_string())?;
-        bincode::serialize(&bundle).map_err(|e| e.to_string())?
-    };
+    let storage_key = crate::commands::identity::e2ee_storage_key(directories.data_dir(), password)?;
+    let mgr_arc = e2ee::init_with_dir(&e2ee_dir, storage_key)
+        .map_err(|e| format!("Failed to unlock e2ee state: {}", e))?;
+    let bundle = mgr_arc
+        .lock()
+        .await
+        .generate_prekey_bundle(e2ee::ONE_TIME_KEY_TARGET)
+        .map_err(|e| e.to_string())?;
+
+    publish_prekey_bundle(identity, &bundle, network_tx).await
+}
+
+pub(super) async fn publish_prekey_bundle(
+    identity: &Identity,
+    bundle: &e2ee::PrekeyBundle,
+    network_tx: &TokioSender<Vec<u8>>,
+) -> Result<(), String> {
+    let bundle_bytes = bundle.to_bytes().map_err(|e| e.to_string())?;
 
     let signature = identity
         .keypair()
@@ -1,26 +1,80 @@
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+use libp2p::request_response::RequestId;
 use libp2p::swarm::Swarm;
+use libp2p::PeerId;
 use tokio::sync::Mutex;
 
+use aegis_protocol::hlc::HybridClock;
+use aegis_shared_types::{DeniedPeerRecord, ProxySettings};
 use crypto::identity::Identity;
-use network::{AerpRouter, Behaviour, Topic};
+use network::validation::GossipValidator;
+use network::{AerpRouter, Behaviour, DenyList, TopicRegistry};
+
+/// Direct delivery awaiting the next hop's response. `outbox_id` is set for
+/// frames we originated and points at their durable outbox entry.
+pub(super) struct PendingDelivery {
+    pub path: Vec<PeerId>,
+    pub outbox_id: Option<i64>,
+}
+
+/// Copies of a multipath delivery still in flight, and whether one of them
+/// has already been accepted.
+pub(super) struct MultipathDelivery {
+    pub outstanding: usize,
+    pub accepted: bool,
+}
 
 #[derive(Clone)]
 pub(super) struct NetworkResources {
     pub shared_swarm: Arc<Mutex<Swarm<Behaviour>>>,
     pub router: Arc<Mutex<AerpRouter>>,
-    pub topic: Topic,
+    pub topics: Arc<Mutex<TopicRegistry>>,
+    /// Checks gossip frames before they are handled or forwarded.
+    pub validator: Arc<Mutex<GossipValidator>>,
+    /// Blocked and banned peers the swarm refuses.
+    pub deny_list: Arc<Mutex<DenyList>>,
+    pub pending_direct: Arc<Mutex<HashMap<RequestId, PendingDelivery>>>,
+    /// Outbox entries sent over more than one route, by entry.
+    pub multipath: Arc<Mutex<HashMap<i64, MultipathDelivery>>>,
+    /// Peers that advertised the mailbox protocol.
+    pub mailbox_relays: Arc<Mutex<HashSet<PeerId>>>,
+    /// Mailbox deposits awaiting the relay's answer, by outbox entry.
+    pub pending_deposits: Arc<Mutex<HashMap<RequestId, i64>>>,
+    /// Stamps the messages we send and takes in the stamps of those we
+    /// receive, so chats are ordered the same way on every peer.
+    pub clock: Arc<Mutex<HybridClock>>,
 }
 
-pub(super) async fn initialize_network(identity: &Identity) -> Result<NetworkResources, String> {
-    let (swarm, topic, router) = network::initialize_network(identity.keypair().clone())
+pub(super) async fn initialize_network(
+    identity: &Identity,
+    proxy: Option<&ProxySettings>,
+    denied_peers: &[DeniedPeerRecord],
+) -> Result<NetworkResources, String> {
+    // A saved proxy that no longer parses must not fall back to direct
+    // connections behind the user's back.
+    let proxy = proxy.map(crate::connectivity::proxy_config).transpose()?;
+    let (mut swarm, topics, router) = network::initialize_network(identity.keypair().clone(), proxy)
         .await
         .map_err(|e| format!("Failed to initialize network: {}", e))?;
+    let mut deny_list = DenyList::new();
+    deny_list.restore(
+        &mut swarm,
+        denied_peers.iter().filter_map(crate::connectivity::denied_peer).collect(),
+        chrono::Utc::now(),
+    );
 
     Ok(NetworkResources {
         shared_swarm: Arc::new(Mutex::new(swarm)),
         router: Arc::new(Mutex::new(router)),
-        topic,
+        topics: Arc::new(Mutex::new(topics)),
+        validator: Arc::new(Mutex::new(GossipValidator::new())),
+        deny_list: Arc::new(Mutex::new(deny_list)),
+        pending_direct: Arc::new(Mutex::new(HashMap::new())),
+        multipath: Arc::new(Mutex::new(HashMap::new())),
+        mailbox_relays: Arc::new(Mutex::new(HashSet::new())),
+        pending_deposits: Arc::new(Mutex::new(HashMap::new())),
+        clock: Arc::new(Mutex::new(HybridClock::new())),
     })
 }
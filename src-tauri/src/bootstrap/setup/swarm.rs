@@ -1,7 +1,6 @@
-use std::collections::VecDeque;
 use std::sync::Arc;
 use tauri::{AppHandle, Runtime};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc;
 use libp2p::futures::StreamExt;
 use libp2p::swarm::SwarmEvent;
 
@@ -19,7 +18,6 @@ pub(super) fn spawn_swarm_processing<R: Runtime>(
     mut net_rx: mpsc::Receiver<Vec<u8>>,
     mut file_rx: mpsc::Receiver<aegis_shared_types::FileTransferCommand>,
     event_tx: mpsc::Sender<AepMessage>,
-    outbox: Arc<Mutex<VecDeque<Vec<u8>>>>,
 ) {
     let ctx = Arc::new(context::AppContext {
         app,
@@ -27,13 +25,16 @@ pub(super) fn spawn_swarm_processing<R: Runtime>(
         app_state,
         db_pool,
         event_tx,
-        outbox,
     });
 
     let ctx_clone = ctx.clone();
 
     tokio::spawn(async move {
+        handlers::mailbox::dial_configured_relays(&ctx_clone).await;
+        handlers::discovery::bootstrap_dht(&ctx_clone).await;
         let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+        let mut cover_interval = tokio::time::interval(crate::network::privacy::COVER_INTERVAL);
+        let mut history_interval = tokio::time::interval(handlers::history::SYNC_INTERVAL);
         loop {
             tokio::select! {
                 maybe = net_rx.recv() => {
@@ -55,9 +56,24 @@ pub(super) fn spawn_swarm_processing<R: Runtime>(
                         SwarmEvent::Behaviour(ComposedEvent::Gossipsub(e)) => {
                             handlers::gossip::handle_gossip_event(&ctx_clone, e).await;
                         }
+                        SwarmEvent::Behaviour(ComposedEvent::Identify(e)) => {
+                            handlers::discovery::handle_identify_event(&ctx_clone, e).await;
+                        }
                         SwarmEvent::Behaviour(ComposedEvent::Mdns(e)) => {
                             handlers::discovery::handle_mdns_event(&ctx_clone, e).await;
                         }
+                        SwarmEvent::Behaviour(ComposedEvent::Direct(e)) => {
+                            handlers::direct::handle_direct_event(&ctx_clone, e).await;
+                        }
+                        SwarmEvent::Behaviour(ComposedEvent::Mailbox(e)) => {
+                            handlers::mailbox::handle_mailbox_event(&ctx_clone, e).await;
+                        }
+                        SwarmEvent::Behaviour(ComposedEvent::Sync(e)) => {
+                            handlers::history::handle_sync_event(&ctx_clone, e).await;
+                        }
+                        SwarmEvent::Behaviour(ComposedEvent::Kademlia(e)) => {
+                            handlers::discovery::handle_kademlia_event(&ctx_clone, e).await;
+                        }
                         SwarmEvent::Behaviour(ComposedEvent::ReqRes(msg)) => {
                             match msg {
                                 libp2p::request_response::RequestResponseEvent::Message { peer, message } => {
@@ -66,12 +82,42 @@ pub(super) fn spawn_swarm_processing<R: Runtime>(
                                 _ => {}
                             }
                         }
+                        SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established, .. } => {
+                            let local_peer = ctx_clone.app_state.identity.peer_id();
+                            crate::network::transports::on_connection_established(&local_peer, &peer_id, &endpoint);
+                            if let Some((_, quality)) = crate::network::transports::best_link(&local_peer, &peer_id) {
+                                ctx_clone.network.router.lock().await.observe_direct_link(local_peer, peer_id, quality);
+                            }
+                            handlers::outbox::expedite_peer(&ctx_clone, &peer_id).await;
+                            if num_established.get() == 1 {
+                                handlers::history::sync_with_peer(&ctx_clone, &peer_id).await;
+                            }
+                        }
+                        SwarmEvent::ConnectionClosed { peer_id, endpoint, num_established, .. } => {
+                            let local_peer = ctx_clone.app_state.identity.peer_id();
+                            crate::network::transports::on_connection_closed(&local_peer, &peer_id, &endpoint);
+                            if num_established == 0 {
+                                crate::network::forget_peer(&local_peer, &peer_id);
+                            }
+                        }
+                        SwarmEvent::NewListenAddr { address, .. } => {
+                            if crate::network::circuit::is_circuit(&address) {
+                                eprintln!("Reachable through relay at {}", address);
+                            }
+                        }
                         _ => {}
                     }
                 }
                 _ = interval.tick() => {
                     handlers::outbox::flush_pending(&ctx_clone).await;
                 }
+                _ = history_interval.tick() => {
+                    handlers::history::sync_connected_peers(&ctx_clone).await;
+                }
+                _ = cover_interval.tick() => {
+                    let mut swarm = ctx_clone.network.shared_swarm.lock().await;
+                    crate::network::privacy::send_cover_traffic(&mut swarm);
+                }
             }
         }
     });
@@ -4,7 +4,10 @@ use tokio::sync::mpsc::{Receiver, Sender as TokioSender};
 use aegis_protocol::AepMessage;
 use crypto::identity::Identity;
 
+use super::super::topics::{membership_topic_scopes, topic_resync_signal};
 use super::super::{broadcast_group_key_update, rotate_and_broadcast_group_key};
+use super::identity::publish_prekey_bundle;
+use super::network::NetworkResources;
 
 pub(super) fn spawn_event_dispatcher<R: Runtime>(
     app: AppHandle<R>,
@@ -19,6 +22,30 @@ pub(super) fn spawn_event_dispatcher<R: Runtime>(
     });
 }
 
+/// Periodically rotates the signed prekey and refills the one-time key pool
+/// so peers can keep opening sessions while we are offline.
+pub(super) fn spawn_prekey_maintenance(identity: Identity, network_tx: TokioSender<Vec<u8>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(10 * 60));
+        loop {
+            let _ = interval.tick().await;
+            let Some(manager) = e2ee::global() else {
+                continue;
+            };
+            let refreshed = manager.lock().await.replenish_prekeys();
+            match refreshed {
+                Ok(Some(bundle)) => {
+                    if let Err(error) = publish_prekey_bundle(&identity, &bundle, &network_tx).await {
+                        eprintln!("Failed to publish refreshed prekeys: {}", error);
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => eprintln!("Prekey maintenance failed: {}", error),
+            }
+        }
+    });
+}
+
 pub(super) fn spawn_group_key_rotation(
     db_pool: sqlx::Pool<sqlx::Sqlite>,
     identity: Identity,
@@ -91,6 +118,7 @@ pub(super) fn spawn_group_key_rotation(
                                 &server.id,
                                 &Some(channel.id.clone()),
                                 epoch,
+                                None,
                             )
                             .await
                             {
@@ -116,3 +144,139 @@ pub(super) fn spawn_group_key_rotation(
         }
     });
 }
+
+/// Archives threads that have gone without replies for a while and tells the
+/// UI which ones.
+pub(super) fn spawn_thread_archiving<R: Runtime>(app: AppHandle<R>, db_pool: sqlx::Pool<sqlx::Sqlite>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60));
+        loop {
+            interval.tick().await;
+            match aep::database::archive_inactive_threads(&db_pool, chrono::Utc::now()).await {
+                Ok(archived) if !archived.is_empty() => {
+                    if let Err(error) = app.emit("threads-archived", serde_json::json!({ "threadIds": archived })) {
+                        eprintln!("Failed to emit threads-archived event: {}", error);
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => eprintln!("Failed to archive inactive threads: {}", error),
+            }
+        }
+    });
+}
+
+/// Keeps the gossip subscriptions in line with the servers and group chats we
+/// belong to. Resyncs whenever membership changes and every few minutes in
+/// case a change arrived without a signal.
+pub(super) fn spawn_topic_subscriptions(
+    network: NetworkResources,
+    db_pool: sqlx::Pool<sqlx::Sqlite>,
+    user_id: String,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(5 * 60));
+        loop {
+            match membership_topic_scopes(&db_pool, &user_id).await {
+                Ok(scopes) => {
+                    let mut swarm = network.shared_swarm.lock().await;
+                    let report = network
+                        .topics
+                        .lock()
+                        .await
+                        .sync(&mut swarm.behaviour_mut().gossipsub, scopes);
+                    if !report.subscribed.is_empty() || !report.unsubscribed.is_empty() {
+                        eprintln!(
+                            "Gossip topics updated: joined {}, left {}",
+                            report.subscribed.len(),
+                            report.unsubscribed.len()
+                        );
+                    }
+                }
+                Err(error) => eprintln!("Failed to load memberships for topics: {}", error),
+            }
+
+            tokio::select! {
+                _ = topic_resync_signal().notified() => {}
+                _ = interval.tick() => {}
+            }
+        }
+    });
+}
+
+/// Re-walks the DHT periodically so the routing table keeps up with peers
+/// joining and leaving.
+pub(super) fn spawn_dht_refresh(network: NetworkResources) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(5 * 60));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let mut swarm = network.shared_swarm.lock().await;
+            crate::network::dht::refresh(&mut swarm);
+        }
+    });
+}
+
+/// Lets peers back in once their time-limited bans run out.
+pub(super) fn spawn_deny_list_expiry(network: NetworkResources) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+            let mut deny_list = network.deny_list.lock().await;
+            let mut swarm = network.shared_swarm.lock().await;
+            for entry in deny_list.expire(&mut swarm, chrono::Utc::now()) {
+                eprintln!("Deny list entry for {} expired", entry.peer);
+            }
+        }
+    });
+}
+
+/// Renews circuit reservations that failed or were dropped by their relay.
+pub(super) fn spawn_relay_circuits(
+    network: NetworkResources,
+    relays: std::sync::Arc<tokio::sync::Mutex<Vec<aegis_shared_types::RelayRecord>>>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let relays = relays.lock().await.clone();
+            let mut swarm = network.shared_swarm.lock().await;
+            crate::connectivity::sync_relay_circuits(&mut swarm, &relays);
+        }
+    });
+}
+
+/// Floods a signed advert of our direct links every routing interval so
+/// peers several hops away can route to us, and ages out adverts that other
+/// nodes stopped refreshing. Sequence numbers start from the clock so adverts
+/// sent after a restart still supersede the previous run's.
+pub(super) fn spawn_link_state_adverts(network: NetworkResources, identity: Identity) {
+    tokio::spawn(async move {
+        let mut sequence = chrono::Utc::now().timestamp_millis().max(0) as u64;
+        loop {
+            let interval_secs = {
+                let mut swarm = network.shared_swarm.lock().await;
+                let mut router = network.router.lock().await;
+                router.expire_adverts(std::time::Instant::now());
+                let interval_secs = router.config().update_interval_secs.max(1);
+                // Outlives a couple of missed refreshes before peers drop it.
+                let max_age = std::time::Duration::from_secs(interval_secs * 3);
+                sequence += 1;
+                if let Err(error) = crate::network::publish_link_state(
+                    &mut swarm,
+                    &router,
+                    identity.keypair(),
+                    sequence,
+                    max_age,
+                ) {
+                    eprintln!("Failed to publish link-state advert: {}", error);
+                }
+                interval_secs
+            };
+            tokio::time::sleep(std::time::Duration::from_secs(interval_secs)).await;
+        }
+    });
+}
@@ -0,0 +1,35 @@
+use std::sync::OnceLock;
+
+use aegis_protocol::TopicScope;
+use tokio::sync::Notify;
+
+static TOPIC_RESYNC: OnceLock<Notify> = OnceLock::new();
+
+pub(crate) fn topic_resync_signal() -> &'static Notify {
+    TOPIC_RESYNC.get_or_init(Notify::new)
+}
+
+/// Asks the swarm task to recompute its gossip subscriptions. Call after any
+/// change to the servers or group chats we belong to.
+pub(crate) fn request_topic_resync() {
+    topic_resync_signal().notify_one();
+}
+
+/// Topics for every server and group chat `user_id` is a member of.
+pub(crate) async fn membership_topic_scopes(
+    db_pool: &sqlx::Pool<sqlx::Sqlite>,
+    user_id: &str,
+) -> Result<Vec<TopicScope>, String> {
+    let servers = aep::database::get_all_servers(db_pool, user_id)
+        .await
+        .map_err(|e| e.to_string())?;
+    let groups = aep::database::get_group_chats_for_user(db_pool, user_id)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(servers
+        .into_iter()
+        .map(|server| TopicScope::Server(server.id))
+        .chain(groups.into_iter().map(|group| TopicScope::Conversation(group.chat.id)))
+        .collect())
+}
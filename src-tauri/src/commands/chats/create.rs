@@ -45,7 +45,7 @@ async fn create_group_dm_internal(
         return Err("Please select at least one additional member.".to_string());
     }
 
-    let group_id = Scu128::new().to_string();
+    let group_id = Scu128::new().generate();
     let created_at = Utc::now();
     let normalized_name = normalize_group_name(name, &group_id);
 
@@ -68,6 +68,7 @@ async fn create_group_dm_internal(
     database::upsert_group_chat(&state.db_pool, &chat, &members)
         .await
         .map_err(|e| e.to_string())?;
+    crate::bootstrap::request_topic_resync();
 
     let payload = GroupChatPayload::from_chat(&chat, participants.clone());
 
@@ -29,6 +29,7 @@ async fn leave_group_dm_internal(state: AppState, group_id: String) -> Result<()
     if !removed {
         return Err("You are not a member of this group.".to_string());
     }
+    crate::bootstrap::request_topic_resync();
 
     let payload = LeaveGroupChatData {
         group_id: group_id.clone(),
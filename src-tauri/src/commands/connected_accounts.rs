@@ -65,7 +65,7 @@ pub async fn link_external_account(
     }
 
     let mut accounts = load_accounts(&app)?;
-    let id = Scu128::new().to_string();
+    let id = Scu128::new().generate();
     let account = ExternalAccount {
         id,
         provider: normalized_provider.to_string(),
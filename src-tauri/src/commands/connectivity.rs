@@ -1,5 +1,6 @@
 use aegis_shared_types::{
-    ConnectivityEventPayload, ConnectivityGatewayStatus, ConnectivityTransportStatus,
+    ConnectivityEventPayload, ConnectivityGatewayStatus, ConnectivityTransportStatus, DeniedPeerRecord,
+    PrivacySettings, ProxySettings,
 };
 use tauri::{AppHandle, Runtime, State};
 
@@ -51,3 +52,184 @@ pub async fn set_routing_config<R: Runtime>(
 ) -> Result<network::AerpConfig, String> {
     crate::connectivity::set_routing_config(&app, update_interval_secs, min_quality, max_hops).await
 }
+
+#[tauri::command]
+pub async fn get_bootstrap_peers(
+    state_container: State<'_, AppStateContainer>,
+) -> Result<Vec<String>, String> {
+    let state_guard = state_container.0.lock().await;
+    let state = state_guard
+        .as_ref()
+        .ok_or_else(|| "State not initialized".to_string())?;
+    let settings_path = state.app_data_dir.join("settings.json");
+    Ok(crate::settings_store::load_settings(&settings_path)?.bootstrap_peers)
+}
+
+/// Saves the DHT bootstrap peers and seeds the running node with them.
+#[tauri::command]
+pub async fn set_bootstrap_peers(
+    peers: Vec<String>,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<Vec<String>, String> {
+    let peers = crate::connectivity::normalize_bootstrap_peers(peers)?;
+
+    let state_guard = state_container.0.lock().await;
+    let state = state_guard
+        .as_ref()
+        .ok_or_else(|| "State not initialized".to_string())?
+        .clone();
+    drop(state_guard);
+
+    let settings_path = state.app_data_dir.join("settings.json");
+    let mut persisted = crate::settings_store::load_settings(&settings_path)
+        .unwrap_or_else(|_| crate::settings_store::PersistedSettings::default());
+    persisted.bootstrap_peers = peers.clone();
+    crate::settings_store::save_settings(&settings_path, &persisted)?;
+
+    crate::connectivity::apply_bootstrap_peers(&peers).await?;
+    Ok(peers)
+}
+
+#[tauri::command]
+pub async fn get_proxy_settings(
+    state_container: State<'_, AppStateContainer>,
+) -> Result<Option<ProxySettings>, String> {
+    let state_guard = state_container.0.lock().await;
+    let state = state_guard
+        .as_ref()
+        .ok_or_else(|| "State not initialized".to_string())?;
+    let settings_path = state.app_data_dir.join("settings.json");
+    Ok(crate::settings_store::load_settings(&settings_path)?.proxy)
+}
+
+/// Saves the SOCKS5 proxy, or clears it with `None`. The swarm's transport is
+/// fixed when it starts, so this takes effect after a restart.
+#[tauri::command]
+pub async fn set_proxy_settings(
+    proxy: Option<ProxySettings>,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<Option<ProxySettings>, String> {
+    let proxy = match proxy {
+        Some(settings) => {
+            let config = crate::connectivity::proxy_config(&settings)?;
+            Some(ProxySettings {
+                address: config.address.to_string(),
+                strict: config.strict,
+            })
+        }
+        None => None,
+    };
+
+    let state_guard = state_container.0.lock().await;
+    let state = state_guard
+        .as_ref()
+        .ok_or_else(|| "State not initialized".to_string())?
+        .clone();
+    drop(state_guard);
+
+    let settings_path = state.app_data_dir.join("settings.json");
+    let mut persisted = crate::settings_store::load_settings(&settings_path)
+        .unwrap_or_else(|_| crate::settings_store::PersistedSettings::default());
+    persisted.proxy = proxy.clone();
+    crate::settings_store::save_settings(&settings_path, &persisted)?;
+    Ok(proxy)
+}
+
+#[tauri::command]
+pub async fn get_privacy_settings() -> Result<PrivacySettings, String> {
+    Ok(crate::connectivity::privacy_settings(
+        &crate::network::privacy::privacy_config(),
+    ))
+}
+
+/// Saves privacy mode and applies it to frames sent from now on.
+#[tauri::command]
+pub async fn set_privacy_settings(
+    privacy: PrivacySettings,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<PrivacySettings, String> {
+    let config = crate::connectivity::privacy_config(&privacy);
+    let settings = crate::connectivity::privacy_settings(&config);
+
+    let state_guard = state_container.0.lock().await;
+    let state = state_guard
+        .as_ref()
+        .ok_or_else(|| "State not initialized".to_string())?
+        .clone();
+    drop(state_guard);
+
+    let settings_path = state.app_data_dir.join("settings.json");
+    let mut persisted = crate::settings_store::load_settings(&settings_path)
+        .unwrap_or_else(|_| crate::settings_store::PersistedSettings::default());
+    persisted.privacy = Some(settings.clone());
+    crate::settings_store::save_settings(&settings_path, &persisted)?;
+
+    crate::network::privacy::set_privacy_config(config);
+    Ok(settings)
+}
+
+#[tauri::command]
+pub async fn get_denied_peers() -> Result<Vec<DeniedPeerRecord>, String> {
+    crate::connectivity::denied_peers().await
+}
+
+/// Refuses a peer's connections and gossip, for `duration_secs` or until
+/// lifted. Blocks and server bans add their own entries.
+#[tauri::command]
+pub async fn deny_peer(
+    peer_id: String,
+    duration_secs: Option<u64>,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<Vec<DeniedPeerRecord>, String> {
+    let expires_at = duration_secs
+        .map(|secs| {
+            i64::try_from(secs)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|duration| chrono::Utc::now().checked_add_signed(duration))
+                .ok_or_else(|| format!("Ban duration of {} seconds is too long", secs))
+        })
+        .transpose()?;
+
+    let state_guard = state_container.0.lock().await;
+    let state = state_guard
+        .as_ref()
+        .ok_or_else(|| "State not initialized".to_string())?
+        .clone();
+    drop(state_guard);
+
+    if peer_id == state.identity.peer_id().to_base58() {
+        return Err("Cannot deny your own peer.".to_string());
+    }
+    let settings_path = state.app_data_dir.join("settings.json");
+    crate::connectivity::deny_peer(&settings_path, &peer_id, network::DenyReason::Manual, expires_at).await
+}
+
+/// Lifts a denial made with [`deny_peer`]; blocks and server bans stay.
+#[tauri::command]
+pub async fn lift_peer_denial(
+    peer_id: String,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<Vec<DeniedPeerRecord>, String> {
+    let state_guard = state_container.0.lock().await;
+    let state = state_guard
+        .as_ref()
+        .ok_or_else(|| "State not initialized".to_string())?
+        .clone();
+    drop(state_guard);
+
+    let settings_path = state.app_data_dir.join("settings.json");
+    crate::connectivity::lift_peer_denial(&settings_path, &peer_id, &network::DenyReason::Manual).await
+}
+
+/// Connects to a peer by multiaddr (`.../p2p/<peer id>`) or by the text of a
+/// scanned contact card. Returns the peer ID being dialled.
+#[tauri::command]
+pub async fn dial_peer(target: String) -> Result<String, String> {
+    crate::connectivity::dial_peer(&target).await
+}
+
+#[tauri::command]
+pub async fn get_contact_card() -> Result<String, String> {
+    crate::connectivity::local_contact_card().await
+}
@@ -14,7 +14,7 @@ pub async fn initiate_device_provisioning(
 ) -> CommandResult<DeviceProvisioningState> {
     let state = get_app_state(state_container).await?;
 
-    let bundle_id = Scu128::new().to_string();
+    let bundle_id = Scu128::new().generate();
     let code_phrase = generate_code_phrase();
     let bundle = create_bundle(&bundle_id, &code_phrase);
     let normalized_code = normalize_phrase(&code_phrase);
@@ -59,7 +59,7 @@ pub(super) async fn block_user_internal(
         (friendship.id, false)
     } else {
         let friendship = Friendship {
-            id: Scu128::new().to_string(),
+            id: Scu128::new().generate(),
             user_a_id: my_id.clone(),
             user_b_id: target_user_id.clone(),
             status: FriendshipStatus::BlockedByA.to_string(),
@@ -79,6 +79,18 @@ pub(super) async fn block_user_internal(
             .map_err(|e| e.to_string())?
             .ok_or_else(|| "Failed to load friendship details.".to_string())?;
 
+    let settings_path = state.app_data_dir.join("settings.json");
+    if let Err(error) = crate::connectivity::deny_peer(
+        &settings_path,
+        &target_user_id,
+        network::DenyReason::Blocked,
+        None,
+    )
+    .await
+    {
+        tracing::warn!(target_user_id = %target_user_id, %error, "failed to deny blocked peer");
+    }
+
     let block_user_data = BlockUserData {
         blocker_id: my_id.clone(),
         blocked_id: target_user_id.clone(),
@@ -149,6 +161,17 @@ pub(super) async fn unblock_user_internal(
         .await
         .map_err(|e| e.to_string())?;
 
+    let settings_path = state.app_data_dir.join("settings.json");
+    if let Err(error) = crate::connectivity::lift_peer_denial(
+        &settings_path,
+        &target_user_id,
+        &network::DenyReason::Blocked,
+    )
+    .await
+    {
+        tracing::warn!(target_user_id = %target_user_id, %error, "failed to lift block on peer");
+    }
+
     let unblock_user_data = UnblockUserData {
         unblocker_id: my_id.clone(),
         unblocked_id: target_user_id.clone(),
@@ -21,7 +21,7 @@ pub async fn send_friend_request(
             let my_id = ensure_caller_identity(&state, &current_user_id)?;
             let now = Utc::now();
             let friendship = Friendship {
-                id: Scu128::new().to_string(),
+                id: Scu128::new().generate(),
                 user_a_id: my_id.clone(),
                 user_b_id: target_user_id.clone(),
                 status: FriendshipStatus::Pending.to_string(),
@@ -7,6 +7,7 @@ use serde::Serialize;
 use std::convert::TryInto;
 use std::fs;
 use std::io::{Read, Write};
+use std::path::Path;
 use tauri::{Manager, Runtime, State};
 
 use crate::commands::state::{with_state, AppStateContainer};
@@ -81,30 +82,122 @@ pub async fn rekey_identity<R: Runtime>(
     }
 
     let identity = get_or_create_identity(&app, old_password)?;
+    let manager = unlock_e2ee_storage(&app_data_dir, old_password)?;
+    // Held until the new files are in place, so nothing is saved under the
+    // old key in between.
+    let mut manager = manager.lock().await;
+
+    // Every file keyed from the passphrase is written next to the one it
+    // replaces and moved into place only once all of them are ready, so a
+    // failure part way leaves the old passphrase working.
+    let mut staged = StagedFiles::default();
+
+    let e2ee_path = manager
+        .storage_path()
+        .ok_or_else(|| "E2EE storage path not set".to_string())?;
+    let e2ee_salt = crypto::generate_salt();
+    let e2ee_key = crypto::derive_key(new_password.as_bytes(), &e2ee_salt)
+        .map_err(|e| format!("Failed to derive e2ee storage key: {}", e))?;
+    staged.write(&e2ee_path, &manager.encrypted_state(&e2ee_key))?;
+    staged.write(&e2ee_salt_path(&app_data_dir), e2ee_salt.as_ref().as_bytes())?;
+
+    let store_paths = message_store_key_paths(&app_data_dir);
+    if store_paths.key.exists() {
+        let master = read_message_store_master(&app_data_dir, old_password)?;
+        let (wrapped, salt, nonce) = crypto::encrypt(&master, new_password.as_bytes())
+            .map_err(|e| format!("Failed to wrap message store key: {}", e))?;
+        staged.write(&store_paths.key, &wrapped)?;
+        staged.write(&store_paths.salt, salt.as_ref().as_bytes())?;
+        staged.write(&store_paths.nonce, nonce.as_slice())?;
+    }
 
     let secret = identity
         .to_secret_bytes()
         .ok_or_else(|| "Could not get secret bytes".to_string())?;
-
     let (encrypted_secret, salt, nonce) = crypto::encrypt(&secret, new_password.as_bytes())
         .map_err(|e| format!("Failed to encrypt identity: {}", e))?;
+    staged.write(&identity_path, &encrypted_secret)?;
+    staged.write(&salt_path, salt.as_ref().as_bytes())?;
+    staged.write(&nonce_path, nonce.as_ref())?;
 
-    fs::File::create(&identity_path)
-        .map_err(|e| e.to_string())?
-        .write_all(&encrypted_secret)
-        .map_err(|e| e.to_string())?;
-    fs::File::create(&salt_path)
-        .map_err(|e| e.to_string())?
-        .write_all(salt.as_ref().as_bytes())
-        .map_err(|e| e.to_string())?;
-    fs::File::create(&nonce_path)
-        .map_err(|e| e.to_string())?
-        .write_all(nonce.as_ref())
-        .map_err(|e| e.to_string())?;
-
+    staged.commit()?;
+    manager.set_pickle_key(e2ee_key);
     Ok(())
 }
 
+/// Files written next to the ones they replace, then moved into place
+/// together by [`StagedFiles::commit`]. Whatever is still staged when this is
+/// dropped is deleted.
+#[derive(Default)]
+struct StagedFiles {
+    /// Each target with the file staged for it.
+    files: Vec<(std::path::PathBuf, std::path::PathBuf)>,
+}
+
+impl StagedFiles {
+    fn write(&mut self, target: &Path, bytes: &[u8]) -> Result<(), String> {
+        let staged = sibling_path(target, "staged");
+        fs::write(&staged, bytes).map_err(|e| e.to_string())?;
+        self.files.push((target.to_path_buf(), staged));
+        Ok(())
+    }
+
+    /// Moves every staged file into place. When one cannot be moved, the
+    /// ones already moved are put back, so either all targets change or none
+    /// do.
+    fn commit(mut self) -> Result<(), String> {
+        let files = std::mem::take(&mut self.files);
+        let mut replaced: Vec<(&Path, Option<std::path::PathBuf>)> = Vec::new();
+        for (target, staged) in &files {
+            let backup = target.exists().then(|| sibling_path(target, "previous"));
+            let moved = match &backup {
+                Some(backup) => fs::rename(target, backup),
+                None => Ok(()),
+            }
+            .and_then(|_| fs::rename(staged, target));
+
+            if let Err(e) = moved {
+                replaced.push((target.as_path(), backup));
+                for (target, backup) in replaced.into_iter().rev() {
+                    match backup {
+                        Some(backup) if backup.exists() => {
+                            let _ = fs::rename(&backup, target);
+                        }
+                        Some(_) => {}
+                        None => {
+                            let _ = fs::remove_file(target);
+                        }
+                    }
+                }
+                for (_, staged) in &files {
+                    let _ = fs::remove_file(staged);
+                }
+                return Err(format!("Failed to replace {}: {}", target.display(), e));
+            }
+            replaced.push((target.as_path(), backup));
+        }
+        for backup in replaced.into_iter().filter_map(|(_, backup)| backup) {
+            let _ = fs::remove_file(backup);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagedFiles {
+    fn drop(&mut self) {
+        for (_, staged) in &self.files {
+            let _ = fs::remove_file(staged);
+        }
+    }
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> std::path::PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
 #[tauri::command]
 pub async fn reset_identity<R: Runtime>(
     app: tauri::AppHandle<R>,
@@ -124,6 +217,24 @@ pub async fn reset_identity<R: Runtime>(
     if nonce_path.exists() {
         fs::remove_file(&nonce_path).map_err(|e| e.to_string())?;
     }
+    // The Olm account belongs to the discarded identity and its pickles are
+    // keyed from the old passphrase, so they cannot be carried over.
+    let e2ee_state_path = app_data_dir.join("e2ee").join("state.e2ee");
+    if e2ee_state_path.exists() {
+        fs::remove_file(&e2ee_state_path).map_err(|e| e.to_string())?;
+    }
+    let e2ee_salt = e2ee_salt_path(&app_data_dir);
+    if e2ee_salt.exists() {
+        fs::remove_file(&e2ee_salt).map_err(|e| e.to_string())?;
+    }
+    // Messages sealed under the old store key become unreadable; a new key is
+    // created on the next unlock.
+    let store_paths = message_store_key_paths(&app_data_dir);
+    for path in [store_paths.key, store_paths.salt, store_paths.nonce] {
+        if path.exists() {
+            fs::remove_file(&path).map_err(|e| e.to_string())?;
+        }
+    }
 
     let identity = Identity::generate();
     let secret = identity
@@ -158,6 +269,110 @@ pub async fn unlock_identity<R: Runtime>(
     crate::bootstrap::initialize_app_state(app, password, state_container).await
 }
 
+fn e2ee_salt_path(app_data_dir: &Path) -> std::path::PathBuf {
+    app_data_dir.join("e2ee").join("state.salt")
+}
+
+/// Derives the key protecting the local e2ee pickles from the identity
+/// passphrase, creating the salt on first use.
+pub(crate) fn e2ee_storage_key(app_data_dir: &Path, password: &str) -> Result<[u8; 32], String> {
+    let salt_path = e2ee_salt_path(app_data_dir);
+    let salt = if salt_path.exists() {
+        let salt_bytes = fs::read(&salt_path).map_err(|e| e.to_string())?;
+        SaltString::from_b64(&String::from_utf8_lossy(&salt_bytes)).map_err(|e| e.to_string())?
+    } else {
+        if let Some(parent) = salt_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let salt = crypto::generate_salt();
+        fs::write(&salt_path, salt.as_ref().as_bytes()).map_err(|e| e.to_string())?;
+        salt
+    };
+
+    crypto::derive_key(password.as_bytes(), &salt)
+        .map_err(|e| format!("Failed to derive e2ee storage key: {}", e))
+}
+
+/// The e2ee manager, loading it with the key derived from `password` when
+/// the app has not unlocked it yet.
+fn unlock_e2ee_storage(
+    app_data_dir: &Path,
+    password: &str,
+) -> Result<std::sync::Arc<tokio::sync::Mutex<e2ee::Manager>>, String> {
+    match e2ee::global() {
+        Some(manager) => Ok(manager),
+        None => {
+            let key = e2ee_storage_key(app_data_dir, password)?;
+            e2ee::init_with_dir(app_data_dir.join("e2ee"), key)
+                .map_err(|e| format!("Failed to unlock e2ee state: {}", e))
+        }
+    }
+}
+
+struct MessageStoreKeyPaths {
+    key: std::path::PathBuf,
+    salt: std::path::PathBuf,
+    nonce: std::path::PathBuf,
+}
+
+fn message_store_key_paths(app_data_dir: &Path) -> MessageStoreKeyPaths {
+    MessageStoreKeyPaths {
+        key: app_data_dir.join("message_store.key"),
+        salt: app_data_dir.join("message_store.salt"),
+        nonce: app_data_dir.join("message_store.nonce"),
+    }
+}
+
+fn write_message_store_key(
+    app_data_dir: &Path,
+    master: &[u8; 32],
+    password: &str,
+) -> Result<(), String> {
+    let paths = message_store_key_paths(app_data_dir);
+    let (wrapped, salt, nonce) = crypto::encrypt(master, password.as_bytes())
+        .map_err(|e| format!("Failed to wrap message store key: {}", e))?;
+    fs::write(&paths.key, wrapped).map_err(|e| e.to_string())?;
+    fs::write(&paths.salt, salt.as_ref().as_bytes()).map_err(|e| e.to_string())?;
+    fs::write(&paths.nonce, nonce.as_slice()).map_err(|e| e.to_string())
+}
+
+fn read_message_store_master(app_data_dir: &Path, password: &str) -> Result<[u8; 32], String> {
+    let paths = message_store_key_paths(app_data_dir);
+    let wrapped = fs::read(&paths.key).map_err(|e| e.to_string())?;
+    let salt_bytes = fs::read(&paths.salt).map_err(|e| e.to_string())?;
+    let salt =
+        SaltString::from_b64(&String::from_utf8_lossy(&salt_bytes)).map_err(|e| e.to_string())?;
+    let nonce_bytes = fs::read(&paths.nonce).map_err(|e| e.to_string())?;
+    let nonce_array: [u8; 24] = nonce_bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| "Invalid nonce length".to_string())?;
+
+    let master = crypto::decrypt(&wrapped, password.as_bytes(), &salt, &nonce_array.into())
+        .map_err(|e| format!("Failed to unwrap message store key: {}", e))?;
+    master
+        .as_slice()
+        .try_into()
+        .map_err(|_| "Invalid message store key length".to_string())
+}
+
+/// Unwraps the random key sealing the message database, creating it on first
+/// use. The key itself never changes; only its passphrase wrapping does, so a
+/// passphrase change does not have to rewrite every message.
+pub(crate) fn message_store_key(
+    app_data_dir: &Path,
+    password: &str,
+) -> Result<crypto::at_rest::AtRestKey, String> {
+    let master = if message_store_key_paths(app_data_dir).key.exists() {
+        read_message_store_master(app_data_dir, password)?
+    } else {
+        let master = crypto::at_rest::AtRestKey::generate_master();
+        write_message_store_key(app_data_dir, &master, password)?;
+        master
+    };
+    Ok(crypto::at_rest::AtRestKey::from_master(&master))
+}
+
 pub(crate) fn get_or_create_identity<R: Runtime>(
     app: &tauri::AppHandle<R>,
     password: &str,
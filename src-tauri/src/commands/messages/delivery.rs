@@ -140,7 +140,7 @@ pub(super) async fn persist_and_broadcast_message(
 
     let payload_conversation_id = Some(chat_id_local.clone());
 
-    let message_id = Scu128::new().to_string();
+    let message_id = Scu128::new().generate();
     let timestamp = chrono::Utc::now();
 
     let mut db_attachments = Vec::new();
@@ -164,7 +164,7 @@ pub(super) async fn persist_and_broadcast_message(
             return Err(format!("Attachment '{name}' is missing binary data"));
         }
 
-        let attachment_id = Scu128::new().to_string();
+        let attachment_id = Scu128::new().generate();
         let data_len = data.len() as u64;
         let sanitized_size = if size == 0 || size != data_len {
             data_len
@@ -211,12 +211,25 @@ pub(super) async fn persist_and_broadcast_message(
         edited_at: None,
         edited_by: None,
         expires_at: expires_at.clone(),
+        delivery_status: None,
+        thread_id: None,
+        thread: None,
     };
 
     database::insert_message(&state.db_pool, &new_local_message, &attachment_data)
         .await
         .map_err(|e| e.to_string())?;
 
+    // Replying in a thread follows it.
+    if let Some(thread_id) = database::thread_of_message(&state.db_pool, &message_id)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        database::follow_thread(&state.db_pool, &thread_id, &peer_id, timestamp)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
     let chat_message_data = aegis_protocol::ChatMessageData {
         id: message_id,
         timestamp,
@@ -402,13 +415,18 @@ pub async fn get_messages(
     chat_id: String,
     limit: i64,
     offset: i64,
+    thread_id: Option<String>,
     state_container: State<'_, AppStateContainer>,
 ) -> Result<Vec<database::Message>, String> {
     let state = state_container.0.lock().await;
     let state = state.as_ref().ok_or("State not initialized")?;
-    database::get_messages_for_chat(&state.db_pool, &chat_id, limit, offset)
-        .await
-        .map_err(|e| e.to_string())
+    let messages = match thread_id {
+        Some(thread_id) => {
+            database::get_messages_for_thread(&state.db_pool, &thread_id, limit, offset).await
+        }
+        None => database::get_messages_for_chat(&state.db_pool, &chat_id, limit, offset).await,
+    };
+    messages.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
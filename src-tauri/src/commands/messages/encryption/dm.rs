@@ -1,8 +1,11 @@
 use std::collections::HashMap;
 use std::sync::atomic::Ordering;
 
+use aegis_protocol::{sealed, AepMessage};
 use aep::database;
 use chrono::Utc;
+use crypto::identity::Identity;
+use libp2p::PeerId;
 use tauri::State;
 
 use crate::commands::state::{with_state_async, AppStateContainer};
@@ -138,6 +141,57 @@ pub async fn decrypt_chat_payload(
     })
 }
 
+/// Encrypts a direct message for `recipient_id` with the sender's id and
+/// signature inside the ciphertext, and seals the Olm packet to the
+/// recipient so only they learn who sent it. Returns the serialized
+/// `SealedChatMessage`.
+async fn seal_dm(
+    identity: Identity,
+    message_id: String,
+    sender_id: String,
+    recipient_id: String,
+    payload: EncryptedDmPayload,
+) -> Result<Vec<u8>, String> {
+    tokio::spawn(async move {
+        let recipient = recipient_id.parse::<PeerId>().map_err(|e| e.to_string())?;
+        let payload = bincode::serialize(&payload).map_err(|e| e.to_string())?;
+        let signed = sealed::signed_bytes(&message_id, &sender_id, &recipient_id, &payload)
+            .map_err(|e| e.to_string())?;
+        let signature = identity.keypair().sign(&signed).map_err(|e| e.to_string())?;
+        let content = sealed::SealedContent {
+            sender: sender_id,
+            signature,
+            payload,
+        };
+        let plaintext = bincode::serialize(&content).map_err(|e| e.to_string())?;
+
+        let pkt = {
+            let e2ee_arc = e2ee::init_global_manager();
+            let mut mgr = e2ee_arc.lock().await;
+            mgr.encrypt_for(&recipient_id, &plaintext)
+                .map_err(|e| format!("E2EE encrypt error: {e}"))?
+        };
+        let packet = sealed::SealedPacket {
+            init: pkt.init,
+            enc_header: pkt.enc_header,
+            enc_content: pkt.enc_content,
+        };
+        let sealed = sealed::sender_secret(identity.keypair(), &recipient, &message_id)
+            .and_then(|secret| secret.seal(&message_id, &packet))
+            .map_err(|e| e.to_string())?;
+
+        let aep_message = AepMessage::SealedChatMessage {
+            message_id,
+            ephemeral_key: sealed.ephemeral_key,
+            recipient_hint: sealed.recipient_hint,
+            ciphertext: sealed.ciphertext,
+        };
+        bincode::serialize(&aep_message).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
 #[tauri::command]
 pub async fn send_encrypted_dm(
     recipient_id: String,
@@ -155,8 +209,9 @@ pub async fn send_encrypted_dm(
             let my_id = state.identity.peer_id().to_base58();
             let expires_at = parse_optional_datetime(expires_at)?;
 
+            let message_id = Scu128::new().generate();
             let new_local_message = database::Message {
-                id: Scu128::new().to_string(),
+                id: message_id.clone(),
                 chat_id: recipient_id.clone(),
                 sender_id: my_id.clone(),
                 content: message.clone(),
@@ -171,6 +226,9 @@ pub async fn send_encrypted_dm(
                 edited_at: None,
                 edited_by: None,
                 expires_at,
+                delivery_status: None,
+                thread_id: None,
+                thread: None,
             };
             database::insert_message(&state.db_pool, &new_local_message, &[])
                 .await
@@ -184,52 +242,8 @@ pub async fn send_encrypted_dm(
                 reply_snapshot_snippet,
             };
 
-            let identity = state.identity.clone();
-            let recipient_id_clone = recipient_id.clone();
-            let my_id_clone = my_id.clone();
-
-            let (pkt, signature) = tokio::spawn(async move {
-                let plaintext = bincode::serialize(&payload).map_err(|e| e.to_string())?;
-
-                let pkt = {
-                    let e2ee_arc = e2ee::init_global_manager();
-                    let mut mgr = e2ee_arc.lock().await;
-                    mgr.encrypt_for(&recipient_id_clone, &plaintext)
-                        .map_err(|e| format!("E2EE encrypt error: {e}"))?
-                };
-
-                let payload_sig_bytes = bincode::serialize(&(
-                    my_id_clone,
-                    recipient_id_clone,
-                    &pkt.enc_header,
-                    &pkt.enc_content,
-                ))
-                .map_err(|e| e.to_string())?;
-
-                let signature = identity
-                    .keypair()
-                    .sign(&payload_sig_bytes)
-                    .map_err(|e| e.to_string())?;
-
-                Ok::<_, String>((pkt, signature))
-            })
-            .await
-            .map_err(|e| e.to_string())??;
-
-            let aep_message = aegis_protocol::AepMessage::EncryptedChatMessage {
-                sender: my_id,
-                recipient: recipient_id,
-                init: pkt.init,
-                enc_header: pkt.enc_header,
-                enc_content: pkt.enc_content,
-                signature: Some(signature),
-            };
-
-            let serialized_message = tokio::task::spawn_blocking(move || {
-                bincode::serialize(&aep_message).map_err(|e| e.to_string())
-            })
-            .await
-            .map_err(|e| e.to_string())??;
+            let serialized_message =
+                seal_dm(state.identity.clone(), message_id, my_id, recipient_id, payload).await?;
 
             state
                 .network_tx
@@ -265,7 +279,7 @@ pub async fn send_encrypted_dm_with_attachments(
                 return Err("Voice memo attachments are disabled by your settings.".to_string());
             }
 
-            let message_id = Scu128::new().to_string();
+            let message_id = Scu128::new().generate();
             let timestamp = Utc::now();
             let mut db_attachments = Vec::with_capacity(attachments.len());
             let mut attachment_data = Vec::with_capacity(attachments.len());
@@ -284,7 +298,7 @@ pub async fn send_encrypted_dm_with_attachments(
                 }
 
                 let sanitized_size = normalize_size(size, data.len());
-                let attachment_id = Scu128::new().to_string();
+                let attachment_id = Scu128::new().generate();
                 let attachment = database::Attachment {
                     id: attachment_id,
                     message_id: message_id.clone(),
@@ -307,7 +321,7 @@ pub async fn send_encrypted_dm_with_attachments(
             }
 
             let new_local_message = database::Message {
-                id: message_id,
+                id: message_id.clone(),
                 chat_id: recipient_id.clone(),
                 sender_id: my_id.clone(),
                 content: message.clone(),
@@ -322,6 +336,9 @@ pub async fn send_encrypted_dm_with_attachments(
                 edited_at: None,
                 edited_by: None,
                 expires_at,
+                delivery_status: None,
+                thread_id: None,
+                thread: None,
             };
             database::insert_message(&state.db_pool, &new_local_message, &attachment_data)
                 .await
@@ -335,52 +352,8 @@ pub async fn send_encrypted_dm_with_attachments(
                 reply_snapshot_snippet,
             };
 
-            let identity = state.identity.clone();
-            let recipient_id_clone = recipient_id.clone();
-            let my_id_clone = my_id.clone();
-
-            let (pkt, signature) = tokio::spawn(async move {
-                let plaintext = bincode::serialize(&payload).map_err(|e| e.to_string())?;
-
-                let pkt = {
-                    let e2ee_arc = e2ee::init_global_manager();
-                    let mut mgr = e2ee_arc.lock().await;
-                    mgr.encrypt_for(&recipient_id_clone, &plaintext)
-                        .map_err(|e| format!("E2EE encrypt error: {e}"))?
-                };
-
-                let payload_sig_bytes = bincode::serialize(&(
-                    my_id_clone,
-                    recipient_id_clone,
-                    &pkt.enc_header,
-                    &pkt.enc_content,
-                ))
-                .map_err(|e| e.to_string())?;
-
-                let signature = identity
-                    .keypair()
-                    .sign(&payload_sig_bytes)
-                    .map_err(|e| e.to_string())?;
-
-                Ok::<_, String>((pkt, signature))
-            })
-            .await
-            .map_err(|e| e.to_string())??;
-
-            let aep_message = aegis_protocol::AepMessage::EncryptedChatMessage {
-                sender: my_id,
-                recipient: recipient_id,
-                init: pkt.init,
-                enc_header: pkt.enc_header,
-                enc_content: pkt.enc_content,
-                signature: Some(signature),
-            };
-
-            let serialized_message = tokio::task::spawn_blocking(move || {
-                bincode::serialize(&aep_message).map_err(|e| e.to_string())
-            })
-            .await
-            .map_err(|e| e.to_string())??;
+            let serialized_message =
+                seal_dm(state.identity.clone(), message_id, my_id, recipient_id, payload).await?;
 
             state
                 .network_tx
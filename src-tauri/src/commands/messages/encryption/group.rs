@@ -12,82 +12,17 @@ pub async fn rotate_group_key(
     epoch: u64,
     state_container: State<'_, AppStateContainer>,
 ) -> Result<(), String> {
-    with_state_async(state_container, move |state| {
-        let server_id = server_id.clone();
-        let channel_id = channel_id.clone();
-        async move {
-            use rand::RngCore;
-
-            let mut key = [0u8; 32];
-            rand::rngs::OsRng.fill_bytes(&mut key);
-
-            {
-                let arc = e2ee::init_global_manager();
-                let mut mgr = arc.lock().await;
-                mgr.set_group_key(&server_id, &channel_id, epoch, &key);
-            }
-
-            let members = aep::database::get_server_members(&state.db_pool, &server_id)
-                .await
-                .map_err(|e| e.to_string())?;
-
-            let my_id = state.identity.peer_id().to_base58();
-            let mut slots = Vec::new();
-            for member in members {
-                if member.id == my_id {
-                    continue;
-                }
-
-                let arc = e2ee::init_global_manager();
-                let mut mgr = arc.lock().await;
-                let pkt = mgr
-                    .encrypt_for(&member.id, &key)
-                    .map_err(|e| format!("E2EE encrypt error: {e}"))?;
-                slots.push(aegis_protocol::EncryptedDmSlot {
-                    recipient: member.id,
-                    init: pkt.init,
-                    enc_header: pkt.enc_header,
-                    enc_content: pkt.enc_content,
-                });
-            }
-
-            let identity = state.identity.clone();
-            let server_id_clone = server_id.clone();
-            let channel_id_clone = channel_id.clone();
-            let issuer_id = my_id;
-
-            let bytes = tokio::task::spawn_blocking(move || {
-                let payload = bincode::serialize(&(
-                    issuer_id,
-                    &server_id_clone,
-                    &channel_id_clone,
-                    epoch,
-                    &slots,
-                ))
-                .map_err(|e| e.to_string())?;
-                let signature = identity
-                    .keypair()
-                    .sign(&payload)
-                    .map_err(|e| e.to_string())?;
-
-                let aep_msg = aegis_protocol::AepMessage::GroupKeyUpdate {
-                    server_id: server_id_clone,
-                    channel_id: channel_id_clone,
-                    epoch,
-                    slots,
-                    signature: Some(signature),
-                };
-                bincode::serialize(&aep_msg).map_err(|e| e.to_string())
-            })
-            .await
-            .map_err(|e| e.to_string())??;
-
-            state
-                .network_tx
-                .send(bytes)
-                .await
-                .map_err(|e| e.to_string())
-        }
+    with_state_async(state_container, move |state| async move {
+        crate::bootstrap::rotate_and_broadcast_group_key(
+            &state.db_pool,
+            state.identity.clone(),
+            &state.network_tx,
+            &server_id,
+            &channel_id,
+            epoch,
+            None,
+        )
+        .await
     })
     .await
 }
@@ -125,6 +60,15 @@ pub async fn send_encrypted_group_message(
 
             let serialized_payload = bincode::serialize(&payload).map_err(|e| e.to_string())?;
 
+            crate::bootstrap::ensure_sender_key(
+                &state.db_pool,
+                identity.clone(),
+                &state.network_tx,
+                &server_id_clone,
+                &channel_id_clone,
+            )
+            .await?;
+
             let (epoch, nonce, ciphertext) = {
                 let arc = e2ee::init_global_manager();
                 let mut mgr = arc.lock().await;
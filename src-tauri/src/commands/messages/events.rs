@@ -29,6 +29,15 @@ pub struct TypingIndicatorEventPayload {
     pub timestamp: String,
 }
 
+#[derive(Clone, Debug, Serialize)]
+pub struct DeliveryStatusEventPayload {
+    #[serde(rename = "messageId")]
+    pub message_id: String,
+    #[serde(rename = "recipientId")]
+    pub recipient_id: String,
+    pub status: aep::database::DeliveryStatus,
+}
+
 pub(super) async fn broadcast_read_receipt(
     state: AppState,
     chat_id: String,
@@ -5,6 +5,7 @@ mod helpers;
 mod link_preview;
 mod moderation;
 mod reactions;
+mod threads;
 mod types;
 
 pub use delivery::*;
@@ -14,6 +15,7 @@ pub use helpers::parse_optional_datetime;
 pub use link_preview::*;
 pub use moderation::*;
 pub use reactions::*;
+pub use threads::*;
 pub use types::*;
 
 #[cfg(test)]
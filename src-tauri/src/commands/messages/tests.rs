@@ -214,7 +214,7 @@ async fn delete_message_is_broadcast_and_applied() {
         .await
         .expect("insert user");
 
-    let message_id = Scu128::new().to_string();
+    let message_id = Scu128::new().generate();
     let chat_id = "chat-123".to_string();
 
     let message = database::Message {
@@ -233,6 +233,9 @@ async fn delete_message_is_broadcast_and_applied() {
         edited_at: None,
         edited_by: None,
         expires_at: None,
+        delivery_status: None,
+        thread_id: None,
+        thread: None,
     };
     database::insert_message(&local_db, &message, &[])
         .await
@@ -374,7 +377,7 @@ async fn edit_message_is_persisted_and_broadcast() {
         .await
         .expect("insert user");
 
-    let message_id = Scu128::new().to_string();
+    let message_id = Scu128::new().generate();
     let chat_id = "chat-456".to_string();
 
     let message = database::Message {
@@ -393,6 +396,9 @@ async fn edit_message_is_persisted_and_broadcast() {
         edited_at: None,
         edited_by: None,
         expires_at: None,
+        delivery_status: None,
+        thread_id: None,
+        thread: None,
     };
     database::insert_message(&local_db, &message, &[])
         .await
@@ -493,3 +499,109 @@ async fn edit_message_is_persisted_and_broadcast() {
         panic!("expected edit message event");
     }
 }
+
+#[tokio::test]
+async fn edits_that_arrive_before_their_message_are_applied_with_it() {
+    let dir = tempdir().expect("tempdir");
+    let db_pool = aep::database::initialize_db(dir.path().join("db.sqlite"))
+        .await
+        .expect("init db");
+
+    let author = Identity::generate();
+    let user_id = author.peer_id().to_base58();
+    let user = User {
+        id: user_id.clone(),
+        username: "Author".into(),
+        avatar: "avatar.png".into(),
+        is_online: true,
+        public_key: Some(
+            bs58::encode(author.keypair().public().to_protobuf_encoding()).into_string(),
+        ),
+        bio: None,
+        tag: None,
+        status_message: None,
+        location: None,
+    };
+    user_service::insert_user(&db_pool, &user)
+        .await
+        .expect("insert user");
+
+    let message_id = Scu128::new().generate();
+    let chat_id = "chat-789".to_string();
+    let data = aegis_protocol::ChatMessageData {
+        id: message_id.clone(),
+        timestamp: Utc::now(),
+        sender: user_id.clone(),
+        content: "Original".into(),
+        channel_id: None,
+        server_id: None,
+        conversation_id: Some(chat_id.clone()),
+        attachments: Vec::new(),
+        expires_at: None,
+        reply_to_message_id: None,
+        reply_snapshot_author: None,
+        reply_snapshot_snippet: None,
+    };
+    let signature = author
+        .keypair()
+        .sign(&bincode::serialize(&data).expect("serialize message"))
+        .expect("sign message");
+    let message = AepMessage::ChatMessage {
+        id: data.id,
+        timestamp: data.timestamp,
+        sender: data.sender,
+        content: data.content,
+        channel_id: data.channel_id,
+        server_id: data.server_id,
+        conversation_id: data.conversation_id,
+        attachments: data.attachments,
+        expires_at: data.expires_at,
+        reply_to_message_id: data.reply_to_message_id,
+        reply_snapshot_author: data.reply_snapshot_author,
+        reply_snapshot_snippet: data.reply_snapshot_snippet,
+        signature: Some(signature),
+    };
+
+    let edit_data = aegis_protocol::MessageEditData {
+        message_id: message_id.clone(),
+        chat_id: chat_id.clone(),
+        editor_id: user_id.clone(),
+        new_content: "Edited".into(),
+        edited_at: Utc::now(),
+    };
+    let signature = author
+        .keypair()
+        .sign(&bincode::serialize(&edit_data).expect("serialize edit"))
+        .expect("sign edit");
+    let edit = AepMessage::EditMessage {
+        message_id: edit_data.message_id,
+        chat_id: edit_data.chat_id,
+        editor_id: edit_data.editor_id,
+        new_content: edit_data.new_content,
+        edited_at: edit_data.edited_at,
+        signature: Some(signature),
+    };
+
+    let state = build_app_state(Identity::generate(), db_pool.clone());
+    aep::handle_aep_message(edit, &db_pool, state.clone())
+        .await
+        .expect("edit is held for its message");
+    let stored = database::get_messages_for_chat(&db_pool, &chat_id, 10, 0)
+        .await
+        .expect("fetch");
+    assert!(stored.is_empty(), "the edit alone should not create a message");
+
+    aep::handle_aep_message(message, &db_pool, state)
+        .await
+        .expect("message is stored");
+    let stored = database::get_messages_for_chat(&db_pool, &chat_id, 10, 0)
+        .await
+        .expect("fetch");
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].content, "Edited");
+    assert_eq!(stored[0].edited_by.as_deref(), Some(user_id.as_str()));
+    assert!(database::take_message_operations(&db_pool, &message_id)
+        .await
+        .expect("take operations")
+        .is_empty());
+}
@@ -0,0 +1,152 @@
+use chrono::Utc;
+use tauri::State;
+
+use aegis_protocol::{AepMessage, CreateThreadData};
+use aegis_shared_types::AppState;
+use aep::database;
+
+use crate::commands::state::AppStateContainer;
+
+async fn get_initialized_state(state_container: &State<'_, AppStateContainer>) -> Result<AppState, String> {
+    let state_guard = state_container.0.lock().await;
+    state_guard
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| "State not initialized".to_string())
+}
+
+/// Opens a thread on `root_message_id` and follows it. Opening a thread
+/// that already exists returns it as it is.
+#[tauri::command]
+pub async fn create_thread(
+    chat_id: String,
+    root_message_id: String,
+    title: Option<String>,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<database::MessageThread, String> {
+    let state = get_initialized_state(&state_container).await?;
+    let creator_id = state.identity.peer_id().to_base58();
+
+    let metadata = database::get_message_metadata(&state.db_pool, &root_message_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Message {} not found", root_message_id))?;
+    if metadata.chat_id != chat_id {
+        return Err(format!("Message {} is not in chat {}", root_message_id, chat_id));
+    }
+    if let Some(thread) = database::get_thread(&state.db_pool, &root_message_id)
+        .await
+        .map_err(|e| e.to_string())?
+    {
+        return Ok(thread);
+    }
+
+    let title = title
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty());
+    let created_at = Utc::now();
+    let created = database::create_thread(
+        &state.db_pool,
+        &root_message_id,
+        &chat_id,
+        &creator_id,
+        title.as_deref(),
+        created_at,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+    if !created {
+        return Err("Replies in a thread cannot start threads of their own".to_string());
+    }
+    database::follow_thread(&state.db_pool, &root_message_id, &creator_id, created_at)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let thread_data = CreateThreadData {
+        root_message_id: root_message_id.clone(),
+        chat_id: chat_id.clone(),
+        creator_id: creator_id.clone(),
+        title: title.clone(),
+        created_at,
+    };
+    let thread_bytes = bincode::serialize(&thread_data).map_err(|e| e.to_string())?;
+    let signature = state
+        .identity
+        .keypair()
+        .sign(&thread_bytes)
+        .map_err(|e| e.to_string())?;
+
+    let aep_message = AepMessage::CreateThread {
+        root_message_id: root_message_id.clone(),
+        chat_id,
+        creator_id,
+        title,
+        created_at,
+        signature: Some(signature),
+    };
+    let serialized = bincode::serialize(&aep_message).map_err(|e| e.to_string())?;
+    state
+        .network_tx
+        .send(serialized)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    database::get_thread(&state.db_pool, &root_message_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Thread {} not found", root_message_id))
+}
+
+#[tauri::command]
+pub async fn get_threads(
+    chat_id: String,
+    include_archived: Option<bool>,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<Vec<database::MessageThread>, String> {
+    let state = get_initialized_state(&state_container).await?;
+    database::get_threads_for_chat(&state.db_pool, &chat_id, include_archived.unwrap_or(false))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_followed_threads(
+    state_container: State<'_, AppStateContainer>,
+) -> Result<Vec<database::MessageThread>, String> {
+    let state = get_initialized_state(&state_container).await?;
+    let user_id = state.identity.peer_id().to_base58();
+    database::get_followed_threads(&state.db_pool, &user_id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn follow_thread(
+    root_message_id: String,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<(), String> {
+    let state = get_initialized_state(&state_container).await?;
+    let user_id = state.identity.peer_id().to_base58();
+    if database::get_thread(&state.db_pool, &root_message_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .is_none()
+    {
+        return Err(format!("Thread {} not found", root_message_id));
+    }
+    database::follow_thread(&state.db_pool, &root_message_id, &user_id, Utc::now())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn unfollow_thread(
+    root_message_id: String,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<(), String> {
+    let state = get_initialized_state(&state_container).await?;
+    let user_id = state.identity.peer_id().to_base58();
+    database::unfollow_thread(&state.db_pool, &root_message_id, &user_id)
+        .await
+        .map_err(|e| e.to_string())
+}
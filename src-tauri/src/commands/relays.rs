@@ -60,7 +60,7 @@ pub async fn register_relay(
     }
 
     if config.id.trim().is_empty() {
-        config.id = Scu128::new().to_string();
+        config.id = Scu128::new().generate();
     }
 
     config.urls = config
@@ -90,6 +90,9 @@ pub async fn register_relay(
     drop(relays_guard);
 
     persist_relays(&state, snapshot).await?;
+    if let Err(e) = connectivity::refresh_relay_circuits().await {
+        eprintln!("Failed to reserve relay circuits: {}", e);
+    }
     let _ = connectivity::emit_bridge_snapshot(&app).await;
 
     Ok(record)
@@ -120,6 +123,9 @@ pub async fn remove_relay(
     drop(relays_guard);
 
     persist_relays(&state, snapshot).await?;
+    if let Err(e) = connectivity::refresh_relay_circuits().await {
+        eprintln!("Failed to release relay circuits: {}", e);
+    }
     let _ = connectivity::emit_bridge_snapshot(&app).await;
 
     Ok(())
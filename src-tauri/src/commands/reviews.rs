@@ -161,7 +161,7 @@ pub async fn submit_review(
         return Err("You cannot review your own profile.".to_string());
     }
 
-    let review_id = Scu128::new().to_string();
+    let review_id = Scu128::new().generate();
     let new_review = NewReview {
         id: review_id.clone(),
         subject: subject_type,
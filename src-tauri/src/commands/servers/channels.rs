@@ -81,7 +81,7 @@ async fn create_channel_category_internal(
     };
 
     let category = ChannelCategory {
-        id: Scu128::new().to_string(),
+        id: Scu128::new().generate(),
         server_id: request.server_id,
         name,
         position,
@@ -2,6 +2,7 @@ use super::{broadcast_join_event, ensure_server_owner, get_initialized_state};
 use crate::commands::state::AppStateContainer;
 use scu128::Scu128;
 use aegis_protocol::{self, AepMessage};
+use aegis_shared_types::AppState;
 use aep::{database, user_service};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use serde::{Deserialize, Serialize};
@@ -73,9 +74,10 @@ pub async fn create_server(
     database::add_server_member(&state.db_pool, &server.id, &server.owner_id)
         .await
         .map_err(|e| e.to_string())?;
+    crate::bootstrap::request_topic_resync();
 
     let default_channel = database::Channel {
-        id: Scu128::new().to_string(),
+        id: Scu128::new().generate(),
         server_id: server.id.clone(),
         name: "general".to_string(),
         channel_type: "text".to_string(),
@@ -144,7 +146,23 @@ pub async fn leave_server(
 
     database::remove_server_member(&state.db_pool, &server_id, &my_id)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+
+    crate::bootstrap::request_topic_resync();
+    Ok(())
+}
+
+/// Moves every channel of the server to a new key epoch so a removed member
+/// cannot read what is sent after they are gone.
+async fn rotate_keys_after_removal(state: &AppState, server_id: &str) -> Result<(), String> {
+    crate::bootstrap::rotate_server_group_keys(
+        &state.db_pool,
+        state.identity.clone(),
+        &state.network_tx,
+        server_id,
+    )
+    .await
+    .map_err(|e| format!("Member removed, but rotating channel keys failed: {}", e))
 }
 
 #[tauri::command]
@@ -170,7 +188,9 @@ pub async fn remove_server_member(
 
     database::remove_server_member(&state.db_pool, &server_id, &member_id)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+
+    rotate_keys_after_removal(&state, &server_id).await
 }
 
 #[tauri::command]
@@ -240,6 +260,14 @@ pub async fn ban_server_member<R: Runtime>(
         .await
         .map_err(|e| e.to_string())?;
 
+    rotate_keys_after_removal(&state, &server_id).await?;
+
+    let settings_path = state.app_data_dir.join("settings.json");
+    let reason = network::DenyReason::ServerBan { server_id: server_id.clone() };
+    if let Err(error) = crate::connectivity::deny_peer(&settings_path, &user_id, reason, None).await {
+        eprintln!("Failed to deny banned member {}: {}", user_id, error);
+    }
+
     let payload = ServerBanUpdate {
         server_id: server_id.clone(),
         user_id: user_id.clone(),
@@ -265,6 +293,12 @@ pub async fn unban_server_member<R: Runtime>(
         .await
         .map_err(|e| e.to_string())?;
 
+    let settings_path = state.app_data_dir.join("settings.json");
+    let reason = network::DenyReason::ServerBan { server_id: server_id.clone() };
+    if let Err(error) = crate::connectivity::lift_peer_denial(&settings_path, &user_id, &reason).await {
+        eprintln!("Failed to lift ban on {}: {}", user_id, error);
+    }
+
     let payload = ServerBanUpdate {
         server_id: server_id.clone(),
         user_id: user_id.clone(),
@@ -327,5 +361,8 @@ pub async fn delete_server(
 
     database::delete_server(&state.db_pool, &server_id)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+
+    crate::bootstrap::request_topic_resync();
+    Ok(())
 }
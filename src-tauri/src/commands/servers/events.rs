@@ -85,7 +85,7 @@ async fn create_server_event_internal(
     let scheduled_for = parse_schedule(&request.scheduled_for)?;
     let current_user = state.identity.peer_id().to_base58();
     let event = ServerEvent {
-        id: Scu128::new().to_string(),
+        id: Scu128::new().generate(),
         server_id: request.server_id.clone(),
         title: title.to_string(),
         description: sanitize_optional_string(request.description),
@@ -122,7 +122,7 @@ pub async fn generate_server_invite(
         return Err("Only server owners can generate invites.".into());
     }
 
-    let code = Scu128::new().to_string();
+    let code = Scu128::new().generate();
     let created_at = Utc::now();
     let expires_at = expires_after_seconds
         .filter(|seconds| *seconds > 0)
@@ -71,6 +71,8 @@ pub(super) async fn broadcast_join_event(
     server_id: &str,
     user_id: &str,
 ) -> Result<(), String> {
+    crate::bootstrap::request_topic_resync();
+
     let join_server_data = aegis_protocol::JoinServerData {
         server_id: server_id.to_string(),
         user_id: user_id.to_string(),
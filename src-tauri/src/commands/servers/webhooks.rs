@@ -81,7 +81,7 @@ async fn create_server_webhook_internal(
     let creator = state.identity.peer_id().to_base58();
     let now = Utc::now();
     let webhook = ServerWebhook {
-        id: Scu128::new().to_string(),
+        id: Scu128::new().generate(),
         server_id: request.server_id.clone(),
         name,
         url,
@@ -264,7 +264,7 @@ mod tests {
 
     async fn seed_server(db_pool: &sqlx::SqlitePool, owner_id: &str) -> Server {
         let server = Server {
-            id: Scu128::new().to_string(),
+            id: Scu128::new().generate(),
             name: "Test Server".into(),
             owner_id: owner_id.to_string(),
             created_at: Utc::now(),
@@ -336,7 +336,7 @@ mod tests {
         let server = seed_server(&db_pool, &owner_id).await;
 
         let channel = Channel {
-            id: Scu128::new().to_string(),
+            id: Scu128::new().generate(),
             server_id: server.id.clone(),
             name: "alerts".into(),
             channel_type: "text".into(),
@@ -0,0 +1,209 @@
+use crate::commands::state::{with_state_async, AppStateContainer};
+use aegis_shared_types::AppState;
+use aep::database::{self, ContactKeyObservation};
+use aep::user_service;
+use crypto::safety::{compute_safety_number, verify_qr_payload, FingerprintParty};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Runtime, State};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafetyNumberPayload {
+    pub peer_id: String,
+    pub digits: String,
+    pub groups: Vec<String>,
+    pub emoji: Vec<String>,
+    pub qr_payload: String,
+    pub verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_changed_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContactKeyChangedPayload {
+    pub user_id: String,
+    pub e2ee_identity_key: String,
+    pub was_verified: bool,
+}
+
+struct PartyKeys {
+    peer_id: String,
+    identity_public_key: Vec<u8>,
+    identity_public_key_b58: Option<String>,
+    e2ee_identity_key: String,
+}
+
+impl PartyKeys {
+    fn as_party(&self) -> FingerprintParty<'_> {
+        FingerprintParty {
+            peer_id: &self.peer_id,
+            identity_public_key: &self.identity_public_key,
+            e2ee_identity_key: &self.e2ee_identity_key,
+        }
+    }
+}
+
+async fn local_keys(state: &AppState) -> Result<PartyKeys, String> {
+    let manager = e2ee::global().ok_or_else(|| "E2EE not initialized".to_string())?;
+    let e2ee_identity_key = manager.lock().await.identity_key();
+    let identity_public_key = state.identity.public_key_protobuf_bytes();
+    Ok(PartyKeys {
+        peer_id: state.identity.peer_id().to_base58(),
+        identity_public_key_b58: Some(bs58::encode(&identity_public_key).into_string()),
+        identity_public_key,
+        e2ee_identity_key,
+    })
+}
+
+async fn remote_keys(state: &AppState, peer_id: &str) -> Result<PartyKeys, String> {
+    let user = user_service::get_user(&state.db_pool, peer_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Unknown contact".to_string())?;
+    let identity_public_key_b58 = user
+        .public_key
+        .ok_or_else(|| "Contact has not published an identity key".to_string())?;
+    let identity_public_key = bs58::decode(&identity_public_key_b58)
+        .into_vec()
+        .map_err(|e| e.to_string())?;
+
+    let live_key = match e2ee::global() {
+        Some(manager) => manager.lock().await.remote_identity_key(peer_id),
+        None => None,
+    };
+    let e2ee_identity_key = match live_key {
+        Some(key) => key,
+        None => database::get_contact_verification(&state.db_pool, peer_id)
+            .await
+            .map_err(|e| e.to_string())?
+            .and_then(|record| record.e2ee_identity_key)
+            .ok_or_else(|| "No end-to-end key known for this contact yet".to_string())?,
+    };
+
+    Ok(PartyKeys {
+        peer_id: peer_id.to_string(),
+        identity_public_key,
+        identity_public_key_b58: Some(identity_public_key_b58),
+        e2ee_identity_key,
+    })
+}
+
+#[tauri::command]
+pub async fn get_safety_number(
+    peer_id: String,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<SafetyNumberPayload, String> {
+    with_state_async(state_container, move |state| async move {
+        let local = local_keys(&state).await?;
+        let remote = remote_keys(&state, &peer_id).await?;
+        let number = compute_safety_number(&local.as_party(), &remote.as_party());
+
+        let record = database::get_contact_verification(&state.db_pool, &peer_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let verified = record.as_ref().is_some_and(|r| {
+            r.verified && r.e2ee_identity_key.as_deref() == Some(remote.e2ee_identity_key.as_str())
+        });
+
+        Ok(SafetyNumberPayload {
+            peer_id,
+            digits: number.digits,
+            groups: number.groups,
+            emoji: number.emoji,
+            qr_payload: number.qr_payload,
+            verified,
+            key_changed_at: record
+                .and_then(|r| r.key_changed_at)
+                .map(|ts| ts.to_rfc3339()),
+        })
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn verify_safety_number_qr(
+    peer_id: String,
+    payload: String,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<bool, String> {
+    with_state_async(state_container, move |state| async move {
+        let local = local_keys(&state).await?;
+        let remote = remote_keys(&state, &peer_id).await?;
+        if !verify_qr_payload(&local.as_party(), &remote.as_party(), &payload) {
+            return Ok(false);
+        }
+
+        database::set_contact_verified(
+            &state.db_pool,
+            &peer_id,
+            true,
+            remote.identity_public_key_b58.as_deref(),
+            &remote.e2ee_identity_key,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        Ok(true)
+    })
+    .await
+}
+
+#[tauri::command]
+pub async fn set_contact_verified(
+    peer_id: String,
+    verified: bool,
+    state_container: State<'_, AppStateContainer>,
+) -> Result<(), String> {
+    with_state_async(state_container, move |state| async move {
+        let remote = remote_keys(&state, &peer_id).await?;
+        database::set_contact_verified(
+            &state.db_pool,
+            &peer_id,
+            verified,
+            remote.identity_public_key_b58.as_deref(),
+            &remote.e2ee_identity_key,
+        )
+        .await
+        .map_err(|e| e.to_string())
+    })
+    .await
+}
+
+/// Records the e2ee key a contact just advertised and warns the UI when a
+/// previously verified contact switched keys.
+pub(crate) async fn note_contact_e2ee_key<R: Runtime>(
+    app: &AppHandle<R>,
+    db_pool: &sqlx::Pool<sqlx::Sqlite>,
+    user_id: &str,
+    e2ee_identity_key: &str,
+) {
+    let identity_public_key = user_service::get_user(db_pool, user_id)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|user| user.public_key);
+
+    match database::record_contact_keys(
+        db_pool,
+        user_id,
+        identity_public_key.as_deref(),
+        e2ee_identity_key,
+    )
+    .await
+    {
+        Ok(ContactKeyObservation::Changed { was_verified }) => {
+            if let Err(error) = app.emit(
+                "contact-key-changed",
+                ContactKeyChangedPayload {
+                    user_id: user_id.to_string(),
+                    e2ee_identity_key: e2ee_identity_key.to_string(),
+                    was_verified,
+                },
+            ) {
+                eprintln!("Failed to emit contact-key-changed event: {}", error);
+            }
+        }
+        Ok(_) => {}
+        Err(error) => eprintln!("Failed to record keys for {}: {}", user_id, error),
+    }
+}
@@ -0,0 +1,12 @@
+use super::super::relays::{registered_relays, sync_relay_circuits};
+use super::runtime::current_runtime;
+
+/// Brings circuit reservations in line with the registered relays after one
+/// was added or removed.
+pub async fn refresh_relay_circuits() -> Result<usize, String> {
+    let runtime = current_runtime()?;
+    let relays = registered_relays().await;
+    let swarm = runtime.swarm();
+    let mut guard = swarm.lock().await;
+    Ok(sync_relay_circuits(&mut guard, &relays))
+}
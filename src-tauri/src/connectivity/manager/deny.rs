@@ -0,0 +1,111 @@
+use std::path::Path;
+
+use aegis_shared_types::{DeniedPeerReason, DeniedPeerRecord};
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+
+use crate::network;
+
+use super::runtime::current_runtime;
+
+pub fn denied_peer_record(entry: &network::DeniedPeer) -> DeniedPeerRecord {
+    let (reason, server_id) = match &entry.reason {
+        network::DenyReason::Blocked => (DeniedPeerReason::Blocked, None),
+        network::DenyReason::ServerBan { server_id } => {
+            (DeniedPeerReason::ServerBan, Some(server_id.clone()))
+        }
+        network::DenyReason::Manual => (DeniedPeerReason::Manual, None),
+    };
+    DeniedPeerRecord {
+        peer_id: entry.peer.to_base58(),
+        reason,
+        server_id,
+        denied_at: entry.denied_at,
+        expires_at: entry.expires_at,
+    }
+}
+
+/// The network's entry for a saved record, or `None` if the record is
+/// damaged.
+pub fn denied_peer(record: &DeniedPeerRecord) -> Option<network::DeniedPeer> {
+    let reason = match record.reason {
+        DeniedPeerReason::Blocked => network::DenyReason::Blocked,
+        DeniedPeerReason::ServerBan => network::DenyReason::ServerBan {
+            server_id: record.server_id.clone()?,
+        },
+        DeniedPeerReason::Manual => network::DenyReason::Manual,
+    };
+    Some(network::DeniedPeer {
+        peer: record.peer_id.parse().ok()?,
+        reason,
+        denied_at: record.denied_at,
+        expires_at: record.expires_at,
+    })
+}
+
+pub async fn denied_peers() -> Result<Vec<DeniedPeerRecord>, String> {
+    let runtime = current_runtime()?;
+    let deny_list = runtime.deny_list();
+    let guard = deny_list.lock().await;
+    Ok(guard.entries().iter().map(denied_peer_record).collect())
+}
+
+/// Refuses `peer_id` on the running swarm and saves the deny list. Denying
+/// a peer again for the same reason replaces the expiry.
+pub async fn deny_peer(
+    settings_path: &Path,
+    peer_id: &str,
+    reason: network::DenyReason,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Vec<DeniedPeerRecord>, String> {
+    let peer = parse_peer(peer_id)?;
+    let runtime = current_runtime()?;
+    let deny_list = runtime.deny_list();
+    let mut guard = deny_list.lock().await;
+    {
+        let swarm = runtime.swarm();
+        let mut swarm = swarm.lock().await;
+        guard.deny(
+            &mut swarm,
+            network::DeniedPeer { peer, reason, denied_at: Utc::now(), expires_at },
+        );
+    }
+    save_deny_list(settings_path, guard.entries())
+}
+
+/// Lifts one reason for refusing `peer_id`; the peer is let back in once no
+/// other reason holds.
+pub async fn lift_peer_denial(
+    settings_path: &Path,
+    peer_id: &str,
+    reason: &network::DenyReason,
+) -> Result<Vec<DeniedPeerRecord>, String> {
+    let peer = parse_peer(peer_id)?;
+    let runtime = current_runtime()?;
+    let deny_list = runtime.deny_list();
+    let mut guard = deny_list.lock().await;
+    {
+        let swarm = runtime.swarm();
+        let mut swarm = swarm.lock().await;
+        guard.lift(&mut swarm, &peer, reason);
+    }
+    save_deny_list(settings_path, guard.entries())
+}
+
+fn parse_peer(peer_id: &str) -> Result<PeerId, String> {
+    peer_id
+        .parse()
+        .map_err(|_| format!("Invalid peer ID {}", peer_id))
+}
+
+fn save_deny_list(
+    settings_path: &Path,
+    entries: &[network::DeniedPeer],
+) -> Result<Vec<DeniedPeerRecord>, String> {
+    let records: Vec<DeniedPeerRecord> = entries.iter().map(denied_peer_record).collect();
+    let mut persisted = crate::settings_store::load_settings(settings_path)
+        .unwrap_or_else(|_| crate::settings_store::PersistedSettings::default());
+    persisted.denied_peers = records.clone();
+    crate::settings_store::save_settings(settings_path, &persisted)?;
+    Ok(records)
+}
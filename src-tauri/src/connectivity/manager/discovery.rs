@@ -0,0 +1,54 @@
+use libp2p::Multiaddr;
+
+use crate::network;
+
+use super::runtime::current_runtime;
+
+/// Checks and normalises user-entered bootstrap peers. Each must be a
+/// multiaddr ending in `/p2p/<peer id>`; blanks and repeats are dropped.
+pub fn normalize_bootstrap_peers(peers: Vec<String>) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for peer in peers {
+        let peer = peer.trim();
+        if peer.is_empty() {
+            continue;
+        }
+        let address: Multiaddr = peer
+            .parse()
+            .map_err(|e| format!("Invalid bootstrap peer {}: {}", peer, e))?;
+        if network::dht::split_peer_address(&address).is_none() {
+            return Err(format!("Bootstrap peer {} must end with /p2p/<peer id>", peer));
+        }
+        let address = address.to_string();
+        if !normalized.contains(&address) {
+            normalized.push(address);
+        }
+    }
+    Ok(normalized)
+}
+
+/// Seeds the running node's DHT with `peers`.
+pub async fn apply_bootstrap_peers(peers: &[String]) -> Result<usize, String> {
+    let runtime = current_runtime()?;
+    let addresses: Vec<Multiaddr> = peers.iter().filter_map(|peer| peer.parse().ok()).collect();
+    let swarm = runtime.swarm();
+    let mut guard = swarm.lock().await;
+    Ok(network::dht::bootstrap(&mut guard, &addresses))
+}
+
+/// Dials a peer given as a multiaddr or a scanned contact card. Returns its
+/// peer ID.
+pub async fn dial_peer(target: &str) -> Result<String, String> {
+    let runtime = current_runtime()?;
+    let swarm = runtime.swarm();
+    let mut guard = swarm.lock().await;
+    network::dht::dial_contact(&mut guard, target).map(|peer| peer.to_base58())
+}
+
+/// Our contact card, ready to be shown as a QR code.
+pub async fn local_contact_card() -> Result<String, String> {
+    let runtime = current_runtime()?;
+    let swarm = runtime.swarm();
+    let guard = swarm.lock().await;
+    Ok(network::ContactCard::local(&guard).encode())
+}
@@ -1,4 +1,7 @@
 mod bridge_control;
+mod circuits;
+mod deny;
+mod discovery;
 mod orchestrator;
 mod routing;
 mod runtime;
@@ -6,6 +9,13 @@ mod tasks;
 mod transport;
 
 pub use bridge_control::{emit_bridge_snapshot, set_bridge_mode_enabled};
+pub use circuits::refresh_relay_circuits;
+pub use deny::{deny_peer, denied_peer, denied_peer_record, denied_peers, lift_peer_denial};
+pub use discovery::{
+    apply_bootstrap_peers, dial_peer, local_contact_card, normalize_bootstrap_peers,
+};
 pub use routing::set_routing_config;
 pub use tasks::spawn_connectivity_task;
-pub use transport::{set_bluetooth_enabled, set_wifi_direct_enabled};
+pub use transport::{
+    privacy_config, privacy_settings, proxy_config, set_bluetooth_enabled, set_wifi_direct_enabled,
+};
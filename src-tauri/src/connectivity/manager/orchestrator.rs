@@ -8,6 +8,7 @@ use crate::network::{self, TransportSnapshot};
 use super::super::bridge::bridge_state_snapshot;
 use super::super::relays::relay_snapshots;
 use super::super::snapshot::compute_snapshot;
+use super::deny::denied_peer_record;
 use super::runtime::{current_runtime, ConnectivityRuntime};
 
 pub async fn collect_emit_snapshot<R: Runtime>(
@@ -27,11 +28,16 @@ pub async fn collect_and_store_snapshot(
     let bridge_snapshot = bridge_state_snapshot().await;
     let transport_snapshot: TransportSnapshot = network::transport_snapshot();
     let router_snapshot = runtime.router_snapshot().await;
-    let relay_snapshots = relay_snapshots().await;
+    let denied_peers: Vec<_> = {
+        let deny_list = runtime.deny_list();
+        let guard = deny_list.lock().await;
+        guard.entries().iter().map(denied_peer_record).collect()
+    };
 
     let snapshot = {
         let swarm = runtime.swarm();
         let swarm_guard = swarm.lock().await;
+        let relay_snapshots = relay_snapshots(&swarm_guard).await;
         compute_snapshot(
             &swarm_guard,
             &runtime.local_peer_id(),
@@ -39,6 +45,8 @@ pub async fn collect_and_store_snapshot(
             &transport_snapshot,
             Some(&router_snapshot),
             relay_snapshots,
+            &network::frame_stats(),
+            denied_peers,
         )
     };
 
@@ -11,6 +11,7 @@ use crate::network;
 pub struct ConnectivityRuntime {
     swarm: Arc<Mutex<Swarm<network::Behaviour>>>,
     router: Arc<Mutex<network::AerpRouter>>,
+    deny_list: Arc<Mutex<network::DenyList>>,
     local_peer_id: PeerId,
     snapshot_store: Arc<Mutex<Option<ConnectivityEventPayload>>>,
 }
@@ -19,12 +20,14 @@ impl ConnectivityRuntime {
     pub fn new(
         swarm: Arc<Mutex<Swarm<network::Behaviour>>>,
         router: Arc<Mutex<network::AerpRouter>>,
+        deny_list: Arc<Mutex<network::DenyList>>,
         local_peer_id: PeerId,
         snapshot_store: Arc<Mutex<Option<ConnectivityEventPayload>>>,
     ) -> Self {
         Self {
             swarm,
             router,
+            deny_list,
             local_peer_id,
             snapshot_store,
         }
@@ -38,6 +41,10 @@ impl ConnectivityRuntime {
         Arc::clone(&self.router)
     }
 
+    pub fn deny_list(&self) -> Arc<Mutex<network::DenyList>> {
+        Arc::clone(&self.deny_list)
+    }
+
     pub fn local_peer_id(&self) -> PeerId {
         self.local_peer_id.clone()
     }
@@ -63,10 +70,11 @@ static RUNTIME: OnceCell<ConnectivityRuntime> = OnceCell::new();
 pub fn initialise_runtime(
     swarm: Arc<Mutex<Swarm<network::Behaviour>>>,
     router: Arc<Mutex<network::AerpRouter>>,
+    deny_list: Arc<Mutex<network::DenyList>>,
     local_peer_id: PeerId,
     snapshot_store: Arc<Mutex<Option<ConnectivityEventPayload>>>,
 ) -> ConnectivityRuntime {
-    let runtime = ConnectivityRuntime::new(swarm, router, local_peer_id, snapshot_store);
+    let runtime = ConnectivityRuntime::new(swarm, router, deny_list, local_peer_id, snapshot_store);
 
     if RUNTIME.set(runtime.clone()).is_err() {
         return RUNTIME
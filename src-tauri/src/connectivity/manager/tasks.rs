@@ -15,10 +15,11 @@ pub fn spawn_connectivity_task<R: Runtime>(
     app: AppHandle<R>,
     swarm: Arc<Mutex<Swarm<network::Behaviour>>>,
     router: Arc<Mutex<network::AerpRouter>>,
+    deny_list: Arc<Mutex<network::DenyList>>,
     local_peer_id: PeerId,
     snapshot_store: Arc<Mutex<Option<ConnectivityEventPayload>>>,
 ) {
-    let runtime = initialise_runtime(swarm, router, local_peer_id, snapshot_store);
+    let runtime = initialise_runtime(swarm, router, deny_list, local_peer_id, snapshot_store);
 
     tokio::spawn(async { reset_bridge_state().await });
 
@@ -1,4 +1,4 @@
-use aegis_shared_types::ConnectivityTransportStatus;
+use aegis_shared_types::{ConnectivityTransportStatus, CoverTrafficBudgets, PrivacySettings, ProxySettings};
 use tauri::{AppHandle, Runtime};
 
 use crate::connectivity::snapshot::build_transport_status;
@@ -37,3 +37,52 @@ pub async fn set_wifi_direct_enabled<R: Runtime>(
         Ok(build_transport_status(&network::transport_snapshot()))
     }
 }
+
+/// Checks a user-entered proxy and turns it into the network's config. The
+/// address must be `host:port` with a literal IP, such as `127.0.0.1:9050`.
+pub fn proxy_config(settings: &ProxySettings) -> Result<network::ProxyConfig, String> {
+    let address = settings
+        .address
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid proxy address {}: expected ip:port", settings.address))?;
+    Ok(network::ProxyConfig {
+        address,
+        strict: settings.strict,
+    })
+}
+
+/// Overlays saved privacy settings on the network's defaults.
+pub fn privacy_config(settings: &PrivacySettings) -> network::PrivacyConfig {
+    let mut config = network::PrivacyConfig {
+        pad_frames: settings.pad_frames,
+        cover_traffic: settings.cover_traffic,
+        ..network::PrivacyConfig::default()
+    };
+    let budgets = &settings.cover_budgets;
+    for (medium, budget) in [
+        (network::TransportMedium::Tcp, budgets.tcp),
+        (network::TransportMedium::Quic, budgets.quic),
+        (network::TransportMedium::WifiDirect, budgets.wifi_direct),
+        (network::TransportMedium::Bluetooth, budgets.bluetooth),
+    ] {
+        if let Some(budget) = budget {
+            config.cover_budgets.insert(medium, budget);
+        }
+    }
+    config
+}
+
+/// The settings a privacy config amounts to, with every budget filled in.
+pub fn privacy_settings(config: &network::PrivacyConfig) -> PrivacySettings {
+    PrivacySettings {
+        pad_frames: config.pad_frames,
+        cover_traffic: config.cover_traffic,
+        cover_budgets: CoverTrafficBudgets {
+            tcp: Some(config.cover_budget(network::TransportMedium::Tcp)),
+            quic: Some(config.cover_budget(network::TransportMedium::Quic)),
+            wifi_direct: Some(config.cover_budget(network::TransportMedium::WifiDirect)),
+            bluetooth: Some(config.cover_budget(network::TransportMedium::Bluetooth)),
+        },
+    }
+}
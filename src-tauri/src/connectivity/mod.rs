@@ -8,7 +8,10 @@ pub use bridge::{
     note_bridge_forward_success,
 };
 pub use manager::{
-    emit_bridge_snapshot, set_bluetooth_enabled, set_bridge_mode_enabled, set_routing_config,
-    set_wifi_direct_enabled, spawn_connectivity_task,
+    apply_bootstrap_peers, denied_peer, denied_peers, deny_peer, dial_peer, emit_bridge_snapshot,
+    lift_peer_denial, local_contact_card,
+    normalize_bootstrap_peers, privacy_config, privacy_settings, proxy_config, refresh_relay_circuits,
+    set_bluetooth_enabled, set_bridge_mode_enabled, set_routing_config, set_wifi_direct_enabled,
+    spawn_connectivity_task,
 };
-pub use relays::set_relay_store;
+pub use relays::{set_relay_store, sync_relay_circuits};
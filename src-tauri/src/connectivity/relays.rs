@@ -1,21 +1,92 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
-use aegis_shared_types::{RelayRecord, RelaySnapshot};
+use aegis_shared_types::{RelayRecord, RelayReservationState, RelaySnapshot};
+use libp2p::swarm::Swarm;
+use libp2p::{Multiaddr, PeerId};
 use once_cell::sync::OnceCell;
 use tokio::sync::Mutex;
 
+use crate::network::{self, ReservationState};
+
 static RELAY_STORE: OnceCell<Arc<Mutex<Vec<RelayRecord>>>> = OnceCell::new();
 
 pub fn set_relay_store(store: Arc<Mutex<Vec<RelayRecord>>>) {
     let _ = RELAY_STORE.set(store);
 }
 
-pub async fn relay_snapshots() -> Option<Vec<RelaySnapshot>> {
+pub async fn relay_snapshots(swarm: &Swarm<network::Behaviour>) -> Option<Vec<RelaySnapshot>> {
     let store = RELAY_STORE.get()?;
     let guard = store.lock().await;
     if guard.is_empty() {
         None
     } else {
-        Some(guard.iter().map(RelayRecord::to_snapshot).collect())
+        Some(guard.iter().map(|record| relay_snapshot(swarm, record)).collect())
+    }
+}
+
+pub async fn registered_relays() -> Vec<RelayRecord> {
+    match RELAY_STORE.get() {
+        Some(store) => store.lock().await.clone(),
+        None => Vec::new(),
+    }
+}
+
+/// URLs of a relay that name a libp2p circuit relay, i.e. multiaddrs ending
+/// in `/p2p/<relay>`.
+fn circuit_relays(record: &RelayRecord) -> impl Iterator<Item = (PeerId, Multiaddr)> + '_ {
+    record.config.urls.iter().filter_map(|url| {
+        let address: Multiaddr = url.parse().ok()?;
+        let (peer, _) = network::dht::split_peer_address(&address)?;
+        Some((peer, address))
+    })
+}
+
+fn relay_snapshot(swarm: &Swarm<network::Behaviour>, record: &RelayRecord) -> RelaySnapshot {
+    let mut snapshot = record.to_snapshot();
+    let status = circuit_relays(record)
+        .find_map(|(peer, _)| network::circuit_status(swarm, &peer));
+    if let Some(status) = status {
+        snapshot.reservation = Some(match status.state {
+            ReservationState::Pending => RelayReservationState::Pending,
+            ReservationState::Active => RelayReservationState::Active,
+            ReservationState::Failed => RelayReservationState::Failed,
+        });
+        snapshot.circuit_address = status.circuit_address.map(|address| address.to_string());
+        snapshot.relayed_connections = Some(status.relayed_connections);
+        if snapshot.error.is_none() {
+            snapshot.error = status.error;
+        }
+    }
+    snapshot
+}
+
+/// Reserves circuits on the registered relays reachable by multiaddr and
+/// drops reservations on relays that were removed. Other multiaddr URLs are
+/// dialled so they can still take mailbox deposits. Returns how many new
+/// reservations were requested.
+pub fn sync_relay_circuits(swarm: &mut Swarm<network::Behaviour>, relays: &[RelayRecord]) -> usize {
+    let mut registered = HashSet::new();
+    let mut requested = 0;
+    for record in relays {
+        for url in &record.config.urls {
+            let Ok(address) = url.parse::<Multiaddr>() else {
+                continue;
+            };
+            let Some((peer, _)) = network::dht::split_peer_address(&address) else {
+                if let Err(e) = swarm.dial_addr(address.clone()) {
+                    eprintln!("Failed to dial relay {}: {}", address, e);
+                }
+                continue;
+            };
+            registered.insert(peer);
+            match network::circuit::reserve(swarm, &address) {
+                Ok(true) => requested += 1,
+                Ok(false) => {}
+                Err(e) => eprintln!("Failed to reserve a circuit on {}: {}", address, e),
+            }
+        }
     }
+    network::circuit::retain_reservations(swarm, &registered);
+    requested
 }
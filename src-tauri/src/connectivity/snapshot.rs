@@ -2,8 +2,8 @@ use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
 use aegis_shared_types::{
-    ConnectivityEventPayload, ConnectivityGatewayStatus, ConnectivityLink, ConnectivityPeer,
-    ConnectivityTransportStatus, RelaySnapshot,
+    ConnectivityEventPayload, ConnectivityFrameStats, ConnectivityGatewayStatus, ConnectivityLink, ConnectivityPeer,
+    ConnectivityTransportStatus, DeniedPeerRecord, RelaySnapshot, TransportProxyMode,
 };
 use chrono::Utc;
 use libp2p::{swarm::Swarm, PeerId};
@@ -31,6 +31,15 @@ pub fn build_transport_status(snapshot: &TransportSnapshot) -> ConnectivityTrans
                 .collect(),
         ),
         local_peer_id: snapshot.local_peer_id.as_ref().map(|peer| peer.to_base58()),
+        proxy_mode: Some(match network::ProxyMode::of(snapshot.proxy.as_ref()) {
+            network::ProxyMode::Direct => TransportProxyMode::Direct,
+            network::ProxyMode::Proxied => TransportProxyMode::Proxied,
+            network::ProxyMode::Strict => TransportProxyMode::Strict,
+        }),
+        proxy_address: snapshot.proxy.map(|proxy| proxy.address.to_string()),
+        quic_enabled: Some(snapshot.quic_enabled),
+        tcp_peers: Some(snapshot.tcp_peers.iter().map(|peer| peer.to_base58()).collect()),
+        quic_peers: Some(snapshot.quic_peers.iter().map(|peer| peer.to_base58()).collect()),
     }
 }
 
@@ -42,6 +51,8 @@ pub fn compute_snapshot(
     transport_snapshot: &TransportSnapshot,
     router_snapshot: Option<&network::RouterSnapshot>,
     relay_snapshots: Option<Vec<RelaySnapshot>>,
+    frame_stats: &network::FrameStats,
+    denied_peers: Vec<DeniedPeerRecord>,
 ) -> ConnectivityEventPayload {
     let connected: Vec<_> = swarm
         .behaviour()
@@ -272,5 +283,11 @@ pub fn compute_snapshot(
         gateway_status: Some(gateway_status),
         transports: Some(transport_status),
         relays: relay_payload,
+        frames: Some(ConnectivityFrameStats {
+            accepted: Some(frame_stats.accepted),
+            duplicates_dropped: Some(frame_stats.duplicates),
+            expired_dropped: Some(frame_stats.expired),
+        }),
+        denied_peers: Some(denied_peers),
     }
 }
@@ -0,0 +1,181 @@
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use thiserror::Error;
+
+/// Prefix marking a text column value produced by [`AtRestKey::seal_text`].
+pub const TEXT_PREFIX: &str = "aegis-enc:v1:";
+
+/// Header marking a blob produced by [`AtRestKey::seal_bytes`].
+pub const BLOB_MAGIC: [u8; 5] = *b"AEGE\x01";
+
+const NONCE_LEN: usize = 24;
+const CONTENT_INFO: &[u8] = b"aegis-at-rest:content";
+const INDEX_INFO: &[u8] = b"aegis-at-rest:index";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AtRestError {
+    #[error("value is not sealed")]
+    NotSealed,
+    #[error("sealed value is malformed")]
+    Malformed,
+    #[error("sealed value could not be decrypted")]
+    Decrypt,
+}
+
+/// Keys protecting message content stored on disk. One subkey encrypts the
+/// columns, the other blinds search tokens so the full-text index never
+/// holds plaintext words.
+#[derive(Clone)]
+pub struct AtRestKey {
+    content: [u8; 32],
+    index: [u8; 32],
+}
+
+impl std::fmt::Debug for AtRestKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AtRestKey(..)")
+    }
+}
+
+impl AtRestKey {
+    pub fn from_master(master: &[u8; 32]) -> Self {
+        let hk = Hkdf::<Sha256>::new(None, master);
+        let mut content = [0u8; 32];
+        let mut index = [0u8; 32];
+        hk.expand(CONTENT_INFO, &mut content)
+            .expect("32 bytes is a valid HKDF output length");
+        hk.expand(INDEX_INFO, &mut index)
+            .expect("32 bytes is a valid HKDF output length");
+        Self { content, index }
+    }
+
+    pub fn generate_master() -> [u8; 32] {
+        XChaCha20Poly1305::generate_key(&mut OsRng).into()
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(self.content.as_ref().into())
+    }
+
+    pub fn seal_bytes(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher()
+            .encrypt(&nonce, plaintext)
+            .expect("XChaCha20Poly1305 encryption is infallible for in-memory buffers");
+
+        let mut out = Vec::with_capacity(BLOB_MAGIC.len() + NONCE_LEN + ciphertext.len());
+        out.extend_from_slice(&BLOB_MAGIC);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&ciphertext);
+        out
+    }
+
+    pub fn open_bytes(&self, sealed: &[u8]) -> Result<Vec<u8>, AtRestError> {
+        let body = sealed
+            .strip_prefix(&BLOB_MAGIC)
+            .ok_or(AtRestError::NotSealed)?;
+        if body.len() < NONCE_LEN {
+            return Err(AtRestError::Malformed);
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        self.cipher()
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| AtRestError::Decrypt)
+    }
+
+    pub fn seal_text(&self, plaintext: &str) -> String {
+        let sealed = self.seal_bytes(plaintext.as_bytes());
+        format!(
+            "{}{}",
+            TEXT_PREFIX,
+            STANDARD_NO_PAD.encode(&sealed[BLOB_MAGIC.len()..])
+        )
+    }
+
+    pub fn open_text(&self, sealed: &str) -> Result<String, AtRestError> {
+        let encoded = sealed
+            .strip_prefix(TEXT_PREFIX)
+            .ok_or(AtRestError::NotSealed)?;
+        let body = STANDARD_NO_PAD
+            .decode(encoded)
+            .map_err(|_| AtRestError::Malformed)?;
+        let mut framed = Vec::with_capacity(BLOB_MAGIC.len() + body.len());
+        framed.extend_from_slice(&BLOB_MAGIC);
+        framed.extend_from_slice(&body);
+        let plaintext = self.open_bytes(&framed)?;
+        String::from_utf8(plaintext).map_err(|_| AtRestError::Malformed)
+    }
+
+    /// Keyed hash of one search term. Equal terms map to equal tokens, so
+    /// the index can answer exact-word queries without storing the words.
+    pub fn blind_token(&self, term: &str) -> String {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.index)
+            .expect("HMAC accepts keys of any length");
+        mac.update(term.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        digest[..12].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+pub fn is_sealed_text(value: &str) -> bool {
+    value.starts_with(TEXT_PREFIX)
+}
+
+pub fn is_sealed_bytes(value: &[u8]) -> bool {
+    value.starts_with(&BLOB_MAGIC)
+}
+
+/// Splits text into the lowercase terms fed to the search index.
+pub fn search_terms(text: &str) -> Vec<String> {
+    let mut terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+        .collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> AtRestKey {
+        AtRestKey::from_master(&[7u8; 32])
+    }
+
+    #[test]
+    fn text_round_trips_and_hides_plaintext() {
+        let sealed = key().seal_text("meet at the docks");
+        assert!(is_sealed_text(&sealed));
+        assert!(!sealed.contains("docks"));
+        assert_eq!(key().open_text(&sealed).unwrap(), "meet at the docks");
+    }
+
+    #[test]
+    fn wrong_key_fails_to_open() {
+        let sealed = key().seal_bytes(b"attachment");
+        let other = AtRestKey::from_master(&[8u8; 32]);
+        assert_eq!(other.open_bytes(&sealed), Err(AtRestError::Decrypt));
+        assert_eq!(key().open_bytes(b"plain"), Err(AtRestError::NotSealed));
+    }
+
+    #[test]
+    fn blind_tokens_are_stable_per_key() {
+        let terms = search_terms("Hello, hello WORLD");
+        assert_eq!(terms, vec!["hello".to_string(), "world".to_string()]);
+        assert_eq!(key().blind_token("hello"), key().blind_token("hello"));
+        assert_ne!(
+            key().blind_token("hello"),
+            AtRestKey::from_master(&[8u8; 32]).blind_token("hello")
+        );
+    }
+}
@@ -29,9 +29,15 @@ impl Identity {
         password: &[u8],
     ) -> Result<Self, IdentityError> {
         let (ciphertext, salt, nonce) = Self::unpack_encrypted_data(encrypted_secret)?;
-        let mut decrypted_secret = crate::decrypt(&ciphertext, password, &salt, &nonce)
+        let decrypted_secret = crate::decrypt(&ciphertext, password, &salt, &nonce)
             .map_err(|e| IdentityError::Decoding(e.to_string()))?;
-        let secret_key = ed25519::SecretKey::from_bytes(&mut decrypted_secret)
+        Self::from_secret_bytes(decrypted_secret)
+    }
+
+    /// Restores an identity from the raw ed25519 secret returned by
+    /// [`Identity::to_secret_bytes`]. The buffer is zeroed afterwards.
+    pub fn from_secret_bytes(mut secret: Vec<u8>) -> Result<Self, IdentityError> {
+        let secret_key = ed25519::SecretKey::from_bytes(&mut secret)
             .map_err(|e| IdentityError::Decoding(e.to_string()))?;
         let keypair = Keypair::Ed25519(ed25519::Keypair::from(secret_key));
         Ok(Self { keypair })
@@ -1,4 +1,6 @@
+pub mod at_rest;
 pub mod identity;
+pub mod safety;
 
 use argon2::{
     password_hash::{rand_core::OsRng, SaltString},
@@ -9,16 +11,30 @@ use chacha20poly1305::{
     XChaCha20Poly1305, XNonce,
 };
 
-pub fn encrypt(
-    data: &[u8],
+/// Derives a 32-byte symmetric key from `password` using the same Argon2
+/// parameters as [`encrypt`].
+pub fn derive_key(
     password: &[u8],
-) -> Result<(Vec<u8>, SaltString, XNonce), Box<dyn std::error::Error>> {
-    let salt = SaltString::generate(&mut OsRng);
+    salt: &SaltString,
+) -> Result<[u8; 32], Box<dyn std::error::Error>> {
     let argon2 = Argon2::default();
     let mut key_bytes = [0u8; 32];
     argon2
         .hash_password_into(password, salt.as_ref().as_bytes(), &mut key_bytes)
         .map_err(|e| e.to_string())?;
+    Ok(key_bytes)
+}
+
+pub fn generate_salt() -> SaltString {
+    SaltString::generate(&mut OsRng)
+}
+
+pub fn encrypt(
+    data: &[u8],
+    password: &[u8],
+) -> Result<(Vec<u8>, SaltString, XNonce), Box<dyn std::error::Error>> {
+    let salt = generate_salt();
+    let key_bytes = derive_key(password, &salt)?;
 
     let cipher = XChaCha20Poly1305::new(key_bytes.as_ref().into());
     let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
@@ -33,11 +49,7 @@ pub fn decrypt(
     salt: &SaltString,
     nonce: &XNonce,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let argon2 = Argon2::default();
-    let mut key_bytes = [0u8; 32];
-    argon2
-        .hash_password_into(password, salt.as_ref().as_bytes(), &mut key_bytes)
-        .map_err(|e| e.to_string())?;
+    let key_bytes = derive_key(password, salt)?;
 
     let cipher = XChaCha20Poly1305::new(key_bytes.as_ref().into());
     let plaintext = cipher
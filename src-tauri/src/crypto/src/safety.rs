@@ -0,0 +1,171 @@
+use sha2::{Digest, Sha256, Sha512};
+
+const FINGERPRINT_VERSION: u8 = 1;
+const FINGERPRINT_ITERATIONS: usize = 5200;
+const QR_PREFIX: &str = "aegis-verify";
+
+const EMOJI_TABLE: [&str; 64] = [
+    "🐶", "🐱", "🦁", "🐴", "🦄", "🐷", "🐘", "🐰", "🐼", "🐓", "🐧", "🐢", "🐟", "🐙", "🦋", "🌷",
+    "🌳", "🌵", "🍄", "🌏", "🌙", "☁️", "🔥", "🍌", "🍎", "🍓", "🌽", "🍕", "🎂", "❤️", "😀", "🤖",
+    "🎩", "👓", "🔧", "🎅", "👍", "☂️", "⌛", "⏰", "🎁", "💡", "📕", "✏️", "📎", "✂️", "🔒", "🔑",
+    "🔨", "☎️", "🏁", "🚂", "🚲", "✈️", "🚀", "🏆", "⚽", "🎸", "🎺", "🔔", "⚓", "🎧", "📁", "📌",
+];
+
+/// Public key material one side contributes to a safety number.
+#[derive(Debug, Clone, Copy)]
+pub struct FingerprintParty<'a> {
+    pub peer_id: &'a str,
+    /// Protobuf encoding of the libp2p identity public key.
+    pub identity_public_key: &'a [u8],
+    /// Base64 curve25519 identity key of the Olm account.
+    pub e2ee_identity_key: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyNumber {
+    /// 60 decimal digits, identical on both sides.
+    pub digits: String,
+    /// `digits` split into twelve groups of five for display.
+    pub groups: Vec<String>,
+    /// Short emoji rendering of the same value for quick comparison.
+    pub emoji: Vec<String>,
+    /// Payload to render as a QR code; the other side checks it with
+    /// [`verify_qr_payload`].
+    pub qr_payload: String,
+}
+
+fn party_fingerprint(party: &FingerprintParty<'_>) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    hasher.update([FINGERPRINT_VERSION]);
+    hasher.update(party.identity_public_key);
+    hasher.update(party.e2ee_identity_key.as_bytes());
+    hasher.update(party.peer_id.as_bytes());
+    let mut digest: [u8; 64] = hasher.finalize().into();
+
+    for _ in 1..FINGERPRINT_ITERATIONS {
+        let mut hasher = Sha512::new();
+        hasher.update(digest);
+        hasher.update(party.identity_public_key);
+        hasher.update(party.e2ee_identity_key.as_bytes());
+        digest = hasher.finalize().into();
+    }
+    digest
+}
+
+fn fingerprint_digits(fingerprint: &[u8; 64]) -> String {
+    fingerprint[..30]
+        .chunks(5)
+        .map(|chunk| {
+            let value = chunk.iter().fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+            format!("{:05}", value % 100_000)
+        })
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Computes the safety number for a pair of contacts. The result does not
+/// depend on which side is `local`, so both users see the same value.
+pub fn compute_safety_number(
+    local: &FingerprintParty<'_>,
+    remote: &FingerprintParty<'_>,
+) -> SafetyNumber {
+    let local_fp = party_fingerprint(local);
+    let remote_fp = party_fingerprint(remote);
+
+    let mut halves = [fingerprint_digits(&local_fp), fingerprint_digits(&remote_fp)];
+    halves.sort();
+    let digits = halves.concat();
+
+    let groups = digits
+        .as_bytes()
+        .chunks(5)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect();
+
+    let emoji_seed: [u8; 32] = Sha256::digest(digits.as_bytes()).into();
+    let emoji = emoji_seed[..8]
+        .iter()
+        .map(|byte| EMOJI_TABLE[(*byte as usize) % EMOJI_TABLE.len()].to_string())
+        .collect();
+
+    let qr_payload = format!(
+        "{}:{}:{}:{}:{}:{}",
+        QR_PREFIX,
+        FINGERPRINT_VERSION,
+        local.peer_id,
+        to_hex(&local_fp[..32]),
+        remote.peer_id,
+        to_hex(&remote_fp[..32]),
+    );
+
+    SafetyNumber {
+        digits,
+        groups,
+        emoji,
+        qr_payload,
+    }
+}
+
+/// Checks a QR payload scanned from `remote`'s screen against the keys we
+/// hold. The payload lists the scanned device first, so the roles swap.
+pub fn verify_qr_payload(
+    local: &FingerprintParty<'_>,
+    remote: &FingerprintParty<'_>,
+    payload: &str,
+) -> bool {
+    let expected = compute_safety_number(remote, local);
+    expected.qr_payload == payload.trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alice() -> FingerprintParty<'static> {
+        FingerprintParty {
+            peer_id: "alice",
+            identity_public_key: b"alice-ed25519",
+            e2ee_identity_key: "alice-curve",
+        }
+    }
+
+    fn bob() -> FingerprintParty<'static> {
+        FingerprintParty {
+            peer_id: "bob",
+            identity_public_key: b"bob-ed25519",
+            e2ee_identity_key: "bob-curve",
+        }
+    }
+
+    #[test]
+    fn both_sides_see_the_same_number() {
+        let ours = compute_safety_number(&alice(), &bob());
+        let theirs = compute_safety_number(&bob(), &alice());
+        assert_eq!(ours.digits, theirs.digits);
+        assert_eq!(ours.emoji, theirs.emoji);
+        assert_eq!(ours.digits.len(), 60);
+        assert_eq!(ours.groups.len(), 12);
+    }
+
+    #[test]
+    fn key_change_changes_the_number() {
+        let mut rotated = bob();
+        rotated.e2ee_identity_key = "bob-curve-2";
+        let before = compute_safety_number(&alice(), &bob());
+        let after = compute_safety_number(&alice(), &rotated);
+        assert_ne!(before.digits, after.digits);
+    }
+
+    #[test]
+    fn scanned_qr_payload_verifies() {
+        let shown_by_bob = compute_safety_number(&bob(), &alice()).qr_payload;
+        assert!(verify_qr_payload(&alice(), &bob(), &shown_by_bob));
+
+        let mut impostor = bob();
+        impostor.identity_public_key = b"mallory";
+        assert!(!verify_qr_payload(&alice(), &impostor, &shown_by_bob));
+    }
+}
@@ -11,22 +11,134 @@ use vodozemac::megolm::{
     GroupSession, GroupSessionPickle, InboundGroupSession, InboundGroupSessionPickle,
     MegolmMessage, SessionConfig as MegolmSessionConfig, SessionKey,
 };
+use rand::seq::SliceRandom;
 use rand::RngCore;
 use vodozemac::olm::{
     Account, AccountPickle, Message, OlmMessage, PreKeyMessage, Session,
     SessionConfig as OlmSessionConfig, SessionPickle,
 };
-use vodozemac::{Curve25519PublicKey};
-
-
-const PICKLE_KEY: &[u8; 32] = b"aegis_local_storage_key_32_bytes";
+use vodozemac::{Curve25519PublicKey, Ed25519PublicKey, Ed25519Signature};
+#[cfg(test)]
+use vodozemac::Ed25519Keypair;
+
+
+/// Key used by builds that predate passphrase-derived storage keys. Only read
+/// during migration; never used to write new state.
+const LEGACY_PICKLE_KEY: &[u8; 32] = b"aegis_local_storage_key_32_bytes";
+
+/// How long a signed prekey is advertised before it is rotated.
+pub const SIGNED_PREKEY_LIFETIME_SECS: i64 = 7 * 24 * 60 * 60;
+/// Number of published one-time keys the manager tops the pool up to.
+pub const ONE_TIME_KEY_TARGET: usize = 20;
+/// Replenish the pool once fewer than this many published keys remain.
+pub const ONE_TIME_KEY_LOW_WATER: usize = 5;
+/// Sender-key epochs kept per (group, sender) so messages sent just before a
+/// rotation can still be read.
+pub const SENDER_KEY_EPOCHS_RETAINED: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+pub struct OneTimeKey {
+    pub key_id: String,
+    pub key: String,
+}
 
+/// X3DH-style bundle. The signed prekey is the Olm fallback key, which is
+/// used once every published one-time key has been claimed. Bundles travel
+/// over gossip, so several peers may pick the same one-time key; senders pick
+/// at random to keep collisions rare and the fallback covers the rest.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PrekeyBundle {
     pub identity_key: String,
+    pub signing_key: String,
+    pub signed_prekey_id: String,
     pub signed_prekey: String,
     pub signed_prekey_signature: String,
-    pub one_time_key: Option<String>,
+    pub signed_prekey_expires_at: i64,
+    pub one_time_keys: Vec<OneTimeKey>,
+}
+
+impl PrekeyBundle {
+    fn signed_prekey_payload(key_id: &str, key: &str, expires_at: i64) -> String {
+        format!("aegis-spk:{}:{}:{}", key_id, key, expires_at)
+    }
+
+    /// Encoding the owner signs with its identity key when publishing the
+    /// bundle; see [`PrekeyBundle::open`].
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|e| anyhow!("Failed to encode prekey bundle: {}", e))
+    }
+
+    /// Decodes a published bundle and checks it against its owner.
+    /// `signature` must be `owner_key`, the raw Ed25519 identity key the
+    /// owner is known by, signing `bytes`; a bundle is only trusted for the
+    /// owner whose identity vouches for it. Its signed prekey must then be
+    /// signed by the signing key the bundle names and not have expired.
+    pub fn open(bytes: &[u8], signature: &[u8], owner_key: &[u8; 32], now: i64) -> Result<VerifiedBundle> {
+        let owner_key = Ed25519PublicKey::from_slice(owner_key)?;
+        let signature = Ed25519Signature::from_slice(signature)
+            .map_err(|e| anyhow!("Invalid bundle signature: {}", e))?;
+        owner_key
+            .verify(bytes, &signature)
+            .map_err(|e| anyhow!("Bundle not signed by its owner: {}", e))?;
+        let bundle: PrekeyBundle =
+            bincode::deserialize(bytes).map_err(|e| anyhow!("Malformed prekey bundle: {}", e))?;
+        bundle.verify_signed_prekey(now)?;
+        Ok(VerifiedBundle(bundle))
+    }
+
+    /// Checks the signed prekey signature and expiry.
+    fn verify_signed_prekey(&self, now: i64) -> Result<()> {
+        let signing_key = Ed25519PublicKey::from_base64(&self.signing_key)?;
+        let signature = Ed25519Signature::from_base64(&self.signed_prekey_signature)?;
+        let payload = Self::signed_prekey_payload(
+            &self.signed_prekey_id,
+            &self.signed_prekey,
+            self.signed_prekey_expires_at,
+        );
+        signing_key
+            .verify(payload.as_bytes(), &signature)
+            .map_err(|e| anyhow!("Invalid signed prekey signature: {}", e))?;
+        if self.signed_prekey_expires_at <= now {
+            return Err(anyhow!("Signed prekey expired"));
+        }
+        Ok(())
+    }
+}
+
+/// A bundle its owner's identity key was found to vouch for.
+#[derive(Debug, Clone)]
+pub struct VerifiedBundle(PrekeyBundle);
+
+impl VerifiedBundle {
+    pub fn bundle(&self) -> &PrekeyBundle {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+struct SignedPrekey {
+    key_id: String,
+    key: String,
+    signature: String,
+    expires_at: i64,
+}
+
+/// Public prekey bookkeeping kept next to `state.e2ee`. The private halves
+/// live inside the pickled account.
+#[derive(Debug, Clone, Default, Archive, RkyvSerialize, RkyvDeserialize)]
+#[archive(check_bytes)]
+struct PrekeyState {
+    signed_prekey: Option<SignedPrekey>,
+    published_one_time_keys: Vec<OneTimeKey>,
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,7 +161,7 @@ pub struct EncryptedDmPacket {
     pub enc_content: Vec<u8>,
 }
 
-#[derive(Archive, RkyvDeserialize, RkyvSerialize)]
+#[derive(Clone, Archive, RkyvDeserialize, RkyvSerialize)]
 #[archive(check_bytes)]
 struct StoredState {
     account_pickle: String,
@@ -65,52 +177,192 @@ pub struct Manager {
     outbound_group: HashMap<String, GroupSession>,
     inbound_group: HashMap<String, InboundGroupSession>,
     group_keys: HashMap<String, (u64, String)>,
+    prekeys: PrekeyState,
+    remote_bundles: HashMap<String, PrekeyBundle>,
     storage_dir: Option<PathBuf>,
+    pickle_key: [u8; 32],
 }
 
 impl Manager {
-    pub fn new() -> Self {
+    pub fn with_pickle_key(pickle_key: [u8; 32]) -> Self {
         Self {
             account: Account::new(),
             sessions: HashMap::new(),
             outbound_group: HashMap::new(),
             inbound_group: HashMap::new(),
             group_keys: HashMap::new(),
+            prekeys: PrekeyState::default(),
+            remote_bundles: HashMap::new(),
             storage_dir: None,
+            pickle_key,
+        }
+    }
+
+    /// The persisted state as it would be saved under `key`, so a passphrase
+    /// change can write it out together with the other files keyed from the
+    /// passphrase. See [`Manager::set_pickle_key`].
+    pub fn encrypted_state(&self, key: &[u8; 32]) -> Vec<u8> {
+        let sessions_map: HashMap<String, String> = self
+            .sessions
+            .iter()
+            .map(|(k, v)| (k.clone(), v.pickle().encrypt(key)))
+            .collect();
+
+        let outbound_map: HashMap<String, String> = self
+            .outbound_group
+            .iter()
+            .map(|(k, v)| (k.clone(), v.pickle().encrypt(key)))
+            .collect();
+
+        let inbound_map: HashMap<String, String> = self
+            .inbound_group
+            .iter()
+            .map(|(k, v)| (k.clone(), v.pickle().encrypt(key)))
+            .collect();
+
+        let state = StoredState {
+            account_pickle: self.account.pickle().encrypt(key),
+            sessions: sessions_map,
+            outbound_group_sessions: outbound_map,
+            inbound_group_sessions: inbound_map,
+            group_keys: self.group_keys.clone(),
+        };
+
+        rkyv::to_bytes::<_, 1024>(&state).expect("Failed to serialize").to_vec()
+    }
+
+    /// Re-encrypts the persisted state under `new_key`.
+    pub fn rekey(&mut self, new_key: [u8; 32]) -> Result<()> {
+        let previous = std::mem::replace(&mut self.pickle_key, new_key);
+        if let Err(error) = self.save_all() {
+            self.pickle_key = previous;
+            return Err(error);
         }
+        Ok(())
+    }
+
+    /// Saves under `key` from now on. Call once state encrypted under it has
+    /// replaced `state.e2ee`.
+    pub fn set_pickle_key(&mut self, key: [u8; 32]) {
+        self.pickle_key = key;
     }
 
     pub fn identity_key(&self) -> String {
         self.account.identity_keys().curve25519.to_base64()
     }
 
-    pub fn generate_prekey_bundle(&mut self, num_keys: usize) -> Result<PrekeyBundle> {
-        self.account.generate_one_time_keys(num_keys);
-        let id_keys = self.account.identity_keys();
-        let otks = self.account.one_time_keys();
+    fn signed_prekey_expired(&self, now: i64) -> bool {
+        self.prekeys
+            .signed_prekey
+            .as_ref()
+            .map_or(true, |spk| spk.expires_at <= now)
+    }
 
-        let signed_prekey = id_keys.curve25519;        
-        let signature = self.account.sign(&signed_prekey.to_base64());
-        let one_time_key = otks.values().next().map(|k| k.to_base64());
+    fn rotate_signed_prekey(&mut self, now: i64) -> Result<()> {
+        self.account.generate_fallback_key();
+        let (key_id, key) = self
+            .account
+            .fallback_key()
+            .into_iter()
+            .next()
+            .map(|(id, key)| (id.to_base64(), key.to_base64()))
+            .ok_or_else(|| anyhow!("Failed to generate signed prekey"))?;
+        let expires_at = now + SIGNED_PREKEY_LIFETIME_SECS;
+        let payload = PrekeyBundle::signed_prekey_payload(&key_id, &key, expires_at);
+        let signature = self.account.sign(&payload).to_base64();
+
+        self.prekeys.signed_prekey = Some(SignedPrekey {
+            key_id,
+            key,
+            signature,
+            expires_at,
+        });
+        Ok(())
+    }
 
-        let bundle = PrekeyBundle {
-            identity_key: id_keys.curve25519.to_base64(),
-            signed_prekey: signed_prekey.to_base64(),
-            signed_prekey_signature: signature.to_base64(),
-            one_time_key,
-        };
+    /// Rotates the signed prekey if it expired, tops the published one-time
+    /// key pool up to `num_keys` and returns the bundle to advertise.
+    pub fn generate_prekey_bundle(&mut self, num_keys: usize) -> Result<PrekeyBundle> {
+        let now = unix_now();
+        if self.signed_prekey_expired(now) {
+            self.rotate_signed_prekey(now)?;
+        }
 
+        let missing = num_keys.saturating_sub(self.prekeys.published_one_time_keys.len());
+        if missing > 0 {
+            self.account.generate_one_time_keys(missing);
+        }
+        let fresh = self
+            .account
+            .one_time_keys()
+            .into_iter()
+            .map(|(id, key)| OneTimeKey {
+                key_id: id.to_base64(),
+                key: key.to_base64(),
+            });
+        self.prekeys.published_one_time_keys.extend(fresh);
+        self.account.mark_keys_as_published();
+
+        let bundle = self.current_bundle()?;
         self.save_all()?;
         Ok(bundle)
     }
 
-    pub fn create_outbound_session(&mut self, peer_id: &str, bundle: &PrekeyBundle) -> Result<()> {
+    fn current_bundle(&self) -> Result<PrekeyBundle> {
+        let spk = self
+            .prekeys
+            .signed_prekey
+            .as_ref()
+            .ok_or_else(|| anyhow!("No signed prekey generated"))?;
+        let id_keys = self.account.identity_keys();
+
+        Ok(PrekeyBundle {
+            identity_key: id_keys.curve25519.to_base64(),
+            signing_key: id_keys.ed25519.to_base64(),
+            signed_prekey_id: spk.key_id.clone(),
+            signed_prekey: spk.key.clone(),
+            signed_prekey_signature: spk.signature.clone(),
+            signed_prekey_expires_at: spk.expires_at,
+            one_time_keys: self.prekeys.published_one_time_keys.clone(),
+        })
+    }
+
+    pub fn published_one_time_key_count(&self) -> usize {
+        self.prekeys.published_one_time_keys.len()
+    }
+
+    pub fn needs_replenishment(&self) -> bool {
+        self.published_one_time_key_count() < ONE_TIME_KEY_LOW_WATER
+            || self.signed_prekey_expired(unix_now())
+    }
+
+    /// Returns a fresh bundle to publish when the one-time key pool ran low or
+    /// the signed prekey expired, otherwise `None`.
+    pub fn replenish_prekeys(&mut self) -> Result<Option<PrekeyBundle>> {
+        if !self.needs_replenishment() {
+            return Ok(None);
+        }
+        self.generate_prekey_bundle(ONE_TIME_KEY_TARGET).map(Some)
+    }
+
+    fn mark_one_time_key_used(&mut self, key: &Curve25519PublicKey) {
+        let encoded = key.to_base64();
+        self.prekeys
+            .published_one_time_keys
+            .retain(|otk| otk.key != encoded);
+    }
+
+    /// Starts an Olm session from a verified bundle, claiming a random
+    /// one-time key or the signed prekey when none are left.
+    fn create_outbound_session(&mut self, peer_id: &str, bundle: &PrekeyBundle) -> Result<()> {
+        bundle.verify_signed_prekey(unix_now())?;
         let identity_key = Curve25519PublicKey::from_base64(&bundle.identity_key)?;
+        let claimed = bundle
+            .one_time_keys
+            .choose(&mut rand::thread_rng())
+            .map(|otk| otk.key.clone());
         let one_time_key = Curve25519PublicKey::from_base64(
-            bundle
-                .one_time_key
-                .as_ref()
-                .ok_or_else(|| anyhow!("Missing one-time key"))?,
+            claimed.as_deref().unwrap_or(&bundle.signed_prekey),
         )?;
 
         let session = self.account.create_outbound_session(
@@ -120,11 +372,18 @@ impl Manager {
         );
 
         self.sessions.insert(peer_id.to_string(), session);
+        if let (Some(claimed), Some(stored)) = (claimed, self.remote_bundles.get_mut(peer_id)) {
+            stored.one_time_keys.retain(|otk| otk.key != claimed);
+        }
         self.save_all()?;
         Ok(())
     }
 
     pub fn encrypt_direct(&mut self, peer_id: &str, plaintext: &str) -> Result<EncryptedPacket> {
+        self.encrypt_bytes(peer_id, plaintext.as_bytes())
+    }
+
+    fn encrypt_bytes(&mut self, peer_id: &str, plaintext: &[u8]) -> Result<EncryptedPacket> {
         let session = self
             .sessions
             .get_mut(peer_id)
@@ -152,7 +411,9 @@ impl Manager {
             if let Some(identity_key) = peer_identity_key {
                 if let Some(init_bytes) = &packet.init {
                     let packet_body = std::str::from_utf8(init_bytes).map_err(|e| anyhow!("Invalid UTF-8: {}", e))?;
-                    self.create_inbound_session_from_packet(peer_id, identity_key, packet_body)?;
+                    // Creating the inbound session already consumes the first message.
+                    let peer_key = Curve25519PublicKey::from_base64(identity_key)?;
+                    return self.inbound_session(peer_id, peer_key, &PreKeyMessage::from_base64(packet_body)?);
                 } else {
                     return Err(anyhow!("No session exists for {} and no initiation packet provided.", peer_id));
                 }
@@ -178,12 +439,64 @@ impl Manager {
     pub fn create_inbound_session_from_packet(&mut self, peer_id: &str, peer_identity_key_b64: &str, packet_body: &str) -> Result<String> {
         let peer_key = Curve25519PublicKey::from_base64(peer_identity_key_b64)?;
         let prekey_msg = PreKeyMessage::from_base64(packet_body)?;
-        let result = self.account.create_inbound_session(peer_key, &prekey_msg)?;
-        
+        let plaintext = self.inbound_session(peer_id, peer_key, &prekey_msg)?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    fn inbound_session(&mut self, peer_id: &str, peer_key: Curve25519PublicKey, prekey_msg: &PreKeyMessage) -> Result<Vec<u8>> {
+        let result = self.account.create_inbound_session(peer_key, prekey_msg)?;
+        self.mark_one_time_key_used(&prekey_msg.one_time_key());
+
         self.sessions.insert(peer_id.to_string(), result.session);
         self.save_all()?;
-        
-        Ok(String::from_utf8(result.plaintext)?)
+
+        Ok(result.plaintext)
+    }
+
+    /// Decrypts a packet that does not name its sender. An initiation packet
+    /// belongs to the session it names or, from a new session, to the peer
+    /// whose verified bundle carries its identity key; other packets are
+    /// tried against every session. Returns the peer the packet came from
+    /// with the plaintext.
+    pub fn decrypt_sealed(&mut self, packet: &EncryptedPacket) -> Result<(String, Vec<u8>)> {
+        if let Some(init_bytes) = &packet.init {
+            let body_str = std::str::from_utf8(init_bytes).map_err(|e| anyhow!("Invalid UTF-8: {}", e))?;
+            let prekey_msg = PreKeyMessage::from_base64(body_str)?;
+            let session_id = prekey_msg.session_id();
+            let known = self
+                .sessions
+                .iter()
+                .find(|(_, session)| session.session_id() == session_id)
+                .map(|(peer_id, _)| peer_id.clone());
+            if let Some(peer_id) = known {
+                let session = self.sessions.get_mut(&peer_id).unwrap();
+                let plaintext = session.decrypt(&OlmMessage::PreKey(prekey_msg))?;
+                self.save_all()?;
+                return Ok((peer_id, plaintext));
+            }
+
+            let identity_key = prekey_msg.identity_key();
+            let identity_b64 = identity_key.to_base64();
+            let peer_id = self
+                .remote_bundles
+                .iter()
+                .find(|(_, bundle)| bundle.identity_key == identity_b64)
+                .map(|(peer_id, _)| peer_id.clone())
+                .ok_or_else(|| anyhow!("No known peer has identity key {}", identity_b64))?;
+            let plaintext = self.inbound_session(&peer_id, identity_key, &prekey_msg)?;
+            return Ok((peer_id, plaintext));
+        }
+
+        let body_str = std::str::from_utf8(&packet.enc_header).map_err(|e| anyhow!("Invalid UTF-8: {}", e))?;
+        let message = OlmMessage::Normal(Message::from_base64(body_str)?);
+        // A session that cannot decrypt the message is left unchanged.
+        let opened = self
+            .sessions
+            .iter_mut()
+            .find_map(|(peer_id, session)| session.decrypt(&message).ok().map(|plaintext| (peer_id.clone(), plaintext)));
+        let (peer_id, plaintext) = opened.ok_or_else(|| anyhow!("No session decrypts the sealed packet"))?;
+        self.save_all()?;
+        Ok((peer_id, plaintext))
     }
 
     pub fn create_group_session(&mut self, group_id: &str) -> Result<String> {
@@ -249,18 +562,13 @@ impl Manager {
         }
     }
 
-    pub fn set_group_key(&mut self, server_id: &str, channel_id: &Option<String>, epoch: u64, key: &[u8]) {
-        let group_id = self.format_group_id(server_id, channel_id);
-        let session_key_b64 = String::from_utf8(key.to_vec()).unwrap_or_default();
-        self.group_keys.insert(group_id, (epoch, session_key_b64));
-        let _ = self.save_all();
-    }
-
     pub fn get_group_key(&self, server_id: &str, channel_id: &Option<String>) -> Option<(u64, Vec<u8>)> {
         let group_id = self.format_group_id(server_id, channel_id);
         self.group_keys.get(&group_id).map(|(e, k)| (*e, k.as_bytes().to_vec()))
     }
 
+    /// Starts a fresh Megolm sender session for `epoch`, replacing ours for
+    /// the group. Returns the session key to hand out to current members.
     pub fn generate_and_set_group_key(&mut self, server_id: &str, channel_id: &Option<String>, epoch: u64) -> Vec<u8> {
         let session = GroupSession::new(MegolmSessionConfig::default());
         let session_key = session.session_key().to_base64();
@@ -271,9 +579,82 @@ impl Manager {
         session_key.into_bytes()
     }
 
+    /// Epoch of our own sender session for the group, if we have one.
+    pub fn outbound_group_epoch(&self, server_id: &str, channel_id: &Option<String>) -> Option<u64> {
+        let group_id = self.format_group_id(server_id, channel_id);
+        if !self.outbound_group.contains_key(&group_id) {
+            return None;
+        }
+        self.group_keys.get(&group_id).map(|(epoch, _)| *epoch)
+    }
+
+    /// Channels of `server_id` we currently hold a sender session for.
+    pub fn outbound_groups_for_server(&self, server_id: &str) -> Vec<Option<String>> {
+        let prefix = format!("{}:", server_id);
+        self.outbound_group
+            .keys()
+            .filter_map(|group_id| {
+                if group_id == server_id {
+                    Some(None)
+                } else {
+                    group_id.strip_prefix(&prefix).map(|c| Some(c.to_string()))
+                }
+            })
+            .collect()
+    }
+
+    fn sender_key_id(group_id: &str, sender_id: &str, epoch: u64) -> String {
+        format!("{}|{}|{}", group_id, sender_id, epoch)
+    }
+
+    /// Highest epoch any sender has distributed for the group.
+    pub fn latest_group_epoch(&self, server_id: &str, channel_id: &Option<String>) -> Option<u64> {
+        let prefix = format!("{}|", self.format_group_id(server_id, channel_id));
+        self.inbound_group
+            .keys()
+            .filter_map(|id| id.strip_prefix(&prefix))
+            .filter_map(|rest| rest.rsplit_once('|'))
+            .filter_map(|(_, epoch)| epoch.parse().ok())
+            .chain(self.outbound_group_epoch(server_id, channel_id))
+            .max()
+    }
+
+    /// Stores the sender session `sender_id` handed us for `epoch`. Only the
+    /// newest [`SENDER_KEY_EPOCHS_RETAINED`] epochs per sender are kept.
+    pub fn add_sender_key(
+        &mut self,
+        server_id: &str,
+        channel_id: &Option<String>,
+        sender_id: &str,
+        epoch: u64,
+        session_key: &[u8],
+    ) -> Result<()> {
+        let session_key_b64 = std::str::from_utf8(session_key).map_err(|e| anyhow!("Invalid UTF-8: {}", e))?;
+        let key = SessionKey::from_base64(session_key_b64)?;
+        let group_id = self.format_group_id(server_id, channel_id);
+        self.inbound_group.insert(
+            Self::sender_key_id(&group_id, sender_id, epoch),
+            InboundGroupSession::new(&key, MegolmSessionConfig::default()),
+        );
+
+        let prefix = format!("{}|{}|", group_id, sender_id);
+        let mut epochs: Vec<u64> = self
+            .inbound_group
+            .keys()
+            .filter_map(|id| id.strip_prefix(&prefix))
+            .filter_map(|epoch| epoch.parse().ok())
+            .collect();
+        epochs.sort_unstable_by(|a, b| b.cmp(a));
+        for stale in epochs.into_iter().skip(SENDER_KEY_EPOCHS_RETAINED) {
+            self.inbound_group
+                .remove(&Self::sender_key_id(&group_id, sender_id, stale));
+        }
+
+        self.save_all()
+    }
+
     pub fn encrypt_for(&mut self, peer_id: &str, plaintext: &[u8]) -> Result<EncryptedDmPacket> {
-        let plaintext_str = String::from_utf8(plaintext.to_vec()).map_err(|e| anyhow!("Invalid UTF-8: {}", e))?;
-        let packet = self.encrypt_direct(peer_id, &plaintext_str)?;
+        let packet = self.encrypt_bytes(peer_id, plaintext)?;
         Ok(EncryptedDmPacket {
             init: packet.init,
             enc_header: packet.enc_header,
@@ -289,20 +670,43 @@ impl Manager {
         let epoch = self.group_keys.get(&group_id).map(|(e,_)| *e).unwrap_or(0);
         let mut nonce = [0u8; 16];
         rand::rngs::OsRng.fill_bytes(&mut nonce);
+        // Persist the advanced ratchet so a restart never reuses a message index.
+        self.save_all()?;
         Ok((epoch, nonce.to_vec(), ciphertext_obj.to_base64().into_bytes()))
     }
 
-    pub fn add_remote_bundle(&mut self, user_id: &str, bundle: PrekeyBundle) {
-        let _ = self.create_outbound_session(user_id, &bundle);
+    /// Curve25519 identity key from the last verified bundle of `peer_id`.
+    pub fn remote_identity_key(&self, peer_id: &str) -> Option<String> {
+        self.remote_bundles.get(peer_id).map(|bundle| bundle.identity_key.clone())
+    }
+
+    /// Stores a peer's bundle and opens a session unless one already exists
+    /// for the same identity key.
+    pub fn add_remote_bundle(&mut self, user_id: &str, bundle: VerifiedBundle) -> Result<()> {
+        let VerifiedBundle(bundle) = bundle;
+        bundle.verify_signed_prekey(unix_now())?;
+        let identity_changed = self
+            .remote_bundles
+            .get(user_id)
+            .is_some_and(|known| known.identity_key != bundle.identity_key);
+        self.remote_bundles.insert(user_id.to_string(), bundle.clone());
+
+        if identity_changed || !self.sessions.contains_key(user_id) {
+            self.create_outbound_session(user_id, &bundle)?;
+        }
+        Ok(())
     }
 
     pub fn decrypt_from(&mut self, peer_id: &str, packet: &EncryptedPacket) -> Result<Vec<u8>> {
         self.decrypt_direct(peer_id, packet, None)
     }
 
-    pub fn decrypt_group_message(&mut self, server_id: &str, channel_id: &Option<String>, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    pub fn decrypt_group_message(&mut self, server_id: &str, channel_id: &Option<String>, sender_id: &str, epoch: u64, ciphertext: &[u8]) -> Result<Vec<u8>> {
         let group_id = self.format_group_id(server_id, channel_id);
-        let session = self.inbound_group.get_mut(&group_id).ok_or_else(|| anyhow!("No inbound session for group {}", group_id))?;
+        let session = self
+            .inbound_group
+            .get_mut(&Self::sender_key_id(&group_id, sender_id, epoch))
+            .ok_or_else(|| anyhow!("No sender key from {} for group {} epoch {}", sender_id, group_id, epoch))?;
         let ciphertext_b64 = std::str::from_utf8(ciphertext).map_err(|e| anyhow!("Invalid UTF-8: {}", e))?;
         let msg = MegolmMessage::from_base64(ciphertext_b64)?;
         let res = session.decrypt(&msg)?;
@@ -313,123 +717,123 @@ impl Manager {
         self.storage_dir = Some(dir);
     }
 
-    fn storage_path(&self) -> Option<PathBuf> {
+    pub fn storage_path(&self) -> Option<PathBuf> {
         self.storage_dir.as_ref().map(|d| d.join("state.e2ee"))
     }
 
+    fn prekey_state_path(&self) -> Option<PathBuf> {
+        self.storage_dir.as_ref().map(|d| d.join("state.prekeys"))
+    }
+
     pub fn save_all(&self) -> Result<()> {
         let path = self
             .storage_path()
             .ok_or_else(|| anyhow!("Storage path not set"))?;
 
-        let sessions_map: HashMap<String, String> = self
-            .sessions
-            .iter()
-            .map(|(k, v)| (k.clone(), v.pickle().encrypt(PICKLE_KEY)))
-            .collect();
-
-        let outbound_map: HashMap<String, String> = self
-            .outbound_group
-            .iter()
-            .map(|(k, v)| (k.clone(), v.pickle().encrypt(PICKLE_KEY)))
-            .collect();
-
-        let inbound_map: HashMap<String, String> = self
-            .inbound_group
-            .iter()
-            .map(|(k, v)| (k.clone(), v.pickle().encrypt(PICKLE_KEY)))
-            .collect();
-
-        let state = StoredState {
-            account_pickle: self.account.pickle().encrypt(PICKLE_KEY),
-            sessions: sessions_map,
-            outbound_group_sessions: outbound_map,
-            inbound_group_sessions: inbound_map,
-            group_keys: self.group_keys.clone(),
-        };
-
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
         }
 
-        let bytes = rkyv::to_bytes::<_, 1024>(&state).expect("Failed to serialize");
-        std::fs::write(path, &bytes)?;
+        std::fs::write(path, self.encrypted_state(&self.pickle_key))?;
+
+        if let Some(prekey_path) = self.prekey_state_path() {
+            let prekey_bytes = rkyv::to_bytes::<_, 256>(&self.prekeys).expect("Failed to serialize");
+            std::fs::write(prekey_path, &prekey_bytes)?;
+        }
         Ok(())
     }
 }
 
 static GLOBAL: OnceCell<Arc<TokioMutex<Manager>>> = OnceCell::new();
 
-pub fn init_with_dir(path: impl AsRef<Path>) -> Arc<TokioMutex<Manager>> {
+fn load_state(st: StoredState, key: &[u8; 32]) -> Result<Manager> {
+    let account = AccountPickle::from_encrypted(&st.account_pickle, key)
+        .map(Account::from_pickle)
+        .map_err(|e| anyhow!("Failed to decrypt account pickle: {}", e))?;
+
+    let sessions = st
+        .sessions
+        .into_iter()
+        .filter_map(|(k, v)| {
+            SessionPickle::from_encrypted(&v, key)
+                .ok()
+                .map(|p| (k, Session::from_pickle(p)))
+        })
+        .collect();
+
+    let outbound_group = st
+        .outbound_group_sessions
+        .into_iter()
+        .filter_map(|(k, v)| {
+            GroupSessionPickle::from_encrypted(&v, key)
+                .map(|p| (k, GroupSession::from_pickle(p)))
+                .ok()
+        })
+        .collect();
+
+    let inbound_group = st
+        .inbound_group_sessions
+        .into_iter()
+        .filter_map(|(k, v)| {
+            InboundGroupSessionPickle::from_encrypted(&v, key)
+                .map(|p| (k, InboundGroupSession::from_pickle(p)))
+                .ok()
+        })
+        .collect();
+
+    Ok(Manager {
+        account,
+        sessions,
+        outbound_group,
+        inbound_group,
+        group_keys: st.group_keys,
+        prekeys: PrekeyState::default(),
+        remote_bundles: HashMap::new(),
+        storage_dir: None,
+        pickle_key: *key,
+    })
+}
+
+/// Loads `state.e2ee` from `path` using `pickle_key`. State written by older
+/// builds under the legacy constant key is migrated to `pickle_key` on load.
+/// Fails rather than silently replacing the account when the key is wrong.
+pub fn init_with_dir(path: impl AsRef<Path>, pickle_key: [u8; 32]) -> Result<Arc<TokioMutex<Manager>>> {
     let dir = path.as_ref().to_path_buf();
     let state_file = dir.join("state.e2ee");
 
-    let manager = if state_file.exists() {
-        match std::fs::read(&state_file) {
-            Ok(bytes) => {
-                if let Ok(archived) = rkyv::check_archived_root::<StoredState>(&bytes) {
-                    let st: StoredState = archived.deserialize(&mut rkyv::Infallible).expect("Infallible");
-                    let account = AccountPickle::from_encrypted(&st.account_pickle, PICKLE_KEY)
-                        .map(Account::from_pickle)
-                        .unwrap_or_else(|_| Account::new());
-
-                    let sessions = st
-                        .sessions
-                        .into_iter()
-                        .filter_map(|(k, v)| {
-                            SessionPickle::from_encrypted(&v, PICKLE_KEY)
-                                .ok()
-                                .map(|p| (k, Session::from_pickle(p)))
-                        })
-                        .collect();
-
-                    let outbound_group = st
-                        .outbound_group_sessions
-                        .into_iter()
-                        .filter_map(|(k, v)| {
-                            GroupSessionPickle::from_encrypted(&v, PICKLE_KEY)
-                                .map(|p| (k, GroupSession::from_pickle(p)))
-                                .ok()
-                        })
-                        .collect();
-
-                    let inbound_group = st
-                        .inbound_group_sessions
-                        .into_iter()
-                        .filter_map(|(k, v)| {
-                            InboundGroupSessionPickle::from_encrypted(&v, PICKLE_KEY)
-                                .map(|p| (k, InboundGroupSession::from_pickle(p)))
-                                .ok()
-                        })
-                        .collect();
-
-                    Manager {
-                        account,
-                        sessions,
-                        outbound_group,
-                        inbound_group,
-                        group_keys: st.group_keys,
-                        storage_dir: Some(dir.clone()),
-                    }
-                } else {
-                    Manager::new()
-                }
+    let mut manager = if state_file.exists() {
+        let bytes = std::fs::read(&state_file)?;
+        let archived = rkyv::check_archived_root::<StoredState>(&bytes)
+            .map_err(|e| anyhow!("Corrupt e2ee state: {}", e))?;
+        let st: StoredState = archived.deserialize(&mut rkyv::Infallible).expect("Infallible");
+
+        match load_state(st.clone(), &pickle_key) {
+            Ok(manager) => manager,
+            Err(error) => {
+                let mut migrated = load_state(st, LEGACY_PICKLE_KEY).map_err(|_| error)?;
+                migrated.pickle_key = pickle_key;
+                migrated
             }
-            Err(_) => Manager::new(),
         }
     } else {
-        Manager::new()
+        Manager::with_pickle_key(pickle_key)
     };
 
+    if let Ok(bytes) = std::fs::read(dir.join("state.prekeys")) {
+        if let Ok(archived) = rkyv::check_archived_root::<PrekeyState>(&bytes) {
+            manager.prekeys = archived.deserialize(&mut rkyv::Infallible).expect("Infallible");
+        }
+    }
+
     let wrapper = Arc::new(TokioMutex::new(manager));
     {
         let mut g = wrapper.try_lock().unwrap();
         g.set_storage_dir(dir);
-        let _ = g.save_all();
+        g.save_all()?;
     }
 
     let _ = GLOBAL.set(wrapper.clone());
-    wrapper
+    Ok(wrapper)
 }
 
 pub fn global() -> Option<Arc<TokioMutex<Manager>>> {
@@ -439,3 +843,162 @@ pub fn global() -> Option<Arc<TokioMutex<Manager>>> {
 pub fn init_global_manager() -> Arc<TokioMutex<Manager>> {
     global().expect("e2ee not initialized")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_legacy_state_and_rekeys() {
+        let dir = tempfile::tempdir().expect("tempdir");
+
+        let mut legacy = Manager::with_pickle_key(*LEGACY_PICKLE_KEY);
+        legacy.set_storage_dir(dir.path().to_path_buf());
+        legacy.save_all().expect("save legacy");
+        let identity_key = legacy.identity_key();
+
+        let first_key = [7u8; 32];
+        let migrated = init_with_dir(dir.path(), first_key).expect("migrate");
+        assert_eq!(migrated.try_lock().unwrap().identity_key(), identity_key);
+
+        let second_key = [9u8; 32];
+        migrated.try_lock().unwrap().rekey(second_key).expect("rekey");
+
+        assert!(init_with_dir(dir.path(), first_key).is_err());
+        let reloaded = init_with_dir(dir.path(), second_key).expect("reload");
+        assert_eq!(reloaded.try_lock().unwrap().identity_key(), identity_key);
+    }
+
+    fn manager_in(dir: &Path) -> Manager {
+        let mut manager = Manager::with_pickle_key([1u8; 32]);
+        manager.set_storage_dir(dir.to_path_buf());
+        manager
+    }
+
+    /// Publishes `bundle` the way its owner would, signed by `owner`.
+    fn sign_bundle(bundle: &PrekeyBundle, owner: &Ed25519Keypair) -> (Vec<u8>, Vec<u8>) {
+        let bytes = bundle.to_bytes().unwrap();
+        let signature = owner.sign(&bytes).to_bytes().to_vec();
+        (bytes, signature)
+    }
+
+    fn published(bundle: &PrekeyBundle) -> VerifiedBundle {
+        let owner = Ed25519Keypair::new();
+        let (bytes, signature) = sign_bundle(bundle, &owner);
+        PrekeyBundle::open(&bytes, &signature, owner.public_key().as_bytes(), unix_now()).expect("verified")
+    }
+
+    #[test]
+    fn falls_back_to_signed_prekey_when_one_time_keys_run_out() {
+        let (alice_dir, bob_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let mut alice = manager_in(alice_dir.path());
+        let mut bob = manager_in(bob_dir.path());
+
+        let mut bundle = bob.generate_prekey_bundle(1).expect("bundle");
+        assert_eq!(bundle.one_time_keys.len(), 1);
+        bundle.one_time_keys.clear();
+
+        alice.add_remote_bundle("bob", published(&bundle)).expect("session");
+        let packet = alice.encrypt_direct("bob", "hello").expect("encrypt");
+        let plaintext = bob
+            .decrypt_direct("alice", &packet, Some(&alice.identity_key()))
+            .expect("decrypt");
+        assert_eq!(plaintext, b"hello");
+    }
+
+    #[test]
+    fn replenishes_after_one_time_keys_are_claimed() {
+        let (alice_dir, bob_dir) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let mut alice = manager_in(alice_dir.path());
+        let mut bob = manager_in(bob_dir.path());
+
+        let bundle = bob.generate_prekey_bundle(ONE_TIME_KEY_LOW_WATER).expect("bundle");
+        assert!(!bob.needs_replenishment());
+
+        alice.add_remote_bundle("bob", published(&bundle)).expect("session");
+        let packet = alice.encrypt_direct("bob", "hi").expect("encrypt");
+        bob.decrypt_direct("alice", &packet, Some(&alice.identity_key()))
+            .expect("decrypt");
+
+        assert!(bob.needs_replenishment());
+        let refreshed = bob.replenish_prekeys().expect("replenish").expect("bundle");
+        assert_eq!(refreshed.one_time_keys.len(), ONE_TIME_KEY_TARGET);
+    }
+
+    #[test]
+    fn sealed_packets_reveal_their_sender_on_decryption() {
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut alice = manager_in(dirs[0].path());
+        let mut bob = manager_in(dirs[1].path());
+        let mut carol = manager_in(dirs[2].path());
+
+        let alice_bundle = alice.generate_prekey_bundle(1).expect("bundle");
+        alice.add_remote_bundle("bob", published(&bob.generate_prekey_bundle(1).expect("bundle"))).expect("session");
+        // Bob also opened a session of his own towards Alice, which her
+        // first packet replaces.
+        bob.add_remote_bundle("alice", published(&alice_bundle)).expect("session");
+
+        let binary = vec![0xff, 0x00, 0xfe];
+        let first = alice.encrypt_for("bob", &binary).expect("encrypt");
+        let packet = EncryptedPacket { init: first.init, enc_header: first.enc_header, enc_content: first.enc_content };
+        assert_eq!(bob.decrypt_sealed(&packet).expect("decrypt"), ("alice".to_string(), binary));
+        assert!(carol.decrypt_sealed(&packet).is_err());
+
+        let second = alice.encrypt_for("bob", b"again").expect("encrypt");
+        let packet = EncryptedPacket { init: second.init, enc_header: second.enc_header, enc_content: second.enc_content };
+        assert_eq!(bob.decrypt_sealed(&packet).expect("decrypt").1, b"again");
+
+        let reply = bob.encrypt_for("alice", b"hi").expect("encrypt");
+        assert!(reply.init.is_none());
+        let packet = EncryptedPacket { init: reply.init, enc_header: reply.enc_header, enc_content: reply.enc_content };
+        assert_eq!(alice.decrypt_sealed(&packet).expect("decrypt"), ("bob".to_string(), b"hi".to_vec()));
+    }
+
+    #[test]
+    fn rejects_bundles_their_owner_did_not_sign() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bob = manager_in(dir.path());
+        let owner = Ed25519Keypair::new();
+        let owner_key = owner.public_key();
+        let bundle = bob.generate_prekey_bundle(1).expect("bundle");
+
+        let (bytes, signature) = sign_bundle(&bundle, &owner);
+        assert!(PrekeyBundle::open(&bytes, &signature, owner_key.as_bytes(), unix_now()).is_ok());
+
+        // A bundle that verifies on its own, signed by anyone but the owner.
+        let impostor = Ed25519Keypair::new();
+        let (bytes, signature) = sign_bundle(&bundle, &impostor);
+        assert!(PrekeyBundle::open(&bytes, &signature, owner_key.as_bytes(), unix_now()).is_err());
+
+        let mut tampered = bundle;
+        tampered.signed_prekey_expires_at += 1;
+        let (bytes, signature) = sign_bundle(&tampered, &owner);
+        assert!(PrekeyBundle::open(&bytes, &signature, owner_key.as_bytes(), unix_now()).is_err());
+    }
+
+    #[test]
+    fn rotated_sender_key_locks_out_members_without_it() {
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let mut owner = manager_in(dirs[0].path());
+        let mut member = manager_in(dirs[1].path());
+        let mut removed = manager_in(dirs[2].path());
+        let channel = Some("general".to_string());
+
+        let first = owner.generate_and_set_group_key("srv", &channel, 1);
+        member.add_sender_key("srv", &channel, "owner", 1, &first).unwrap();
+        removed.add_sender_key("srv", &channel, "owner", 1, &first).unwrap();
+
+        let (epoch, _, ciphertext) = owner.encrypt_group_message("srv", &channel, b"before").unwrap();
+        assert_eq!(removed.decrypt_group_message("srv", &channel, "owner", epoch, &ciphertext).unwrap(), b"before");
+
+        let second = owner.generate_and_set_group_key("srv", &channel, 2);
+        member.add_sender_key("srv", &channel, "owner", 2, &second).unwrap();
+        assert_eq!(member.latest_group_epoch("srv", &channel), Some(2));
+        assert_eq!(owner.outbound_groups_for_server("srv"), vec![channel.clone()]);
+
+        let (epoch, _, ciphertext) = owner.encrypt_group_message("srv", &channel, b"after").unwrap();
+        assert_eq!(epoch, 2);
+        assert_eq!(member.decrypt_group_message("srv", &channel, "owner", epoch, &ciphertext).unwrap(), b"after");
+        assert!(removed.decrypt_group_message("srv", &channel, "owner", epoch, &ciphertext).is_err());
+    }
+}
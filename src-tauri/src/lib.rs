@@ -1,6 +1,7 @@
 pub mod bootstrap;
 pub mod commands;
 pub mod connectivity;
+pub mod relay_node;
 pub mod settings_store;
 pub mod scu128;
 
@@ -31,6 +32,11 @@ pub async fn run() {
             commands::messages::unpin_message,
             commands::messages::add_reaction,
             commands::messages::remove_reaction,
+            commands::messages::create_thread,
+            commands::messages::get_threads,
+            commands::messages::get_followed_threads,
+            commands::messages::follow_thread,
+            commands::messages::unfollow_thread,
             commands::messages::send_encrypted_dm,
             commands::messages::send_encrypted_dm_with_attachments,
             commands::messages::send_read_receipt,
@@ -135,7 +141,21 @@ pub async fn run() {
             commands::connectivity::set_bluetooth_enabled,
             commands::connectivity::set_wifi_direct_enabled,
             commands::connectivity::set_routing_config,
+            commands::connectivity::get_bootstrap_peers,
+            commands::connectivity::set_bootstrap_peers,
+            commands::connectivity::get_proxy_settings,
+            commands::connectivity::set_proxy_settings,
+            commands::connectivity::get_privacy_settings,
+            commands::connectivity::set_privacy_settings,
+            commands::connectivity::get_denied_peers,
+            commands::connectivity::deny_peer,
+            commands::connectivity::lift_peer_denial,
+            commands::connectivity::dial_peer,
+            commands::connectivity::get_contact_card,
             commands::collaboration::send_collaboration_update,
+            commands::verification::get_safety_number,
+            commands::verification::verify_safety_number_qr,
+            commands::verification::set_contact_verified,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -3,5 +3,13 @@
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == aegis_lib::relay_node::RELAY_FLAG) {
+        if let Err(e) = aegis_lib::relay_node::run_from_args(&args).await {
+            eprintln!("Relay node failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     aegis_lib::run().await
 }
@@ -2,6 +2,9 @@ use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::{Duration, Instant};
 
 use libp2p::PeerId;
+
+use crate::frames::FrameId;
+use crate::link_state::VerifiedAdvert;
 use rkyv::{Archive, Serialize as RkyvSerialize, Deserialize as RkyvDeserialize};
 use serde::{Serialize as SerdeSerialize, Deserialize as SerdeDeserialize};
 
@@ -31,6 +34,7 @@ impl Default for AerpConfig {
 
 #[derive(Clone, Debug, SerdeSerialize, SerdeDeserialize, Archive, RkyvSerialize, RkyvDeserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
+#[archive(check_bytes)]
 #[archive_attr(derive(Debug))]
 pub struct RouteMetrics {
     pub hop_count: u32,
@@ -53,6 +57,7 @@ impl RouteMetrics {
 
 #[derive(Clone, Debug, SerdeSerialize, SerdeDeserialize, Archive, RkyvSerialize, RkyvDeserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
+#[archive(check_bytes)]
 #[archive_attr(derive(Debug))]
 pub struct LinkQuality {
     pub latency_ms: f64,
@@ -95,6 +100,13 @@ impl LinkState {
     }
 }
 
+/// Newest link-state advert merged for an origin.
+#[derive(Clone, Debug)]
+struct AdvertRecord {
+    sequence: u64,
+    expires_at: Instant,
+}
+
 #[derive(Clone, Debug)]
 pub struct RouteSnapshot {
     pub target: PeerId,
@@ -124,6 +136,7 @@ pub struct AerpRouter {
     adjacency: HashMap<PeerId, HashMap<PeerId, LinkState>>,
     known_peers: HashSet<PeerId>,
     cached_routes: HashMap<PeerId, RouteSnapshot>,
+    adverts: HashMap<PeerId, AdvertRecord>,
     config: AerpConfig,
 }
 
@@ -138,6 +151,7 @@ impl AerpRouter {
             adjacency,
             known_peers,
             cached_routes: HashMap::new(),
+            adverts: HashMap::new(),
             config: AerpConfig::default(),
         }
     }
@@ -175,6 +189,7 @@ impl AerpRouter {
             neighbours.remove(peer);
         }
         self.cached_routes.remove(peer);
+        self.adverts.remove(peer);
     }
 
     pub fn observe_direct_link(&mut self, a: PeerId, b: PeerId, quality: LinkQuality) {
@@ -243,6 +258,77 @@ impl AerpRouter {
         self.recompute_routes();
     }
 
+    /// Our own links refreshed within `max_age`, as advertised to others.
+    pub fn local_links(&self, max_age: Duration) -> Vec<(PeerId, LinkQuality)> {
+        let cutoff = Instant::now() - max_age;
+        self.adjacency
+            .get(&self.local_peer)
+            .map(|neighbours| {
+                neighbours
+                    .iter()
+                    .filter(|(_, state)| state.last_updated >= cutoff)
+                    .map(|(peer, state)| (*peer, state.quality.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Replaces the origin's links with the ones it advertised, unless an
+    /// advert with the same or a later sequence number was already merged.
+    /// Returns whether the graph changed.
+    pub fn merge_advert(&mut self, advert: VerifiedAdvert) -> bool {
+        let origin = advert.origin;
+        if origin == self.local_peer {
+            return false;
+        }
+        if let Some(current) = self.adverts.get(&origin) {
+            if advert.sequence <= current.sequence {
+                return false;
+            }
+        }
+
+        self.observe_peer(origin);
+        let mut links = HashMap::new();
+        for (peer, quality) in advert.links {
+            if peer == origin {
+                continue;
+            }
+            self.observe_peer(peer);
+            links.insert(peer, LinkState::new(quality));
+        }
+        self.adjacency.insert(origin, links);
+        self.adverts.insert(
+            origin,
+            AdvertRecord {
+                sequence: advert.sequence,
+                expires_at: Instant::now() + advert.max_age,
+            },
+        );
+        self.recompute_routes();
+        true
+    }
+
+    /// Forgets the links of origins whose advert was not refreshed in time.
+    /// Returns how many origins aged out.
+    pub fn expire_adverts(&mut self, now: Instant) -> usize {
+        let expired: Vec<PeerId> = self
+            .adverts
+            .iter()
+            .filter(|(_, record)| record.expires_at <= now)
+            .map(|(origin, _)| *origin)
+            .collect();
+        for origin in &expired {
+            self.adverts.remove(origin);
+            if let Some(neighbours) = self.adjacency.get_mut(origin) {
+                neighbours.clear();
+            }
+        }
+        if !expired.is_empty() {
+            self.recompute_routes();
+        }
+        expired.len()
+    }
+
     pub fn recompute_routes(&mut self) {
         self.cached_routes.clear();
         let targets: Vec<_> = self
@@ -264,7 +350,46 @@ impl AerpRouter {
         self.cached_routes.values().cloned().collect()
     }
 
+    pub fn route_to(&self, target: &PeerId) -> Option<RouteSnapshot> {
+        self.cached_routes.get(target).cloned()
+    }
+
+    /// Up to `count` routes to `target` that share no intermediate peer, best
+    /// first, so losing one relay or link takes out at most one of them. The
+    /// direct link, when there is one, counts as one of the routes.
+    pub fn disjoint_routes(&self, target: &PeerId, count: usize) -> Vec<RouteSnapshot> {
+        let mut routes: Vec<RouteSnapshot> = Vec::new();
+        let mut avoid: HashSet<PeerId> = HashSet::new();
+        let mut allow_direct = true;
+        while routes.len() < count {
+            let Some(route) = self.compute_route_avoiding(target, &avoid, allow_direct) else {
+                break;
+            };
+            if route.metrics.quality() < self.config.min_route_quality {
+                break;
+            }
+            let intermediates = &route.path[1..route.path.len() - 1];
+            if intermediates.is_empty() {
+                allow_direct = false;
+            }
+            avoid.extend(intermediates.iter().cloned());
+            routes.push(route);
+        }
+        routes
+    }
+
     fn compute_route(&self, target: &PeerId) -> Option<RouteSnapshot> {
+        self.compute_route_avoiding(target, &HashSet::new(), true)
+    }
+
+    /// Best route to `target` that does not pass through any peer in `avoid`
+    /// and, unless `allow_direct`, does not use a direct link to it.
+    fn compute_route_avoiding(
+        &self,
+        target: &PeerId,
+        avoid: &HashSet<PeerId>,
+        allow_direct: bool,
+    ) -> Option<RouteSnapshot> {
         if target == &self.local_peer {
             return None;
         }
@@ -311,7 +436,10 @@ impl AerpRouter {
 
             if let Some(neighbours) = self.adjacency.get(&peer) {
                 for (next, state) in neighbours {
-                    if path.contains(next) {
+                    if path.contains(next) || avoid.contains(next) {
+                        continue;
+                    }
+                    if !allow_direct && peer == self.local_peer && next == target {
                         continue;
                     }
                     let mut next_path = path.clone();
@@ -349,8 +477,15 @@ impl AerpRouter {
 
 #[derive(Clone, Debug, Archive, RkyvSerialize, RkyvDeserialize, SerdeSerialize, SerdeDeserialize)]
 #[serde(rename_all = "camelCase")]
+#[archive(check_bytes)]
 #[archive_attr(derive(Debug))]
 pub struct RoutedEnvelope {
+    /// Unique per transmission, so each hop can drop replays and loops.
+    pub frame_id: FrameId,
+    /// Hops left; each forwarding node decrements it.
+    pub ttl: u8,
+    /// Peer that sent the envelope; empty for sealed frames, which do not
+    /// give their sender away.
     pub origin: String,
     pub destination: String,
     pub path: Vec<String>,
@@ -358,11 +493,38 @@ pub struct RoutedEnvelope {
     pub payload: Vec<u8>,
 }
 
+impl RoutedEnvelope {
+    pub fn path_peers(&self) -> Vec<PeerId> {
+        self.path
+            .iter()
+            .filter_map(|value| value.parse::<PeerId>().ok())
+            .collect()
+    }
+
+    /// Peer that should receive the envelope after `local`, if `local` is on
+    /// the path and is not its last hop.
+    pub fn next_hop_after(&self, local: &PeerId) -> Option<PeerId> {
+        let path = self.path_peers();
+        path.iter()
+            .position(|peer| peer == local)
+            .and_then(|idx| path.get(idx + 1).cloned())
+    }
+}
+
 #[derive(Debug, Archive, RkyvSerialize, RkyvDeserialize, SerdeSerialize, SerdeDeserialize)]
 #[serde(rename_all = "camelCase")]
+#[archive(check_bytes)]
 #[archive_attr(derive(Debug))]
 pub enum RoutedFrame {
-    Broadcast { origin: String, payload: Vec<u8> },
+    /// `ttl` bounds re-publication by nodes that bridge the frame onto
+    /// another transport; gossipsub's own forwarding is caught by the seen
+    /// cache.
+    Broadcast {
+        frame_id: FrameId,
+        ttl: u8,
+        origin: String,
+        payload: Vec<u8>,
+    },
     Routed { envelope: RoutedEnvelope },
 }
 
@@ -451,4 +613,125 @@ mod tests {
         let route_a = router.compute_route(&peer_a).expect("route");
         assert!(route.metrics.score(router.config()) < route_a.metrics.score(router.config()));
     }
+
+    fn advert(origin: &PeerId, sequence: u64, links: &[(&PeerId, f64)]) -> VerifiedAdvert {
+        VerifiedAdvert {
+            origin: *origin,
+            sequence,
+            max_age: Duration::from_secs(30),
+            links: links
+                .iter()
+                .map(|(peer, reliability)| {
+                    (
+                        **peer,
+                        LinkQuality {
+                            latency_ms: 20.0,
+                            reliability: *reliability,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn adverts_extend_routes_beyond_direct_links() {
+        let (local, relay, bridge, target) = (peer(), peer(), peer(), peer());
+        let mut router = AerpRouter::new(local);
+        router.observe_direct_link(
+            local,
+            relay,
+            LinkQuality {
+                latency_ms: 10.0,
+                reliability: 0.95,
+            },
+        );
+        assert!(router.route_to(&target).is_none());
+
+        assert!(router.merge_advert(advert(&relay, 2, &[(&local, 0.95), (&bridge, 0.9)])));
+        assert!(router.merge_advert(advert(&bridge, 1, &[(&relay, 0.9), (&target, 0.9)])));
+        let route = router.route_to(&target).expect("multi-hop route");
+        assert_eq!(route.path, vec![local, relay, bridge, target]);
+
+        assert!(router.merge_advert(advert(&bridge, 3, &[(&relay, 0.9)])));
+        assert!(router.route_to(&target).is_none());
+        // An older or replayed advert cannot bring back links the origin
+        // has since withdrawn.
+        assert!(!router.merge_advert(advert(&bridge, 1, &[(&relay, 0.9), (&target, 0.9)])));
+        assert!(router.route_to(&target).is_none());
+        assert!(!router.merge_advert(advert(&local, 9, &[(&target, 0.9)])));
+
+        assert_eq!(router.expire_adverts(Instant::now() + Duration::from_secs(60)), 2);
+        assert!(router.route_to(&bridge).is_none());
+        assert!(router.route_to(&relay).is_some());
+        assert_eq!(router.local_links(Duration::from_secs(60)).len(), 1);
+    }
+
+    #[test]
+    fn disjoint_routes_share_no_relay() {
+        let (local, relay_a, relay_b, relay_c, target) = (peer(), peer(), peer(), peer(), peer());
+        let good = LinkQuality {
+            latency_ms: 10.0,
+            reliability: 0.95,
+        };
+        let mut router = AerpRouter::new(local);
+        router.observe_direct_link(local, relay_a, good.clone());
+        router.observe_direct_link(local, relay_b, good.clone());
+        router.observe_direct_link(relay_a, target, good.clone());
+        router.observe_direct_link(relay_b, target, good.clone());
+        // A detour through relay_a must not count as a second route.
+        router.observe_direct_link(relay_a, relay_c, good.clone());
+        router.observe_direct_link(relay_c, target, good.clone());
+
+        let routes = router.disjoint_routes(&target, 3);
+        assert_eq!(routes.len(), 2);
+        let first_hops: HashSet<_> = routes.iter().map(|route| route.path[1]).collect();
+        assert_eq!(first_hops, HashSet::from([relay_a, relay_b]));
+
+        router.observe_direct_link(local, target, good);
+        let routes = router.disjoint_routes(&target, 3);
+        assert_eq!(routes.len(), 3);
+        assert_eq!(routes[0].path, vec![local, target]);
+        assert_eq!(router.disjoint_routes(&target, 1).len(), 1);
+    }
+
+    #[test]
+    fn envelope_names_the_next_hop() {
+        let (origin, relay, target) = (peer(), peer(), peer());
+        let envelope = RoutedEnvelope {
+            frame_id: crate::frames::new_frame_id(),
+            ttl: crate::frames::DEFAULT_FRAME_TTL,
+            origin: origin.to_base58(),
+            destination: target.to_base58(),
+            path: vec![origin.to_base58(), relay.to_base58(), target.to_base58()],
+            metrics: RouteMetrics {
+                hop_count: 2,
+                total_latency_ms: 20.0,
+                reliability: 0.9,
+            },
+            payload: Vec::new(),
+        };
+
+        assert_eq!(envelope.next_hop_after(&origin), Some(relay));
+        assert_eq!(envelope.next_hop_after(&relay), Some(target));
+        assert_eq!(envelope.next_hop_after(&target), None);
+        assert_eq!(envelope.next_hop_after(&peer()), None);
+    }
+
+    #[test]
+    fn frames_carry_their_layout_version() {
+        let frame = RoutedFrame::Broadcast {
+            frame_id: crate::frames::new_frame_id(),
+            ttl: crate::frames::DEFAULT_FRAME_TTL,
+            origin: peer().to_base58(),
+            payload: b"payload".to_vec(),
+        };
+        let bytes = crate::encode_frame(&frame).unwrap();
+        assert!(bytes.starts_with(&crate::ROUTED_FRAME_MAGIC));
+        assert!(matches!(
+            crate::decode_frame(&bytes),
+            Ok(RoutedFrame::Broadcast { payload, .. }) if payload == b"payload"
+        ));
+        assert!(crate::decode_frame(&bytes[crate::ROUTED_FRAME_MAGIC.len()..]).is_err());
+    }
 }
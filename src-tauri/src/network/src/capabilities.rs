@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use aegis_protocol::wire::{self, PeerCapabilities};
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+use once_cell::sync::OnceCell;
+use parking_lot::RwLock;
+
+#[derive(Default)]
+pub struct CapabilityRegistry {
+    peers: RwLock<HashMap<PeerId, PeerCapabilities>>,
+}
+
+impl CapabilityRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records what a peer announced through identify. Returns the parsed
+    /// capabilities, or `None` when the protocol string is not an Aegis one.
+    pub fn record(&self, peer: PeerId, protocol_version: &str) -> Option<PeerCapabilities> {
+        let caps = wire::parse_identify_protocol_version(protocol_version)?;
+        self.peers.write().insert(peer, caps);
+        Some(caps)
+    }
+
+    pub fn forget(&self, peer: &PeerId) {
+        self.peers.write().remove(peer);
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<PeerCapabilities> {
+        self.peers.read().get(peer).copied()
+    }
+
+    /// Envelope version to use with `peer`, if both sides share one.
+    pub fn negotiated_version(&self, peer: &PeerId) -> Option<u16> {
+        self.get(peer)
+            .and_then(|remote| PeerCapabilities::local().negotiate(&remote))
+    }
+
+    pub fn incompatible_peers(&self) -> Vec<PeerId> {
+        let local = PeerCapabilities::local();
+        self.peers
+            .read()
+            .iter()
+            .filter(|(_, caps)| local.negotiate(caps).is_none())
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+}
+
+/// One registry per local peer, so swarms sharing a process keep what their
+/// peers announced to each of them apart.
+static REGISTRIES: OnceCell<RwLock<HashMap<PeerId, Arc<CapabilityRegistry>>>> = OnceCell::new();
+
+/// Registry of what peers announced to `local`.
+pub fn registry(local: &PeerId) -> Arc<CapabilityRegistry> {
+    let registries = REGISTRIES.get_or_init(Default::default);
+    if let Some(registry) = registries.read().get(local) {
+        return registry.clone();
+    }
+    registries.write().entry(*local).or_default().clone()
+}
+
+pub fn record_peer_capabilities(local: &PeerId, peer: PeerId, protocol_version: &str) -> Option<PeerCapabilities> {
+    registry(local).record(peer, protocol_version)
+}
+
+pub fn peer_capabilities(local: &PeerId, peer: &PeerId) -> Option<PeerCapabilities> {
+    registry(local).get(peer)
+}
+
+pub fn negotiated_version(local: &PeerId, peer: &PeerId) -> Option<u16> {
+    registry(local).negotiated_version(peer)
+}
+
+/// Drops what `peer` announced to `local`; called once its last connection
+/// closes.
+pub fn forget_peer(local: &PeerId, peer: &PeerId) {
+    registry(local).forget(peer);
+}
+
+/// Capabilities `local` writes frames for `peer` with. A peer that has not
+/// identified yet is assumed to run this build.
+pub fn outbound_capabilities(local: &PeerId, peer: &PeerId) -> PeerCapabilities {
+    peer_capabilities(local, peer).unwrap_or_else(PeerCapabilities::local)
+}
+
+/// What all of `peers` can read, for frames `local` publishes to every one
+/// of them at once. Peers that share no envelope version with us could not
+/// read such a frame anyway and are left out.
+pub fn shared_capabilities<'a>(local: &PeerId, peers: impl IntoIterator<Item = &'a PeerId>) -> PeerCapabilities {
+    let own = PeerCapabilities::local();
+    peers
+        .into_iter()
+        .map(|peer| outbound_capabilities(local, peer))
+        .filter(|caps| own.negotiate(caps).is_some())
+        .fold(own, |shared, caps| shared.intersect(&caps))
+}
+
+/// Rewrites a message frame for receivers with `caps`; see
+/// [`wire::adapt`]. A frame that cannot be rewritten goes out as it is, so a
+/// receiver too old for its layout turns it down by version rather than
+/// misreading it.
+pub fn adapt_outgoing(data: Vec<u8>, caps: &PeerCapabilities) -> Vec<u8> {
+    match wire::adapt(data.clone(), caps) {
+        Ok(adapted) => adapted,
+        Err(error) => {
+            eprintln!("Sending frame as it is: {}", error);
+            data
+        }
+    }
+}
+
+/// Signs an adapted frame as sent by `keypair` now. Legacy raw frames cannot
+/// carry a signature and go out as they are.
+pub fn sign_outgoing(data: Vec<u8>, keypair: &Keypair) -> Vec<u8> {
+    if !data.starts_with(&wire::WIRE_MAGIC) {
+        return data;
+    }
+    match wire::sign(data.clone(), keypair, chrono::Utc::now()) {
+        Ok(signed) => signed,
+        Err(error) => {
+            eprintln!("Sending frame unsigned: {}", error);
+            data
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swarms_in_one_process_keep_their_peers_apart() {
+        let relay = Keypair::generate_ed25519().public().to_peer_id();
+        let alice = Keypair::generate_ed25519().public().to_peer_id();
+        let bob = Keypair::generate_ed25519().public().to_peer_id();
+        let version = wire::identify_protocol_version();
+
+        assert!(record_peer_capabilities(&relay, bob, &version).is_some());
+        assert!(record_peer_capabilities(&alice, bob, &version).is_some());
+        assert_eq!(peer_capabilities(&bob, &alice), None);
+
+        // The relay losing bob leaves what bob told alice in place.
+        forget_peer(&relay, &bob);
+        assert_eq!(peer_capabilities(&relay, &bob), None);
+        assert_eq!(peer_capabilities(&alice, &bob), Some(PeerCapabilities::local()));
+    }
+}
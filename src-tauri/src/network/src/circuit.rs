@@ -0,0 +1,584 @@
+use std::collections::{HashMap, HashSet};
+use std::task::{Context, Poll};
+
+use libp2p::core::ConnectedPoint;
+use libp2p::core::connection::{ConnectionId, ListenerId};
+use libp2p::multiaddr::Protocol;
+use libp2p::core::transport::dummy::DummyTransport;
+use libp2p::relay::{self, Relay, RelayConfig, RelayTransport};
+use libp2p::swarm::{
+    DialError, IntoProtocolsHandler, NetworkBehaviour, NetworkBehaviourAction, PollParameters,
+    ProtocolsHandler, Swarm,
+};
+use libp2p::{Multiaddr, PeerId};
+
+use crate::dht::split_peer_address;
+use crate::Behaviour;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReservationState {
+    /// Listening through the relay was requested; the connection to it is
+    /// still being set up.
+    Pending,
+    /// Connected to the relay, which now forwards circuits to us.
+    Active,
+    /// The relay could not be reached or dropped us. Retried on the next
+    /// maintenance pass.
+    Failed,
+}
+
+/// What a registered relay is doing for us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitStatus {
+    pub state: ReservationState,
+    /// Address other peers dial to reach us through the relay.
+    pub circuit_address: Option<Multiaddr>,
+    pub error: Option<String>,
+    /// Open connections that currently run through the relay.
+    pub relayed_connections: u32,
+}
+
+#[derive(Debug)]
+struct Reservation {
+    /// Relay address without the `/p2p/` suffix; incoming circuits report it
+    /// as their local address.
+    transport: Multiaddr,
+    listener: Option<ListenerId>,
+    status: CircuitStatus,
+}
+
+/// Reservations on relays plus the connections they carry.
+#[derive(Debug, Default)]
+pub struct CircuitRegistry {
+    reservations: HashMap<PeerId, Reservation>,
+    listeners: HashMap<ListenerId, PeerId>,
+    relayed_peers: HashMap<PeerId, u32>,
+    upgrading: HashSet<PeerId>,
+}
+
+impl CircuitRegistry {
+    /// Returns `true` when a new listener should be opened for `relay`, i.e.
+    /// it has no reservation yet or the previous one failed.
+    pub fn begin(&mut self, relay: PeerId, transport: Multiaddr) -> bool {
+        match self.reservations.get(&relay) {
+            Some(existing) if existing.status.state != ReservationState::Failed => false,
+            _ => {
+                let relayed_connections = self
+                    .reservations
+                    .get(&relay)
+                    .map(|existing| existing.status.relayed_connections)
+                    .unwrap_or(0);
+                self.reservations.insert(
+                    relay,
+                    Reservation {
+                        transport,
+                        listener: None,
+                        status: CircuitStatus {
+                            state: ReservationState::Pending,
+                            circuit_address: None,
+                            error: None,
+                            relayed_connections,
+                        },
+                    },
+                );
+                true
+            }
+        }
+    }
+
+    pub fn attach_listener(&mut self, relay: &PeerId, listener: ListenerId) {
+        if let Some(reservation) = self.reservations.get_mut(relay) {
+            reservation.listener = Some(listener);
+            self.listeners.insert(listener, *relay);
+        }
+    }
+
+    pub fn fail(&mut self, relay: &PeerId, error: String) {
+        if let Some(reservation) = self.reservations.get_mut(relay) {
+            if let Some(listener) = reservation.listener.take() {
+                self.listeners.remove(&listener);
+            }
+            reservation.status.state = ReservationState::Failed;
+            reservation.status.circuit_address = None;
+            reservation.status.error = Some(error);
+        }
+    }
+
+    pub fn listening(&mut self, listener: &ListenerId, address: Multiaddr) -> bool {
+        let Some(relay) = self.listeners.get(listener) else {
+            return false;
+        };
+        if let Some(reservation) = self.reservations.get_mut(relay) {
+            reservation.status.state = ReservationState::Active;
+            reservation.status.circuit_address = Some(address);
+            reservation.status.error = None;
+        }
+        true
+    }
+
+    pub fn closed(&mut self, listener: &ListenerId, error: String) -> bool {
+        let Some(relay) = self.listeners.get(listener).copied() else {
+            return false;
+        };
+        self.fail(&relay, error);
+        true
+    }
+
+    /// Forgets reservations on relays that are no longer registered and
+    /// returns their listeners so they can be closed.
+    pub fn retain(&mut self, relays: &HashSet<PeerId>) -> Vec<ListenerId> {
+        let dropped: Vec<PeerId> = self
+            .reservations
+            .keys()
+            .filter(|relay| !relays.contains(relay))
+            .copied()
+            .collect();
+        dropped
+            .into_iter()
+            .filter_map(|relay| {
+                let listener = self.reservations.remove(&relay)?.listener?;
+                self.listeners.remove(&listener);
+                Some(listener)
+            })
+            .collect()
+    }
+
+    /// Relay a circuit endpoint runs through: named in the address when we
+    /// dialled, otherwise matched against the relay transports we reserved
+    /// on.
+    fn relay_of(&self, endpoint: &ConnectedPoint) -> Option<PeerId> {
+        let address = circuit_endpoint(endpoint)?;
+        let mut transport = Multiaddr::empty();
+        for protocol in address.iter() {
+            match protocol {
+                Protocol::P2pCircuit => break,
+                Protocol::P2p(hash) => return PeerId::from_multihash(hash).ok(),
+                other => transport.push(other),
+            }
+        }
+        self.reservations
+            .iter()
+            .find(|(_, reservation)| reservation.transport == transport)
+            .map(|(relay, _)| *relay)
+    }
+
+    pub fn connection_established(&mut self, peer: &PeerId, endpoint: &ConnectedPoint) {
+        if circuit_endpoint(endpoint).is_none() {
+            self.upgrading.remove(peer);
+            return;
+        }
+        *self.relayed_peers.entry(*peer).or_default() += 1;
+        if let Some(relay) = self.relay_of(endpoint) {
+            if let Some(reservation) = self.reservations.get_mut(&relay) {
+                reservation.status.relayed_connections += 1;
+            }
+        }
+    }
+
+    pub fn connection_closed(&mut self, peer: &PeerId, endpoint: &ConnectedPoint) {
+        if circuit_endpoint(endpoint).is_none() {
+            return;
+        }
+        if let Some(count) = self.relayed_peers.get_mut(peer) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                self.relayed_peers.remove(peer);
+                self.upgrading.remove(peer);
+            }
+        }
+        if let Some(relay) = self.relay_of(endpoint) {
+            if let Some(reservation) = self.reservations.get_mut(&relay) {
+                let status = &mut reservation.status;
+                status.relayed_connections = status.relayed_connections.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Direct addresses worth dialling for a peer we only reach through a
+    /// relay. Empty once an attempt is under way.
+    pub fn upgrade_candidates(&mut self, peer: &PeerId, listen_addrs: &[Multiaddr]) -> Vec<Multiaddr> {
+        if !self.relayed_peers.contains_key(peer) || self.upgrading.contains(peer) {
+            return Vec::new();
+        }
+        let candidates: Vec<Multiaddr> = listen_addrs
+            .iter()
+            .filter(|address| !is_circuit(address))
+            .map(|address| address.clone().with(Protocol::P2p((*peer).into())))
+            .collect();
+        if !candidates.is_empty() {
+            self.upgrading.insert(*peer);
+        }
+        candidates
+    }
+
+    pub fn status(&self, relay: &PeerId) -> Option<CircuitStatus> {
+        self.reservations
+            .get(relay)
+            .map(|reservation| reservation.status.clone())
+    }
+}
+
+pub fn is_circuit(address: &Multiaddr) -> bool {
+    address.iter().any(|protocol| matches!(protocol, Protocol::P2pCircuit))
+}
+
+fn circuit_endpoint(endpoint: &ConnectedPoint) -> Option<&Multiaddr> {
+    match endpoint {
+        ConnectedPoint::Dialer { address } => Some(address).filter(|address| is_circuit(address)),
+        ConnectedPoint::Listener { local_addr, .. } => {
+            Some(local_addr).filter(|address| is_circuit(address))
+        }
+    }
+}
+
+/// The circuit relay behaviour plus this swarm's reservations. Every node
+/// can listen through a relay and dial `/p2p-circuit` addresses, but only
+/// relay nodes (`hop`) carry circuits between other peers.
+pub struct CircuitRelay {
+    inner: Relay,
+    hop: bool,
+    /// Knows no connections, so it answers every hop request it is handed
+    /// with a refusal. Its transport is kept only so it stays pollable.
+    refusals: Relay,
+    _refusals_transport: RelayTransport<DummyTransport>,
+    circuits: CircuitRegistry,
+}
+
+impl CircuitRelay {
+    pub fn new(inner: Relay, hop: bool) -> Self {
+        let (refusals_transport, refusals) =
+            relay::new_transport_and_behaviour(RelayConfig::default(), DummyTransport::new());
+        CircuitRelay {
+            inner,
+            hop,
+            refusals,
+            _refusals_transport: refusals_transport,
+            circuits: CircuitRegistry::default(),
+        }
+    }
+}
+
+/// libp2p-relay 0.4 keeps its handler events private and has no switch for
+/// acting as a hop, so the request is told apart by its debug name.
+fn is_hop_request(event: &impl std::fmt::Debug) -> bool {
+    format!("{:?}", event).starts_with("RelayHandlerEvent::IncomingRelayReq")
+}
+
+type RelayHandlerEvent =
+    <<<Relay as NetworkBehaviour>::ProtocolsHandler as IntoProtocolsHandler>::Handler as ProtocolsHandler>::OutEvent;
+
+impl NetworkBehaviour for CircuitRelay {
+    type ProtocolsHandler = <Relay as NetworkBehaviour>::ProtocolsHandler;
+    type OutEvent = <Relay as NetworkBehaviour>::OutEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        self.inner.new_handler()
+    }
+
+    fn addresses_of_peer(&mut self, peer: &PeerId) -> Vec<Multiaddr> {
+        self.inner.addresses_of_peer(peer)
+    }
+
+    fn inject_connected(&mut self, peer: &PeerId) {
+        self.inner.inject_connected(peer)
+    }
+
+    fn inject_disconnected(&mut self, peer: &PeerId) {
+        self.inner.inject_disconnected(peer)
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+        errors: Option<&Vec<Multiaddr>>,
+    ) {
+        self.circuits.connection_established(peer, endpoint);
+        self.inner
+            .inject_connection_established(peer, connection, endpoint, errors)
+    }
+
+    fn inject_connection_closed(
+        &mut self,
+        peer: &PeerId,
+        connection: &ConnectionId,
+        endpoint: &ConnectedPoint,
+        handler: <Self::ProtocolsHandler as IntoProtocolsHandler>::Handler,
+    ) {
+        self.circuits.connection_closed(peer, endpoint);
+        self.inner
+            .inject_connection_closed(peer, connection, endpoint, handler)
+    }
+
+    fn inject_address_change(
+        &mut self,
+        peer: &PeerId,
+        connection: &ConnectionId,
+        old: &ConnectedPoint,
+        new: &ConnectedPoint,
+    ) {
+        self.inner.inject_address_change(peer, connection, old, new)
+    }
+
+    fn inject_event(&mut self, peer: PeerId, connection: ConnectionId, event: RelayHandlerEvent) {
+        if !self.hop && is_hop_request(&event) {
+            self.refusals.inject_event(peer, connection, event);
+        } else {
+            self.inner.inject_event(peer, connection, event)
+        }
+    }
+
+    fn inject_dial_failure(
+        &mut self,
+        peer: Option<PeerId>,
+        handler: Self::ProtocolsHandler,
+        error: &DialError,
+    ) {
+        self.inner.inject_dial_failure(peer, handler, error)
+    }
+
+    fn inject_listen_failure(
+        &mut self,
+        local_addr: &Multiaddr,
+        send_back_addr: &Multiaddr,
+        handler: Self::ProtocolsHandler,
+    ) {
+        self.inner
+            .inject_listen_failure(local_addr, send_back_addr, handler)
+    }
+
+    fn inject_new_listener(&mut self, id: ListenerId) {
+        self.inner.inject_new_listener(id)
+    }
+
+    fn inject_new_listen_addr(&mut self, id: ListenerId, address: &Multiaddr) {
+        self.circuits.listening(&id, address.clone());
+        self.inner.inject_new_listen_addr(id, address)
+    }
+
+    fn inject_expired_listen_addr(&mut self, id: ListenerId, address: &Multiaddr) {
+        self.inner.inject_expired_listen_addr(id, address)
+    }
+
+    fn inject_listener_error(&mut self, id: ListenerId, error: &(dyn std::error::Error + 'static)) {
+        self.inner.inject_listener_error(id, error)
+    }
+
+    fn inject_listener_closed(&mut self, id: ListenerId, reason: Result<(), &std::io::Error>) {
+        let error = match reason {
+            Ok(()) => "Relay connection closed".to_string(),
+            Err(error) => error.to_string(),
+        };
+        self.circuits.closed(&id, error);
+        self.inner.inject_listener_closed(id, reason)
+    }
+
+    fn inject_new_external_addr(&mut self, address: &Multiaddr) {
+        self.inner.inject_new_external_addr(address)
+    }
+
+    fn inject_expired_external_addr(&mut self, address: &Multiaddr) {
+        self.inner.inject_expired_external_addr(address)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
+    ) -> Poll<NetworkBehaviourAction<Self::OutEvent, Self::ProtocolsHandler>> {
+        if let Poll::Ready(action) = self.refusals.poll(cx, params) {
+            return Poll::Ready(action);
+        }
+        self.inner.poll(cx, params)
+    }
+}
+
+/// Asks the relay at `relay_addr` (ending in `/p2p/<relay>`) to forward
+/// circuits to us by listening on its `/p2p-circuit` address. Returns
+/// `false` when a reservation is already pending or active.
+pub fn reserve(swarm: &mut Swarm<Behaviour>, relay_addr: &Multiaddr) -> Result<bool, String> {
+    if is_circuit(relay_addr) {
+        return Err("Relay address must not itself be a circuit".into());
+    }
+    let (relay, transport) = split_peer_address(relay_addr)
+        .ok_or_else(|| "Relay address must end with /p2p/<peer id>".to_string())?;
+    if &relay == swarm.local_peer_id() {
+        return Ok(false);
+    }
+    if !swarm.behaviour_mut().relay.circuits.begin(relay, transport) {
+        return Ok(false);
+    }
+    match swarm.listen_on(relay_addr.clone().with(Protocol::P2pCircuit)) {
+        Ok(listener) => {
+            swarm.behaviour_mut().relay.circuits.attach_listener(&relay, listener);
+            Ok(true)
+        }
+        Err(error) => {
+            let message = format!("Failed to listen via relay {}: {:?}", relay, error);
+            swarm.behaviour_mut().relay.circuits.fail(&relay, message.clone());
+            Err(message)
+        }
+    }
+}
+
+/// Drops reservations on relays that were unregistered.
+pub fn retain_reservations(swarm: &mut Swarm<Behaviour>, relays: &HashSet<PeerId>) {
+    let stale = swarm.behaviour_mut().relay.circuits.retain(relays);
+    for listener in stale {
+        swarm.remove_listener(listener);
+    }
+}
+
+/// Tries the direct addresses a relayed peer announced through identify so
+/// traffic can leave the relay. libp2p 0.40 ships no DCUtR, so this only
+/// succeeds when one side is directly reachable; otherwise the circuit stays
+/// in use. Returns how many dials were started.
+pub fn upgrade_to_direct(swarm: &mut Swarm<Behaviour>, peer: &PeerId, listen_addrs: &[Multiaddr]) -> usize {
+    let candidates = swarm
+        .behaviour_mut()
+        .relay
+        .circuits
+        .upgrade_candidates(peer, listen_addrs);
+    crate::transports::preferred_addresses(swarm.local_peer_id(), peer, candidates)
+        .into_iter()
+        .filter(|address| swarm.dial_addr(address.clone()).is_ok())
+        .count()
+}
+
+pub fn circuit_status(swarm: &Swarm<Behaviour>, relay: &PeerId) -> Option<CircuitStatus> {
+    swarm.behaviour().relay.circuits.status(relay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ComposedEvent;
+    use libp2p::futures::StreamExt;
+    use libp2p::identify::IdentifyEvent;
+    use libp2p::identity::Keypair;
+    use libp2p::relay::RelayConfig;
+    use libp2p::request_response::ProtocolSupport;
+    use libp2p::swarm::SwarmEvent;
+    use std::time::Duration;
+
+    async fn loopback_swarm(hop: bool) -> (Swarm<Behaviour>, Multiaddr) {
+        let (mut swarm, _, _) = crate::build_swarm(
+            Keypair::generate_ed25519(),
+            ProtocolSupport::Full,
+            RelayConfig::default(),
+            hop,
+            None,
+            "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+        )
+        .await
+        .expect("swarm");
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                if crate::quic::socket_addr(&address).is_none() {
+                    let address = address.with(Protocol::P2p((*swarm.local_peer_id()).into()));
+                    return (swarm, address);
+                }
+            }
+        }
+    }
+
+    /// Feeds identify events into the upgrade the app wires up. Returns the
+    /// peer and endpoint of a new connection.
+    fn drive(
+        swarm: &mut Swarm<Behaviour>,
+        event: SwarmEvent<ComposedEvent, impl std::fmt::Debug>,
+    ) -> Option<(PeerId, ConnectedPoint)> {
+        match event {
+            SwarmEvent::Behaviour(ComposedEvent::Identify(IdentifyEvent::Received { peer_id, info })) => {
+                upgrade_to_direct(swarm, &peer_id, &info.listen_addrs);
+                None
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => Some((peer_id, endpoint)),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn peers_connect_through_a_relay_and_then_directly() {
+        let (mut relay, relay_address) = loopback_swarm(true).await;
+        let (mut alice, _) = loopback_swarm(false).await;
+        let (mut bob, _) = loopback_swarm(false).await;
+        let relay_id = *relay.local_peer_id();
+        let bob_id = *bob.local_peer_id();
+
+        assert!(reserve(&mut bob, &relay_address).expect("reserve"));
+        assert!(!reserve(&mut bob, &relay_address).expect("reserve again"));
+        assert_eq!(circuit_status(&bob, &relay_id).unwrap().state, ReservationState::Pending);
+
+        let outcome = tokio::time::timeout(Duration::from_secs(30), async {
+            let mut dialled = false;
+            let mut relayed = false;
+            loop {
+                let connected = tokio::select! {
+                    event = relay.select_next_some() => { drive(&mut relay, event); None }
+                    event = bob.select_next_some() => { drive(&mut bob, event); None }
+                    event = alice.select_next_some() => drive(&mut alice, event),
+                };
+                if !dialled {
+                    if let Some(circuit) = circuit_status(&bob, &relay_id).and_then(|status| status.circuit_address) {
+                        let target = circuit.with(Protocol::P2p(bob_id.into()));
+                        alice.dial_addr(target).expect("dial through relay");
+                        dialled = true;
+                    }
+                }
+                match connected {
+                    Some((peer, endpoint)) if peer == bob_id => {
+                        if circuit_endpoint(&endpoint).is_some() {
+                            relayed = true;
+                        } else if relayed {
+                            return;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+        assert!(outcome.is_ok(), "alice never upgraded her relayed connection to bob");
+
+        let status = circuit_status(&bob, &relay_id).unwrap();
+        assert_eq!(status.state, ReservationState::Active);
+        assert!(status.relayed_connections >= 1);
+    }
+
+    #[tokio::test]
+    async fn clients_do_not_carry_circuits() {
+        let (mut client, client_address) = loopback_swarm(false).await;
+        let (mut alice, _) = loopback_swarm(false).await;
+        let (mut bob, _) = loopback_swarm(false).await;
+        let client_id = *client.local_peer_id();
+        let bob_id = *bob.local_peer_id();
+
+        assert!(reserve(&mut bob, &client_address).expect("reserve"));
+
+        let outcome = tokio::time::timeout(Duration::from_secs(30), async {
+            let mut dialled = false;
+            loop {
+                tokio::select! {
+                    _ = client.select_next_some() => {}
+                    _ = bob.select_next_some() => {}
+                    event = alice.select_next_some() => match event {
+                        SwarmEvent::ConnectionEstablished { peer_id, .. } if peer_id == bob_id => return false,
+                        SwarmEvent::OutgoingConnectionError { .. } if dialled => return true,
+                        _ => {}
+                    },
+                }
+                if !dialled {
+                    if let Some(circuit) = circuit_status(&bob, &client_id).and_then(|status| status.circuit_address) {
+                        let target = circuit.with(Protocol::P2p(bob_id.into()));
+                        alice.dial_addr(target).expect("dial through client");
+                        dialled = true;
+                    }
+                }
+            }
+        })
+        .await;
+        assert_eq!(outcome, Ok(true), "a client relayed alice's circuit to bob");
+    }
+}
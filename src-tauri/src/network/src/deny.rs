@@ -0,0 +1,198 @@
+//! Peers the swarm refuses to deal with: users we blocked and members banned
+//! from servers we own. A denied peer's connections are closed and refused,
+//! and gossipsub drops every message it sends or publishes. A peer can be
+//! denied for several reasons at once; it is let back in only once the last
+//! one is lifted or has expired.
+
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use libp2p::swarm::Swarm;
+use libp2p::PeerId;
+
+use crate::Behaviour;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DenyReason {
+    Blocked,
+    ServerBan { server_id: String },
+    /// Denied from the connectivity settings rather than by moderation.
+    Manual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeniedPeer {
+    pub peer: PeerId,
+    pub reason: DenyReason,
+    pub denied_at: DateTime<Utc>,
+    /// Permanent when unset.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl DeniedPeer {
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DenyList {
+    entries: Vec<DeniedPeer>,
+}
+
+impl DenyList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &[DeniedPeer] {
+        &self.entries
+    }
+
+    /// Whether `peer` is denied for a reason that has not run out. Entries
+    /// only leave the list on the next [`DenyList::expire`], so their expiry
+    /// is checked here as well.
+    pub fn is_denied(&self, peer: &PeerId) -> bool {
+        self.is_denied_at(peer, Utc::now())
+    }
+
+    fn is_denied_at(&self, peer: &PeerId, now: DateTime<Utc>) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.peer == *peer && !entry.is_expired(now))
+    }
+
+    /// Denies `entry.peer` for `entry.reason`, replacing the expiry of an
+    /// earlier entry for the same reason.
+    pub fn deny(&mut self, swarm: &mut Swarm<Behaviour>, entry: DeniedPeer) {
+        let peer = entry.peer;
+        self.insert(entry);
+        enforce(swarm, &peer);
+    }
+
+    /// Lifts one reason for denying `peer`. Returns whether the peer is let
+    /// back in.
+    pub fn lift(&mut self, swarm: &mut Swarm<Behaviour>, peer: &PeerId, reason: &DenyReason) -> bool {
+        let released = self.remove(peer, reason);
+        if released {
+            allow(swarm, peer);
+        }
+        released
+    }
+
+    /// Drops entries that have run out and lets in the peers no longer
+    /// denied for any reason. Returns the dropped entries.
+    pub fn expire(&mut self, swarm: &mut Swarm<Behaviour>, now: DateTime<Utc>) -> Vec<DeniedPeer> {
+        let (expired, released) = self.take_expired(now);
+        for peer in &released {
+            allow(swarm, peer);
+        }
+        expired
+    }
+
+    /// Loads saved entries, skipping those that expired meanwhile.
+    pub fn restore(&mut self, swarm: &mut Swarm<Behaviour>, entries: Vec<DeniedPeer>, now: DateTime<Utc>) {
+        for entry in entries.into_iter().filter(|entry| !entry.is_expired(now)) {
+            self.deny(swarm, entry);
+        }
+    }
+
+    fn insert(&mut self, entry: DeniedPeer) {
+        match self
+            .entries
+            .iter_mut()
+            .find(|existing| existing.peer == entry.peer && existing.reason == entry.reason)
+        {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+
+    fn remove(&mut self, peer: &PeerId, reason: &DenyReason) -> bool {
+        let before = self.entries.len();
+        self.entries
+            .retain(|entry| !(entry.peer == *peer && entry.reason == *reason));
+        before != self.entries.len() && !self.is_denied(peer)
+    }
+
+    fn take_expired(&mut self, now: DateTime<Utc>) -> (Vec<DeniedPeer>, HashSet<PeerId>) {
+        let (expired, kept): (Vec<_>, Vec<_>) =
+            self.entries.drain(..).partition(|entry| entry.is_expired(now));
+        self.entries = kept;
+        let released = expired
+            .iter()
+            .map(|entry| entry.peer)
+            .filter(|peer| !self.is_denied_at(peer, now))
+            .collect();
+        (expired, released)
+    }
+}
+
+fn enforce(swarm: &mut Swarm<Behaviour>, peer: &PeerId) {
+    swarm.ban_peer_id(*peer);
+    swarm.behaviour_mut().gossipsub.blacklist_peer(peer);
+}
+
+fn allow(swarm: &mut Swarm<Behaviour>, peer: &PeerId) {
+    swarm.unban_peer_id(*peer);
+    swarm.behaviour_mut().gossipsub.remove_blacklisted_peer(peer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(peer: PeerId, reason: DenyReason, expires_at: Option<DateTime<Utc>>) -> DeniedPeer {
+        DeniedPeer { peer, reason, denied_at: Utc::now(), expires_at }
+    }
+
+    #[test]
+    fn peers_stay_denied_until_every_reason_is_gone() {
+        let peer = PeerId::random();
+        let now = Utc::now();
+        let ban = DenyReason::ServerBan { server_id: "server".into() };
+        let mut list = DenyList::new();
+
+        list.insert(entry(peer, DenyReason::Blocked, None));
+        list.insert(entry(peer, ban, Some(now + chrono::Duration::hours(1))));
+        assert!(!list.remove(&peer, &DenyReason::Blocked));
+        assert!(list.is_denied(&peer));
+
+        let (expired, released) = list.take_expired(now);
+        assert!(expired.is_empty() && released.is_empty());
+
+        let (expired, released) = list.take_expired(now + chrono::Duration::hours(2));
+        assert_eq!(expired.len(), 1);
+        assert!(released.contains(&peer));
+        assert!(!list.is_denied(&peer));
+    }
+
+    #[test]
+    fn expired_entries_stop_denying_before_they_are_swept() {
+        let peer = PeerId::random();
+        let now = Utc::now();
+        let mut list = DenyList::new();
+
+        list.insert(entry(peer, DenyReason::Manual, Some(now + chrono::Duration::hours(1))));
+        assert!(list.is_denied_at(&peer, now));
+        assert!(!list.is_denied_at(&peer, now + chrono::Duration::hours(2)));
+        assert_eq!(list.entries().len(), 1);
+
+        list.insert(entry(peer, DenyReason::Blocked, Some(now - chrono::Duration::seconds(1))));
+        assert!(!list.is_denied(&PeerId::random()));
+        assert!(list.is_denied(&peer));
+    }
+
+    #[test]
+    fn denying_again_replaces_the_expiry() {
+        let peer = PeerId::random();
+        let now = Utc::now();
+        let mut list = DenyList::new();
+
+        list.insert(entry(peer, DenyReason::Manual, Some(now)));
+        list.insert(entry(peer, DenyReason::Manual, None));
+        assert_eq!(list.entries().len(), 1);
+        let (expired, _) = list.take_expired(now + chrono::Duration::days(365));
+        assert!(expired.is_empty());
+    }
+}
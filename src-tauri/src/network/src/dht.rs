@@ -0,0 +1,329 @@
+use std::time::Duration;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use libp2p::kad::record::store::MemoryStore;
+use libp2p::kad::{GetClosestPeersOk, Kademlia, KademliaConfig, KademliaEvent, QueryId, QueryResult};
+use libp2p::multiaddr::Protocol;
+use libp2p::swarm::Swarm;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::Behaviour;
+
+/// Kademlia protocol name; kept apart from the public IPFS DHT so lookups
+/// only ever reach Aegis nodes.
+pub const KADEMLIA_PROTOCOL_NAME: &[u8] = b"/aegis/kad/1.0.0";
+
+/// Prefix of the contact cards shared as QR codes.
+pub const CONTACT_CARD_PREFIX: &str = "aegis-contact::";
+
+pub fn new_kademlia(local_peer: PeerId) -> Kademlia<MemoryStore> {
+    let mut config = KademliaConfig::default();
+    config.set_protocol_name(KADEMLIA_PROTOCOL_NAME);
+    config.set_query_timeout(Duration::from_secs(30));
+    Kademlia::with_config(local_peer, MemoryStore::new(local_peer), config)
+}
+
+/// Splits `/ip4/.../tcp/.../p2p/<peer>` into the peer and the address used to
+/// reach it. Addresses without a trailing peer ID are rejected, since the DHT
+/// indexes peers by ID.
+pub fn split_peer_address(address: &Multiaddr) -> Option<(PeerId, Multiaddr)> {
+    let mut transport = address.clone();
+    match transport.pop()? {
+        Protocol::P2p(hash) => {
+            let peer = PeerId::from_multihash(hash).ok()?;
+            Some((peer, transport))
+        }
+        _ => None,
+    }
+}
+
+/// Seeds the routing table with `peers` and starts a bootstrap query.
+/// Returns how many of the addresses were usable.
+pub fn bootstrap(swarm: &mut Swarm<Behaviour>, peers: &[Multiaddr]) -> usize {
+    let mut added = 0;
+    for address in peers {
+        let Some((peer, transport)) = split_peer_address(address) else {
+            continue;
+        };
+        if &peer == swarm.local_peer_id() {
+            continue;
+        }
+        swarm.behaviour_mut().kademlia.add_address(&peer, transport);
+        added += 1;
+    }
+    refresh(swarm);
+    added
+}
+
+/// Walks the DHT towards our own ID, which fills the routing table with
+/// nearby peers. Returns `false` when no peer is known to start from.
+pub fn refresh(swarm: &mut Swarm<Behaviour>) -> bool {
+    swarm.behaviour_mut().kademlia.bootstrap().is_ok()
+}
+
+/// Feeds the listen addresses a peer announced through identify into the
+/// routing table.
+pub fn record_identified(swarm: &mut Swarm<Behaviour>, peer: &PeerId, listen_addrs: &[Multiaddr]) {
+    for address in listen_addrs {
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(peer, dialable(peer, address.clone()));
+    }
+}
+
+/// Relayed addresses are announced as `<relay>/p2p-circuit`; dialling one
+/// needs the destination appended.
+fn dialable(peer: &PeerId, address: Multiaddr) -> Multiaddr {
+    if crate::circuit::is_circuit(&address) {
+        address.with(Protocol::P2p((*peer).into()))
+    } else {
+        address
+    }
+}
+
+/// Starts looking up `peer`'s addresses. [`on_kademlia_event`] dials it once
+/// the lookup finds it.
+pub fn lookup_peer(swarm: &mut Swarm<Behaviour>, peer: &PeerId) -> QueryId {
+    swarm.behaviour_mut().kademlia.get_closest_peers(*peer)
+}
+
+/// Dials the target of a finished peer lookup when the DHT knew it. Returns
+/// the peer that was dialled.
+pub fn on_kademlia_event(swarm: &mut Swarm<Behaviour>, event: &KademliaEvent) -> Option<PeerId> {
+    let KademliaEvent::OutboundQueryCompleted {
+        result: QueryResult::GetClosestPeers(Ok(GetClosestPeersOk { key, peers })),
+        ..
+    } = event
+    else {
+        return None;
+    };
+    let target = PeerId::from_bytes(key).ok()?;
+    if !peers.contains(&target) || swarm.is_connected(&target) {
+        return None;
+    }
+    swarm.dial(&target).ok()?;
+    Some(target)
+}
+
+/// Dials a peer given as a multiaddr ending in `/p2p/<peer>` or as a contact
+/// card. A card without addresses falls back to a DHT lookup.
+pub fn dial_contact(swarm: &mut Swarm<Behaviour>, target: &str) -> Result<PeerId, String> {
+    let target = target.trim();
+    let (peer, addresses) = if target.starts_with(CONTACT_CARD_PREFIX) {
+        let card = ContactCard::decode(target)?;
+        (card.peer()?, card.addresses())
+    } else {
+        let address: Multiaddr = target
+            .parse()
+            .map_err(|e| format!("Invalid multiaddr: {}", e))?;
+        let (peer, transport) = split_peer_address(&address)
+            .ok_or_else(|| "Address must end with /p2p/<peer id>".to_string())?;
+        (peer, vec![transport])
+    };
+    if &peer == swarm.local_peer_id() {
+        return Err("Cannot dial ourselves".into());
+    }
+
+    if addresses.is_empty() {
+        lookup_peer(swarm, &peer);
+        return Ok(peer);
+    }
+    for address in addresses {
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .add_address(&peer, dialable(&peer, address));
+    }
+    swarm
+        .dial(&peer)
+        .map_err(|e| format!("Failed to dial {}: {:?}", peer, e))?;
+    Ok(peer)
+}
+
+/// What another device needs to reach us: our peer ID and the addresses we
+/// listen on. Encoded as text for QR codes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContactCard {
+    pub peer_id: String,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+}
+
+impl ContactCard {
+    /// Card for the local node, listing the addresses it listens on.
+    pub fn local(swarm: &Swarm<Behaviour>) -> Self {
+        Self {
+            peer_id: swarm.local_peer_id().to_base58(),
+            addresses: swarm
+                .listeners()
+                .chain(swarm.external_addresses().map(|record| &record.addr))
+                .filter(|address| !is_unspecified(address))
+                .map(|address| address.to_string())
+                .collect(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        format!("{}{}", CONTACT_CARD_PREFIX, BASE64.encode(json))
+    }
+
+    pub fn decode(value: &str) -> Result<Self, String> {
+        let encoded = value
+            .trim()
+            .strip_prefix(CONTACT_CARD_PREFIX)
+            .ok_or_else(|| "Not an Aegis contact card".to_string())?;
+        let json = BASE64
+            .decode(encoded)
+            .map_err(|e| format!("Malformed contact card: {}", e))?;
+        serde_json::from_slice(&json).map_err(|e| format!("Malformed contact card: {}", e))
+    }
+
+    pub fn peer(&self) -> Result<PeerId, String> {
+        self.peer_id
+            .parse()
+            .map_err(|_| format!("Invalid peer ID in contact card: {}", self.peer_id))
+    }
+
+    /// Usable transport addresses; a trailing `/p2p/` component is dropped.
+    pub fn addresses(&self) -> Vec<Multiaddr> {
+        self.addresses
+            .iter()
+            .filter_map(|address| address.parse::<Multiaddr>().ok())
+            .map(|address| match split_peer_address(&address) {
+                Some((_, transport)) => transport,
+                None => address,
+            })
+            .collect()
+    }
+}
+
+fn is_unspecified(address: &Multiaddr) -> bool {
+    address.iter().any(|protocol| match protocol {
+        Protocol::Ip4(ip) => ip.is_unspecified(),
+        Protocol::Ip6(ip) => ip.is_unspecified(),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ComposedEvent;
+    use libp2p::futures::StreamExt;
+    use libp2p::identify::IdentifyEvent;
+    use libp2p::identity::Keypair;
+    use libp2p::swarm::SwarmEvent;
+
+    async fn loopback_swarm() -> Swarm<Behaviour> {
+        let (swarm, _, _) = crate::initialize_relay_network(
+            Keypair::generate_ed25519(),
+            "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+        )
+        .await
+        .expect("swarm");
+        swarm
+    }
+
+    async fn listen_address(swarm: &mut Swarm<Behaviour>) -> Multiaddr {
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+                if crate::quic::socket_addr(&address).is_none() {
+                    return address.with(Protocol::P2p((*swarm.local_peer_id()).into()));
+                }
+            }
+        }
+    }
+
+    /// Handles the events the app feeds into the DHT. Returns the peer a
+    /// connection was established with, if any.
+    fn drive(swarm: &mut Swarm<Behaviour>, event: SwarmEvent<ComposedEvent, impl std::fmt::Debug>) -> Option<PeerId> {
+        match event {
+            SwarmEvent::Behaviour(ComposedEvent::Identify(IdentifyEvent::Received { peer_id, info })) => {
+                record_identified(swarm, &peer_id, &info.listen_addrs);
+                None
+            }
+            SwarmEvent::Behaviour(ComposedEvent::Kademlia(event)) => {
+                on_kademlia_event(swarm, &event);
+                None
+            }
+            SwarmEvent::ConnectionEstablished { peer_id, .. } => Some(peer_id),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn contact_cards_round_trip() {
+        let peer = Keypair::generate_ed25519().public().to_peer_id();
+        let card = ContactCard {
+            peer_id: peer.to_base58(),
+            addresses: vec![
+                format!("/ip4/192.168.1.4/tcp/4001/p2p/{}", peer),
+                "/ip4/10.0.0.2/tcp/4001".into(),
+                "not an address".into(),
+            ],
+        };
+        let decoded = ContactCard::decode(&card.encode()).expect("decode");
+        assert_eq!(decoded, card);
+        assert_eq!(decoded.peer().unwrap(), peer);
+        assert_eq!(
+            decoded.addresses(),
+            vec![
+                "/ip4/192.168.1.4/tcp/4001".parse::<Multiaddr>().unwrap(),
+                "/ip4/10.0.0.2/tcp/4001".parse().unwrap(),
+            ]
+        );
+        assert!(ContactCard::decode("aegis-device-login::e30=").is_err());
+        assert!(split_peer_address(&"/ip4/10.0.0.2/tcp/4001".parse().unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn peers_find_each_other_through_a_bootstrap_node() {
+        let mut hub = loopback_swarm().await;
+        let mut alice = loopback_swarm().await;
+        let mut carol = loopback_swarm().await;
+        let hub_address = listen_address(&mut hub).await;
+        listen_address(&mut alice).await;
+        listen_address(&mut carol).await;
+
+        assert_eq!(bootstrap(&mut alice, std::slice::from_ref(&hub_address)), 1);
+        assert_eq!(bootstrap(&mut carol, &[hub_address]), 1);
+        let hub_id = *hub.local_peer_id();
+        let carol_id = *carol.local_peer_id();
+        let alice_id = *alice.local_peer_id();
+
+        let found = tokio::time::timeout(Duration::from_secs(30), async {
+            let mut looked_up = false;
+            loop {
+                let connected = tokio::select! {
+                    event = hub.select_next_some() => { drive(&mut hub, event); None }
+                    event = carol.select_next_some() => drive(&mut carol, event).map(|peer| (carol_id, peer)),
+                    event = alice.select_next_some() => drive(&mut alice, event).map(|peer| (alice_id, peer)),
+                };
+                if connected == Some((alice_id, carol_id)) || connected == Some((carol_id, alice_id)) {
+                    return;
+                }
+                // Ask only once the hub has carol in its routing table, so
+                // the lookup is answered from it.
+                if !looked_up && alice.is_connected(&hub_id) && knows(&mut hub, &carol_id) {
+                    lookup_peer(&mut alice, &carol_id);
+                    looked_up = true;
+                }
+            }
+        })
+        .await;
+        assert!(found.is_ok(), "alice never connected to carol");
+    }
+
+    fn knows(swarm: &mut Swarm<Behaviour>, peer: &PeerId) -> bool {
+        swarm
+            .behaviour_mut()
+            .kademlia
+            .kbucket(*peer)
+            .map(|bucket| bucket.iter().any(|entry| entry.node.key.preimage() == peer))
+            .unwrap_or(false)
+    }
+}
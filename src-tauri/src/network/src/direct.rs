@@ -0,0 +1,92 @@
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{ProtocolName, RequestResponseCodec};
+use rkyv::{Archive, Deserialize, Serialize};
+use std::io;
+
+use crate::aerp::RoutedEnvelope;
+use crate::rkyv_utils::{deserialize, serialize};
+
+/// Hop-by-hop delivery of routed envelopes. Each hop only talks to the next
+/// peer on `RoutedEnvelope::path`, so ciphertext and its metadata never reach
+/// peers outside the route. The version moves with the layout of
+/// [`RoutedEnvelope`], so peers on another layout never open a stream.
+#[derive(Debug, Clone)]
+pub struct DirectDeliveryProtocol;
+
+impl ProtocolName for DirectDeliveryProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        b"/aegis/route/2"
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Archive, Serialize, Deserialize, serde::Serialize, serde::Deserialize)]
+#[archive(check_bytes)]
+pub enum DirectDeliveryResponse {
+    /// The envelope reached its destination.
+    Delivered,
+    /// The envelope was handed to the next hop.
+    Forwarded,
+    Rejected(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectDeliveryCodec;
+
+#[async_trait::async_trait]
+impl RequestResponseCodec for DirectDeliveryCodec {
+    type Protocol = DirectDeliveryProtocol;
+    type Request = RoutedEnvelope;
+    type Response = DirectDeliveryResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &DirectDeliveryProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut buf = Vec::new();
+        AsyncReadExt::read_to_end(io, &mut buf).await?;
+        deserialize(&buf)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &DirectDeliveryProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let mut buf = Vec::new();
+        AsyncReadExt::read_to_end(io, &mut buf).await?;
+        deserialize(&buf)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &DirectDeliveryProtocol,
+        io: &mut T,
+        req: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = serialize(&req)?;
+        AsyncWriteExt::write_all(io, &bytes).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &DirectDeliveryProtocol,
+        io: &mut T,
+        res: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = serialize(&res)?;
+        AsyncWriteExt::write_all(io, &bytes).await
+    }
+}
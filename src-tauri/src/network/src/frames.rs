@@ -0,0 +1,153 @@
+use std::collections::{HashSet, VecDeque};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+
+/// Hops a frame may take before it is dropped. Comfortably above the
+/// router's hop limit so only looping or amplified frames run out.
+pub const DEFAULT_FRAME_TTL: u8 = 8;
+
+/// Frame IDs remembered for duplicate detection.
+pub const SEEN_CACHE_CAPACITY: usize = 8192;
+
+pub type FrameId = [u8; 16];
+
+/// What the seen cache remembers a frame by: a digest of its ID together
+/// with the origin and the signed payload it carries. The ID alone is the
+/// sender's choice and visible on the wire, so anyone could spend it first
+/// to get the real frame dropped.
+pub type FrameKey = [u8; 32];
+
+pub fn new_frame_id() -> FrameId {
+    rand::random()
+}
+
+pub fn frame_key(id: &FrameId, origin: &str, payload: &[u8]) -> FrameKey {
+    let mut hasher = Sha256::new();
+    hasher.update(id);
+    hasher.update(origin.as_bytes());
+    hasher.update([0]);
+    hasher.update(payload);
+    hasher.finalize().into()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameVerdict {
+    Fresh,
+    /// Already seen: a replay, a loop, or the same frame over a second
+    /// transport or bridge.
+    Duplicate,
+    /// Ran out of hops before reaching us.
+    Expired,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub accepted: u64,
+    pub duplicates: u64,
+    pub expired: u64,
+}
+
+/// Bounded memory of recently seen frames; the oldest are forgotten first.
+#[derive(Debug)]
+pub struct SeenCache {
+    capacity: usize,
+    order: VecDeque<FrameKey>,
+    ids: HashSet<FrameKey>,
+    stats: FrameStats,
+}
+
+impl SeenCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            order: VecDeque::new(),
+            ids: HashSet::new(),
+            stats: FrameStats::default(),
+        }
+    }
+
+    /// Decides whether a frame arriving with `ttl` hops left should be
+    /// processed, and remembers it if so.
+    pub fn admit(&mut self, id: &FrameKey, ttl: u8) -> FrameVerdict {
+        if self.ids.contains(id) {
+            self.stats.duplicates += 1;
+            return FrameVerdict::Duplicate;
+        }
+        if ttl == 0 {
+            self.stats.expired += 1;
+            return FrameVerdict::Expired;
+        }
+
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.order.push_back(*id);
+        self.ids.insert(*id);
+        self.stats.accepted += 1;
+        FrameVerdict::Fresh
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+}
+
+static SEEN_FRAMES: Lazy<Mutex<SeenCache>> =
+    Lazy::new(|| Mutex::new(SeenCache::new(SEEN_CACHE_CAPACITY)));
+
+/// Checks a frame against the node-wide seen cache shared by every transport.
+/// See [`frame_key`] for what makes two frames the same.
+pub fn admit_frame(id: &FrameId, origin: &str, payload: &[u8], ttl: u8) -> FrameVerdict {
+    SEEN_FRAMES.lock().admit(&frame_key(id, origin, payload), ttl)
+}
+
+/// Counts a frame we dropped because it had no hops left to forward with.
+pub fn note_expired_frame() {
+    SEEN_FRAMES.lock().stats.expired += 1;
+}
+
+pub fn frame_stats() -> FrameStats {
+    SEEN_FRAMES.lock().stats()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_replays_and_expired_frames_and_forgets_oldest() {
+        let mut cache = SeenCache::new(2);
+        let key = |payload: &[u8]| frame_key(&new_frame_id(), "origin", payload);
+        let (a, b, c) = (key(b"a"), key(b"b"), key(b"c"));
+
+        assert_eq!(cache.admit(&a, DEFAULT_FRAME_TTL), FrameVerdict::Fresh);
+        assert_eq!(cache.admit(&a, DEFAULT_FRAME_TTL), FrameVerdict::Duplicate);
+        assert_eq!(cache.admit(&b, 0), FrameVerdict::Expired);
+        // An expired copy does not shadow one that arrives over a shorter path.
+        assert_eq!(cache.admit(&b, 3), FrameVerdict::Fresh);
+        assert_eq!(cache.admit(&c, 1), FrameVerdict::Fresh);
+        assert_eq!(cache.admit(&a, DEFAULT_FRAME_TTL), FrameVerdict::Fresh);
+
+        assert_eq!(
+            cache.stats(),
+            FrameStats {
+                accepted: 4,
+                duplicates: 1,
+                expired: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn frame_ids_cannot_be_spent_by_someone_else() {
+        let id = new_frame_id();
+        let real = frame_key(&id, "alice", b"signed by alice");
+        assert_ne!(frame_key(&id, "mallory", b"signed by alice"), real);
+        assert_ne!(frame_key(&id, "alice", b"forged"), real);
+        assert_eq!(frame_key(&id, "alice", b"signed by alice"), real);
+    }
+}
@@ -1,26 +1,44 @@
 pub mod aerp;
 pub mod bluetooth;
+pub mod capabilities;
+pub mod circuit;
+pub mod deny;
+pub mod dht;
+pub mod direct;
+pub mod frames;
+pub mod link_state;
+pub mod mailbox;
+pub mod privacy;
+pub mod proxy;
+pub mod quic;
+pub mod sync;
+pub mod topics;
 pub mod transports;
+pub mod validation;
 pub mod wifi_direct;
 
 use gossipsub::error::PublishError;
 use libp2p::{
-    core::upgrade,
-    gossipsub::{self, Gossipsub, GossipsubConfig, GossipsubEvent, MessageAuthenticity},
+    core::{either::EitherOutput, muxing::StreamMuxerBox, transport::Boxed, upgrade},
+    gossipsub::{self, Gossipsub, GossipsubConfigBuilder, GossipsubEvent, MessageAuthenticity},
     identify,
     identity::Keypair,
+    kad::{record::store::MemoryStore, Kademlia, KademliaEvent},
     mdns, mplex, noise,
     noise::{Keypair as NoiseKeypair, X25519Spec},
-    swarm::Swarm,
-    tcp::TcpConfig,
+    relay::{self, RelayConfig},
+    swarm::{toggle::Toggle, Swarm, SwarmBuilder},
+    tcp::{tokio::TcpStream, TokioTcpConfig},
+    yamux,
     Transport,
 };
 use std::error::Error;
 use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use libp2p::request_response::{
-    self, ProtocolName, RequestResponse, RequestResponseCodec, RequestResponseConfig,
+    self, ProtocolName, RequestId, RequestResponse, RequestResponseCodec, RequestResponseConfig,
     RequestResponseEvent,
 };
+use libp2p::{Multiaddr, PeerId};
 use libp2p::NetworkBehaviour;
 use std::io;
 
@@ -31,16 +49,44 @@ pub use aerp::{
     AerpConfig, AerpRouter, LinkQuality, RouteMetrics, RouteSnapshot, RoutedEnvelope, RoutedFrame,
     RouterSnapshot,
 };
+pub use capabilities::{
+    forget_peer, negotiated_version, outbound_capabilities, peer_capabilities,
+    record_peer_capabilities,
+};
+pub use circuit::{circuit_status, CircuitStatus, ReservationState};
+pub use deny::{DenyList, DenyReason, DeniedPeer};
+pub use dht::ContactCard;
+pub use direct::{DirectDeliveryCodec, DirectDeliveryProtocol, DirectDeliveryResponse};
+pub use frames::{
+    admit_frame, frame_stats, FrameId, FrameKey, FrameStats, FrameVerdict, DEFAULT_FRAME_TTL,
+};
+pub use link_state::{link_state_topic, AdvertisedLink, LinkStateAdvert, VerifiedAdvert};
+pub use mailbox::{
+    advertises_mailbox, MailboxCodec, MailboxItem, MailboxProtocol, MailboxRequest, MailboxResponse,
+};
+pub use privacy::PrivacyConfig;
+pub use sync::{SyncCodec, SyncProtocol, SyncRequest, SyncResponse, MAX_FETCH_EVENTS};
+pub use proxy::{ProxyConfig, ProxyMode};
 pub type Topic = gossipsub::IdentTopic;
+pub use topics::{topic_for, TopicRegistry, TopicSyncReport};
 pub use transports::{TransportMedium, TransportSnapshot};
 
+use quic::QuicTransport;
+
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "ComposedEvent")]
 pub struct Behaviour {
     pub gossipsub: Gossipsub,
     pub identify: identify::Identify,
-    pub mdns: mdns::Mdns,
+    /// Disabled in strict proxy mode, where announcing ourselves on the LAN
+    /// would give away our address.
+    pub mdns: Toggle<mdns::Mdns>,
     pub req_res: RequestResponse<FileTransferCodec>,
+    pub direct: RequestResponse<DirectDeliveryCodec>,
+    pub mailbox: RequestResponse<MailboxCodec>,
+    pub sync: RequestResponse<SyncCodec>,
+    pub kademlia: Kademlia<MemoryStore>,
+    pub relay: circuit::CircuitRelay,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -49,6 +95,13 @@ pub enum ComposedEvent {
     Identify(identify::IdentifyEvent),
     Mdns(mdns::MdnsEvent),
     ReqRes(RequestResponseEvent<FileTransferRequest, FileTransferResponse>),
+    Direct(RequestResponseEvent<RoutedEnvelope, DirectDeliveryResponse>),
+    Mailbox(RequestResponseEvent<MailboxRequest, MailboxResponse>),
+    Sync(RequestResponseEvent<SyncRequest, SyncResponse>),
+    Kademlia(KademliaEvent),
+    /// The circuit relay behaviour reports nothing; connections it carries
+    /// surface as ordinary swarm events.
+    Relay,
 }
 
 impl From<GossipsubEvent> for ComposedEvent {
@@ -71,6 +124,31 @@ impl From<RequestResponseEvent<FileTransferRequest, FileTransferResponse>> for C
         ComposedEvent::ReqRes(e)
     }
 }
+impl From<RequestResponseEvent<RoutedEnvelope, DirectDeliveryResponse>> for ComposedEvent {
+    fn from(e: RequestResponseEvent<RoutedEnvelope, DirectDeliveryResponse>) -> Self {
+        ComposedEvent::Direct(e)
+    }
+}
+impl From<RequestResponseEvent<MailboxRequest, MailboxResponse>> for ComposedEvent {
+    fn from(e: RequestResponseEvent<MailboxRequest, MailboxResponse>) -> Self {
+        ComposedEvent::Mailbox(e)
+    }
+}
+impl From<RequestResponseEvent<SyncRequest, SyncResponse>> for ComposedEvent {
+    fn from(e: RequestResponseEvent<SyncRequest, SyncResponse>) -> Self {
+        ComposedEvent::Sync(e)
+    }
+}
+impl From<KademliaEvent> for ComposedEvent {
+    fn from(e: KademliaEvent) -> Self {
+        ComposedEvent::Kademlia(e)
+    }
+}
+impl From<()> for ComposedEvent {
+    fn from(_: ()) -> Self {
+        ComposedEvent::Relay
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct FileTransferProtocol;
@@ -169,34 +247,145 @@ impl RequestResponseCodec for FileTransferCodec {
     }
 }
 
+/// Client swarm. With a `proxy`, outbound TCP dials go through that SOCKS5
+/// proxy; in strict mode we also skip mDNS and only listen through relay
+/// circuits, so nothing reaches us without going through Tor or a relay.
 pub async fn initialize_network(
     local_key: Keypair,
-) -> Result<(Swarm<Behaviour>, Topic, AerpRouter), Box<dyn Error>> {
+    proxy: Option<ProxyConfig>,
+) -> Result<(Swarm<Behaviour>, TopicRegistry, AerpRouter), Box<dyn Error>> {
+    transports::set_proxy(proxy);
+    build_swarm(
+        local_key,
+        request_response::ProtocolSupport::Outbound,
+        RelayConfig::default(),
+        false,
+        proxy,
+        "/ip4/0.0.0.0/tcp/0".parse()?,
+    )
+    .await
+}
+
+/// Swarm for a headless relay node: the same behaviour as a client, but it
+/// carries circuits between other peers, accepts mailbox deposits, keeps
+/// idle circuit connections open for longer
+/// and listens on a fixed address so LAN peers and configured clients can
+/// reach it.
+pub async fn initialize_relay_network(
+    local_key: Keypair,
+    listen_addr: Multiaddr,
+) -> Result<(Swarm<Behaviour>, TopicRegistry, AerpRouter), Box<dyn Error>> {
+    let relay_config = RelayConfig {
+        connection_idle_timeout: std::time::Duration::from_secs(10 * 60),
+        ..RelayConfig::default()
+    };
+    build_swarm(
+        local_key,
+        request_response::ProtocolSupport::Full,
+        relay_config,
+        true,
+        None,
+        listen_addr,
+    )
+    .await
+}
+
+/// The TCP layer underneath relaying and encryption.
+fn tcp_transport(proxy: Option<ProxyConfig>) -> Boxed<TcpStream> {
+    match ProxyMode::of(proxy.as_ref()) {
+        ProxyMode::Direct => TokioTcpConfig::new().nodelay(true).boxed(),
+        // The SOCKS5 transport refuses to listen, so listening falls through
+        // to plain TCP while every dial it can handle goes to the proxy.
+        ProxyMode::Proxied => proxy::Socks5Transport::new(proxy.unwrap().address)
+            .or_transport(TokioTcpConfig::new().nodelay(true))
+            .map(|output, _| match output {
+                EitherOutput::First(stream) | EitherOutput::Second(stream) => stream,
+            })
+            .boxed(),
+        ProxyMode::Strict => proxy::Socks5Transport::new(proxy.unwrap().address).boxed(),
+    }
+}
+
+async fn build_swarm(
+    local_key: Keypair,
+    mailbox_support: request_response::ProtocolSupport,
+    relay_config: RelayConfig,
+    hop: bool,
+    proxy: Option<ProxyConfig>,
+    listen_addr: Multiaddr,
+) -> Result<(Swarm<Behaviour>, TopicRegistry, AerpRouter), Box<dyn Error>> {
     let local_peer_id = libp2p::PeerId::from(local_key.public());
 
     transports::register_local_peer(local_peer_id.clone());
 
     let noise_keys = NoiseKeypair::<X25519Spec>::new().into_authentic(&local_key)?;
 
-    let transport = TcpConfig::new()
+    // QUIC runs over UDP, which a SOCKS5 proxy cannot carry, so proxied
+    // nodes stay on TCP rather than leak their address.
+    let quic_listen_addr = match ProxyMode::of(proxy.as_ref()) {
+        ProxyMode::Direct => quic::listen_addr_for(&listen_addr),
+        ProxyMode::Proxied | ProxyMode::Strict => None,
+    };
+    let quic = match quic_listen_addr {
+        Some(_) => Some(QuicTransport::new(&local_key)?),
+        None => None,
+    };
+
+    // Every node can be reached through `/p2p-circuit` addresses; only relay
+    // nodes (`hop`) carry circuits for peers that cannot reach each other.
+    let (relay_transport, relay) =
+        relay::new_transport_and_behaviour(relay_config, tcp_transport(proxy));
+    let transport = relay_transport
         .upgrade(upgrade::Version::V1)
         .authenticate(noise::NoiseConfig::xx(noise_keys).into_authenticated())
-        .multiplex(mplex::MplexConfig::new())
+        // Yamux first: relayed connections stall over mplex. Mplex stays on
+        // offer for peers that predate circuits.
+        .multiplex(upgrade::SelectUpgrade::new(
+            yamux::YamuxConfig::default(),
+            mplex::MplexConfig::new(),
+        ))
         .boxed();
+    // QUIC authenticates and multiplexes by itself. Circuits still reach
+    // relays over it, since the relay behaviour dials through the swarm.
+    let transport: Boxed<(PeerId, StreamMuxerBox)> = match quic {
+        Some(quic) => transport
+            .or_transport(quic.map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer))))
+            .map(|output, _| match output {
+                EitherOutput::First(output) | EitherOutput::Second(output) => output,
+            })
+            .boxed(),
+        None => transport.boxed(),
+    };
+    let transport = transports::MeasuredTransport { local: local_peer_id, inner: transport }.boxed();
 
-    let gossipsub_config = GossipsubConfig::default();
+    // Frames are only forwarded once the application has validated them.
+    let gossipsub_config = GossipsubConfigBuilder::default()
+        .validate_messages()
+        .build()
+        .expect("Failed to build gossipsub config");
     let mut gossipsub = Gossipsub::new(
         MessageAuthenticity::Signed(local_key.clone()),
         gossipsub_config,
     )
     .expect("Failed to create gossipsub behavior");
+    let (score_params, score_thresholds) = validation::peer_score_params();
+    gossipsub.with_peer_score(score_params, score_thresholds)?;
 
-    let topic = Topic::new("aegis-global-chat");
-    gossipsub.subscribe(&topic)?;
+    let topics = TopicRegistry::new(&mut gossipsub)?;
+    gossipsub.subscribe(&link_state_topic())?;
+    validation::score_topic(&mut gossipsub, &link_state_topic());
 
-    let identify_cfg = identify::IdentifyConfig::new("aegis/1.0.0".into(), local_key.public());
+    let identify_cfg = identify::IdentifyConfig::new(
+        aegis_protocol::wire::identify_protocol_version(),
+        local_key.public(),
+    );
     let identify = identify::Identify::new(identify_cfg);
-    let mdns = mdns::Mdns::new(mdns::MdnsConfig::default()).await?;
+    let strict = ProxyMode::of(proxy.as_ref()) == ProxyMode::Strict;
+    let mdns = if strict {
+        None
+    } else {
+        Some(mdns::Mdns::new(mdns::MdnsConfig::default()).await?)
+    };
 
     let rr_cfg = RequestResponseConfig::default();
     let protocols = std::iter::once((
@@ -205,93 +394,344 @@ pub async fn initialize_network(
     ));
     let req_res = RequestResponse::new(FileTransferCodec, protocols, rr_cfg);
 
+    let direct = RequestResponse::new(
+        DirectDeliveryCodec,
+        std::iter::once((DirectDeliveryProtocol, request_response::ProtocolSupport::Full)),
+        RequestResponseConfig::default(),
+    );
+
+    let mailbox = RequestResponse::new(
+        MailboxCodec,
+        std::iter::once((MailboxProtocol, mailbox_support)),
+        RequestResponseConfig::default(),
+    );
+
+    let sync = RequestResponse::new(
+        SyncCodec,
+        std::iter::once((SyncProtocol, request_response::ProtocolSupport::Full)),
+        RequestResponseConfig::default(),
+    );
+
     let behaviour = Behaviour {
         gossipsub,
         identify,
-        mdns,
+        mdns: mdns.into(),
         req_res,
+        direct,
+        mailbox,
+        sync,
+        kademlia: dht::new_kademlia(local_peer_id),
+        relay: circuit::CircuitRelay::new(relay, hop),
     };
     let router = AerpRouter::new(local_peer_id.clone());
 
-    let mut swarm = Swarm::new(transport, behaviour, local_peer_id);
+    // Connection tasks run on tokio, which the TCP and SOCKS5 sockets need.
+    let mut swarm = SwarmBuilder::new(transport, behaviour, local_peer_id)
+        .executor(Box::new(|future| {
+            tokio::spawn(future);
+        }))
+        .build();
+
+    if !strict {
+        swarm.listen_on(listen_addr)?;
+    }
+    if let Some(address) = quic_listen_addr {
+        swarm.listen_on(address)?;
+        transports::set_quic_enabled(true);
+    }
+
+    Ok((swarm, topics, router))
+}
 
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+/// Marker in front of every routed frame published over gossip. Its last
+/// byte is the frame layout version, so frames in another layout are turned
+/// away instead of being misread.
+pub const ROUTED_FRAME_MAGIC: [u8; 5] = *b"AEGR\x02";
 
-    Ok((swarm, topic, router))
+pub fn encode_frame(frame: &RoutedFrame) -> io::Result<Vec<u8>> {
+    let mut bytes = ROUTED_FRAME_MAGIC.to_vec();
+    bytes.extend_from_slice(&serialize(frame)?);
+    Ok(bytes)
 }
 
+pub fn decode_frame(bytes: &[u8]) -> io::Result<RoutedFrame> {
+    let body = bytes.strip_prefix(&ROUTED_FRAME_MAGIC).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "not a routed frame of this layout")
+    })?;
+    // rkyv reads the frame in place, so it has to start on an aligned address.
+    let mut aligned = rkyv::AlignedVec::with_capacity(body.len());
+    aligned.extend_from_slice(body);
+    deserialize(&aligned)
+}
+
+/// Publishes `data` to everyone on `topic`, signed by `keypair` as sent now.
+/// Used for traffic that is meant for a whole audience, such as presence or
+/// a server's channels. The frame is written for what every subscriber can
+/// read. Returns `false` when no peer is subscribed to the topic, so nothing
+/// was sent.
 pub async fn send_data(
     swarm: &mut Swarm<Behaviour>,
     topic: &Topic,
+    keypair: &Keypair,
+    data: Vec<u8>,
+) -> Result<bool, Box<dyn Error>> {
+    let topic_hash = topic.hash();
+    let subscribers: Vec<PeerId> = swarm
+        .behaviour()
+        .gossipsub
+        .all_peers()
+        .filter(|(_, topics)| topics.contains(&&topic_hash))
+        .map(|(peer, _)| *peer)
+        .collect();
+    let caps = capabilities::shared_capabilities(swarm.local_peer_id(), &subscribers);
+    let data = capabilities::adapt_outgoing(data, &caps);
+    let signed = aegis_protocol::wire::sign(data, keypair, chrono::Utc::now())?;
+    let frame = RoutedFrame::Broadcast {
+        frame_id: frames::new_frame_id(),
+        ttl: DEFAULT_FRAME_TTL,
+        origin: keypair.public().to_peer_id().to_base58(),
+        payload: privacy::pad_outgoing(signed, &caps),
+    };
+    let bytes = encode_frame(&frame)?;
+    match swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), bytes)
+    {
+        Ok(_) => Ok(true),
+        Err(PublishError::InsufficientPeers) => Ok(false),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// A direct delivery handed to the first hop, awaiting its response.
+#[derive(Debug, Clone)]
+pub struct DirectDispatch {
+    pub request_id: RequestId,
+    pub path: Vec<PeerId>,
+}
+
+/// Routes a message for a single recipient is sent over in multipath mode.
+pub const MULTIPATH_ROUTES: usize = 3;
+
+/// Sends `data` towards `destination` over the direct-delivery protocol.
+/// Uses the router's best path, or a one-hop path when the destination is
+/// connected but not yet in the routing table. Returns `None` when no path
+/// is known. The frame is signed by `sender`, so the destination can check
+/// the origin the envelope names. Without a `sender` the frame goes unsigned
+/// and the envelope names no origin, for sealed frames that must not give
+/// their sender away.
+pub fn send_direct(
+    swarm: &mut Swarm<Behaviour>,
     router: &mut AerpRouter,
+    sender: Option<&Keypair>,
+    destination: &PeerId,
     data: Vec<u8>,
-) -> Result<(), Box<dyn Error>> {
+) -> Option<DirectDispatch> {
+    send_multipath(swarm, router, sender, destination, 1, data).into_iter().next()
+}
+
+/// Like [`send_direct`], but sends a copy of `data` over each of up to
+/// `routes` disjoint paths. Every copy carries the same frame ID, so the
+/// destination's seen cache keeps only the first to arrive. Returns one
+/// dispatch per copy; empty when no path is known.
+pub fn send_multipath(
+    swarm: &mut Swarm<Behaviour>,
+    router: &mut AerpRouter,
+    sender: Option<&Keypair>,
+    destination: &PeerId,
+    routes: usize,
+    data: Vec<u8>,
+) -> Vec<DirectDispatch> {
+    let local = *router.local_peer();
+    if destination == &local {
+        return Vec::new();
+    }
+
     router.recompute_routes();
-    let origin = router.local_peer().to_base58();
-    let routes = router.routes();
-
-    if routes.is_empty() {
-        let frame = RoutedFrame::Broadcast {
-            origin,
-            payload: data,
-        };
-        let bytes = serialize(&frame)?;
-        match swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(topic.clone(), bytes)
-        {
-            Ok(_) => {}
-            Err(PublishError::InsufficientPeers) => return Ok(()),
-            Err(e) => return Err(Box::new(e)),
-        }
-        return Ok(());
+    let mut paths: Vec<(Vec<PeerId>, aerp::RouteMetrics)> = router
+        .disjoint_routes(destination, routes.max(1))
+        .into_iter()
+        .filter(|route| route.path.len() >= 2)
+        .map(|route| (route.path, route.metrics))
+        .collect();
+    if paths.is_empty() && swarm.is_connected(destination) {
+        paths.push((
+            vec![local, *destination],
+            aerp::RouteMetrics {
+                hop_count: 1,
+                total_latency_ms: aerp::LinkQuality::default().latency_ms,
+                reliability: aerp::LinkQuality::default().reliability,
+            },
+        ));
     }
 
-    let mut any_sent = false;
-    for route in routes {
-        let path_strings: Vec<String> = route.path.iter().map(|peer| peer.to_base58()).collect();
-        let destination = route.target.to_base58();
-        let envelope = RoutedEnvelope {
-            origin: origin.clone(),
-            destination,
-            path: path_strings,
-            metrics: route.metrics.clone(),
-            payload: data.clone(),
-        };
-        let frame = RoutedFrame::Routed { envelope };
-        let bytes = serialize(&frame)?;
-        match swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(topic.clone(), bytes)
-        {
-            Ok(_) => {
-                any_sent = true;
+    let frame_id = frames::new_frame_id();
+    let caps = capabilities::outbound_capabilities(&local, destination);
+    let mut data = capabilities::adapt_outgoing(data, &caps);
+    if let Some(keypair) = sender {
+        data = capabilities::sign_outgoing(data, keypair);
+    }
+    paths
+        .into_iter()
+        .map(|(path, metrics)| {
+            let mut envelope = RoutedEnvelope {
+                frame_id,
+                ttl: DEFAULT_FRAME_TTL,
+                origin: String::new(),
+                destination: destination.to_base58(),
+                path: path.iter().map(|peer| peer.to_base58()).collect(),
+                metrics,
+                payload: data.clone(),
+            };
+            name_origin(&mut envelope, sender);
+            privacy::pad_envelope(&mut envelope, &caps);
+            let request_id = swarm
+                .behaviour_mut()
+                .direct
+                .send_request(&path[1], envelope);
+            DirectDispatch { request_id, path }
+        })
+        .collect()
+}
+
+/// Names the envelope's origin, the first entry of its path, after
+/// `sender`. Sealed frames have no sender and leave both empty, so hops past
+/// the first cannot tell where the frame came from.
+fn name_origin(envelope: &mut RoutedEnvelope, sender: Option<&Keypair>) {
+    match sender {
+        Some(keypair) => envelope.origin = keypair.public().to_peer_id().to_base58(),
+        None => {
+            envelope.origin.clear();
+            if let Some(first) = envelope.path.first_mut() {
+                first.clear();
             }
-            Err(PublishError::InsufficientPeers) => continue,
-            Err(e) => return Err(Box::new(e)),
         }
     }
+}
+
+/// Passes an envelope we are relaying on to the next hop of its path, using
+/// up one of its hops. Returns `None` when it has none left to give.
+pub fn forward_envelope(
+    swarm: &mut Swarm<Behaviour>,
+    next_hop: &PeerId,
+    mut envelope: RoutedEnvelope,
+) -> Option<RequestId> {
+    envelope.ttl = envelope.ttl.saturating_sub(1);
+    if envelope.ttl == 0 {
+        frames::note_expired_frame();
+        return None;
+    }
+    Some(swarm.behaviour_mut().direct.send_request(next_hop, envelope))
+}
 
-    if !any_sent {
-        let fallback = RoutedFrame::Broadcast {
-            origin,
-            payload: data,
-        };
-        let bytes = serialize(&fallback)?;
-        match swarm
-            .behaviour_mut()
-            .gossipsub
-            .publish(topic.clone(), bytes)
-        {
-            Ok(_) => {}
-            Err(PublishError::InsufficientPeers) => return Ok(()),
-            Err(e) => return Err(Box::new(e)),
-        }
+/// Asks `relay` to hold `data` for `destination` until it comes online. The
+/// frame is signed by `sender`, so the destination can tell it really comes
+/// from the origin the relay hands it over with. Sealed frames have no
+/// sender and are deposited unsigned, naming no origin.
+pub fn deposit_in_mailbox(
+    swarm: &mut Swarm<Behaviour>,
+    sender: Option<&Keypair>,
+    relay: &PeerId,
+    destination: &PeerId,
+    data: Vec<u8>,
+) -> RequestId {
+    let local = *swarm.local_peer_id();
+    let quality = aerp::LinkQuality::default();
+    let caps = capabilities::outbound_capabilities(&local, destination);
+    let mut data = capabilities::adapt_outgoing(data, &caps);
+    if let Some(keypair) = sender {
+        data = capabilities::sign_outgoing(data, keypair);
     }
+    let mut envelope = RoutedEnvelope {
+        frame_id: frames::new_frame_id(),
+        ttl: DEFAULT_FRAME_TTL,
+        origin: String::new(),
+        destination: destination.to_base58(),
+        path: vec![local.to_base58(), relay.to_base58(), destination.to_base58()],
+        metrics: aerp::RouteMetrics {
+            hop_count: 2,
+            total_latency_ms: quality.latency_ms * 2.0,
+            reliability: quality.reliability,
+        },
+        payload: data,
+    };
+    name_origin(&mut envelope, sender);
+    privacy::pad_envelope(&mut envelope, &caps);
+    swarm
+        .behaviour_mut()
+        .mailbox
+        .send_request(relay, MailboxRequest::Deposit(envelope))
+}
+
+/// Requests the next page of envelopes `relay` holds for us.
+pub fn fetch_mailbox(swarm: &mut Swarm<Behaviour>, relay: &PeerId, limit: u32) -> RequestId {
+    swarm
+        .behaviour_mut()
+        .mailbox
+        .send_request(relay, MailboxRequest::Fetch { limit })
+}
 
-    Ok(())
+/// Tells `relay` it can drop the envelopes we have processed.
+pub fn acknowledge_mailbox(swarm: &mut Swarm<Behaviour>, relay: &PeerId, ids: Vec<i64>) -> RequestId {
+    swarm
+        .behaviour_mut()
+        .mailbox
+        .send_request(relay, MailboxRequest::Ack { ids })
+}
+
+/// Sends our digests of `chat_id` to `peer`, which answers with the keys of
+/// the events we may be missing.
+pub fn request_history_summary(
+    swarm: &mut Swarm<Behaviour>,
+    peer: &PeerId,
+    chat_id: String,
+    buckets: Vec<aegis_protocol::history::BucketDigest>,
+) -> RequestId {
+    swarm
+        .behaviour_mut()
+        .sync
+        .send_request(peer, SyncRequest::Summary { chat_id, buckets })
+}
+
+/// Asks `peer` for events of `chat_id` we lack.
+pub fn fetch_history_events(
+    swarm: &mut Swarm<Behaviour>,
+    peer: &PeerId,
+    chat_id: String,
+    ids: Vec<String>,
+) -> RequestId {
+    swarm
+        .behaviour_mut()
+        .sync
+        .send_request(peer, SyncRequest::Fetch { chat_id, ids })
+}
+
+/// Signs our current direct links and floods them on the link-state topic.
+/// Only links the router has measured are advertised; a peer we are merely
+/// connected to says nothing about how well it carries traffic. Returns
+/// `false` when no peer was there to receive the advert.
+pub fn publish_link_state(
+    swarm: &mut Swarm<Behaviour>,
+    router: &AerpRouter,
+    keypair: &Keypair,
+    sequence: u64,
+    max_age: std::time::Duration,
+) -> Result<bool, Box<dyn Error>> {
+    let links = router
+        .local_links(max_age)
+        .into_iter()
+        .take(link_state::MAX_ADVERTISED_LINKS)
+        .map(|(peer, quality)| AdvertisedLink { peer: peer.to_base58(), quality })
+        .collect();
+
+    let advert = LinkStateAdvert::sign(keypair, sequence, max_age, links)?;
+    let bytes = link_state::encode_advert(&advert)?;
+    match swarm.behaviour_mut().gossipsub.publish(link_state_topic(), bytes) {
+        Ok(_) => Ok(true),
+        Err(PublishError::InsufficientPeers) => Ok(false),
+        Err(e) => Err(Box::new(e)),
+    }
 }
 
 pub fn has_any_peers(swarm: &Swarm<Behaviour>) -> bool {
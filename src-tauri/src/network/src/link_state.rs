@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use libp2p::gossipsub::IdentTopic;
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::aerp::LinkQuality;
+use crate::rkyv_utils::{deserialize, serialize};
+
+/// Gossip topic every node floods its link-state adverts on.
+pub const LINK_STATE_TOPIC: &str = "aegis/link-state";
+
+/// Longest lifetime a receiver grants an advert, whatever it asks for.
+pub const MAX_ADVERT_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// Adverts listing more links than this are ignored.
+pub const MAX_ADVERTISED_LINKS: usize = 256;
+
+pub fn link_state_topic() -> IdentTopic {
+    IdentTopic::new(LINK_STATE_TOPIC)
+}
+
+#[derive(Debug, Clone, PartialEq, Archive, Serialize, Deserialize, serde::Serialize, serde::Deserialize)]
+#[archive(check_bytes)]
+pub struct AdvertisedLink {
+    pub peer: String,
+    pub quality: LinkQuality,
+}
+
+/// A node's direct links as it sees them. Each node floods a fresh advert
+/// with a higher `sequence` every routing interval; receivers keep only the
+/// newest one per origin and drop it once `max_age_secs` pass without a
+/// refresh, so links of nodes that went away age out of the graph.
+#[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize, serde::Deserialize)]
+#[archive(check_bytes)]
+pub struct LinkStateAdvert {
+    pub origin: String,
+    pub sequence: u64,
+    pub max_age_secs: u32,
+    pub links: Vec<AdvertisedLink>,
+    /// Protobuf encoding of the origin's public key.
+    pub public_key: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+/// Advert contents after the signature and origin have been checked.
+#[derive(Debug, Clone)]
+pub struct VerifiedAdvert {
+    pub origin: PeerId,
+    pub sequence: u64,
+    pub max_age: Duration,
+    pub links: Vec<(PeerId, LinkQuality)>,
+}
+
+impl LinkStateAdvert {
+    pub fn sign(
+        keypair: &Keypair,
+        sequence: u64,
+        max_age: Duration,
+        links: Vec<AdvertisedLink>,
+    ) -> Result<Self, String> {
+        let public_key = keypair.public();
+        let mut advert = LinkStateAdvert {
+            origin: public_key.to_peer_id().to_base58(),
+            sequence,
+            max_age_secs: max_age.as_secs().min(u32::MAX as u64) as u32,
+            links,
+            public_key: public_key.to_protobuf_encoding(),
+            signature: Vec::new(),
+        };
+        advert.signature = keypair
+            .sign(&advert.signing_bytes()?)
+            .map_err(|e| format!("Failed to sign link-state advert: {}", e))?;
+        Ok(advert)
+    }
+
+    fn signing_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(&(
+            "aegis-link-state-v1",
+            &self.origin,
+            self.sequence,
+            self.max_age_secs,
+            &self.links,
+        ))
+        .map_err(|e| e.to_string())
+    }
+
+    /// Checks that the advert was signed by the key its origin is derived
+    /// from. Links naming unparseable peers are dropped.
+    pub fn verify(&self) -> Result<VerifiedAdvert, String> {
+        if self.links.len() > MAX_ADVERTISED_LINKS {
+            return Err("too many links".into());
+        }
+        let public_key = PublicKey::from_protobuf_encoding(&self.public_key)
+            .map_err(|e| format!("invalid public key: {}", e))?;
+        let origin = public_key.to_peer_id();
+        if origin.to_base58() != self.origin {
+            return Err("origin does not match public key".into());
+        }
+        if !public_key.verify(&self.signing_bytes()?, &self.signature) {
+            return Err("invalid signature".into());
+        }
+
+        let max_age = Duration::from_secs(self.max_age_secs as u64).min(MAX_ADVERT_AGE);
+        let links = self
+            .links
+            .iter()
+            .filter_map(|link| {
+                let peer = link.peer.parse::<PeerId>().ok()?;
+                let quality = LinkQuality {
+                    latency_ms: link.quality.latency_ms.max(0.0),
+                    reliability: link.quality.reliability.clamp(0.0, 1.0),
+                };
+                Some((peer, quality))
+            })
+            .collect();
+        Ok(VerifiedAdvert {
+            origin,
+            sequence: self.sequence,
+            max_age,
+            links,
+        })
+    }
+}
+
+pub fn encode_advert(advert: &LinkStateAdvert) -> std::io::Result<Vec<u8>> {
+    serialize(advert)
+}
+
+pub fn decode_advert(bytes: &[u8]) -> std::io::Result<LinkStateAdvert> {
+    deserialize(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links() -> Vec<AdvertisedLink> {
+        vec![AdvertisedLink {
+            peer: Keypair::generate_ed25519().public().to_peer_id().to_base58(),
+            quality: LinkQuality { latency_ms: 12.0, reliability: 0.9 },
+        }]
+    }
+
+    #[test]
+    fn signed_adverts_verify_and_tampering_is_caught() {
+        let keypair = Keypair::generate_ed25519();
+        let advert = LinkStateAdvert::sign(&keypair, 7, Duration::from_secs(3600), links())
+            .expect("sign");
+        let decoded = decode_advert(&encode_advert(&advert).unwrap()).unwrap();
+        let verified = decoded.verify().expect("verify");
+        assert_eq!(verified.origin, keypair.public().to_peer_id());
+        assert_eq!(verified.sequence, 7);
+        assert_eq!(verified.links.len(), 1);
+        assert_eq!(verified.max_age, MAX_ADVERT_AGE);
+
+        let mut bumped = advert.clone();
+        bumped.sequence += 1;
+        assert!(bumped.verify().is_err());
+
+        let mut forged = advert.clone();
+        forged.origin = Keypair::generate_ed25519().public().to_peer_id().to_base58();
+        assert!(forged.verify().is_err());
+
+        let other = Keypair::generate_ed25519();
+        let mut rekeyed = advert;
+        rekeyed.public_key = other.public().to_protobuf_encoding();
+        assert!(rekeyed.verify().is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use futures::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{ProtocolName, RequestResponseCodec};
+use rkyv::{Archive, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io;
+
+use crate::aerp::RoutedEnvelope;
+use crate::rkyv_utils::{deserialize, read_limited, serialize};
+
+/// Store-and-forward mailboxes kept by relay nodes. Clients deposit routed
+/// envelopes for peers that are offline; the recipient collects them when it
+/// reconnects and acknowledges what it has processed. Only relays accept the
+/// protocol inbound, so it doubles as the marker identify uses to find them.
+#[derive(Debug, Clone)]
+pub struct MailboxProtocol;
+
+/// Largest mailbox request a relay reads, which also bounds the envelopes it
+/// takes in.
+pub const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// Largest mailbox response a client reads. Relays fill a page with
+/// envelopes up to [`FETCH_BUDGET_BYTES`] so it stays below this.
+pub const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Envelope bytes a relay puts in one page. One request's worth of room is
+/// left for the framing around them.
+pub const FETCH_BUDGET_BYTES: usize = MAX_RESPONSE_BYTES - MAX_REQUEST_BYTES;
+
+/// Versioned with the layout of [`RoutedEnvelope`], like the direct-delivery
+/// protocol.
+pub const MAILBOX_PROTOCOL_NAME: &str = "/aegis/mailbox/2";
+
+impl ProtocolName for MailboxProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        MAILBOX_PROTOCOL_NAME.as_bytes()
+    }
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize, serde::Deserialize)]
+#[archive(check_bytes)]
+pub enum MailboxRequest {
+    /// Hold the envelope until `destination` collects it. The relay only
+    /// accepts envelopes whose origin is the depositing peer, or that name
+    /// no origin at all.
+    Deposit(RoutedEnvelope),
+    /// Envelopes held for the requesting peer, oldest first.
+    Fetch { limit: u32 },
+    /// Drops envelopes the requesting peer has processed.
+    Ack { ids: Vec<i64> },
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize, serde::Deserialize)]
+#[archive(check_bytes)]
+pub struct MailboxItem {
+    pub id: i64,
+    pub envelope: RoutedEnvelope,
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize, serde::Deserialize)]
+#[archive(check_bytes)]
+pub enum MailboxResponse {
+    Deposited,
+    /// A page of held envelopes and how many are still waiting after it.
+    Messages { items: Vec<MailboxItem>, remaining: u32 },
+    Acknowledged,
+    Rejected(String),
+}
+
+/// Whether a peer's identify protocols show it keeps mailboxes.
+pub fn advertises_mailbox(protocols: &[String]) -> bool {
+    protocols.iter().any(|protocol| protocol == MAILBOX_PROTOCOL_NAME)
+}
+
+/// Content digest a relay uses to store repeated deposits of the same frame
+/// once. Frame IDs differ between retransmissions, so they are left out.
+pub fn envelope_digest(envelope: &RoutedEnvelope) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(envelope.origin.as_bytes());
+    hasher.update([0]);
+    hasher.update(envelope.destination.as_bytes());
+    hasher.update([0]);
+    hasher.update(&envelope.payload);
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn encode_envelope(envelope: &RoutedEnvelope) -> io::Result<Vec<u8>> {
+    serialize(envelope)
+}
+
+pub fn decode_envelope(bytes: &[u8]) -> io::Result<RoutedEnvelope> {
+    deserialize(bytes)
+}
+
+#[derive(Debug, Clone)]
+pub struct MailboxCodec;
+
+#[async_trait::async_trait]
+impl RequestResponseCodec for MailboxCodec {
+    type Protocol = MailboxProtocol;
+    type Request = MailboxRequest;
+    type Response = MailboxResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &MailboxProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let buf = read_limited(io, MAX_REQUEST_BYTES).await?;
+        deserialize(&buf)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &MailboxProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let buf = read_limited(io, MAX_RESPONSE_BYTES).await?;
+        deserialize(&buf)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &MailboxProtocol,
+        io: &mut T,
+        req: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = serialize(&req)?;
+        AsyncWriteExt::write_all(io, &bytes).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &MailboxProtocol,
+        io: &mut T,
+        res: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = serialize(&res)?;
+        AsyncWriteExt::write_all(io, &bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aerp::RouteMetrics;
+
+    fn envelope(payload: &[u8]) -> RoutedEnvelope {
+        RoutedEnvelope {
+            frame_id: crate::frames::new_frame_id(),
+            ttl: crate::frames::DEFAULT_FRAME_TTL,
+            origin: "origin".into(),
+            destination: "destination".into(),
+            path: vec!["origin".into(), "relay".into(), "destination".into()],
+            metrics: RouteMetrics { hop_count: 2, total_latency_ms: 100.0, reliability: 0.9 },
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn envelopes_round_trip_and_digest_by_content() {
+        let original = envelope(b"sealed frame");
+        let decoded = decode_envelope(&encode_envelope(&original).unwrap()).unwrap();
+        assert_eq!(decoded.payload, original.payload);
+        assert_eq!(decoded.path, original.path);
+
+        let mut rerouted = original.clone();
+        rerouted.path = vec!["origin".into(), "other-relay".into(), "destination".into()];
+        assert_eq!(envelope_digest(&original), envelope_digest(&rerouted));
+        assert_ne!(envelope_digest(&original), envelope_digest(&envelope(b"another frame")));
+
+        assert!(advertises_mailbox(&["/ipfs/id/1.0.0".into(), MAILBOX_PROTOCOL_NAME.into()]));
+        assert!(!advertises_mailbox(&["/aegis/route/2".into(), "/aegis/mailbox/1".into()]));
+    }
+}
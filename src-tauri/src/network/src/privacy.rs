@@ -0,0 +1,316 @@
+//! Optional privacy mode against traffic analysis. Frames are padded to
+//! fixed size buckets so their length says little about the message inside
+//! or the route it takes, and every neighbour gets a steady trickle of cover frames so send times
+//! do not give real messages away. Cover frames travel one hop over the
+//! direct-delivery protocol, like any routed message, and are dropped unread.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use aegis_protocol::wire::{self, PeerCapabilities};
+use libp2p::swarm::Swarm;
+use libp2p::PeerId;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rand::seq::SliceRandom;
+
+use crate::aerp::{LinkQuality, RouteMetrics, RoutedEnvelope};
+use crate::frames::{self, DEFAULT_FRAME_TTL};
+use crate::transports::{self, TransportMedium};
+use crate::Behaviour;
+
+/// How often cover frames go out to each neighbour the budgets allow.
+pub const COVER_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivacyConfig {
+    pub pad_frames: bool,
+    pub cover_traffic: bool,
+    /// Bytes per second of cover traffic each transport may carry, shared
+    /// by all neighbours reached over it.
+    pub cover_budgets: HashMap<TransportMedium, u32>,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            pad_frames: false,
+            cover_traffic: false,
+            cover_budgets: HashMap::from([
+                (TransportMedium::Tcp, 4096),
+                (TransportMedium::Quic, 4096),
+                (TransportMedium::WifiDirect, 2048),
+                // One cover frame every two seconds at most.
+                (TransportMedium::Bluetooth, 128),
+            ]),
+        }
+    }
+}
+
+impl PrivacyConfig {
+    pub fn cover_budget(&self, medium: TransportMedium) -> u32 {
+        self.cover_budgets.get(&medium).copied().unwrap_or(0)
+    }
+
+    /// Padding is on and receivers with `caps` know to strip it.
+    fn pads_for(&self, caps: &PeerCapabilities) -> bool {
+        self.pad_frames && caps.features & wire::features::PADDED != 0
+    }
+
+    /// Pads an outgoing wire frame for receivers with `caps`. Frames that
+    /// cannot be padded go out as they are rather than not at all.
+    pub fn pad(&self, data: Vec<u8>, caps: &PeerCapabilities) -> Vec<u8> {
+        if !self.pads_for(caps) {
+            return data;
+        }
+        match wire::pad(data.clone()) {
+            Ok(padded) => padded,
+            Err(error) => {
+                eprintln!("Sending frame unpadded: {}", error);
+                data
+            }
+        }
+    }
+
+    /// Pads the frame `envelope` carries so the encoded envelope as a whole,
+    /// route included, is one of the padding buckets. Padding the frame
+    /// alone would leave the length of the path showing.
+    pub fn pad_envelope(&self, envelope: &mut RoutedEnvelope, caps: &PeerCapabilities) {
+        if !self.pads_for(caps) || !envelope.payload.starts_with(&wire::WIRE_MAGIC) {
+            return;
+        }
+        let frame = std::mem::take(&mut envelope.payload);
+        let Ok(route) = encoded_len(envelope) else {
+            envelope.payload = frame;
+            return;
+        };
+        let target = wire::padded_size(route + frame.len() + wire::PADDING_OVERHEAD);
+        let mut size = target - route;
+        // The archive aligns what follows the payload, so the first guess
+        // can overshoot by a few bytes.
+        for _ in 0..4 {
+            let padded = match wire::pad_exact(frame.clone(), size) {
+                Ok(padded) => padded,
+                Err(error) => {
+                    eprintln!("Sending frame unpadded: {}", error);
+                    break;
+                }
+            };
+            envelope.payload = padded;
+            match encoded_len(envelope) {
+                Ok(encoded) if encoded == target => return,
+                Ok(encoded) if encoded > target => size -= encoded - target,
+                Ok(encoded) => size += target - encoded,
+                Err(_) => break,
+            }
+        }
+        envelope.payload = frame;
+    }
+}
+
+fn encoded_len(envelope: &RoutedEnvelope) -> std::io::Result<usize> {
+    crate::rkyv_utils::serialize(envelope).map(|bytes| bytes.len())
+}
+
+/// Token bucket metering the cover traffic of one transport.
+#[derive(Debug)]
+struct CoverBudget {
+    available: f64,
+    refilled_at: Instant,
+}
+
+impl CoverBudget {
+    fn new(now: Instant) -> Self {
+        Self { available: 0.0, refilled_at: now }
+    }
+
+    /// Spends `bytes` if the budget has them. Unused budget carries over for
+    /// up to one frame or one second's worth, whichever is larger, so small
+    /// budgets still get a frame out now and then.
+    fn try_spend(&mut self, bytes_per_sec: u32, bytes: usize, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        let cap = f64::from(bytes_per_sec).max(bytes as f64);
+        self.available = (self.available + elapsed * f64::from(bytes_per_sec)).min(cap);
+        self.refilled_at = now;
+        if bytes_per_sec == 0 || self.available < bytes as f64 {
+            return false;
+        }
+        self.available -= bytes as f64;
+        true
+    }
+}
+
+#[derive(Debug, Default)]
+struct PrivacyState {
+    config: PrivacyConfig,
+    budgets: HashMap<TransportMedium, CoverBudget>,
+    cover_frames_sent: u64,
+}
+
+static PRIVACY: Lazy<Mutex<PrivacyState>> = Lazy::new(|| Mutex::new(PrivacyState::default()));
+
+pub fn privacy_config() -> PrivacyConfig {
+    PRIVACY.lock().config.clone()
+}
+
+/// Takes effect immediately for frames sent from now on.
+pub fn set_privacy_config(config: PrivacyConfig) {
+    let mut state = PRIVACY.lock();
+    state.config = config;
+    state.budgets.clear();
+}
+
+pub fn cover_frames_sent() -> u64 {
+    PRIVACY.lock().cover_frames_sent
+}
+
+/// Pads an outgoing wire frame for receivers with `caps` when padding is
+/// on; see [`PrivacyConfig::pad`].
+pub fn pad_outgoing(data: Vec<u8>, caps: &PeerCapabilities) -> Vec<u8> {
+    PRIVACY.lock().config.pad(data, caps)
+}
+
+/// Pads a routed envelope for its destination's `caps` when padding is on;
+/// see [`PrivacyConfig::pad_envelope`].
+pub fn pad_envelope(envelope: &mut RoutedEnvelope, caps: &PeerCapabilities) {
+    PRIVACY.lock().config.pad_envelope(envelope, caps)
+}
+
+/// Sends one cover frame to each neighbour whose transport has budget left.
+/// Call every [`COVER_INTERVAL`]; does nothing while cover traffic is off.
+/// Returns the number of frames sent.
+pub fn send_cover_traffic(swarm: &mut Swarm<Behaviour>) -> usize {
+    if !PRIVACY.lock().config.cover_traffic {
+        return 0;
+    }
+    let local = *swarm.local_peer_id();
+    let mut neighbours: Vec<(PeerId, TransportMedium)> = transports::neighbours(&local);
+    // Spread a tight budget over every neighbour rather than the same few.
+    neighbours.shuffle(&mut rand::thread_rng());
+
+    let frame = match wire::cover_frame() {
+        Ok(frame) => frame,
+        Err(error) => {
+            eprintln!("Failed to build cover frame: {}", error);
+            return 0;
+        }
+    };
+    let now = Instant::now();
+    let mut sent = 0;
+    let mut state = PRIVACY.lock();
+    for (peer, medium) in neighbours {
+        if !swarm.is_connected(&peer) {
+            continue;
+        }
+        let budget = state.config.cover_budget(medium);
+        let spent = state
+            .budgets
+            .entry(medium)
+            .or_insert_with(|| CoverBudget::new(now))
+            .try_spend(budget, frame.len(), now);
+        if !spent {
+            continue;
+        }
+        let quality = LinkQuality::default();
+        let envelope = RoutedEnvelope {
+            frame_id: frames::new_frame_id(),
+            ttl: DEFAULT_FRAME_TTL,
+            origin: local.to_base58(),
+            destination: peer.to_base58(),
+            path: vec![local.to_base58(), peer.to_base58()],
+            metrics: RouteMetrics {
+                hop_count: 1,
+                total_latency_ms: quality.latency_ms,
+                reliability: quality.reliability,
+            },
+            payload: frame.clone(),
+        };
+        swarm.behaviour_mut().direct.send_request(&peer, envelope);
+        sent += 1;
+    }
+    state.cover_frames_sent += sent as u64;
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cover_budget_limits_bytes_per_second() {
+        let start = Instant::now();
+        let mut budget = CoverBudget::new(start);
+        assert!(!budget.try_spend(512, 256, start));
+
+        let later = start + Duration::from_secs(1);
+        assert!(budget.try_spend(512, 256, later));
+        assert!(budget.try_spend(512, 256, later));
+        assert!(!budget.try_spend(512, 256, later));
+
+        // A budget below one frame still lets a frame through once enough
+        // time has passed.
+        let mut slow = CoverBudget::new(start);
+        assert!(!slow.try_spend(128, 256, start + Duration::from_secs(1)));
+        assert!(slow.try_spend(128, 256, start + Duration::from_secs(2)));
+        assert!(!slow.try_spend(0, 256, start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn pads_only_when_enabled() {
+        let frame = wire::seal(b"hello".to_vec()).unwrap();
+        let caps = PeerCapabilities::local();
+        assert_eq!(PrivacyConfig::default().pad(frame.clone(), &caps), frame);
+
+        let padding = PrivacyConfig { pad_frames: true, ..PrivacyConfig::default() };
+        assert_eq!(padding.pad(frame.clone(), &caps).len(), wire::PADDING_BUCKETS[0]);
+    }
+
+    #[test]
+    fn leaves_frames_unpadded_for_peers_that_cannot_strip_padding() {
+        let frame = wire::seal(b"hello".to_vec()).unwrap();
+        let padding = PrivacyConfig { pad_frames: true, ..PrivacyConfig::default() };
+        let caps = PeerCapabilities {
+            features: wire::SUPPORTED_FEATURES & !wire::features::PADDED,
+            ..PeerCapabilities::local()
+        };
+        assert_eq!(padding.pad(frame.clone(), &caps), frame);
+
+        let mut envelope = envelope_over(3, frame.clone());
+        padding.pad_envelope(&mut envelope, &caps);
+        assert_eq!(envelope.payload, frame);
+    }
+
+    fn envelope_over(hops: usize, payload: Vec<u8>) -> RoutedEnvelope {
+        let peers: Vec<String> = (0..=hops)
+            .map(|_| libp2p::identity::Keypair::generate_ed25519().public().to_peer_id().to_base58())
+            .collect();
+        RoutedEnvelope {
+            frame_id: frames::new_frame_id(),
+            ttl: DEFAULT_FRAME_TTL,
+            origin: peers[0].clone(),
+            destination: peers[hops].clone(),
+            path: peers,
+            metrics: RouteMetrics { hop_count: hops as u32, total_latency_ms: 0.0, reliability: 1.0 },
+            payload,
+        }
+    }
+
+    #[test]
+    fn padded_envelopes_do_not_give_their_path_length_away() {
+        let padding = PrivacyConfig { pad_frames: true, ..PrivacyConfig::default() };
+        let caps = PeerCapabilities::local();
+        for message in [b"hi".to_vec(), vec![7u8; 900], vec![7u8; 5000]] {
+            let frame = wire::seal(message).unwrap();
+            let sizes: Vec<usize> = (1..=6)
+                .map(|hops| {
+                    let mut envelope = envelope_over(hops, frame.clone());
+                    padding.pad_envelope(&mut envelope, &caps);
+                    assert!(envelope.payload.len() > frame.len());
+                    encoded_len(&envelope).unwrap()
+                })
+                .collect();
+            assert!(wire::PADDING_BUCKETS.contains(&sizes[0]), "{} is not a bucket", sizes[0]);
+            assert!(sizes.iter().all(|size| *size == sizes[0]), "sizes differ: {:?}", sizes);
+        }
+    }
+}
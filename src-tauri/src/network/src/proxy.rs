@@ -0,0 +1,290 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::future::{self, BoxFuture, FutureExt};
+use futures::stream;
+use libp2p::core::transport::{ListenerEvent, TransportError};
+use libp2p::multiaddr::Protocol;
+use libp2p::tcp::tokio::TcpStream;
+use libp2p::{Multiaddr, Transport};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// How long the proxy may take to set up a connection. Tor circuits are
+/// slow to build, so this is generous.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Routes outbound TCP dials through a SOCKS5 proxy such as a local Tor
+/// daemon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub address: SocketAddr,
+    /// Also stop listening for direct connections and announcing ourselves
+    /// over mDNS, so no peer learns our IP address.
+    pub strict: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProxyMode {
+    #[default]
+    Direct,
+    /// Outbound dials use the proxy; we still listen and use mDNS.
+    Proxied,
+    /// Everything goes through the proxy or relay circuits.
+    Strict,
+}
+
+impl ProxyMode {
+    pub fn of(proxy: Option<&ProxyConfig>) -> Self {
+        match proxy {
+            None => ProxyMode::Direct,
+            Some(config) if config.strict => ProxyMode::Strict,
+            Some(_) => ProxyMode::Proxied,
+        }
+    }
+}
+
+/// Where the proxy should connect to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl Target {
+    /// Accepts `/ip4|ip6|dns|dns4|dns6/<host>/tcp/<port>`, optionally ending in
+    /// `/p2p/<peer>`. Domains are resolved by the proxy so lookups do not
+    /// leak either.
+    fn from_multiaddr(address: &Multiaddr) -> Option<Self> {
+        let mut protocols = address.iter();
+        let host = protocols.next()?;
+        let port = match protocols.next()? {
+            Protocol::Tcp(port) => port,
+            _ => return None,
+        };
+        match protocols.next() {
+            None | Some(Protocol::P2p(_)) => {}
+            Some(_) => return None,
+        }
+        if protocols.next().is_some() {
+            return None;
+        }
+        match host {
+            Protocol::Ip4(ip) => Some(Target::Ip(SocketAddr::new(IpAddr::V4(ip), port))),
+            Protocol::Ip6(ip) => Some(Target::Ip(SocketAddr::new(IpAddr::V6(ip), port))),
+            Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => {
+                Some(Target::Domain(name.into_owned(), port))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Dial-only TCP transport that tunnels every connection through a SOCKS5
+/// proxy. It never listens; combine it with a TCP transport for that.
+#[derive(Debug, Clone, Copy)]
+pub struct Socks5Transport {
+    proxy: SocketAddr,
+}
+
+impl Socks5Transport {
+    pub fn new(proxy: SocketAddr) -> Self {
+        Self { proxy }
+    }
+}
+
+impl Transport for Socks5Transport {
+    type Output = TcpStream;
+    type Error = io::Error;
+    type Listener = stream::Pending<Result<ListenerEvent<Self::ListenerUpgrade, io::Error>, io::Error>>;
+    type ListenerUpgrade = future::Pending<Result<TcpStream, io::Error>>;
+    type Dial = BoxFuture<'static, Result<TcpStream, io::Error>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<io::Error>> {
+        Err(TransportError::MultiaddrNotSupported(addr))
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<io::Error>> {
+        let target = Target::from_multiaddr(&addr).ok_or(TransportError::MultiaddrNotSupported(addr))?;
+        let proxy = self.proxy;
+        Ok(async move {
+            tokio::time::timeout(CONNECT_TIMEOUT, connect(proxy, &target))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "SOCKS5 proxy timed out"))?
+                .map(TcpStream)
+        }
+        .boxed())
+    }
+
+    fn address_translation(&self, _listen: &Multiaddr, _observed: &Multiaddr) -> Option<Multiaddr> {
+        None
+    }
+}
+
+async fn connect(proxy: SocketAddr, target: &Target) -> io::Result<tokio::net::TcpStream> {
+    let mut stream = tokio::net::TcpStream::connect(proxy).await?;
+    stream.set_nodelay(true)?;
+
+    stream.write_all(&[SOCKS_VERSION, 1, NO_AUTHENTICATION]).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice != [SOCKS_VERSION, NO_AUTHENTICATION] {
+        return Err(socks_error("proxy requires an unsupported authentication method"));
+    }
+
+    let mut request = vec![SOCKS_VERSION, CONNECT, 0];
+    let port = match target {
+        Target::Ip(SocketAddr::V4(address)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&address.ip().octets());
+            address.port()
+        }
+        Target::Ip(SocketAddr::V6(address)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&address.ip().octets());
+            address.port()
+        }
+        Target::Domain(name, port) => {
+            let length = u8::try_from(name.len()).map_err(|_| socks_error("domain name too long"))?;
+            request.push(ATYP_DOMAIN);
+            request.push(length);
+            request.extend_from_slice(name.as_bytes());
+            *port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(socks_error("proxy is not a SOCKS5 server"));
+    }
+    if reply[1] != 0 {
+        return Err(socks_error(&format!("proxy refused the connection (code {})", reply[1])));
+    }
+    // Skip the bound address, which is of no use to us.
+    let bound = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(socks_error("malformed proxy reply")),
+    };
+    let mut skipped = vec![0u8; bound + 2];
+    stream.read_exact(&mut skipped).await?;
+    Ok(stream)
+}
+
+fn socks_error(message: &str) -> io::Error {
+    io::Error::other(format!("SOCKS5: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ComposedEvent;
+    use libp2p::futures::StreamExt;
+    use libp2p::identity::Keypair;
+    use libp2p::swarm::SwarmEvent;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Minimal SOCKS5 server standing in for Tor: no authentication, CONNECT
+    /// to IP targets only. Counts the connections it tunnels.
+    async fn socks_stand_in() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let tunnelled = Arc::new(AtomicUsize::new(0));
+        let counter = tunnelled.clone();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let mut greeting = [0u8; 3];
+                    client.read_exact(&mut greeting).await.ok()?;
+                    client.write_all(&[SOCKS_VERSION, NO_AUTHENTICATION]).await.ok()?;
+                    let mut header = [0u8; 4];
+                    client.read_exact(&mut header).await.ok()?;
+                    let mut ip = [0u8; 4];
+                    client.read_exact(&mut ip).await.ok()?;
+                    let port = client.read_u16().await.ok()?;
+                    let mut upstream = tokio::net::TcpStream::connect((std::net::Ipv4Addr::from(ip), port)).await.ok()?;
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    client.write_all(&[SOCKS_VERSION, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0]).await.ok()?;
+                    tokio::io::copy_bidirectional(&mut client, &mut upstream).await.ok()
+                });
+            }
+        });
+        (address, tunnelled)
+    }
+
+    #[test]
+    fn parses_dialable_targets() {
+        let parse = |address: &str| Target::from_multiaddr(&address.parse().unwrap());
+        assert_eq!(
+            parse("/ip4/10.0.0.2/tcp/4001"),
+            Some(Target::Ip("10.0.0.2:4001".parse().unwrap()))
+        );
+        assert_eq!(
+            parse("/dns4/relay.example.org/tcp/443/p2p/12D3KooWGaYsBhuNjFdkaVYQuoQRE5bsNNgmUw9ByX9AQRUjfeEw"),
+            Some(Target::Domain("relay.example.org".into(), 443))
+        );
+        assert_eq!(parse("/ip4/10.0.0.2/udp/4001"), None);
+        assert_eq!(parse("/ip4/10.0.0.2/tcp/4001/p2p-circuit"), None);
+    }
+
+    #[tokio::test]
+    async fn strict_mode_dials_only_through_the_proxy() {
+        let (proxy, tunnelled) = socks_stand_in().await;
+        let (mut strict, _, _) = crate::initialize_network(
+            Keypair::generate_ed25519(),
+            Some(ProxyConfig { address: proxy, strict: true }),
+        )
+        .await
+        .expect("strict swarm");
+        let (mut remote, _, _) = crate::initialize_relay_network(
+            Keypair::generate_ed25519(),
+            "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+        )
+        .await
+        .expect("remote swarm");
+        let remote_address = loop {
+            // SOCKS5 only carries TCP, so skip the QUIC listener.
+            if let SwarmEvent::NewListenAddr { address, .. } = remote.select_next_some().await {
+                if crate::quic::socket_addr(&address).is_none() {
+                    break address;
+                }
+            }
+        };
+
+        assert_eq!(strict.listeners().count(), 0);
+        assert!(!strict.behaviour().mdns.is_enabled());
+        assert_eq!(ProxyMode::of(crate::transport_snapshot().proxy.as_ref()), ProxyMode::Strict);
+
+        strict.dial_addr(remote_address).expect("dial through proxy");
+        let remote_id = *remote.local_peer_id();
+        let connected = tokio::time::timeout(Duration::from_secs(20), async {
+            loop {
+                tokio::select! {
+                    _ = remote.select_next_some() => {}
+                    event = strict.select_next_some() => {
+                        if let SwarmEvent::<ComposedEvent, _>::ConnectionEstablished { peer_id, .. } = event {
+                            return peer_id;
+                        }
+                    }
+                }
+            }
+        })
+        .await
+        .expect("strict swarm never connected");
+        assert_eq!(connected, remote_id);
+        assert_eq!(tunnelled.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,656 @@
+//! QUIC transport. Every libp2p substream is a QUIC stream of its own, so a
+//! lost packet only holds up the stream it belongs to, and no noise or yamux
+//! runs on top. Both ends authenticate during the TLS 1.3 handshake with
+//! their libp2p Ed25519 identity key, presented as an RFC 7250 raw public
+//! key; the peer ID is derived from the key the other side proved it holds.
+//! This is not the libp2p QUIC handshake, which wraps the key in a
+//! certificate extension, so only Aegis nodes can dial each other over it.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::{BoxFuture, FutureExt};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::ready;
+use futures::stream::{self, BoxStream, StreamExt};
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerEvent};
+use libp2p::core::transport::{ListenerEvent, TransportError};
+use libp2p::identity::{ed25519, Keypair, PublicKey};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId, Transport};
+use parking_lot::Mutex;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::AlwaysResolvesClientRawPublicKeys;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, SubjectPublicKeyInfoDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::AlwaysResolvesServerRawPublicKeys;
+use rustls::sign::CertifiedKey;
+use rustls::{DigitallySignedStruct, DistinguishedName, SignatureScheme};
+
+/// Identifies our protocol to QUIC peers; also used as the TLS server name,
+/// which nothing checks.
+const ALPN: &[u8] = b"aegis";
+const SERVER_NAME: &str = "aegis";
+
+/// How long a connection may stay silent before QUIC drops it. This also
+/// bounds dials to addresses that never answer, so keep it short.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keeps connections without open streams from reaching [`IDLE_TIMEOUT`].
+const KEEP_ALIVE: Duration = Duration::from_secs(4);
+
+/// DER that precedes the 32 key bytes in an Ed25519 SubjectPublicKeyInfo.
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+/// DER that precedes the 32 secret bytes in an Ed25519 PKCS#8 v1 key.
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+pub type QuicListenerUpgrade = BoxFuture<'static, io::Result<(PeerId, QuicMuxer)>>;
+
+#[derive(Clone)]
+pub struct QuicTransport {
+    state: Arc<Mutex<QuicState>>,
+}
+
+struct QuicState {
+    server_config: quinn::ServerConfig,
+    client_config: quinn::ClientConfig,
+    /// Endpoints we listen on. Dials go out from these too, so peers see our
+    /// listening port and NAT mappings are shared with incoming traffic.
+    listeners: Vec<quinn::Endpoint>,
+    /// Dial-only endpoints for address families we do not listen on.
+    dialers: Vec<quinn::Endpoint>,
+}
+
+impl QuicTransport {
+    /// Fails unless `identity` is an Ed25519 key, the only kind the
+    /// handshake carries.
+    pub fn new(identity: &Keypair) -> Result<Self, String> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let identity = Arc::new(raw_public_key(&provider, identity)?);
+        let verifier = Arc::new(IdentityVerifier(provider.clone()));
+        let transport_config = Arc::new({
+            let mut config = quinn::TransportConfig::default();
+            config.keep_alive_interval(Some(KEEP_ALIVE));
+            config.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().expect("idle timeout fits in a VarInt")));
+            config
+        });
+
+        let mut server_crypto = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| e.to_string())?
+            .with_client_cert_verifier(verifier.clone())
+            .with_cert_resolver(Arc::new(AlwaysResolvesServerRawPublicKeys::new(identity.clone())));
+        server_crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let server_crypto = quinn::crypto::rustls::QuicServerConfig::try_from(server_crypto)
+            .map_err(|e| e.to_string())?;
+        let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(server_crypto));
+        server_config.transport_config(transport_config.clone());
+
+        let mut client_crypto = rustls::ClientConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(|e| e.to_string())?
+            .dangerous()
+            .with_custom_certificate_verifier(verifier)
+            .with_client_cert_resolver(Arc::new(AlwaysResolvesClientRawPublicKeys::new(identity)));
+        client_crypto.alpn_protocols = vec![ALPN.to_vec()];
+        let client_crypto = quinn::crypto::rustls::QuicClientConfig::try_from(client_crypto)
+            .map_err(|e| e.to_string())?;
+        let mut client_config = quinn::ClientConfig::new(Arc::new(client_crypto));
+        client_config.transport_config(transport_config);
+
+        Ok(Self {
+            state: Arc::new(Mutex::new(QuicState {
+                server_config,
+                client_config,
+                listeners: Vec::new(),
+                dialers: Vec::new(),
+            })),
+        })
+    }
+
+    /// An endpoint to dial `target` from, preferring one we listen on.
+    fn dialing_endpoint(&self, target: &SocketAddr) -> io::Result<(quinn::Endpoint, quinn::ClientConfig)> {
+        let mut state = self.state.lock();
+        let same_family = |local: &SocketAddr| local.is_ipv4() == target.is_ipv4();
+        // A socket bound to loopback cannot send to other hosts, and the
+        // kernel drops such packets silently, so the dial would just hang.
+        let reaches_target = |endpoint: &&quinn::Endpoint| {
+            endpoint
+                .local_addr()
+                .map(|local| {
+                    same_family(&local)
+                        && (local.ip().is_unspecified() || local.ip().is_loopback() == target.ip().is_loopback())
+                })
+                .unwrap_or(false)
+        };
+        let existing = state
+            .listeners
+            .iter()
+            .find(reaches_target)
+            .or_else(|| {
+                state
+                    .dialers
+                    .iter()
+                    .find(|endpoint| endpoint.local_addr().map(|local| same_family(&local)).unwrap_or(false))
+            })
+            .cloned();
+        let endpoint = match existing {
+            Some(endpoint) => endpoint,
+            None => {
+                let unspecified = if target.is_ipv4() {
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+                } else {
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+                };
+                let endpoint = quinn::Endpoint::client(SocketAddr::new(unspecified, 0))?;
+                state.dialers.push(endpoint.clone());
+                endpoint
+            }
+        };
+        Ok((endpoint, state.client_config.clone()))
+    }
+}
+
+/// Our identity key presented as an RFC 7250 raw public key.
+fn raw_public_key(provider: &CryptoProvider, identity: &Keypair) -> Result<CertifiedKey, String> {
+    let Keypair::Ed25519(keypair) = identity else {
+        return Err("QUIC needs an Ed25519 identity key".to_string());
+    };
+    let secret = keypair.secret();
+    let mut pkcs8 = ED25519_PKCS8_PREFIX.to_vec();
+    pkcs8.extend_from_slice(secret.as_ref());
+    let key = provider
+        .key_provider
+        .load_private_key(PrivateKeyDer::Pkcs8(pkcs8.into()))
+        .map_err(|e| e.to_string())?;
+    let public_key = key
+        .public_key()
+        .ok_or_else(|| "QUIC key has no public half".to_string())?;
+    Ok(CertifiedKey::new(
+        vec![CertificateDer::from(public_key.as_ref().to_vec())],
+        key,
+    ))
+}
+
+/// Peer ID of an Ed25519 raw public key.
+fn peer_id_of(spki: &[u8]) -> Option<PeerId> {
+    let key = spki.strip_prefix(&ED25519_SPKI_PREFIX[..])?;
+    let key = ed25519::PublicKey::decode(key).ok()?;
+    Some(PeerId::from(PublicKey::Ed25519(key)))
+}
+
+/// Peer ID of the key the remote end signed the handshake with.
+fn remote_peer(connection: &quinn::Connection) -> io::Result<PeerId> {
+    connection
+        .peer_identity()
+        .and_then(|identity| identity.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .and_then(|keys| keys.first().and_then(|key| peer_id_of(key.as_ref())))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "QUIC peer presented no Ed25519 identity"))
+}
+
+/// Peer named by a trailing `/p2p/<peer>`, if any.
+fn expected_peer(address: &Multiaddr) -> Option<PeerId> {
+    match address.iter().last()? {
+        Protocol::P2p(hash) => PeerId::from_multihash(hash).ok(),
+        _ => None,
+    }
+}
+
+/// Accepts any Ed25519 identity key and checks the peer holds it. Whether
+/// it is the peer we meant to reach is checked against the dialled address
+/// once the handshake is done.
+#[derive(Debug)]
+struct IdentityVerifier(Arc<CryptoProvider>);
+
+impl IdentityVerifier {
+    fn check_key(key: &CertificateDer<'_>) -> Result<(), rustls::Error> {
+        peer_id_of(key.as_ref())
+            .map(|_| ())
+            .ok_or_else(|| rustls::Error::General("Peer key is not an Ed25519 identity".into()))
+    }
+
+    fn verify_signature(
+        &self,
+        message: &[u8],
+        key: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature_with_raw_key(
+            message,
+            &SubjectPublicKeyInfoDer::from(key.as_ref()),
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+}
+
+impl ServerCertVerifier for IdentityVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Self::check_key(end_entity).map(|()| ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::General("QUIC requires TLS 1.3".into()))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+impl ClientCertVerifier for IdentityVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Self::check_key(end_entity).map(|()| ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Err(rustls::Error::General("QUIC requires TLS 1.3".into()))
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verify_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+
+    fn requires_raw_public_keys(&self) -> bool {
+        true
+    }
+}
+
+/// `/ip4|ip6/<address>/udp/<port>/quic`, optionally followed by `/p2p/<peer>`.
+pub fn socket_addr(address: &Multiaddr) -> Option<SocketAddr> {
+    let mut protocols = address.iter();
+    let ip = match protocols.next()? {
+        Protocol::Ip4(ip) => IpAddr::V4(ip),
+        Protocol::Ip6(ip) => IpAddr::V6(ip),
+        _ => return None,
+    };
+    let port = match protocols.next()? {
+        Protocol::Udp(port) => port,
+        _ => return None,
+    };
+    if protocols.next()? != Protocol::Quic {
+        return None;
+    }
+    match protocols.next() {
+        None | Some(Protocol::P2p(_)) => {}
+        Some(_) => return None,
+    }
+    protocols.next().is_none().then_some(SocketAddr::new(ip, port))
+}
+
+pub fn multiaddr(address: SocketAddr) -> Multiaddr {
+    Multiaddr::empty()
+        .with(address.ip().into())
+        .with(Protocol::Udp(address.port()))
+        .with(Protocol::Quic)
+}
+
+/// The QUIC address on the same IP and port number as a TCP listen address.
+pub fn listen_addr_for(tcp: &Multiaddr) -> Option<Multiaddr> {
+    let mut protocols = tcp.iter();
+    let ip = protocols.next()?;
+    let port = match protocols.next()? {
+        Protocol::Tcp(port) => port,
+        _ => return None,
+    };
+    match ip {
+        Protocol::Ip4(_) | Protocol::Ip6(_) => Some(
+            Multiaddr::empty()
+                .with(ip)
+                .with(Protocol::Udp(port))
+                .with(Protocol::Quic),
+        ),
+        _ => None,
+    }
+}
+
+/// The addresses peers can reach a socket bound to `local` on: every
+/// interface address when bound to an unspecified IP.
+fn listen_addresses(local: SocketAddr) -> Vec<Multiaddr> {
+    if !local.ip().is_unspecified() {
+        return vec![multiaddr(local)];
+    }
+    if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|interface| interface.ip())
+        .filter(|ip| ip.is_ipv4() == local.is_ipv4())
+        .map(|ip| multiaddr(SocketAddr::new(ip, local.port())))
+        .collect()
+}
+
+impl Transport for QuicTransport {
+    type Output = (PeerId, QuicMuxer);
+    type Error = io::Error;
+    type Listener = BoxStream<'static, Result<ListenerEvent<QuicListenerUpgrade, io::Error>, io::Error>>;
+    type ListenerUpgrade = QuicListenerUpgrade;
+    type Dial = BoxFuture<'static, io::Result<(PeerId, QuicMuxer)>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<io::Error>> {
+        let socket = socket_addr(&addr).ok_or(TransportError::MultiaddrNotSupported(addr))?;
+        let endpoint = {
+            let mut state = self.state.lock();
+            let endpoint = quinn::Endpoint::server(state.server_config.clone(), socket)
+                .map_err(TransportError::Other)?;
+            state.listeners.push(endpoint.clone());
+            endpoint
+        };
+        let local = endpoint.local_addr().map_err(TransportError::Other)?;
+        let local_addr = multiaddr(local);
+
+        let announced = stream::iter(
+            listen_addresses(local)
+                .into_iter()
+                .map(|address| Ok(ListenerEvent::NewAddress(address))),
+        );
+        let incoming = stream::unfold(endpoint, move |endpoint| {
+            let local_addr = local_addr.clone();
+            async move {
+                let incoming = endpoint.accept().await?;
+                let remote_addr = multiaddr(incoming.remote_address());
+                let upgrade = async move {
+                    let connection = incoming.await?;
+                    let peer = remote_peer(&connection)?;
+                    Ok((peer, QuicMuxer::new(connection)))
+                }
+                .boxed();
+                let event = ListenerEvent::Upgrade {
+                    upgrade,
+                    local_addr,
+                    remote_addr,
+                };
+                Some((Ok(event), endpoint))
+            }
+        });
+        Ok(announced.chain(incoming).boxed())
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<io::Error>> {
+        let target = socket_addr(&addr).ok_or_else(|| TransportError::MultiaddrNotSupported(addr.clone()))?;
+        let expected = expected_peer(&addr);
+        let (endpoint, config) = self.dialing_endpoint(&target).map_err(TransportError::Other)?;
+        let connecting = endpoint
+            .connect_with(config, target, SERVER_NAME)
+            .map_err(|e| TransportError::Other(io::Error::other(e)))?;
+        Ok(async move {
+            let connection = connecting.await?;
+            let peer = remote_peer(&connection)?;
+            if expected.is_some_and(|expected| expected != peer) {
+                connection.close(0u32.into(), b"");
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("QUIC peer at {} is {}, not the peer dialled", addr, peer),
+                ));
+            }
+            Ok((peer, QuicMuxer::new(connection)))
+        }
+        .boxed())
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        // Dials leave from the listening socket, so the port a peer observed
+        // is the one it can reach us on.
+        libp2p::core::address_translation(listen, observed)
+    }
+}
+
+type OpenStream = BoxFuture<'static, Result<(quinn::SendStream, quinn::RecvStream), quinn::ConnectionError>>;
+
+fn accept_stream(connection: quinn::Connection) -> OpenStream {
+    async move { connection.accept_bi().await }.boxed()
+}
+
+/// Hands out the connection's bidirectional QUIC streams as substreams.
+/// Closes the connection when dropped.
+pub struct QuicMuxer {
+    connection: quinn::Connection,
+    incoming: Mutex<OpenStream>,
+}
+
+impl QuicMuxer {
+    fn new(connection: quinn::Connection) -> Self {
+        let incoming = Mutex::new(accept_stream(connection.clone()));
+        QuicMuxer { connection, incoming }
+    }
+}
+
+impl Drop for QuicMuxer {
+    fn drop(&mut self) {
+        self.connection.close(0u32.into(), b"");
+    }
+}
+
+pub struct QuicSubstream {
+    send: quinn::SendStream,
+    recv: quinn::RecvStream,
+}
+
+impl StreamMuxer for QuicMuxer {
+    type Substream = QuicSubstream;
+    type OutboundSubstream = OpenStream;
+    type Error = io::Error;
+
+    fn poll_event(&self, cx: &mut Context<'_>) -> Poll<io::Result<StreamMuxerEvent<QuicSubstream>>> {
+        let mut incoming = self.incoming.lock();
+        let (send, recv) = ready!(incoming.poll_unpin(cx))?;
+        *incoming = accept_stream(self.connection.clone());
+        Poll::Ready(Ok(StreamMuxerEvent::InboundSubstream(QuicSubstream { send, recv })))
+    }
+
+    fn open_outbound(&self) -> OpenStream {
+        let connection = self.connection.clone();
+        async move { connection.open_bi().await }.boxed()
+    }
+
+    fn poll_outbound(&self, cx: &mut Context<'_>, opening: &mut OpenStream) -> Poll<io::Result<QuicSubstream>> {
+        let (send, recv) = ready!(opening.poll_unpin(cx))?;
+        Poll::Ready(Ok(QuicSubstream { send, recv }))
+    }
+
+    fn destroy_outbound(&self, _opening: OpenStream) {}
+
+    fn read_substream(&self, cx: &mut Context<'_>, s: &mut QuicSubstream, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        AsyncRead::poll_read(Pin::new(&mut s.recv), cx, buf)
+    }
+
+    fn write_substream(&self, cx: &mut Context<'_>, s: &mut QuicSubstream, buf: &[u8]) -> Poll<io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut s.send), cx, buf)
+    }
+
+    fn flush_substream(&self, cx: &mut Context<'_>, s: &mut QuicSubstream) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut s.send), cx)
+    }
+
+    fn shutdown_substream(&self, cx: &mut Context<'_>, s: &mut QuicSubstream) -> Poll<io::Result<()>> {
+        AsyncWrite::poll_close(Pin::new(&mut s.send), cx)
+    }
+
+    fn destroy_substream(&self, _s: QuicSubstream) {}
+
+    fn close(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.connection.close(0u32.into(), b"");
+        Poll::Ready(Ok(()))
+    }
+
+    fn flush_all(&self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ComposedEvent;
+    use crate::TransportMedium;
+    use libp2p::identify::IdentifyEvent;
+    use libp2p::swarm::{Swarm, SwarmEvent};
+
+    #[test]
+    fn parses_quic_addresses_only() {
+        let parse = |address: &str| socket_addr(&address.parse().unwrap());
+        assert_eq!(parse("/ip4/10.0.0.2/udp/4001/quic"), Some("10.0.0.2:4001".parse().unwrap()));
+        assert_eq!(
+            parse("/ip6/::1/udp/4001/quic/p2p/12D3KooWGaYsBhuNjFdkaVYQuoQRE5bsNNgmUw9ByX9AQRUjfeEw"),
+            Some("[::1]:4001".parse().unwrap())
+        );
+        assert_eq!(parse("/ip4/10.0.0.2/udp/4001"), None);
+        assert_eq!(parse("/ip4/10.0.0.2/tcp/4001"), None);
+        assert_eq!(parse("/ip4/10.0.0.2/udp/4001/quic/p2p-circuit"), None);
+    }
+
+    async fn quic_server() -> (Swarm<crate::Behaviour>, Multiaddr) {
+        let (mut server, _, _) = crate::initialize_relay_network(
+            Keypair::generate_ed25519(),
+            "/ip4/127.0.0.1/tcp/0".parse().unwrap(),
+        )
+        .await
+        .expect("server swarm");
+        loop {
+            if let SwarmEvent::NewListenAddr { address, .. } = server.select_next_some().await {
+                if socket_addr(&address).is_some() {
+                    return (server, address);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn peers_connect_over_quic() {
+        let (mut server, quic_address) = quic_server().await;
+        let (mut client, _, _) = crate::initialize_network(Keypair::generate_ed25519(), None)
+            .await
+            .expect("client swarm");
+
+        client.dial_addr(quic_address).expect("dial over QUIC");
+        let server_id = *server.local_peer_id();
+        let endpoint = tokio::time::timeout(Duration::from_secs(20), async {
+            let mut endpoint = None;
+            loop {
+                tokio::select! {
+                    _ = server.select_next_some() => {}
+                    event = client.select_next_some() => match event {
+                        SwarmEvent::ConnectionEstablished { peer_id, endpoint: established, .. } => {
+                            assert_eq!(peer_id, server_id);
+                            endpoint = Some(established);
+                        }
+                        // Identify runs on a substream of its own, so this
+                        // only arrives once streams open over the connection.
+                        SwarmEvent::Behaviour(ComposedEvent::Identify(IdentifyEvent::Received { peer_id, .. }))
+                            if peer_id == server_id =>
+                        {
+                            return endpoint.expect("identified before connecting");
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        })
+        .await
+        .expect("client never identified the server over QUIC");
+
+        assert_eq!(
+            crate::transports::medium_of(endpoint.get_remote_address()),
+            TransportMedium::Quic
+        );
+        let (medium, _) = crate::transports::best_link(client.local_peer_id(), &server_id).expect("handshake was measured");
+        assert_eq!(medium, TransportMedium::Quic);
+    }
+
+    #[tokio::test]
+    async fn refuses_a_peer_other_than_the_one_dialled() {
+        let server_key = Keypair::generate_ed25519();
+        let server_id = PeerId::from(server_key.public());
+        let server = QuicTransport::new(&server_key).expect("server transport");
+        let mut listener = server
+            .listen_on("/ip4/127.0.0.1/udp/0/quic".parse().unwrap())
+            .expect("listen over QUIC");
+        let address = match listener.next().await {
+            Some(Ok(ListenerEvent::NewAddress(address))) => address,
+            _ => panic!("listener announced no address"),
+        };
+        tokio::spawn(async move {
+            let mut accepted = Vec::new();
+            while let Some(Ok(event)) = listener.next().await {
+                if let ListenerEvent::Upgrade { upgrade, .. } = event {
+                    accepted.extend(upgrade.await.ok());
+                }
+            }
+        });
+
+        let client = QuicTransport::new(&Keypair::generate_ed25519()).expect("client transport");
+        let impostor = PeerId::from(Keypair::generate_ed25519().public());
+        let refused = client
+            .clone()
+            .dial(address.clone().with(Protocol::P2p(impostor.into())))
+            .expect("dial over QUIC")
+            .await
+            .err()
+            .expect("connected to a peer other than the one in the address");
+        assert_eq!(refused.kind(), io::ErrorKind::PermissionDenied);
+
+        let (peer, _) = client
+            .dial(address.with(Protocol::P2p(server_id.into())))
+            .expect("dial over QUIC")
+            .await
+            .expect("dial the server");
+        assert_eq!(peer, server_id);
+    }
+}
@@ -18,3 +18,22 @@ where
     rkyv::from_bytes(bytes)
         .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Deserialization error: {}", e)))
 }
+
+/// Reads what is left of a request-response stream, refusing to buffer more
+/// than `limit` bytes of it.
+pub async fn read_limited<T>(io: &mut T, limit: usize) -> std::io::Result<Vec<u8>>
+where
+    T: futures::io::AsyncRead + Unpin,
+{
+    use futures::io::AsyncReadExt;
+
+    let mut buf = Vec::new();
+    io.take(limit as u64 + 1).read_to_end(&mut buf).await?;
+    if buf.len() > limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Message exceeds {} bytes", limit),
+        ));
+    }
+    Ok(buf)
+}
@@ -0,0 +1,157 @@
+use aegis_protocol::history::{BucketDigest, HistoryEvent, HistoryKey};
+use futures::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{ProtocolName, RequestResponseCodec};
+use rkyv::{Archive, Deserialize, Serialize};
+use std::io;
+
+use crate::rkyv_utils::{deserialize, read_limited, serialize};
+
+/// Anti-entropy for chat history. A peer sends the per-day digests of a chat
+/// it shares with the other; the other answers with the keys of its events
+/// on the days that differ, and the first fetches the ones it lacks. Both
+/// ends start a round when they connect, so each pulls what the other has.
+#[derive(Debug, Clone)]
+pub struct SyncProtocol;
+
+pub const SYNC_PROTOCOL_NAME: &str = "/aegis/sync/1";
+
+/// Most events a peer hands out per fetch.
+pub const MAX_FETCH_EVENTS: usize = 64;
+
+/// Largest sync request a peer reads: a summary of every day of a chat's
+/// history, or a fetch of [`MAX_FETCH_EVENTS`] ids.
+pub const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// Largest sync response a peer reads. Peers fill a fetch with events up to
+/// [`FETCH_BUDGET_BYTES`] so it stays below this.
+pub const MAX_RESPONSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// Event payload bytes a peer puts in one fetch response. One request's
+/// worth of room is left for the framing around them.
+pub const FETCH_BUDGET_BYTES: usize = MAX_RESPONSE_BYTES - MAX_REQUEST_BYTES;
+
+impl ProtocolName for SyncProtocol {
+    fn protocol_name(&self) -> &[u8] {
+        SYNC_PROTOCOL_NAME.as_bytes()
+    }
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize, serde::Deserialize)]
+#[archive(check_bytes)]
+pub enum SyncRequest {
+    /// Our digests of `chat_id`, oldest day first.
+    Summary { chat_id: String, buckets: Vec<BucketDigest> },
+    /// Events of `chat_id` we lack, at most [`MAX_FETCH_EVENTS`] of them.
+    Fetch { chat_id: String, ids: Vec<String> },
+}
+
+#[derive(Debug, Clone, Archive, Serialize, Deserialize, serde::Serialize, serde::Deserialize)]
+#[archive(check_bytes)]
+pub enum SyncResponse {
+    /// Every event the responder holds on the days whose digests differ.
+    Keys { chat_id: String, keys: Vec<HistoryKey> },
+    /// The requested events the responder still holds.
+    Events { chat_id: String, events: Vec<HistoryEvent> },
+    Rejected(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncCodec;
+
+#[async_trait::async_trait]
+impl RequestResponseCodec for SyncCodec {
+    type Protocol = SyncProtocol;
+    type Request = SyncRequest;
+    type Response = SyncResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &SyncProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let buf = read_limited(io, MAX_REQUEST_BYTES).await?;
+        deserialize(&buf)
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &SyncProtocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let buf = read_limited(io, MAX_RESPONSE_BYTES).await?;
+        deserialize(&buf)
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &SyncProtocol,
+        io: &mut T,
+        req: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = serialize(&req)?;
+        AsyncWriteExt::write_all(io, &bytes).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &SyncProtocol,
+        io: &mut T,
+        res: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let bytes = serialize(&res)?;
+        AsyncWriteExt::write_all(io, &bytes).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aegis_protocol::history::{summarize, HistoryKind};
+
+    #[test]
+    fn requests_and_responses_round_trip() {
+        let keys = vec![HistoryKey { id: "m1".into(), timestamp_ms: 42 }];
+        let request = SyncRequest::Summary { chat_id: "group".into(), buckets: summarize(&keys) };
+        match deserialize::<SyncRequest>(&serialize(&request).unwrap()).unwrap() {
+            SyncRequest::Summary { chat_id, buckets } => {
+                assert_eq!(chat_id, "group");
+                assert_eq!(buckets, summarize(&keys));
+            }
+            other => panic!("unexpected request {:?}", other),
+        }
+
+        let event = HistoryEvent {
+            id: "m1".into(),
+            chat_id: "group".into(),
+            message_id: "m1".into(),
+            kind: HistoryKind::Message,
+            author: "alice".into(),
+            timestamp_ms: 42,
+            payload: vec![1, 2, 3],
+        };
+        let response = SyncResponse::Events { chat_id: "group".into(), events: vec![event.clone()] };
+        match deserialize::<SyncResponse>(&serialize(&response).unwrap()).unwrap() {
+            SyncResponse::Events { events, .. } => assert_eq!(events, vec![event]),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_requests_are_refused() {
+        let mut io = futures::io::Cursor::new(vec![0u8; MAX_REQUEST_BYTES + 1]);
+        let error = SyncCodec.read_request(&SyncProtocol, &mut io).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
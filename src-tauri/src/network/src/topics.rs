@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use aegis_protocol::TopicScope;
+use libp2p::gossipsub::{Gossipsub, IdentTopic, TopicHash};
+
+pub const GLOBAL_TOPIC: &str = "aegis-global-chat";
+
+pub fn topic_for(scope: &TopicScope) -> IdentTopic {
+    match scope {
+        TopicScope::Global => IdentTopic::new(GLOBAL_TOPIC),
+        TopicScope::Server(server_id) => IdentTopic::new(format!("aegis/server/{}", server_id)),
+        TopicScope::Conversation(conversation_id) => {
+            IdentTopic::new(format!("aegis/conversation/{}", conversation_id))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicSyncReport {
+    pub subscribed: Vec<TopicScope>,
+    pub unsubscribed: Vec<TopicScope>,
+}
+
+/// Gossip topics the swarm is subscribed to, keyed by hash so incoming
+/// messages can be matched back to the server or conversation they belong to.
+/// The global topic is always kept.
+#[derive(Debug, Clone)]
+pub struct TopicRegistry {
+    subscribed: HashMap<TopicHash, TopicScope>,
+}
+
+impl TopicRegistry {
+    pub fn new(gossipsub: &mut Gossipsub) -> Result<Self, libp2p::gossipsub::error::SubscriptionError> {
+        let global = topic_for(&TopicScope::Global);
+        gossipsub.subscribe(&global)?;
+        crate::validation::score_topic(gossipsub, &global);
+        let mut subscribed = HashMap::new();
+        subscribed.insert(global.hash(), TopicScope::Global);
+        Ok(Self { subscribed })
+    }
+
+    pub fn global(&self) -> IdentTopic {
+        topic_for(&TopicScope::Global)
+    }
+
+    pub fn scope_of(&self, hash: &TopicHash) -> Option<&TopicScope> {
+        self.subscribed.get(hash)
+    }
+
+    pub fn is_subscribed(&self, scope: &TopicScope) -> bool {
+        self.subscribed.contains_key(&topic_for(scope).hash())
+    }
+
+    /// Topic to publish a message of `scope` on. Falls back to the global
+    /// topic for audiences we are not part of, e.g. a direct conversation.
+    pub fn publish_topic(&self, scope: &TopicScope) -> IdentTopic {
+        if self.is_subscribed(scope) {
+            topic_for(scope)
+        } else {
+            self.global()
+        }
+    }
+
+    /// Brings the subscriptions in line with `desired`, leaving topics that
+    /// are no longer wanted and joining new ones.
+    pub fn sync(
+        &mut self,
+        gossipsub: &mut Gossipsub,
+        desired: impl IntoIterator<Item = TopicScope>,
+    ) -> TopicSyncReport {
+        let mut wanted: HashMap<TopicHash, TopicScope> = desired
+            .into_iter()
+            .filter(|scope| *scope != TopicScope::Global)
+            .map(|scope| (topic_for(&scope).hash(), scope))
+            .collect();
+        let mut report = TopicSyncReport::default();
+
+        let stale: Vec<TopicHash> = self
+            .subscribed
+            .iter()
+            .filter(|(hash, scope)| **scope != TopicScope::Global && !wanted.contains_key(*hash))
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        for hash in stale {
+            if let Some(scope) = self.subscribed.remove(&hash) {
+                if let Err(error) = gossipsub.unsubscribe(&topic_for(&scope)) {
+                    eprintln!("Failed to leave topic {:?}: {:?}", scope, error);
+                }
+                report.unsubscribed.push(scope);
+            }
+        }
+
+        wanted.retain(|hash, _| !self.subscribed.contains_key(hash));
+        for (hash, scope) in wanted {
+            let topic = topic_for(&scope);
+            match gossipsub.subscribe(&topic) {
+                Ok(_) => {
+                    crate::validation::score_topic(gossipsub, &topic);
+                    self.subscribed.insert(hash, scope.clone());
+                    report.subscribed.push(scope);
+                }
+                Err(error) => eprintln!("Failed to join topic {:?}: {:?}", scope, error),
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::gossipsub::{GossipsubConfig, MessageAuthenticity};
+    use libp2p::identity::Keypair;
+
+    #[test]
+    fn sync_follows_membership_and_keeps_global() {
+        let mut gossipsub = Gossipsub::new(
+            MessageAuthenticity::Signed(Keypair::generate_ed25519()),
+            GossipsubConfig::default(),
+        )
+        .expect("gossipsub");
+        let mut registry = TopicRegistry::new(&mut gossipsub).expect("registry");
+
+        let server = TopicScope::Server("server-1".into());
+        let group = TopicScope::Conversation("group-1".into());
+        let report = registry.sync(&mut gossipsub, vec![server.clone(), group.clone()]);
+        assert_eq!(report.subscribed.len(), 2);
+        assert_eq!(registry.publish_topic(&server).hash(), topic_for(&server).hash());
+
+        let report = registry.sync(&mut gossipsub, vec![group.clone()]);
+        assert_eq!(report.unsubscribed, vec![server.clone()]);
+        assert!(report.subscribed.is_empty());
+        assert_eq!(registry.publish_topic(&server).hash(), registry.global().hash());
+        assert_eq!(
+            registry.scope_of(&registry.global().hash()),
+            Some(&TopicScope::Global)
+        );
+        assert_eq!(gossipsub.topics().count(), 2);
+    }
+
+    #[test]
+    fn scoped_topics_only_admit_their_own_traffic() {
+        let server = TopicScope::Server("server-1".into());
+        assert!(server.admits(&TopicScope::Server("server-1".into())));
+        assert!(!server.admits(&TopicScope::Server("server-2".into())));
+        assert!(!server.admits(&TopicScope::Global));
+        assert!(TopicScope::Global.admits(&server));
+    }
+}
@@ -1,18 +1,34 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io;
 use std::sync::Arc;
+use std::time::Instant;
 
-use libp2p::PeerId;
+use futures::future::FutureExt;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::{Boxed, TransportError};
+use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId, Transport};
 use once_cell::sync::OnceCell;
 use parking_lot::RwLock;
 use tokio::sync::broadcast;
 
+use crate::aerp::{AerpConfig, LinkQuality, RouteMetrics};
+use crate::proxy::ProxyConfig;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub enum TransportMedium {
     Tcp,
+    Quic,
     Bluetooth,
     WifiDirect,
 }
 
+/// Weight of a new handshake measurement against the running average.
+const LINK_SMOOTHING: f64 = 0.35;
+/// Latency recorded for a dial that failed, on top of zero reliability.
+const FAILED_HANDSHAKE_MS: f64 = 5_000.0;
+
 #[derive(Clone, Debug, Default)]
 pub struct TransportSnapshot {
     pub local_peer_id: Option<PeerId>,
@@ -20,6 +36,12 @@ pub struct TransportSnapshot {
     pub wifi_direct_enabled: bool,
     pub bluetooth_peers: Vec<PeerId>,
     pub wifi_direct_peers: Vec<PeerId>,
+    /// SOCKS5 proxy outbound TCP dials go through, if any.
+    pub proxy: Option<ProxyConfig>,
+    pub quic_enabled: bool,
+    /// Peers with a direct connection over each IP transport.
+    pub tcp_peers: Vec<PeerId>,
+    pub quic_peers: Vec<PeerId>,
 }
 
 #[derive(Default)]
@@ -29,6 +51,38 @@ struct TransportInner {
     wifi_direct_enabled: bool,
     bluetooth_peers: HashSet<PeerId>,
     wifi_direct_peers: HashSet<PeerId>,
+    proxy: Option<ProxyConfig>,
+    quic_enabled: bool,
+    /// Direct links of each local peer, so swarms sharing a process do not
+    /// see each other's connections.
+    links: HashMap<PeerId, DirectLinks>,
+}
+
+impl TransportInner {
+    fn connected_over(&self, medium: TransportMedium) -> Vec<PeerId> {
+        self.local_peer_id
+            .and_then(|local| self.links.get(&local))
+            .map(|links| links.connected_over(medium))
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+struct DirectLinks {
+    /// Open direct connections per IP transport and peer.
+    connections: HashMap<(TransportMedium, PeerId), u32>,
+    /// Handshake latency and dial success per IP transport and peer.
+    quality: HashMap<(TransportMedium, PeerId), LinkQuality>,
+}
+
+impl DirectLinks {
+    fn connected_over(&self, medium: TransportMedium) -> Vec<PeerId> {
+        self.connections
+            .keys()
+            .filter(|(connection_medium, _)| *connection_medium == medium)
+            .map(|(_, peer)| *peer)
+            .collect()
+    }
 }
 
 #[derive(Default)]
@@ -71,10 +125,98 @@ impl TransportManager {
         publish_snapshot(self.snapshot());
     }
 
+    pub fn set_proxy(&self, proxy: Option<ProxyConfig>) {
+        self.inner.write().proxy = proxy;
+        publish_snapshot(self.snapshot());
+    }
+
+    pub fn set_quic_enabled(&self, enabled: bool) {
+        self.inner.write().quic_enabled = enabled;
+        publish_snapshot(self.snapshot());
+    }
+
+    pub fn connection_changed(&self, local: PeerId, medium: TransportMedium, peer: PeerId, opened: bool) {
+        let mut guard = self.inner.write();
+        let connections = &mut guard.links.entry(local).or_default().connections;
+        let count = connections.entry((medium, peer)).or_insert(0);
+        if opened {
+            *count += 1;
+        } else {
+            *count = count.saturating_sub(1);
+        }
+        let changed = *count == u32::from(opened);
+        if *count == 0 {
+            connections.remove(&(medium, peer));
+        }
+        drop(guard);
+        if changed {
+            publish_snapshot(self.snapshot());
+        }
+    }
+
+    /// Folds one dial outcome from `local` into the peer's link over
+    /// `medium`.
+    pub fn observe_link(&self, local: PeerId, medium: TransportMedium, peer: PeerId, quality: LinkQuality) {
+        let mut guard = self.inner.write();
+        guard
+            .links
+            .entry(local)
+            .or_default()
+            .quality
+            .entry((medium, peer))
+            .and_modify(|link| {
+                link.latency_ms += LINK_SMOOTHING * (quality.latency_ms - link.latency_ms);
+                link.reliability += LINK_SMOOTHING * (quality.reliability - link.reliability);
+            })
+            .or_insert(quality);
+    }
+
+    /// The transport with the better measured link from `local` to `peer`,
+    /// scored the way the router scores a one-hop route.
+    pub fn best_link(&self, local: &PeerId, peer: &PeerId) -> Option<(TransportMedium, LinkQuality)> {
+        let config = AerpConfig::default();
+        let score = |quality: &LinkQuality| {
+            RouteMetrics {
+                hop_count: 1,
+                total_latency_ms: quality.latency_ms,
+                reliability: quality.reliability,
+            }
+            .score(&config)
+        };
+        let guard = self.inner.read();
+        let links = guard.links.get(local)?;
+        [TransportMedium::Tcp, TransportMedium::Quic]
+            .into_iter()
+            .filter_map(|medium| links.quality.get(&(medium, *peer)).map(|quality| (medium, quality.clone())))
+            .min_by(|(_, a), (_, b)| score(a).total_cmp(&score(b)))
+    }
+
+    /// Peers `local` holds a direct connection to, with the transport it is
+    /// carried over. A peer also seen over Bluetooth or Wi-Fi Direct counts
+    /// as reached over that, since it is the slower link to spare.
+    pub fn neighbours(&self, local: &PeerId) -> Vec<(PeerId, TransportMedium)> {
+        let guard = self.inner.read();
+        let Some(links) = guard.links.get(local) else {
+            return Vec::new();
+        };
+        let mut neighbours: HashMap<PeerId, TransportMedium> = HashMap::new();
+        for (medium, peer) in links.connections.keys() {
+            let medium = if guard.bluetooth_peers.contains(peer) {
+                TransportMedium::Bluetooth
+            } else if guard.wifi_direct_peers.contains(peer) {
+                TransportMedium::WifiDirect
+            } else {
+                *medium
+            };
+            neighbours.entry(*peer).or_insert(medium);
+        }
+        neighbours.into_iter().collect()
+    }
+
     pub fn set_enabled(&self, medium: TransportMedium, enabled: bool) -> bool {
         let mut guard = self.inner.write();
         match medium {
-            TransportMedium::Tcp => false,
+            TransportMedium::Tcp | TransportMedium::Quic => false,
             TransportMedium::Bluetooth => {
                 let changed = guard.bluetooth_enabled != enabled;
                 guard.bluetooth_enabled = enabled;
@@ -105,7 +247,7 @@ impl TransportManager {
     pub fn clear_medium(&self, medium: TransportMedium) {
         let mut guard = self.inner.write();
         match medium {
-            TransportMedium::Tcp => {}
+            TransportMedium::Tcp | TransportMedium::Quic => {}
             TransportMedium::Bluetooth => guard.bluetooth_peers.clear(),
             TransportMedium::WifiDirect => guard.wifi_direct_peers.clear(),
         }
@@ -116,7 +258,7 @@ impl TransportManager {
     pub fn set_peer_presence(&self, medium: TransportMedium, peer_id: PeerId, present: bool) {
         let mut guard = self.inner.write();
         let peers = match medium {
-            TransportMedium::Tcp => return,
+            TransportMedium::Tcp | TransportMedium::Quic => return,
             TransportMedium::Bluetooth => &mut guard.bluetooth_peers,
             TransportMedium::WifiDirect => &mut guard.wifi_direct_peers,
         };
@@ -138,6 +280,10 @@ impl TransportManager {
             wifi_direct_enabled: guard.wifi_direct_enabled,
             bluetooth_peers: guard.bluetooth_peers.iter().cloned().collect(),
             wifi_direct_peers: guard.wifi_direct_peers.iter().cloned().collect(),
+            proxy: guard.proxy,
+            quic_enabled: guard.quic_enabled,
+            tcp_peers: guard.connected_over(TransportMedium::Tcp),
+            quic_peers: guard.connected_over(TransportMedium::Quic),
         }
     }
 
@@ -175,6 +321,10 @@ pub fn register_local_peer(peer_id: PeerId) {
     global_manager().set_local_peer(peer_id);
 }
 
+pub fn set_proxy(proxy: Option<ProxyConfig>) {
+    global_manager().set_proxy(proxy);
+}
+
 pub fn set_peer_presence(medium: TransportMedium, peer_id: PeerId, present: bool) {
     global_manager().set_peer_presence(medium, peer_id, present);
 }
@@ -186,3 +336,128 @@ pub fn clear_medium(medium: TransportMedium) {
 pub fn local_peer_id() -> Option<PeerId> {
     global_manager().local_peer_id()
 }
+
+pub fn set_quic_enabled(enabled: bool) {
+    global_manager().set_quic_enabled(enabled);
+}
+
+/// The IP transport an address uses.
+pub fn medium_of(address: &Multiaddr) -> TransportMedium {
+    if address.iter().any(|protocol| protocol == Protocol::Quic) {
+        TransportMedium::Quic
+    } else {
+        TransportMedium::Tcp
+    }
+}
+
+/// The IP transport of a connection, or `None` for relayed connections,
+/// which are not direct over either.
+fn direct_medium(endpoint: &ConnectedPoint) -> Option<TransportMedium> {
+    let (local, remote) = match endpoint {
+        ConnectedPoint::Dialer { address } => (None, address),
+        ConnectedPoint::Listener { local_addr, send_back_addr } => (Some(local_addr), send_back_addr),
+    };
+    if crate::circuit::is_circuit(remote) || local.is_some_and(crate::circuit::is_circuit) {
+        return None;
+    }
+    Some(medium_of(remote))
+}
+
+pub fn on_connection_established(local: &PeerId, peer: &PeerId, endpoint: &ConnectedPoint) {
+    if let Some(medium) = direct_medium(endpoint) {
+        global_manager().connection_changed(*local, medium, *peer, true);
+    }
+}
+
+pub fn on_connection_closed(local: &PeerId, peer: &PeerId, endpoint: &ConnectedPoint) {
+    if let Some(medium) = direct_medium(endpoint) {
+        global_manager().connection_changed(*local, medium, *peer, false);
+    }
+}
+
+pub fn neighbours(local: &PeerId) -> Vec<(PeerId, TransportMedium)> {
+    global_manager().neighbours(local)
+}
+
+pub fn best_link(local: &PeerId, peer: &PeerId) -> Option<(TransportMedium, LinkQuality)> {
+    global_manager().best_link(local, peer)
+}
+
+/// Narrows `addresses` to the transport with the better measured link from
+/// `local` to `peer`, when we have measured both and the peer offers that
+/// transport. Dialling every address would open a connection per transport,
+/// and requests go out over whichever one libp2p picks.
+pub fn preferred_addresses(local: &PeerId, peer: &PeerId, addresses: Vec<Multiaddr>) -> Vec<Multiaddr> {
+    let Some((medium, _)) = best_link(local, peer) else {
+        return addresses;
+    };
+    let preferred: Vec<Multiaddr> = addresses
+        .iter()
+        .filter(|address| medium_of(address) == medium)
+        .cloned()
+        .collect();
+    if preferred.is_empty() {
+        addresses
+    } else {
+        preferred
+    }
+}
+
+/// Wraps the upgraded swarm transport of `local` to time each outbound
+/// handshake, connect through noise and muxer negotiation, per IP transport.
+#[derive(Clone)]
+pub(crate) struct MeasuredTransport {
+    pub local: PeerId,
+    pub inner: Boxed<(PeerId, StreamMuxerBox)>,
+}
+
+impl Transport for MeasuredTransport {
+    type Output = (PeerId, StreamMuxerBox);
+    type Error = io::Error;
+    type Listener = <Boxed<(PeerId, StreamMuxerBox)> as Transport>::Listener;
+    type ListenerUpgrade = <Boxed<(PeerId, StreamMuxerBox)> as Transport>::ListenerUpgrade;
+    type Dial = futures::future::BoxFuture<'static, io::Result<(PeerId, StreamMuxerBox)>>;
+
+    fn listen_on(self, addr: Multiaddr) -> Result<Self::Listener, TransportError<io::Error>> {
+        self.inner.listen_on(addr)
+    }
+
+    fn dial(self, addr: Multiaddr) -> Result<Self::Dial, TransportError<io::Error>> {
+        let medium = direct_medium(&ConnectedPoint::Dialer { address: addr.clone() });
+        let expected_peer = crate::dht::split_peer_address(&addr).map(|(peer, _)| peer);
+        let local = self.local;
+        let started = Instant::now();
+        let dial = self.inner.dial(addr)?;
+        Ok(async move {
+            let result = dial.await;
+            if let Some(medium) = medium {
+                match &result {
+                    Ok((peer, _)) => global_manager().observe_link(
+                        local,
+                        medium,
+                        *peer,
+                        LinkQuality {
+                            latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+                            reliability: 1.0,
+                        },
+                    ),
+                    Err(_) => {
+                        if let Some(peer) = expected_peer {
+                            let failed = LinkQuality {
+                                latency_ms: FAILED_HANDSHAKE_MS,
+                                reliability: 0.0,
+                            };
+                            global_manager().observe_link(local, medium, peer, failed);
+                        }
+                    }
+                }
+            }
+            result
+        }
+        .boxed())
+    }
+
+    fn address_translation(&self, listen: &Multiaddr, observed: &Multiaddr) -> Option<Multiaddr> {
+        self.inner.address_translation(listen, observed)
+    }
+}
@@ -0,0 +1,359 @@
+//! Checks gossip frames before they are handled or passed on. Gossipsub runs
+//! in manual validation mode, so nothing is forwarded until the validator
+//! accepts it. Frames must carry the signature their message's
+//! [`SignaturePolicy`] asks for, signed within [`REPLAY_WINDOW`] of now and
+//! not seen before. Sealed frames carry no signature; their clock stamp,
+//! which they must have, stands in for the signing time and a digest of
+//! their content tells copies apart. Rejected frames count against the peer that sent them
+//! through gossipsub's invalid message score; once that falls below the
+//! graylist threshold, the peer's gossip is no longer processed.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use aegis_protocol::wire::{self, DecodedMessage, WireError};
+use aegis_protocol::{SignaturePolicy, TopicScope};
+use chrono::{DateTime, TimeZone, Utc};
+use libp2p::gossipsub::{
+    score_parameter_decay, Gossipsub, IdentTopic, MessageAcceptance, PeerScoreParams,
+    PeerScoreThresholds, TopicScoreParams,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::link_state::{self, VerifiedAdvert};
+
+/// How far a frame's signing time may be from our clock. Signed frames seen
+/// within the window are remembered, so a copy replayed later is dropped.
+pub const REPLAY_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// Largest sealed frame accepted, the most a mailbox relay would hold for
+/// one. Nobody vouches for a sealed frame, so this bounds what anyone can
+/// make the mesh carry under no name.
+pub const MAX_SEALED_FRAME_BYTES: usize = crate::mailbox::MAX_REQUEST_BYTES;
+
+/// Most frames remembered for replay checks. Past it, the frames closest to
+/// leaving the replay window are forgotten first.
+const MAX_SEEN_FRAMES: usize = 100_000;
+
+/// How often frames that left the replay window are forgotten.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Score weight of invalid frames, applied to the square of their count:
+/// one puts a peer below the gossip threshold, three below the graylist.
+const INVALID_MESSAGE_WEIGHT: f64 = -10.0;
+
+/// Time it takes for a peer's invalid frames to be forgiven.
+const INVALID_MESSAGE_DECAY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    Wire(WireError),
+    /// Published on a topic other than the one its audience uses.
+    WrongTopic { topic: TopicScope, scope: TopicScope },
+    Unsigned,
+    /// Signed by someone other than the author it names.
+    WrongSigner { signer: String, author: String },
+    /// Signed, or stamped when sealed, too long ago or in the future.
+    Stale { signed_at: DateTime<Utc> },
+    /// A sealed frame without a clock stamp, whose age cannot be told.
+    Unclocked,
+    /// A frame already seen.
+    Replayed,
+    /// A sealed frame over [`MAX_SEALED_FRAME_BYTES`].
+    TooLarge { size: usize },
+    LinkState(String),
+}
+
+impl Rejection {
+    /// Frames from newer peers, repeats of valid frames and frames that
+    /// aged on the way or come from a peer whose clock is off are dropped
+    /// without holding them against the sender; everything else is
+    /// penalised.
+    pub fn acceptance(&self) -> MessageAcceptance {
+        match self {
+            Rejection::Wire(
+                WireError::UnsupportedVersion { .. } | WireError::UnsupportedFeatures { .. },
+            )
+            | Rejection::Stale { .. }
+            | Rejection::Unclocked
+            | Rejection::Replayed => MessageAcceptance::Ignore,
+            _ => MessageAcceptance::Reject,
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Wire(error) => write!(f, "{}", error),
+            Rejection::WrongTopic { topic, scope } => {
+                write!(f, "{:?} message published on {:?}", scope, topic)
+            }
+            Rejection::Unsigned => write!(f, "unsigned frame"),
+            Rejection::WrongSigner { signer, author } => {
+                write!(f, "signed by {} on behalf of {}", signer, author)
+            }
+            Rejection::Stale { signed_at } => write!(f, "signed at {} outside the replay window", signed_at),
+            Rejection::Unclocked => write!(f, "sealed frame without a clock stamp"),
+            Rejection::Replayed => write!(f, "replayed frame"),
+            Rejection::TooLarge { size } => write!(f, "sealed frame of {} bytes", size),
+            Rejection::LinkState(reason) => write!(f, "bad link-state advert: {}", reason),
+        }
+    }
+}
+
+/// Remembers the signatures of recently accepted frames, or the content
+/// digests of sealed ones, until they fall out of the replay window.
+#[derive(Debug, Default)]
+pub struct GossipValidator {
+    seen: HashMap<Vec<u8>, DateTime<Utc>>,
+    next_prune: Option<DateTime<Utc>>,
+}
+
+impl GossipValidator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes a wire frame published on `topic` and checks it against its
+    /// message's signature policy.
+    pub fn validate(
+        &mut self,
+        frame: &[u8],
+        topic: &TopicScope,
+        now: DateTime<Utc>,
+    ) -> Result<DecodedMessage, Rejection> {
+        let decoded = wire::decode(frame).map_err(Rejection::Wire)?;
+        let scope = decoded.message.topic_scope();
+        if !topic.admits(&scope) {
+            return Err(Rejection::WrongTopic { topic: topic.clone(), scope });
+        }
+
+        let policy = decoded.message.signature_policy();
+        let (key, sent_at) = if policy == SignaturePolicy::Sealed {
+            if frame.len() > MAX_SEALED_FRAME_BYTES {
+                return Err(Rejection::TooLarge { size: frame.len() });
+            }
+            // Padding differs between copies, so the digest covers the
+            // message alone.
+            let content = bincode::serialize(&decoded.message)
+                .map_err(|e| Rejection::Wire(WireError::Malformed(e.to_string())))?;
+            let stamped_at = decoded
+                .clock
+                .and_then(|clock| Utc.timestamp_millis_opt(clock.wall_ms).single())
+                .ok_or(Rejection::Unclocked)?;
+            (Sha256::digest(&content).to_vec(), stamped_at)
+        } else {
+            let signer = decoded.signer.as_ref().ok_or(Rejection::Unsigned)?;
+            if policy == SignaturePolicy::Author {
+                let signer = signer.peer_id.to_base58();
+                if let Some(author) = decoded.message.author() {
+                    if author != signer {
+                        return Err(Rejection::WrongSigner { signer, author: author.to_string() });
+                    }
+                }
+            }
+            (signer.signature.clone(), signer.signed_at)
+        };
+
+        let window = chrono::Duration::from_std(REPLAY_WINDOW).expect("replay window fits");
+        if (now - sent_at).abs() > window {
+            return Err(Rejection::Stale { signed_at: sent_at });
+        }
+        if self.seen.get(&key).is_some_and(|expires_at| *expires_at > now) {
+            return Err(Rejection::Replayed);
+        }
+        self.remember(key, sent_at + window, now);
+        Ok(decoded)
+    }
+
+    fn remember(&mut self, key: Vec<u8>, expires_at: DateTime<Utc>, now: DateTime<Utc>) {
+        if self.next_prune.is_none_or(|at| now >= at) || self.seen.len() >= MAX_SEEN_FRAMES {
+            self.prune(now);
+        }
+        self.seen.insert(key, expires_at);
+    }
+
+    /// Forgets frames that left the replay window. If the rest still fill
+    /// the memory, the quarter closest to leaving it goes too, so a full
+    /// memory is not scanned again for every frame.
+    fn prune(&mut self, now: DateTime<Utc>) {
+        self.seen.retain(|_, expires_at| *expires_at > now);
+        if self.seen.len() >= MAX_SEEN_FRAMES {
+            let mut expiries: Vec<_> = self.seen.values().copied().collect();
+            let (_, cutoff, _) = expiries.select_nth_unstable(MAX_SEEN_FRAMES / 4);
+            let cutoff = *cutoff;
+            self.seen.retain(|_, expires_at| *expires_at > cutoff);
+        }
+        let interval = chrono::Duration::from_std(PRUNE_INTERVAL).expect("prune interval fits");
+        self.next_prune = Some(now + interval);
+    }
+
+    /// Link-state adverts are signed by their origin; the router drops
+    /// adverts older than the newest one it holds.
+    pub fn validate_link_state(&self, data: &[u8]) -> Result<VerifiedAdvert, Rejection> {
+        let advert = link_state::decode_advert(data).map_err(|e| Rejection::LinkState(e.to_string()))?;
+        advert.verify().map_err(Rejection::LinkState)
+    }
+}
+
+/// Peer scoring driven by invalid frames only. Mesh delivery counters stay
+/// off: chat traffic is too sparse for them to tell an idle peer from one
+/// withholding messages.
+pub fn peer_score_params() -> (PeerScoreParams, PeerScoreThresholds) {
+    let params = PeerScoreParams {
+        // Peers on one LAN or behind one relay share an address.
+        ip_colocation_factor_weight: 0.0,
+        ..PeerScoreParams::default()
+    };
+    (params, PeerScoreThresholds::default())
+}
+
+pub fn topic_score_params() -> TopicScoreParams {
+    TopicScoreParams {
+        topic_weight: 1.0,
+        time_in_mesh_weight: 0.0,
+        first_message_deliveries_weight: 0.0,
+        mesh_message_deliveries_weight: 0.0,
+        mesh_failure_penalty_weight: 0.0,
+        invalid_message_deliveries_weight: INVALID_MESSAGE_WEIGHT,
+        invalid_message_deliveries_decay: score_parameter_decay(INVALID_MESSAGE_DECAY),
+        ..TopicScoreParams::default()
+    }
+}
+
+/// Scores invalid frames on `topic`. Does nothing on a gossipsub without
+/// peer scoring.
+pub fn score_topic(gossipsub: &mut Gossipsub, topic: &IdentTopic) {
+    let _ = gossipsub.set_topic_params(topic.clone(), topic_score_params());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aegis_protocol::hlc::Hlc;
+    use aegis_protocol::AepMessage;
+    use libp2p::identity::Keypair;
+
+    fn typing(user_id: String) -> AepMessage {
+        AepMessage::TypingIndicator {
+            chat_id: "chat".into(),
+            user_id,
+            is_typing: true,
+            timestamp: Utc::now(),
+            signature: None,
+        }
+    }
+
+    fn signed(message: &AepMessage, keypair: &Keypair, signed_at: DateTime<Utc>) -> Vec<u8> {
+        wire::sign(wire::encode(message).unwrap(), keypair, signed_at).unwrap()
+    }
+
+    #[test]
+    fn enforces_signature_policy_and_replay_window() {
+        let author = Keypair::generate_ed25519();
+        let author_id = author.public().to_peer_id().to_base58();
+        let topic = TopicScope::Conversation("chat".into());
+        let now = Utc::now();
+        let mut validator = GossipValidator::new();
+
+        let frame = signed(&typing(author_id.clone()), &author, now);
+        assert!(validator.validate(&frame, &topic, now).is_ok());
+        assert_eq!(validator.validate(&frame, &topic, now).unwrap_err(), Rejection::Replayed);
+        assert!(matches!(Rejection::Replayed.acceptance(), MessageAcceptance::Ignore));
+
+        let unsigned = wire::encode(&typing(author_id.clone())).unwrap();
+        assert_eq!(validator.validate(&unsigned, &topic, now).unwrap_err(), Rejection::Unsigned);
+
+        let impostor = Keypair::generate_ed25519();
+        let forged = signed(&typing(author_id.clone()), &impostor, now);
+        assert!(matches!(
+            validator.validate(&forged, &topic, now),
+            Err(Rejection::WrongSigner { .. })
+        ));
+
+        let old = now - chrono::Duration::minutes(10);
+        let stale = signed(&typing(author_id.clone()), &author, old);
+        assert!(matches!(
+            validator.validate(&stale, &topic, now),
+            Err(Rejection::Stale { .. })
+        ));
+
+        let elsewhere = TopicScope::Server("server".into());
+        let frame = signed(&typing(author_id), &author, now);
+        assert!(matches!(
+            validator.validate(&frame, &elsewhere, now),
+            Err(Rejection::WrongTopic { .. })
+        ));
+    }
+
+    fn sealed(ciphertext: Vec<u8>) -> AepMessage {
+        AepMessage::SealedChatMessage {
+            message_id: "m1".into(),
+            ephemeral_key: vec![0; 32],
+            recipient_hint: vec![0; 16],
+            ciphertext,
+        }
+    }
+
+    #[test]
+    fn sealed_messages_need_no_signature_but_are_not_replayed() {
+        let now = Utc::now();
+        let mut validator = GossipValidator::new();
+
+        let frame = wire::seal_clocked(bincode::serialize(&sealed(Vec::new())).unwrap(), Hlc::from_timestamp(now)).unwrap();
+        assert!(validator.validate(&frame, &TopicScope::Global, now).is_ok());
+        assert_eq!(validator.validate(&frame, &TopicScope::Global, now).unwrap_err(), Rejection::Replayed);
+        // Padding does not make a copy look new.
+        let padded = wire::pad(frame.clone()).unwrap();
+        assert_ne!(padded, frame);
+        assert_eq!(validator.validate(&padded, &TopicScope::Global, now).unwrap_err(), Rejection::Replayed);
+
+        let old = now - chrono::Duration::minutes(10);
+        let stale = wire::seal_clocked(bincode::serialize(&sealed(vec![1])).unwrap(), Hlc::from_timestamp(old)).unwrap();
+        let rejection = validator.validate(&stale, &TopicScope::Global, now).unwrap_err();
+        assert!(matches!(rejection, Rejection::Stale { .. }));
+        assert!(matches!(rejection.acceptance(), MessageAcceptance::Ignore));
+
+        let unclocked = wire::encode(&sealed(vec![2])).unwrap();
+        let rejection = validator.validate(&unclocked, &TopicScope::Global, now).unwrap_err();
+        assert_eq!(rejection, Rejection::Unclocked);
+        assert!(matches!(rejection.acceptance(), MessageAcceptance::Ignore));
+
+        let huge = wire::encode(&sealed(vec![0; MAX_SEALED_FRAME_BYTES])).unwrap();
+        assert!(matches!(
+            validator.validate(&huge, &TopicScope::Global, now),
+            Err(Rejection::TooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn replay_memory_is_pruned_on_a_timer_and_capped() {
+        let now = Utc::now();
+        let window = chrono::Duration::from_std(REPLAY_WINDOW).unwrap();
+        let mut validator = GossipValidator::new();
+
+        validator.remember(vec![0], now + window, now);
+        validator.remember(vec![1], now + chrono::Duration::seconds(1), now);
+        // Expired frames stay until the next prune, but no longer count.
+        let later = now + chrono::Duration::seconds(2);
+        validator.remember(vec![2], later + window, later);
+        assert_eq!(validator.seen.len(), 3);
+        let pruned_at = now + chrono::Duration::from_std(PRUNE_INTERVAL).unwrap();
+        validator.remember(vec![3], pruned_at + window, pruned_at);
+        assert_eq!(validator.seen.len(), 3);
+        assert!(!validator.seen.contains_key(&vec![1]));
+
+        for i in 0..MAX_SEEN_FRAMES as u64 {
+            let expires_at = pruned_at + window + chrono::Duration::milliseconds(i as i64);
+            validator.remember(i.to_be_bytes().to_vec(), expires_at, pruned_at);
+        }
+        assert!(validator.seen.len() <= MAX_SEEN_FRAMES);
+        assert!(validator.seen.len() > MAX_SEEN_FRAMES / 2);
+        // The frames closest to leaving the window were forgotten first.
+        assert!(!validator.seen.contains_key(&vec![0]));
+        assert!(validator.seen.contains_key(&(MAX_SEEN_FRAMES as u64 - 1).to_be_bytes()[..]));
+    }
+}
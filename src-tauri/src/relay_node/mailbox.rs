@@ -0,0 +1,111 @@
+use aep::database::{self, MailboxDeposit, MailboxQuota};
+use libp2p::PeerId;
+use network::mailbox::{
+    decode_envelope, encode_envelope, envelope_digest, FETCH_BUDGET_BYTES, MAX_REQUEST_BYTES,
+};
+use network::{MailboxItem, MailboxRequest, MailboxResponse, RoutedEnvelope};
+
+/// Most envelopes returned by one fetch, whatever the client asks for.
+const MAX_FETCH: u32 = 64;
+
+/// Answers a mailbox request from `peer`. Peers can only deposit envelopes
+/// they originated, or sealed ones that name no origin, and only fetch or
+/// acknowledge their own mailbox. Deposits count against the depositing
+/// peer's quota either way.
+pub(super) async fn handle_request(
+    db_pool: &sqlx::Pool<sqlx::Sqlite>,
+    quota: &MailboxQuota,
+    peer: &PeerId,
+    request: MailboxRequest,
+) -> MailboxResponse {
+    match request {
+        MailboxRequest::Deposit(envelope) => deposit(db_pool, quota, peer, envelope).await,
+        MailboxRequest::Fetch { limit } => {
+            let fetched = database::fetch_mailbox(
+                db_pool,
+                &peer.to_base58(),
+                chrono::Utc::now(),
+                limit.clamp(1, MAX_FETCH),
+            )
+            .await;
+            match fetched {
+                Ok((envelopes, remaining)) => page(envelopes, remaining),
+                Err(e) => {
+                    eprintln!("Failed to load mailbox for {}: {}", peer, e);
+                    MailboxResponse::Rejected("mailbox unavailable".into())
+                }
+            }
+        }
+        MailboxRequest::Ack { ids } => {
+            match database::acknowledge_mailbox_envelopes(db_pool, &peer.to_base58(), &ids).await {
+                Ok(_) => MailboxResponse::Acknowledged,
+                Err(e) => {
+                    eprintln!("Failed to acknowledge mailbox for {}: {}", peer, e);
+                    MailboxResponse::Rejected("mailbox unavailable".into())
+                }
+            }
+        }
+    }
+}
+
+/// Fills a page with as many of `envelopes` as fit in the client's read
+/// limit; the rest count as remaining and come with the next fetch.
+fn page(envelopes: Vec<database::MailboxEnvelope>, remaining: u32) -> MailboxResponse {
+    let mut items = Vec::with_capacity(envelopes.len());
+    let mut budget = FETCH_BUDGET_BYTES;
+    let mut left_out = 0;
+    for stored in envelopes {
+        if stored.envelope.len() > budget {
+            left_out += 1;
+            continue;
+        }
+        let Ok(envelope) = decode_envelope(&stored.envelope) else { continue; };
+        budget -= stored.envelope.len();
+        items.push(MailboxItem { id: stored.id, envelope });
+    }
+    MailboxResponse::Messages { items, remaining: remaining + left_out }
+}
+
+async fn deposit(
+    db_pool: &sqlx::Pool<sqlx::Sqlite>,
+    quota: &MailboxQuota,
+    peer: &PeerId,
+    envelope: RoutedEnvelope,
+) -> MailboxResponse {
+    let depositor = peer.to_base58();
+    // Sealed frames name no origin; any other must be the depositor's own.
+    if !envelope.origin.is_empty() && envelope.origin != depositor {
+        return MailboxResponse::Rejected("origin does not match depositor".into());
+    }
+    if envelope.destination.parse::<PeerId>().is_err() {
+        return MailboxResponse::Rejected("invalid destination".into());
+    }
+    let Ok(bytes) = encode_envelope(&envelope) else {
+        return MailboxResponse::Rejected("invalid envelope".into());
+    };
+    if bytes.len() > MAX_REQUEST_BYTES {
+        return MailboxResponse::Rejected("envelope too large".into());
+    }
+
+    let stored = database::deposit_mailbox_envelope(
+        db_pool,
+        &envelope.destination,
+        &depositor,
+        &envelope_digest(&envelope),
+        &bytes,
+        quota,
+        chrono::Utc::now(),
+    )
+    .await;
+    match stored {
+        Ok(MailboxDeposit::Stored(_)) => MailboxResponse::Deposited,
+        Ok(MailboxDeposit::QuotaExceeded) => MailboxResponse::Rejected("mailbox full".into()),
+        Ok(MailboxDeposit::DepositorQuotaExceeded) => {
+            MailboxResponse::Rejected("depositor quota exceeded".into())
+        }
+        Err(e) => {
+            eprintln!("Failed to store envelope for {}: {}", envelope.destination, e);
+            MailboxResponse::Rejected("mailbox unavailable".into())
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! Headless relay node: runs the swarm without the Tauri UI, keeps
+//! store-and-forward mailboxes for peers that are offline and carries
+//! `/p2p-circuit` connections for clients that reserved a slot on it.
+//! Started with `aegis --relay`, e.g. on an always-on box on the LAN.
+
+mod mailbox;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use aep::database::{self, MailboxQuota};
+use crypto::identity::Identity;
+use libp2p::futures::StreamExt;
+use libp2p::identify::IdentifyEvent;
+use libp2p::mdns::MdnsEvent;
+use libp2p::request_response::{RequestResponseEvent, RequestResponseMessage};
+use libp2p::swarm::{Swarm, SwarmEvent};
+use libp2p::Multiaddr;
+use network::{ComposedEvent, DirectDeliveryResponse, SyncResponse};
+
+pub const RELAY_FLAG: &str = "--relay";
+
+const DEFAULT_DATA_DIR: &str = "aegis-relay";
+const DEFAULT_LISTEN_ADDR: &str = "/ip4/0.0.0.0/tcp/4001";
+const IDENTITY_FILE: &str = "relay.key";
+const DATABASE_FILE: &str = "relay.db";
+const PURGE_INTERVAL_SECS: u64 = 10 * 60;
+
+#[derive(Debug, Clone)]
+pub struct RelayNodeOptions {
+    pub data_dir: PathBuf,
+    pub listen_addr: Multiaddr,
+    pub quota: MailboxQuota,
+}
+
+impl RelayNodeOptions {
+    /// Parses `--data-dir`, `--listen`, `--max-messages`, `--max-bytes`,
+    /// `--max-depositor-messages`, `--max-depositor-bytes` and `--ttl-hours`;
+    /// anything not given keeps its default.
+    pub fn from_args(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            data_dir: PathBuf::from(DEFAULT_DATA_DIR),
+            listen_addr: DEFAULT_LISTEN_ADDR.parse().map_err(|e| format!("{}", e))?,
+            quota: MailboxQuota::default(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == RELAY_FLAG {
+                continue;
+            }
+            let mut value = || {
+                args.next()
+                    .cloned()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--data-dir" => options.data_dir = PathBuf::from(value()?),
+                "--listen" => {
+                    options.listen_addr = value()?
+                        .parse()
+                        .map_err(|e| format!("Invalid listen address: {}", e))?;
+                }
+                "--max-messages" => options.quota.max_messages = parse_number(arg, &value()?)?,
+                "--max-bytes" => options.quota.max_bytes = parse_number(arg, &value()?)?,
+                "--max-depositor-messages" => {
+                    options.quota.max_depositor_messages = parse_number(arg, &value()?)?
+                }
+                "--max-depositor-bytes" => {
+                    options.quota.max_depositor_bytes = parse_number(arg, &value()?)?
+                }
+                "--ttl-hours" => {
+                    let hours: i64 = parse_number(arg, &value()?)?;
+                    options.quota.ttl = chrono::Duration::hours(hours.max(1));
+                }
+                other => return Err(format!("Unknown relay option: {}", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+pub async fn run_from_args(args: &[String]) -> Result<(), String> {
+    run(RelayNodeOptions::from_args(args)?).await
+}
+
+pub async fn run(options: RelayNodeOptions) -> Result<(), String> {
+    std::fs::create_dir_all(&options.data_dir).map_err(|e| e.to_string())?;
+    let identity = load_or_create_identity(&options.data_dir.join(IDENTITY_FILE))?;
+    let db_pool = database::initialize_db(options.data_dir.join(DATABASE_FILE))
+        .await
+        .map_err(|e| format!("Failed to open relay database: {}", e))?;
+
+    let (mut swarm, _topics, _router) =
+        network::initialize_relay_network(identity.keypair().clone(), options.listen_addr.clone())
+            .await
+            .map_err(|e| format!("Failed to initialize network: {}", e))?;
+
+    eprintln!("Relay node {} starting", identity.peer_id_base58());
+    let mut purge = tokio::time::interval(Duration::from_secs(PURGE_INTERVAL_SECS));
+
+    loop {
+        tokio::select! {
+            event = swarm.select_next_some() => {
+                handle_event(&mut swarm, &db_pool, &options.quota, event).await;
+            }
+            _ = purge.tick() => {
+                match database::purge_expired_mailbox_envelopes(&db_pool, chrono::Utc::now()).await {
+                    Ok(0) => {}
+                    Ok(removed) => eprintln!("Dropped {} expired mailbox envelopes", removed),
+                    Err(e) => eprintln!("Failed to purge mailboxes: {}", e),
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                eprintln!("Relay node shutting down");
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn handle_event<E>(
+    swarm: &mut Swarm<network::Behaviour>,
+    db_pool: &sqlx::Pool<sqlx::Sqlite>,
+    quota: &MailboxQuota,
+    event: SwarmEvent<ComposedEvent, E>,
+) {
+    match event {
+        SwarmEvent::NewListenAddr { address, .. } => {
+            eprintln!("Listening on {}/p2p/{}", address, swarm.local_peer_id());
+        }
+        SwarmEvent::Behaviour(ComposedEvent::Mailbox(RequestResponseEvent::Message {
+            peer,
+            message: RequestResponseMessage::Request { request, channel, .. },
+        })) => {
+            let response = mailbox::handle_request(db_pool, quota, &peer, request).await;
+            if swarm.behaviour_mut().mailbox.send_response(channel, response).is_err() {
+                eprintln!("Mailbox response channel to {} closed", peer);
+            }
+        }
+        SwarmEvent::Behaviour(ComposedEvent::Direct(RequestResponseEvent::Message {
+            message: RequestResponseMessage::Request { channel, .. },
+            ..
+        })) => {
+            // Senders fall back to a mailbox deposit.
+            let rejected = DirectDeliveryResponse::Rejected("relay nodes do not route".into());
+            let _ = swarm.behaviour_mut().direct.send_response(channel, rejected);
+        }
+        SwarmEvent::Behaviour(ComposedEvent::Sync(RequestResponseEvent::Message {
+            message: RequestResponseMessage::Request { channel, .. },
+            ..
+        })) => {
+            let rejected = SyncResponse::Rejected("relay nodes keep no chat history".into());
+            let _ = swarm.behaviour_mut().sync.send_response(channel, rejected);
+        }
+        // Lets clients use the relay as a DHT bootstrap peer.
+        SwarmEvent::Behaviour(ComposedEvent::Identify(IdentifyEvent::Received { peer_id, info })) => {
+            network::dht::record_identified(swarm, &peer_id, &info.listen_addrs);
+        }
+        SwarmEvent::Behaviour(ComposedEvent::Mdns(MdnsEvent::Discovered(list))) => {
+            for (_, address) in list {
+                let _ = swarm.dial_addr(address);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Keeps the relay's peer ID stable across restarts so clients can register
+/// its address.
+fn load_or_create_identity(path: &Path) -> Result<Identity, String> {
+    if path.exists() {
+        let secret = std::fs::read(path).map_err(|e| e.to_string())?;
+        return Identity::from_secret_bytes(secret).map_err(|e| e.to_string());
+    }
+
+    let identity = Identity::generate();
+    let secret = identity
+        .to_secret_bytes()
+        .ok_or_else(|| "Generated identity has no secret key".to_string())?;
+    write_secret(path, &secret).map_err(|e| e.to_string())?;
+    Ok(identity)
+}
+
+#[cfg(unix)]
+fn write_secret(path: &Path, secret: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(secret)
+}
+
+#[cfg(not(unix))]
+fn write_secret(path: &Path, secret: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, secret)
+}
@@ -1,32 +1,137 @@
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
+//! Sortable 128-bit ids. An id is 48 bits of milliseconds since the Unix
+//! epoch followed by 80 bits of entropy, written as 32 lowercase hex digits,
+//! so ids sort as text in the order they were made. The entropy is random,
+//! so ids made by different peers in the same millisecond do not collide;
+//! ids made in the same millisecond by one process count up from the first.
 
-pub struct Scu128 {
-    counter: AtomicU64,
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ENTROPY_BITS: u32 = 80;
+const ENTROPY_MASK: u128 = (1 << ENTROPY_BITS) - 1;
+const TIMESTAMP_MASK: u64 = (1 << 48) - 1;
+
+/// Length of an id's text form.
+pub const ENCODED_LEN: usize = 32;
+
+/// Timestamp and entropy of the last id this process made.
+static LAST: Mutex<(u64, u128)> = Mutex::new((0, 0));
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Scu128Error {
+    InvalidLength(usize),
+    InvalidCharacter(char),
 }
 
+impl fmt::Display for Scu128Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scu128Error::InvalidLength(len) => {
+                write!(f, "id is {} characters long, expected {}", len, ENCODED_LEN)
+            }
+            Scu128Error::InvalidCharacter(ch) => {
+                write!(f, "id contains {:?}, expected lowercase hex digits", ch)
+            }
+        }
+    }
+}
+
+impl Error for Scu128Error {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Scu128Id(u128);
+
+impl Scu128Id {
+    pub fn from_parts(timestamp_ms: u64, entropy: u128) -> Self {
+        Scu128Id(
+            ((timestamp_ms & TIMESTAMP_MASK) as u128) << ENTROPY_BITS | (entropy & ENTROPY_MASK),
+        )
+    }
+
+    pub fn parse(value: &str) -> Result<Self, Scu128Error> {
+        if value.len() != ENCODED_LEN {
+            return Err(Scu128Error::InvalidLength(value.len()));
+        }
+        if let Some(ch) = value.chars().find(|ch| !matches!(ch, '0'..='9' | 'a'..='f')) {
+            return Err(Scu128Error::InvalidCharacter(ch));
+        }
+        u128::from_str_radix(value, 16)
+            .map(Scu128Id)
+            .map_err(|_| Scu128Error::InvalidLength(value.len()))
+    }
+
+    /// Checks that `value` is the text form of an id.
+    pub fn validate(value: &str) -> Result<(), Scu128Error> {
+        Self::parse(value).map(|_| ())
+    }
+
+    /// Milliseconds since the Unix epoch when the id was made. Ids from
+    /// before entropy was added kept their timestamp in the upper 64 bits,
+    /// which leaves their first 16 bits zero.
+    pub fn timestamp_ms(&self) -> u64 {
+        if self.0 >> 112 == 0 {
+            (self.0 >> 64) as u64
+        } else {
+            (self.0 >> ENTROPY_BITS) as u64
+        }
+    }
+
+    pub fn timestamp(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp_ms())
+    }
+
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+}
+
+impl fmt::Display for Scu128Id {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl FromStr for Scu128Id {
+    type Err = Scu128Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::parse(value)
+    }
+}
+
+pub struct Scu128;
+
 impl Scu128 {
     pub fn new() -> Self {
-        Scu128 {
-            counter: AtomicU64::new(0),
-        }
+        Scu128
     }
 
-    pub fn generate(&self) -> String {
-        let timestamp = SystemTime::now()
+    /// A new id, later than every id this process made before it even if
+    /// the system clock steps back.
+    pub fn generate_id(&self) -> Scu128Id {
+        let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .expect("Time went backwards")
-            .as_millis();
-
-        let counter = self.counter.fetch_add(1, Ordering::SeqCst);
-        let high_bits = timestamp;
-        let low_bits = counter;
+            .as_millis() as u64;
 
-        format!("{:016x}{:016x}", high_bits, low_bits)
+        let mut last = LAST.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (last_ms, last_entropy) = *last;
+        let next = if now > last_ms {
+            (now, random_entropy())
+        } else if last_entropy < ENTROPY_MASK {
+            (last_ms, last_entropy + 1)
+        } else {
+            (last_ms + 1, random_entropy())
+        };
+        *last = next;
+        Scu128Id::from_parts(next.0, next.1)
     }
 
-    pub fn to_string(&self) -> String {
-        self.generate()
+    pub fn generate(&self) -> String {
+        self.generate_id().to_string()
     }
 }
 
@@ -35,3 +140,46 @@ impl Default for Scu128 {
         Self::new()
     }
 }
+
+/// Random entropy, with its top bit clear so the ids made in the same
+/// millisecond have room to count up.
+fn random_entropy() -> u128 {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes[6..]).expect("OS randomness unavailable");
+    u128::from_be_bytes(bytes) & (ENTROPY_MASK >> 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_unique_and_sort_in_creation_order() {
+        let generator = Scu128::new();
+        let ids: Vec<String> = (0..1_000).map(|_| generator.generate()).collect();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, ids);
+
+        let first = Scu128Id::parse(&ids[0]).unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        assert!(now - first.timestamp_ms() < 60_000);
+    }
+
+    #[test]
+    fn parsing_checks_the_text_form() {
+        let id = Scu128Id::from_parts(1_700_000_000_000, 42);
+        assert_eq!(Scu128Id::parse(&id.to_string()), Ok(id));
+        assert_eq!(id.timestamp_ms(), 1_700_000_000_000);
+
+        assert_eq!(Scu128Id::validate("abc"), Err(Scu128Error::InvalidLength(3)));
+        let upper = id.to_string().to_uppercase();
+        assert!(matches!(Scu128Id::validate(&upper), Err(Scu128Error::InvalidCharacter(_))));
+        assert!(Scu128Id::validate("+0000000000000000000000000000000").is_err());
+
+        // Ids from before entropy was added still parse and keep their time.
+        let legacy = format!("{:016x}{:016x}", 1_700_000_000_000u64, 7u64);
+        assert_eq!(Scu128Id::parse(&legacy).unwrap().timestamp_ms(), 1_700_000_000_000);
+    }
+}
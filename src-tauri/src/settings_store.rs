@@ -1,7 +1,7 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use aegis_shared_types::{RelayRecord, TrustedDeviceRecord};
+use aegis_shared_types::{DeniedPeerRecord, PrivacySettings, ProxySettings, RelayRecord, TrustedDeviceRecord};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
@@ -13,6 +13,17 @@ pub struct PersistedSettings {
     pub relays: Vec<RelayRecord>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub trusted_devices: Vec<TrustedDeviceRecord>,
+    /// Multiaddrs ending in `/p2p/<peer id>` that seed the DHT.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bootstrap_peers: Vec<String>,
+    /// Read at startup; changing it needs a restart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxySettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub privacy: Option<PrivacySettings>,
+    /// Blocked and banned peers kept off the swarm.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_peers: Vec<DeniedPeerRecord>,
 }
 
 impl PersistedSettings {
@@ -0,0 +1,137 @@
+use aep::database;
+use chrono::Utc;
+use crypto::at_rest::{AtRestKey, BLOB_MAGIC};
+use scu128::Scu128;
+use std::collections::HashMap;
+use tempfile::tempdir;
+
+fn message(chat_id: &str, content: &str) -> database::Message {
+    database::Message {
+        id: Scu128::new().generate(),
+        chat_id: chat_id.to_string(),
+        sender_id: "sender-1".to_string(),
+        content: content.to_string(),
+        timestamp: Utc::now(),
+        read: false,
+        pinned: false,
+        attachments: Vec::new(),
+        reactions: HashMap::new(),
+        reply_to_message_id: None,
+        reply_snapshot_author: None,
+        reply_snapshot_snippet: None,
+        edited_at: None,
+        edited_by: None,
+        expires_at: None,
+        delivery_status: None,
+        thread_id: None,
+        thread: None,
+    }
+}
+
+// The at-rest key is process-global, so the whole lifecycle runs in one test.
+#[tokio::test]
+async fn legacy_rows_are_sealed_and_stay_searchable() {
+    let dir = tempdir().expect("temp dir");
+    let pool = database::initialize_db(dir.path().join("at_rest.db"))
+        .await
+        .expect("init db");
+    sqlx::query("INSERT INTO users (id, username, avatar, is_online) VALUES ('sender-1', 'Sender', '', 0)")
+        .execute(&pool)
+        .await
+        .expect("insert sender");
+
+    let legacy = message("chat-room", "meet at the docks");
+    let attachment = database::AttachmentWithData {
+        metadata: database::Attachment {
+            id: Scu128::new().generate(),
+            message_id: legacy.id.clone(),
+            name: "map.txt".to_string(),
+            content_type: Some("text/plain".to_string()),
+            size: 8,
+        },
+        data: b"pier-nine".to_vec(),
+    };
+    // Plaintext that happens to look sealed must still read back as written.
+    let lookalike = database::AttachmentWithData {
+        metadata: database::Attachment {
+            id: Scu128::new().generate(),
+            message_id: legacy.id.clone(),
+            name: "magic.bin".to_string(),
+            content_type: None,
+            size: 12,
+        },
+        data: [&BLOB_MAGIC[..], b"not sealed"].concat(),
+    };
+    database::insert_message(&pool, &legacy, &[attachment.clone(), lookalike.clone()])
+        .await
+        .expect("insert legacy message");
+    let bytes = database::get_attachment_data(&pool, &lookalike.metadata.id)
+        .await
+        .expect("fetch lookalike");
+    assert_eq!(bytes, lookalike.data);
+
+    database::set_at_rest_key(Some(AtRestKey::from_master(&[3u8; 32])));
+    let report = database::encrypt_existing_rows(&pool)
+        .await
+        .expect("encrypt existing rows");
+    assert_eq!(report.messages, 1);
+    assert_eq!(report.attachments, 2);
+
+    let fresh = message("chat-room", "bring the docks key");
+    database::insert_message(&pool, &fresh, &[])
+        .await
+        .expect("insert sealed message");
+
+    let raw_contents: Vec<String> = sqlx::query_scalar("SELECT content FROM messages")
+        .fetch_all(&pool)
+        .await
+        .expect("raw contents");
+    assert!(raw_contents.iter().all(|c| !c.contains("docks")));
+    let raw_index: Vec<String> = sqlx::query_scalar("SELECT content FROM messages_fts")
+        .fetch_all(&pool)
+        .await
+        .expect("raw index");
+    assert!(raw_index.iter().all(|c| !c.contains("docks")));
+    let raw_data: Vec<u8> = sqlx::query_scalar("SELECT data FROM attachments WHERE id = ?")
+        .bind(&attachment.metadata.id)
+        .fetch_one(&pool)
+        .await
+        .expect("raw attachment");
+    assert_ne!(raw_data, attachment.data);
+
+    let fetched = database::get_messages_for_chat(&pool, "chat-room", 10, 0)
+        .await
+        .expect("fetch messages");
+    assert_eq!(fetched[0].content, legacy.content);
+    assert_eq!(fetched[1].content, fresh.content);
+    let bytes = database::get_attachment_data(&pool, &attachment.metadata.id)
+        .await
+        .expect("fetch attachment");
+    assert_eq!(bytes, attachment.data);
+    let bytes = database::get_attachment_data(&pool, &lookalike.metadata.id)
+        .await
+        .expect("fetch sealed lookalike");
+    assert_eq!(bytes, lookalike.data);
+
+    let hits = database::search_messages(
+        &pool,
+        "chat-room",
+        Some("DOCKS"),
+        10,
+        0,
+        None,
+        None,
+        None,
+        None,
+    )
+    .await
+    .expect("search");
+    assert_eq!(hits.len(), 2);
+
+    let again = database::encrypt_existing_rows(&pool)
+        .await
+        .expect("second pass");
+    assert_eq!(again, database::AtRestMigrationReport::default());
+
+    database::set_at_rest_key(None);
+}
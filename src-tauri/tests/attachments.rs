@@ -11,13 +11,13 @@ async fn message_with_attachments_round_trips() {
 
     let pool = database::initialize_db(db_path).await.expect("init db");
 
-    let message_id = Scu128::new().to_string();
+    let message_id = Scu128::new().generate();
     let chat_id = "chat-room".to_string();
     let sender_id = "sender-1".to_string();
     let content = "hello with files".to_string();
     let attachment_bytes: Vec<u8> = b"file-bytes".to_vec();
 
-    let attachment_id = Scu128::new().to_string();
+    let attachment_id = Scu128::new().generate();
     let attachment = database::Attachment {
         id: attachment_id.clone(),
         message_id: message_id.clone(),
@@ -47,6 +47,9 @@ async fn message_with_attachments_round_trips() {
         edited_at: None,
         edited_by: None,
         expires_at: None,
+        delivery_status: None,
+        thread_id: None,
+        thread: None,
     };
 
     database::insert_message(&pool, &message, &[attachment_with_data])
@@ -83,10 +86,10 @@ async fn message_reply_snapshot_round_trips() {
 
     let pool = database::initialize_db(db_path).await.expect("init db");
 
-    let message_id = Scu128::new().to_string();
+    let message_id = Scu128::new().generate();
     let chat_id = "chat-reply".to_string();
     let sender_id = "replier".to_string();
-    let reply_target = Scu128::new().to_string();
+    let reply_target = Scu128::new().generate();
 
     let message = database::Message {
         id: message_id.clone(),
@@ -104,6 +107,9 @@ async fn message_reply_snapshot_round_trips() {
         edited_at: None,
         edited_by: None,
         expires_at: None,
+        delivery_status: None,
+        thread_id: None,
+        thread: None,
     };
 
     database::insert_message(&pool, &message, &[])
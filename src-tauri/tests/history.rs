@@ -0,0 +1,166 @@
+use aegis_protocol::history::{HistoryEvent, HistoryKey, HistoryKind};
+use aep::database::{self, HistoryRecord};
+use sqlx::{Pool, Sqlite};
+use tempfile::tempdir;
+
+fn event(id: &str, message_id: &str, kind: HistoryKind, author: &str, timestamp_ms: i64) -> HistoryEvent {
+    HistoryEvent {
+        id: id.to_string(),
+        chat_id: "group".to_string(),
+        message_id: message_id.to_string(),
+        kind,
+        author: author.to_string(),
+        timestamp_ms,
+        payload: id.as_bytes().to_vec(),
+    }
+}
+
+async fn keys(pool: &Pool<Sqlite>) -> Vec<(String, i64)> {
+    database::history_keys(pool, "group")
+        .await
+        .expect("history keys")
+        .into_iter()
+        .map(|key| (key.id, key.timestamp_ms))
+        .collect()
+}
+
+#[tokio::test]
+async fn events_keep_the_earliest_time_and_reconcile_with_peers() {
+    let dir = tempdir().expect("temp dir");
+    let pool = database::initialize_db(dir.path().join("history.db"))
+        .await
+        .expect("init db");
+
+    let message = event("m1", "m1", HistoryKind::Message, "alice", 2_000);
+    assert_eq!(database::record_history_event(&pool, &message).await.unwrap(), HistoryRecord::New);
+    assert_eq!(
+        database::record_history_event(&pool, &HistoryEvent { timestamp_ms: 3_000, ..message.clone() })
+            .await
+            .unwrap(),
+        HistoryRecord::Known
+    );
+    assert_eq!(
+        database::record_history_event(&pool, &HistoryEvent { timestamp_ms: 1_000, ..message.clone() })
+            .await
+            .unwrap(),
+        HistoryRecord::Known
+    );
+    assert_eq!(keys(&pool).await, vec![("m1".to_string(), 1_000)]);
+    assert_eq!(
+        database::history_message_time(&pool, "group", "m1").await.unwrap(),
+        Some(1_000)
+    );
+
+    let peer_keys = vec![
+        HistoryKey { id: "m1".to_string(), timestamp_ms: 500 },
+        HistoryKey { id: "m2".to_string(), timestamp_ms: 4_000 },
+    ];
+    let missing = database::reconcile_history_keys(&pool, "group", &peer_keys)
+        .await
+        .expect("reconcile");
+    assert_eq!(missing, vec!["m2".to_string()]);
+    assert_eq!(keys(&pool).await, vec![("m1".to_string(), 500)]);
+
+    // Keys are only matched within their own chat.
+    let missing = database::reconcile_history_keys(&pool, "other", &peer_keys[..1])
+        .await
+        .expect("reconcile other chat");
+    assert_eq!(missing, vec!["m1".to_string()]);
+
+    let events = database::get_history_events(&pool, "group", &["m1".to_string(), "m2".to_string()])
+        .await
+        .expect("get events");
+    assert_eq!(events, vec![HistoryEvent { timestamp_ms: 500, ..message }]);
+}
+
+#[tokio::test]
+async fn deletions_by_the_author_leave_tombstones() {
+    let dir = tempdir().expect("temp dir");
+    let pool = database::initialize_db(dir.path().join("history.db"))
+        .await
+        .expect("init db");
+
+    let record = |event: HistoryEvent| {
+        let pool = pool.clone();
+        async move { database::record_history_event(&pool, &event).await.expect("record") }
+    };
+    assert_eq!(record(event("m1", "m1", HistoryKind::Message, "alice", 1_000)).await, HistoryRecord::New);
+    assert_eq!(record(event("r1", "m1", HistoryKind::Reaction, "bob", 1_000)).await, HistoryRecord::New);
+
+    // Only the author's deletion erases the message.
+    assert_eq!(record(event("d1", "m1", HistoryKind::Delete, "bob", 1_000)).await, HistoryRecord::New);
+    assert_eq!(keys(&pool).await.len(), 3);
+
+    assert_eq!(record(event("d2", "m1", HistoryKind::Delete, "alice", 1_000)).await, HistoryRecord::New);
+    let ids: Vec<String> = keys(&pool).await.into_iter().map(|(id, _)| id).collect();
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&"d1".to_string()) && ids.contains(&"d2".to_string()));
+    assert!(database::get_history_events(&pool, "group", &["m1".to_string(), "r1".to_string()])
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Tombstones still count as held, so peers are not asked for them again,
+    // and events arriving after the deletion are stored as tombstones too.
+    let peer_keys = vec![HistoryKey { id: "m1".to_string(), timestamp_ms: 1_000 }];
+    assert!(database::reconcile_history_keys(&pool, "group", &peer_keys).await.unwrap().is_empty());
+    assert_eq!(record(event("e1", "m1", HistoryKind::Edit, "alice", 2_000)).await, HistoryRecord::Deleted);
+    assert_eq!(record(event("m1", "m1", HistoryKind::Message, "alice", 1_000)).await, HistoryRecord::Known);
+
+    // A message whose deletion reached us first is a tombstone from the start.
+    assert_eq!(record(event("d3", "m2", HistoryKind::Delete, "alice", 1_000)).await, HistoryRecord::New);
+    assert_eq!(record(event("m2", "m2", HistoryKind::Message, "alice", 1_000)).await, HistoryRecord::Deleted);
+}
+
+#[tokio::test]
+async fn private_channels_are_kept_to_the_owner() {
+    let dir = tempdir().expect("temp dir");
+    let pool = database::initialize_db(dir.path().join("history.db"))
+        .await
+        .expect("init db");
+
+    for user in ["owner", "member"] {
+        sqlx::query("INSERT INTO users (id, username, avatar, is_online) VALUES (?, ?, '', 0)")
+            .bind(user)
+            .bind(user)
+            .execute(&pool)
+            .await
+            .expect("insert user");
+    }
+    sqlx::query("INSERT INTO servers (id, name, owner_id) VALUES ('server', 'Server', 'owner')")
+        .execute(&pool)
+        .await
+        .expect("insert server");
+    for user in ["owner", "member"] {
+        sqlx::query("INSERT INTO server_members (server_id, user_id) VALUES ('server', ?)")
+            .bind(user)
+            .execute(&pool)
+            .await
+            .expect("insert member");
+    }
+    for (channel, private) in [("general", false), ("staff", true)] {
+        sqlx::query("INSERT INTO channels (id, server_id, name, channel_type, private) VALUES (?, 'server', ?, 'text', ?)")
+            .bind(channel)
+            .bind(channel)
+            .bind(private)
+            .execute(&pool)
+            .await
+            .expect("insert channel");
+    }
+
+    let may_access = |chat_id: &'static str, user_id: &'static str| {
+        let pool = pool.clone();
+        async move { database::may_access_chat(&pool, chat_id, user_id).await.expect("access") }
+    };
+    assert!(may_access("general", "member").await);
+    assert!(may_access("server", "member").await);
+    assert!(!may_access("staff", "member").await);
+    assert!(may_access("staff", "owner").await);
+    assert!(!may_access("general", "stranger").await);
+
+    let mut shared = database::shared_chats(&pool, "owner", "member")
+        .await
+        .expect("shared chats");
+    shared.sort();
+    assert_eq!(shared, vec!["general".to_string(), "server".to_string()]);
+}
@@ -0,0 +1,141 @@
+use aep::database::{self, MailboxDeposit, MailboxQuota};
+use chrono::{Duration, Utc};
+use tempfile::tempdir;
+
+async fn setup_pool() -> (tempfile::TempDir, sqlx::Pool<sqlx::Sqlite>) {
+    let dir = tempdir().expect("temp dir");
+    let pool = database::initialize_db(dir.path().join("relay.db"))
+        .await
+        .expect("init db");
+    (dir, pool)
+}
+
+fn quota() -> MailboxQuota {
+    MailboxQuota {
+        max_messages: 2,
+        max_bytes: 64,
+        max_depositor_messages: 3,
+        max_depositor_bytes: 96,
+        ttl: Duration::hours(1),
+    }
+}
+
+#[tokio::test]
+async fn mailboxes_enforce_quota_per_recipient_and_dedupe_deposits() {
+    let (_dir, pool) = setup_pool().await;
+    let now = Utc::now();
+    let quota = quota();
+
+    let first = database::deposit_mailbox_envelope(&pool, "bob", "alice", "d1", b"one", &quota, now)
+        .await
+        .expect("first deposit");
+    let MailboxDeposit::Stored(first_id) = first else { panic!("first deposit rejected") };
+    let repeated = database::deposit_mailbox_envelope(&pool, "bob", "alice", "d1", b"one", &quota, now)
+        .await
+        .expect("repeated deposit");
+    assert_eq!(repeated, MailboxDeposit::Stored(first_id));
+
+    database::deposit_mailbox_envelope(&pool, "bob", "alice", "d2", b"two", &quota, now)
+        .await
+        .expect("second deposit");
+    let full = database::deposit_mailbox_envelope(&pool, "bob", "carol", "d3", b"three", &quota, now)
+        .await
+        .expect("third deposit");
+    assert_eq!(full, MailboxDeposit::QuotaExceeded);
+
+    let oversized = database::deposit_mailbox_envelope(&pool, "dave", "alice", "d4", &[0u8; 65], &quota, now)
+        .await
+        .expect("oversized deposit");
+    assert_eq!(oversized, MailboxDeposit::QuotaExceeded);
+    let other = database::deposit_mailbox_envelope(&pool, "dave", "alice", "d4", b"four", &quota, now)
+        .await
+        .expect("other recipient");
+    assert!(matches!(other, MailboxDeposit::Stored(_)));
+
+    let (page, remaining) = database::fetch_mailbox(&pool, "bob", now, 1)
+        .await
+        .expect("fetch page");
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].envelope, b"one");
+    assert_eq!(page[0].depositor, "alice");
+    assert_eq!(remaining, 1);
+
+    let removed = database::acknowledge_mailbox_envelopes(&pool, "dave", &[first_id])
+        .await
+        .expect("foreign ack");
+    assert_eq!(removed, 0);
+    let removed = database::acknowledge_mailbox_envelopes(&pool, "bob", &[first_id])
+        .await
+        .expect("ack");
+    assert_eq!(removed, 1);
+    let (rest, remaining) = database::fetch_mailbox(&pool, "bob", now, 10)
+        .await
+        .expect("fetch rest");
+    assert_eq!(rest.len(), 1);
+    assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn depositors_cannot_take_more_than_their_share() {
+    let (_dir, pool) = setup_pool().await;
+    let now = Utc::now();
+    let quota = quota();
+
+    let mut stored = Vec::new();
+    for (recipient, digest) in [("bob", "d1"), ("bob", "d2"), ("carol", "d3")] {
+        let deposit = database::deposit_mailbox_envelope(&pool, recipient, "mallory", digest, b"spam", &quota, now)
+            .await
+            .expect("deposit");
+        let MailboxDeposit::Stored(id) = deposit else { panic!("deposit rejected") };
+        stored.push(id);
+    }
+    let over = database::deposit_mailbox_envelope(&pool, "dave", "mallory", "d4", b"spam", &quota, now)
+        .await
+        .expect("deposit over share");
+    assert_eq!(over, MailboxDeposit::DepositorQuotaExceeded);
+    let other = database::deposit_mailbox_envelope(&pool, "dave", "alice", "d5", b"hello", &quota, now)
+        .await
+        .expect("other depositor");
+    assert!(matches!(other, MailboxDeposit::Stored(_)));
+
+    database::acknowledge_mailbox_envelopes(&pool, "carol", &[stored[2]])
+        .await
+        .expect("ack");
+    let freed = database::deposit_mailbox_envelope(&pool, "dave", "mallory", "d4", b"spam", &quota, now)
+        .await
+        .expect("deposit after ack");
+    assert!(matches!(freed, MailboxDeposit::Stored(_)));
+}
+
+#[tokio::test]
+async fn expired_envelopes_are_hidden_and_free_their_quota() {
+    let (_dir, pool) = setup_pool().await;
+    let quota = quota();
+    let then = Utc::now() - Duration::hours(2);
+
+    for digest in ["d1", "d2"] {
+        database::deposit_mailbox_envelope(&pool, "bob", "alice", digest, b"old", &quota, then)
+            .await
+            .expect("old deposit");
+    }
+
+    let now = Utc::now();
+    let (visible, remaining) = database::fetch_mailbox(&pool, "bob", now, 10)
+        .await
+        .expect("fetch");
+    assert!(visible.is_empty());
+    assert_eq!(remaining, 0);
+
+    let fresh = database::deposit_mailbox_envelope(&pool, "bob", "alice", "d3", b"new", &quota, now)
+        .await
+        .expect("fresh deposit");
+    assert!(matches!(fresh, MailboxDeposit::Stored(_)));
+
+    database::deposit_mailbox_envelope(&pool, "carol", "alice", "d4", b"old", &quota, then)
+        .await
+        .expect("old deposit for carol");
+    let purged = database::purge_expired_mailbox_envelopes(&pool, now)
+        .await
+        .expect("purge");
+    assert_eq!(purged, 1);
+}
@@ -31,9 +31,9 @@ async fn report_message_command_persists_report_with_context() {
 
     let reporter_identity = Identity::generate();
     let reporter_id = reporter_identity.peer_id().to_base58();
-    let author_id = Scu128::new().to_string();
-    let message_id = Scu128::new().to_string();
-    let chat_id = Scu128::new().to_string();
+    let author_id = Scu128::new().generate();
+    let message_id = Scu128::new().generate();
+    let chat_id = Scu128::new().generate();
     let message_timestamp = Utc::now().to_rfc3339();
 
     sqlx::query!(
@@ -97,7 +97,7 @@ async fn report_message_command_persists_report_with_context() {
 
     let state_container = AppStateContainer(Arc::new(AsyncMutex::new(Some(state))));
 
-        let surrounding_ids = vec![Scu128::new().to_string(), Scu128::new().to_string()];
+        let surrounding_ids = vec![Scu128::new().generate(), Scu128::new().generate()];
 
     report_message_internal(
         ReportMessagePayload {
@@ -0,0 +1,156 @@
+use aep::database::{self, DeliveryStatus};
+use chrono::{Duration, Utc};
+use scu128::Scu128;
+use std::collections::HashMap;
+use tempfile::tempdir;
+
+async fn setup_pool() -> (tempfile::TempDir, sqlx::Pool<sqlx::Sqlite>) {
+    let dir = tempdir().expect("temp dir");
+    let pool = database::initialize_db(dir.path().join("outbox.db"))
+        .await
+        .expect("init db");
+    sqlx::query("INSERT INTO users (id, username, avatar, is_online) VALUES ('me', 'Me', '', 1)")
+        .execute(&pool)
+        .await
+        .expect("insert user");
+    (dir, pool)
+}
+
+async fn insert_sent_message(pool: &sqlx::Pool<sqlx::Sqlite>, chat_id: &str) -> String {
+    let message = database::Message {
+        id: Scu128::new().generate(),
+        chat_id: chat_id.to_string(),
+        sender_id: "me".to_string(),
+        content: "hello".to_string(),
+        timestamp: Utc::now(),
+        read: false,
+        pinned: false,
+        attachments: Vec::new(),
+        reactions: HashMap::new(),
+        reply_to_message_id: None,
+        reply_snapshot_author: None,
+        reply_snapshot_snippet: None,
+        edited_at: None,
+        edited_by: None,
+        expires_at: None,
+        delivery_status: None,
+        thread_id: None,
+        thread: None,
+    };
+    database::insert_message(pool, &message, &[])
+        .await
+        .expect("insert message");
+    message.id
+}
+
+#[tokio::test]
+async fn outbox_entries_back_off_until_acknowledged() {
+    let (_dir, pool) = setup_pool().await;
+    let message_id = insert_sent_message(&pool, "peer-a").await;
+
+    database::enqueue_outbox(&pool, "peer-a", Some(&message_id), true, b"frame-a", None)
+        .await
+        .expect("enqueue direct");
+    database::enqueue_outbox(&pool, database::BROADCAST_DESTINATION, None, false, b"frame-b", None)
+        .await
+        .expect("enqueue broadcast");
+
+    let now = Utc::now();
+    let due = database::due_outbox_entries(&pool, now, 10)
+        .await
+        .expect("due entries");
+    assert_eq!(due.len(), 2);
+    let direct = due.iter().find(|e| e.destination == "peer-a").expect("direct entry");
+    assert_eq!(direct.payload, b"frame-a");
+    assert!(direct.awaits_ack);
+
+    let attempts = database::record_outbox_attempt(&pool, direct, now)
+        .await
+        .expect("record attempt");
+    assert_eq!(attempts, 1);
+    let still_due = database::due_outbox_entries(&pool, now, 10)
+        .await
+        .expect("due after attempt");
+    assert_eq!(still_due.len(), 1);
+    let later = database::due_outbox_entries(&pool, now + database::outbox_backoff(1), 10)
+        .await
+        .expect("due after backoff");
+    assert_eq!(later.len(), 2);
+
+    assert!(database::outbox_backoff(3) > database::outbox_backoff(2));
+    assert_eq!(database::outbox_backoff(40), Duration::minutes(15));
+
+    database::expedite_outbox_destination(&pool, "peer-a")
+        .await
+        .expect("expedite");
+    assert_eq!(
+        database::due_outbox_entries(&pool, Utc::now(), 10)
+            .await
+            .expect("due after expedite")
+            .len(),
+        2
+    );
+
+    let removed = database::remove_acknowledged_outbox_entries(&pool, "peer-a", &message_id)
+        .await
+        .expect("ack");
+    assert_eq!(removed, 1);
+}
+
+#[tokio::test]
+async fn ephemeral_entries_expire_unsent() {
+    let (_dir, pool) = setup_pool().await;
+    let expires_at = Utc::now() + Duration::seconds(15);
+
+    database::enqueue_outbox(
+        &pool,
+        database::BROADCAST_DESTINATION,
+        None,
+        false,
+        b"typing",
+        Some(expires_at),
+    )
+    .await
+    .expect("enqueue ephemeral");
+    database::enqueue_outbox(&pool, database::BROADCAST_DESTINATION, None, false, b"edit", None)
+        .await
+        .expect("enqueue durable");
+    assert_eq!(database::due_outbox_entries(&pool, Utc::now(), 10).await.unwrap().len(), 2);
+
+    let due = database::due_outbox_entries(&pool, expires_at, 10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].payload, b"edit");
+    assert_eq!(database::remove_expired_outbox_entries(&pool, expires_at).await.unwrap(), 1);
+    assert_eq!(database::remove_expired_outbox_entries(&pool, expires_at).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn delivery_status_only_moves_forward_and_shows_in_messages() {
+    let (_dir, pool) = setup_pool().await;
+    let message_id = insert_sent_message(&pool, "group-1").await;
+
+    for (recipient, status) in [
+        ("peer-a", DeliveryStatus::Pending),
+        ("peer-b", DeliveryStatus::Pending),
+        ("peer-a", DeliveryStatus::Read),
+        ("peer-b", DeliveryStatus::Delivered),
+    ] {
+        database::record_delivery_status(&pool, &message_id, recipient, status)
+            .await
+            .expect("record status");
+    }
+
+    let regressed = database::record_delivery_status(&pool, &message_id, "peer-a", DeliveryStatus::Sent)
+        .await
+        .expect("stale status");
+    assert!(!regressed);
+    let failed = database::record_delivery_status(&pool, &message_id, "peer-b", DeliveryStatus::Failed)
+        .await
+        .expect("late failure");
+    assert!(!failed);
+
+    let messages = database::get_messages_for_chat(&pool, "group-1", 10, 0)
+        .await
+        .expect("fetch messages");
+    assert_eq!(messages[0].delivery_status, Some(DeliveryStatus::Delivered));
+}
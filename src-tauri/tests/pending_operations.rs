@@ -0,0 +1,51 @@
+use aep::database::{self, PendingBuffer, PendingQuota};
+use chrono::{Duration, Utc};
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn buffered_operations_are_capped_per_author_and_in_total() {
+    let dir = tempdir().expect("temp dir");
+    let pool = database::initialize_db(dir.path().join("pending.db"))
+        .await
+        .expect("init db");
+    let quota = PendingQuota {
+        max_operations: 3,
+        max_bytes: 1024,
+        max_author_operations: 2,
+        max_author_bytes: 64,
+    };
+    let now = Utc::now();
+
+    let buffer = |message_id: &'static str, author: &'static str, operation: Vec<u8>| {
+        let pool = pool.clone();
+        let quota = quota.clone();
+        async move {
+            database::buffer_message_operation(&pool, message_id, author, &operation, &quota, now)
+                .await
+                .expect("buffer operation")
+        }
+    };
+    assert_eq!(buffer("m1", "alice", vec![1; 16]).await, PendingBuffer::Held);
+    assert_eq!(buffer("m2", "alice", vec![2; 64]).await, PendingBuffer::AuthorQuotaExceeded);
+    assert_eq!(buffer("m2", "alice", vec![2; 16]).await, PendingBuffer::Held);
+    assert_eq!(buffer("m3", "alice", vec![3; 16]).await, PendingBuffer::AuthorQuotaExceeded);
+    assert_eq!(buffer("m3", "bob", vec![3; 16]).await, PendingBuffer::Held);
+    assert_eq!(buffer("m4", "carol", vec![4; 16]).await, PendingBuffer::QuotaExceeded);
+
+    // Taking an author's operations frees their share.
+    assert_eq!(
+        database::take_message_operations(&pool, "m1").await.expect("take"),
+        vec![vec![1; 16]]
+    );
+    assert_eq!(buffer("m4", "alice", vec![4; 16]).await, PendingBuffer::Held);
+
+    // Expired operations make room for new ones.
+    let later = now + database::PENDING_OPERATION_TTL + Duration::seconds(1);
+    assert_eq!(
+        database::buffer_message_operation(&pool, "m5", "carol", &[5], &quota, later)
+            .await
+            .expect("buffer operation"),
+        PendingBuffer::Held
+    );
+    assert!(database::take_message_operations(&pool, "m2").await.expect("take").is_empty());
+}
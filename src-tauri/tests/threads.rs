@@ -0,0 +1,181 @@
+use aegis_protocol::hlc::Hlc;
+use aep::database;
+use chrono::{DateTime, Duration, Utc};
+use scu128::Scu128;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use tempfile::tempdir;
+
+const CHAT_ID: &str = "chat-threads";
+
+async fn setup() -> (tempfile::TempDir, Pool<Sqlite>) {
+    let dir = tempdir().expect("temp dir");
+    let pool = database::initialize_db(dir.path().join("threads.db"))
+        .await
+        .expect("init db");
+    sqlx::query("INSERT INTO users (id, username, avatar, is_online) VALUES ('sender-1', 'Sender', '', 0)")
+        .execute(&pool)
+        .await
+        .expect("insert sender");
+    (dir, pool)
+}
+
+async fn post(
+    pool: &Pool<Sqlite>,
+    reply_to: Option<&str>,
+    timestamp: DateTime<Utc>,
+) -> String {
+    let message = database::Message {
+        id: Scu128::new().generate(),
+        chat_id: CHAT_ID.to_string(),
+        sender_id: "sender-1".to_string(),
+        content: "hello".to_string(),
+        timestamp,
+        read: false,
+        pinned: false,
+        attachments: Vec::new(),
+        reactions: HashMap::new(),
+        reply_to_message_id: reply_to.map(str::to_string),
+        reply_snapshot_author: None,
+        reply_snapshot_snippet: None,
+        edited_at: None,
+        edited_by: None,
+        expires_at: None,
+        delivery_status: None,
+        thread_id: None,
+        thread: None,
+    };
+    database::insert_message(pool, &message, &[])
+        .await
+        .expect("insert message");
+    message.id
+}
+
+async fn thread(pool: &Pool<Sqlite>, root: &str) -> database::MessageThread {
+    database::get_thread(pool, root)
+        .await
+        .expect("get thread")
+        .expect("thread exists")
+}
+
+#[tokio::test]
+async fn threads_take_in_replies_stored_before_and_after_they_open() {
+    let (_dir, pool) = setup().await;
+    let now = Utc::now();
+
+    let root = post(&pool, None, now).await;
+    let reply = post(&pool, Some(&root), now).await;
+    let nested = post(&pool, Some(&reply), now).await;
+    assert!(database::create_thread(&pool, &root, CHAT_ID, "sender-1", Some("Plans"), now)
+        .await
+        .expect("create thread"));
+    assert_eq!(thread(&pool, &root).await.reply_count, 2);
+    for message_id in [&reply, &nested] {
+        assert_eq!(
+            database::thread_of_message(&pool, message_id).await.unwrap().as_deref(),
+            Some(root.as_str())
+        );
+    }
+
+    let later = post(&pool, Some(&nested), now).await;
+    assert_eq!(thread(&pool, &root).await.reply_count, 3);
+    assert_eq!(
+        database::thread_of_message(&pool, &later).await.unwrap().as_deref(),
+        Some(root.as_str())
+    );
+
+    // Replies in a thread cannot open threads of their own, and a thread
+    // is only opened once.
+    assert!(!database::create_thread(&pool, &reply, CHAT_ID, "sender-1", None, now)
+        .await
+        .unwrap());
+    assert!(!database::create_thread(&pool, &root, CHAT_ID, "sender-1", None, now)
+        .await
+        .unwrap());
+    assert_eq!(thread(&pool, &root).await.title.as_deref(), Some("Plans"));
+
+    // A thread opened on a plain reply keeps the replies below it.
+    let other = post(&pool, None, now).await;
+    let answer = post(&pool, Some(&other), now).await;
+    let follow_up = post(&pool, Some(&answer), now).await;
+    assert!(database::create_thread(&pool, &answer, CHAT_ID, "sender-1", None, now)
+        .await
+        .unwrap());
+    assert!(database::create_thread(&pool, &other, CHAT_ID, "sender-1", None, now)
+        .await
+        .unwrap());
+    assert_eq!(thread(&pool, &other).await.reply_count, 0);
+    assert_eq!(
+        database::thread_of_message(&pool, &follow_up).await.unwrap().as_deref(),
+        Some(answer.as_str())
+    );
+}
+
+#[tokio::test]
+async fn deleted_messages_leave_their_threads() {
+    let (_dir, pool) = setup().await;
+    let now = Utc::now();
+
+    let root = post(&pool, None, now).await;
+    let first = post(&pool, Some(&root), now).await;
+    let second = post(&pool, Some(&root), now).await;
+    database::create_thread(&pool, &root, CHAT_ID, "sender-1", None, now)
+        .await
+        .expect("create thread");
+    database::follow_thread(&pool, &root, "sender-1", now)
+        .await
+        .expect("follow thread");
+
+    database::delete_message(&pool, &second).await.expect("delete reply");
+    assert_eq!(thread(&pool, &root).await.reply_count, 1);
+
+    // A deleted root takes its thread and followers with it; its replies
+    // stay as plain replies.
+    database::delete_message(&pool, &root).await.expect("delete root");
+    assert!(database::get_thread(&pool, &root).await.unwrap().is_none());
+    assert!(!database::is_following_thread(&pool, &root, "sender-1").await.unwrap());
+    assert_eq!(database::thread_of_message(&pool, &first).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn threads_are_archived_by_the_stamps_of_their_replies() {
+    let (_dir, pool) = setup().await;
+    let now = Utc::now();
+    let long_ago = now - database::THREAD_ARCHIVE_AFTER - Duration::days(1);
+
+    let root = post(&pool, None, long_ago).await;
+    database::create_thread(&pool, &root, CHAT_ID, "sender-1", None, long_ago)
+        .await
+        .expect("create thread");
+    post(&pool, Some(&root), long_ago).await;
+    assert_eq!(
+        database::archive_inactive_threads(&pool, now).await.unwrap(),
+        vec![root.clone()]
+    );
+    assert!(thread(&pool, &root).await.archived);
+    assert!(database::archive_inactive_threads(&pool, now).await.unwrap().is_empty());
+
+    // A reply backdated by its sender still counts as activity once our
+    // clock stamps it, and brings the thread back.
+    let backdated = post(&pool, Some(&root), long_ago).await;
+    assert!(thread(&pool, &root).await.archived);
+    database::set_message_hlc(&pool, &backdated, Hlc::from_timestamp(now))
+        .await
+        .expect("stamp reply");
+    let revived = thread(&pool, &root).await;
+    assert!(!revived.archived);
+    assert_eq!(revived.reply_count, 2);
+    assert_eq!(revived.last_activity_at.timestamp_millis(), now.timestamp_millis());
+    assert!(database::archive_inactive_threads(&pool, now).await.unwrap().is_empty());
+
+    // A reply dated far ahead cannot keep the thread active for longer.
+    post(&pool, Some(&root), now + Duration::days(365)).await;
+    let ahead = thread(&pool, &root).await;
+    assert!(ahead.last_activity_at < now + Duration::days(1));
+    assert_eq!(
+        database::archive_inactive_threads(&pool, now + database::THREAD_ARCHIVE_AFTER + Duration::days(1))
+            .await
+            .unwrap(),
+        vec![root]
+    );
+}
@@ -117,6 +117,14 @@ export interface EncryptedChatMessage {
   signature?: BytePayload;
 }
 
+/** Direct message whose sender is only revealed to the recipient. */
+export interface SealedChatMessage {
+  message_id: string;
+  ephemeral_key: BytePayload;
+  recipient_hint: BytePayload;
+  ciphertext: BytePayload;
+}
+
 export interface PrekeyBundle {
   user_id: string;
   bundle: BytePayload;
@@ -337,6 +345,7 @@ export interface VoiceChannelPresenceDelta {
 export interface AepMessage {
   ChatMessage?: ChatMessage;
   EncryptedChatMessage?: EncryptedChatMessage;
+  SealedChatMessage?: SealedChatMessage;
   PrekeyBundle?: PrekeyBundle;
   GroupKeyUpdate?: GroupKeyUpdate;
   EncryptedGroupMessage?: EncryptedGroupMessage;
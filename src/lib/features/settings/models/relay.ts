@@ -2,6 +2,8 @@ export type RelayScope = "global" | "server";
 
 export type RelayStatus = "unknown" | "healthy" | "degraded" | "offline";
 
+export type RelayReservationState = "pending" | "active" | "failed";
+
 export interface RelayConfig {
   id: string;
   label: string;
@@ -37,6 +39,9 @@ export interface RelaySnapshot {
   latencyMs?: number | null;
   uptimePercent?: number | null;
   error?: string | null;
+  reservation?: RelayReservationState | null;
+  circuitAddress?: string | null;
+  relayedConnections?: number | null;
 }
 
 export interface RelayHealthUpdate {
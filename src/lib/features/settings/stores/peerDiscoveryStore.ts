@@ -0,0 +1,118 @@
+import { writable, get, type Readable } from "svelte/store";
+import { getInvoke } from "../../../services/tauri";
+import { toasts } from "../../../stores/ToastStore";
+
+interface PeerDiscoveryState {
+  bootstrapPeers: string[];
+  loading: boolean;
+  error: string | null;
+}
+
+interface PeerDiscoveryStore extends Readable<PeerDiscoveryState> {
+  initialize: () => Promise<void>;
+  saveBootstrapPeers: (peers: string[]) => Promise<boolean>;
+  dialPeer: (target: string) => Promise<string | null>;
+  getContactCard: () => Promise<string | null>;
+}
+
+const initialState: PeerDiscoveryState = {
+  bootstrapPeers: [],
+  loading: false,
+  error: null,
+};
+
+const desktopOnlyMessage = "Peer discovery requires the desktop client.";
+
+const errorMessage = (error: unknown, fallback: string) =>
+  typeof error === "string" ? error : fallback;
+
+function createPeerDiscoveryStore(): PeerDiscoveryStore {
+  const { subscribe, update, set } =
+    writable<PeerDiscoveryState>(initialState);
+
+  const initialize = async () => {
+    if (get({ subscribe }).loading) {
+      return;
+    }
+    const invoke = await getInvoke();
+    if (!invoke) {
+      return;
+    }
+
+    update((state) => ({ ...state, loading: true }));
+    try {
+      const peers = await invoke<string[]>("get_bootstrap_peers");
+      set({ bootstrapPeers: peers ?? [], loading: false, error: null });
+    } catch (error) {
+      console.error("Failed to load bootstrap peers", error);
+      update((state) => ({
+        ...state,
+        loading: false,
+        error: "Failed to load bootstrap peers.",
+      }));
+    }
+  };
+
+  const saveBootstrapPeers = async (peers: string[]) => {
+    const invoke = await getInvoke();
+    if (!invoke) {
+      toasts.addToast(desktopOnlyMessage, "warning");
+      return false;
+    }
+
+    try {
+      const saved = await invoke<string[]>("set_bootstrap_peers", { peers });
+      set({ bootstrapPeers: saved ?? [], loading: false, error: null });
+      return true;
+    } catch (error) {
+      console.error("Failed to save bootstrap peers", error);
+      toasts.addToast(
+        errorMessage(error, "Failed to save bootstrap peers."),
+        "error",
+      );
+      return false;
+    }
+  };
+
+  const dialPeer = async (target: string) => {
+    const invoke = await getInvoke();
+    if (!invoke) {
+      toasts.addToast(desktopOnlyMessage, "warning");
+      return null;
+    }
+
+    try {
+      return await invoke<string>("dial_peer", { target });
+    } catch (error) {
+      console.error("Failed to dial peer", error);
+      toasts.addToast(errorMessage(error, "Failed to dial peer."), "error");
+      return null;
+    }
+  };
+
+  const getContactCard = async () => {
+    const invoke = await getInvoke();
+    if (!invoke) {
+      toasts.addToast(desktopOnlyMessage, "warning");
+      return null;
+    }
+
+    try {
+      return await invoke<string>("get_contact_card");
+    } catch (error) {
+      console.error("Failed to build contact card", error);
+      toasts.addToast("Failed to build contact card.", "error");
+      return null;
+    }
+  };
+
+  return {
+    subscribe,
+    initialize,
+    saveBootstrapPeers,
+    dialPeer,
+    getContactCard,
+  };
+}
+
+export const peerDiscoveryStore = createPeerDiscoveryStore();
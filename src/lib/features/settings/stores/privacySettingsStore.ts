@@ -0,0 +1,107 @@
+import { writable, get, type Readable } from "svelte/store";
+import { getInvoke } from "../../../services/tauri";
+import { toasts } from "../../../stores/ToastStore";
+
+/** Cover traffic bytes per second each transport may carry. */
+export interface CoverTrafficBudgets {
+  tcp: number;
+  quic: number;
+  wifiDirect: number;
+  bluetooth: number;
+}
+
+export interface PrivacySettings {
+  padFrames: boolean;
+  coverTraffic: boolean;
+  coverBudgets: CoverTrafficBudgets;
+}
+
+interface PrivacySettingsState {
+  privacy: PrivacySettings;
+  loading: boolean;
+  error: string | null;
+}
+
+interface PrivacySettingsStore extends Readable<PrivacySettingsState> {
+  initialize: () => Promise<void>;
+  savePrivacy: (privacy: PrivacySettings) => Promise<boolean>;
+}
+
+const defaultPrivacy: PrivacySettings = {
+  padFrames: false,
+  coverTraffic: false,
+  coverBudgets: {
+    tcp: 4096,
+    quic: 4096,
+    wifiDirect: 2048,
+    bluetooth: 128,
+  },
+};
+
+const initialState: PrivacySettingsState = {
+  privacy: defaultPrivacy,
+  loading: false,
+  error: null,
+};
+
+function createPrivacySettingsStore(): PrivacySettingsStore {
+  const { subscribe, update, set } =
+    writable<PrivacySettingsState>(initialState);
+
+  const initialize = async () => {
+    if (get({ subscribe }).loading) {
+      return;
+    }
+    const invoke = await getInvoke();
+    if (!invoke) {
+      return;
+    }
+
+    update((state) => ({ ...state, loading: true }));
+    try {
+      const privacy = await invoke<PrivacySettings>("get_privacy_settings");
+      set({ privacy: privacy ?? defaultPrivacy, loading: false, error: null });
+    } catch (error) {
+      console.error("Failed to load privacy settings", error);
+      update((state) => ({
+        ...state,
+        loading: false,
+        error: "Failed to load privacy settings.",
+      }));
+    }
+  };
+
+  const savePrivacy = async (privacy: PrivacySettings) => {
+    const invoke = await getInvoke();
+    if (!invoke) {
+      toasts.addToast(
+        "Privacy settings require the desktop client.",
+        "warning",
+      );
+      return false;
+    }
+
+    try {
+      const saved = await invoke<PrivacySettings>("set_privacy_settings", {
+        privacy,
+      });
+      set({ privacy: saved ?? privacy, loading: false, error: null });
+      return true;
+    } catch (error) {
+      console.error("Failed to save privacy settings", error);
+      toasts.addToast(
+        typeof error === "string" ? error : "Failed to save privacy settings.",
+        "error",
+      );
+      return false;
+    }
+  };
+
+  return {
+    subscribe,
+    initialize,
+    savePrivacy,
+  };
+}
+
+export const privacySettingsStore = createPrivacySettingsStore();
@@ -0,0 +1,84 @@
+import { writable, get, type Readable } from "svelte/store";
+import { getInvoke } from "../../../services/tauri";
+import { toasts } from "../../../stores/ToastStore";
+
+export interface ProxySettings {
+  address: string;
+  strict: boolean;
+}
+
+interface ProxySettingsState {
+  proxy: ProxySettings | null;
+  loading: boolean;
+  error: string | null;
+}
+
+interface ProxySettingsStore extends Readable<ProxySettingsState> {
+  initialize: () => Promise<void>;
+  saveProxy: (proxy: ProxySettings | null) => Promise<boolean>;
+}
+
+const initialState: ProxySettingsState = {
+  proxy: null,
+  loading: false,
+  error: null,
+};
+
+function createProxySettingsStore(): ProxySettingsStore {
+  const { subscribe, update, set } =
+    writable<ProxySettingsState>(initialState);
+
+  const initialize = async () => {
+    if (get({ subscribe }).loading) {
+      return;
+    }
+    const invoke = await getInvoke();
+    if (!invoke) {
+      return;
+    }
+
+    update((state) => ({ ...state, loading: true }));
+    try {
+      const proxy = await invoke<ProxySettings | null>("get_proxy_settings");
+      set({ proxy: proxy ?? null, loading: false, error: null });
+    } catch (error) {
+      console.error("Failed to load proxy settings", error);
+      update((state) => ({
+        ...state,
+        loading: false,
+        error: "Failed to load proxy settings.",
+      }));
+    }
+  };
+
+  const saveProxy = async (proxy: ProxySettings | null) => {
+    const invoke = await getInvoke();
+    if (!invoke) {
+      toasts.addToast("Proxy settings require the desktop client.", "warning");
+      return false;
+    }
+
+    try {
+      const saved = await invoke<ProxySettings | null>("set_proxy_settings", {
+        proxy,
+      });
+      set({ proxy: saved ?? null, loading: false, error: null });
+      return true;
+    } catch (error) {
+      console.error("Failed to save proxy settings", error);
+      toasts.addToast(
+        typeof error === "string" ? error : "Failed to save proxy settings.",
+        "error",
+      );
+      return false;
+    }
+  };
+
+  return {
+    subscribe,
+    initialize,
+    saveProxy,
+  };
+}
+
+export const proxySettingsStore = createProxySettingsStore();
@@ -132,6 +132,15 @@ export function createPostAuthBootstrap({
       });
     });
 
+    // Sealed direct messages only name their sender once opened, so the
+    // backend reports which conversation gained a message.
+    await register<{ chatId: string; messageId: string }>(
+      "direct-message-received",
+      (event) => {
+        void chatStore.refreshChatFromStorage(event.payload.chatId, "dm");
+      },
+    );
+
     await register<MessageReadReceiptEvent>("message-read", (event) => {
       chatStore.handleReadReceipt(event.payload);
     });
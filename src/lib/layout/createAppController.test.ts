@@ -50,6 +50,11 @@ vi.mock("$lib/stores/connectivityStore", async () => {
       bluetoothPeers: [],
       wifiDirectPeers: [],
       localPeerId: null,
+      proxyMode: "direct",
+      proxyAddress: null,
+      quicEnabled: false,
+      tcpPeers: [],
+      quicPeers: [],
     },
     fallbackActive: false,
     fallbackReason: null,
@@ -1,6 +1,7 @@
 import { derived, writable, type Readable } from "svelte/store";
 import { getInvoke, getListen } from "../services/tauri";
 import type {
+  RelayReservationState,
   RelaySnapshot,
   RelayStatus,
   RelayScope,
@@ -58,6 +59,8 @@ export interface ConnectivityEventPayload {
   gatewayStatus?: PartialGatewayStatus | null;
   transports?: PartialTransportStatus | null;
   relays?: Array<PartialRelaySnapshot> | null;
+  frames?: PartialFrameStats | null;
+  deniedPeers?: DeniedPeer[] | null;
 }
 
 export interface ConnectivityState {
@@ -79,6 +82,8 @@ export interface ConnectivityState {
   averageSuccessRate: number | null;
   relays: RelaySnapshot[];
   activeRelayCount: number;
+  frameStats: FrameStats;
+  deniedPeers: DeniedPeer[];
   trustedDeviceSync: TrustedDeviceSyncStatus;
 }
 
@@ -132,6 +137,11 @@ type PartialRelaySnapshot = {
   uptimePercent?: number | null;
   uptime_percent?: number | null;
   error?: string | null;
+  reservation?: RelayReservationState | null;
+  circuitAddress?: string | null;
+  circuit_address?: string | null;
+  relayedConnections?: number | null;
+  relayed_connections?: number | null;
 };
 
 export interface GatewayStatus {
@@ -159,14 +169,53 @@ export interface TransportStatus {
   bluetoothPeers: string[];
   wifiDirectPeers: string[];
   localPeerId: string | null;
+  proxyMode: TransportProxyMode;
+  proxyAddress: string | null;
+  quicEnabled: boolean;
+  tcpPeers: string[];
+  quicPeers: string[];
 }
 
+export type TransportProxyMode = "direct" | "proxied" | "strict";
+
 type PartialTransportStatus = {
   bluetoothEnabled?: boolean | null;
   wifiDirectEnabled?: boolean | null;
   bluetoothPeers?: string[] | null;
   wifiDirectPeers?: string[] | null;
   localPeerId?: string | null;
+  proxyMode?: TransportProxyMode | null;
+  proxyAddress?: string | null;
+  quicEnabled?: boolean | null;
+  tcpPeers?: string[] | null;
+  quicPeers?: string[] | null;
+};
+
+export interface FrameStats {
+  accepted: number;
+  duplicatesDropped: number;
+  expiredDropped: number;
+}
+
+type PartialFrameStats = {
+  accepted?: number | null;
+  duplicatesDropped?: number | null;
+  expiredDropped?: number | null;
+};
+
+/** A peer the swarm refuses to connect to or hear gossip from. */
+export interface DeniedPeer {
+  peerId: string;
+  reason: "blocked" | "serverBan" | "manual";
+  serverId?: string | null;
+  deniedAt: string;
+  expiresAt?: string | null;
+}
+
+const defaultFrameStats: FrameStats = {
+  accepted: 0,
+  duplicatesDropped: 0,
+  expiredDropped: 0,
 };
 
 const defaultGatewayStatus: GatewayStatus = {
@@ -183,6 +232,11 @@ const defaultTransportStatus: TransportStatus = {
   bluetoothPeers: [],
   wifiDirectPeers: [],
   localPeerId: null,
+  proxyMode: "direct",
+  proxyAddress: null,
+  quicEnabled: false,
+  tcpPeers: [],
+  quicPeers: [],
 };
 
 type ConnectivityStore = Readable<ConnectivityState> & {
@@ -214,6 +268,8 @@ const initialState: ConnectivityState = {
   averageSuccessRate: null,
   relays: [],
   activeRelayCount: 0,
+  frameStats: { ...defaultFrameStats },
+  deniedPeers: [],
   trustedDeviceSync: {
     inProgress: false,
     lastSync: null,
@@ -239,6 +295,11 @@ const fallbackSnapshots: ConnectivityEventPayload[] = [
       bluetoothPeers: [],
       wifiDirectPeers: [],
       localPeerId: null,
+      proxyMode: "direct",
+      proxyAddress: null,
+      quicEnabled: false,
+      tcpPeers: [],
+      quicPeers: [],
     },
     relays: [],
   },
@@ -302,6 +363,11 @@ const fallbackSnapshots: ConnectivityEventPayload[] = [
       bluetoothPeers: ["mesh-alpha"],
       wifiDirectPeers: [],
       localPeerId: "self",
+      proxyMode: "direct",
+      proxyAddress: null,
+      quicEnabled: false,
+      tcpPeers: [],
+      quicPeers: [],
     },
     relays: [
       {
@@ -412,6 +478,11 @@ const fallbackSnapshots: ConnectivityEventPayload[] = [
       bluetoothPeers: ["mesh-alpha"],
       wifiDirectPeers: ["mesh-relay"],
       localPeerId: "self",
+      proxyMode: "direct",
+      proxyAddress: null,
+      quicEnabled: false,
+      tcpPeers: [],
+      quicPeers: [],
     },
     relays: [
       {
@@ -443,6 +514,12 @@ const relayStatuses: RelayStatus[] = [
   "degraded",
   "offline",
 ];
+const reservationStates: RelayReservationState[] = [
+  "pending",
+  "active",
+  "failed",
+];
+const proxyModes: TransportProxyMode[] = ["direct", "proxied", "strict"];
 
 function normalizePeer(peer: PartialMeshPeer, index: number): MeshPeer {
   const id = peer.id ?? `peer-${index}`;
@@ -552,6 +629,18 @@ function normalizeTransportStatus(
       )
     : defaultTransportStatus.wifiDirectPeers;
 
+  const tcpPeers = Array.isArray(status.tcpPeers)
+    ? status.tcpPeers.filter(
+        (peer): peer is string => typeof peer === "string" && peer.length > 0,
+      )
+    : defaultTransportStatus.tcpPeers;
+
+  const quicPeers = Array.isArray(status.quicPeers)
+    ? status.quicPeers.filter(
+        (peer): peer is string => typeof peer === "string" && peer.length > 0,
+      )
+    : defaultTransportStatus.quicPeers;
+
   return {
     bluetoothEnabled: Boolean(status.bluetoothEnabled),
     wifiDirectEnabled: Boolean(status.wifiDirectEnabled),
@@ -561,6 +650,17 @@ function normalizeTransportStatus(
       typeof status.localPeerId === "string" && status.localPeerId.length > 0
         ? status.localPeerId
         : null,
+    proxyMode:
+      status.proxyMode && proxyModes.includes(status.proxyMode)
+        ? status.proxyMode
+        : defaultTransportStatus.proxyMode,
+    proxyAddress:
+      typeof status.proxyAddress === "string" && status.proxyAddress.length > 0
+        ? status.proxyAddress
+        : null,
+    quicEnabled: Boolean(status.quicEnabled),
+    tcpPeers,
+    quicPeers,
   };
 }
 
@@ -610,6 +710,17 @@ function normalizeRelay(
     latencyMs: latency,
     uptimePercent: uptime,
     error: relay.error ?? null,
+    reservation:
+      relay.reservation && reservationStates.includes(relay.reservation)
+        ? relay.reservation
+        : null,
+    circuitAddress: relay.circuitAddress ?? relay.circuit_address ?? null,
+    relayedConnections:
+      typeof relay.relayedConnections === "number"
+        ? relay.relayedConnections
+        : typeof relay.relayed_connections === "number"
+          ? relay.relayed_connections
+          : null,
   };
 }
 
@@ -705,6 +816,16 @@ export function createConnectivityStore(): ConnectivityStore {
       const activeRelayCount = relays.filter(
         (relay) => relay.status === "healthy" || relay.status === "degraded",
       ).length;
+      const frameStats = payload.frames
+        ? {
+            accepted: payload.frames.accepted ?? 0,
+            duplicatesDropped: payload.frames.duplicatesDropped ?? 0,
+            expiredDropped: payload.frames.expiredDropped ?? 0,
+          }
+        : current.frameStats;
+      const deniedPeers = Array.isArray(payload.deniedPeers)
+        ? payload.deniedPeers
+        : current.deniedPeers;
 
       const meshPeers =
         typeof payload.meshPeers === "number"
@@ -763,6 +884,8 @@ export function createConnectivityStore(): ConnectivityStore {
         averageSuccessRate,
         relays,
         activeRelayCount,
+        frameStats,
+        deniedPeers,
         trustedDeviceSync: current.trustedDeviceSync,
       } satisfies ConnectivityState;
     });